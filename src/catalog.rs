@@ -1,6 +1,32 @@
-use rmcp::model::Tool;
+use std::collections::HashMap;
+
+use rmcp::model::{Prompt, Resource, ResourceTemplate, Tool, ToolAnnotations};
 use serde::Serialize;
 
+use crate::config::RankWeights;
+
+/// Globals the sandbox defines itself, plus JS keywords and the builtins its
+/// setup code relies on. A server never gets one of these as its proxy name.
+pub const RESERVED_JS_NAMES: &[&str] = &[
+    // cmcp globals
+    "servers", "tools", "resources", "resource_templates", "prompts", "read_resource",
+    "subscribe_resource", "unsubscribe_resource", "resource_updates", "get_prompt", "anyServer",
+    "cmcp", "console",
+    // builtins the setup code uses
+    "JSON", "Object", "Array", "String", "Promise", "Proxy", "Map", "Error", "globalThis",
+    // keywords
+    "await", "break", "case", "catch", "class", "const", "continue", "debugger", "default",
+    "delete", "do", "else", "enum", "export", "extends", "false", "finally", "for", "function",
+    "if", "import", "in", "instanceof", "let", "new", "null", "return", "static", "super",
+    "switch", "this", "throw", "true", "try", "typeof", "var", "void", "while", "with", "yield",
+];
+
+/// Whether `name` can't be used as a server proxy or alias global.
+/// Names starting with `__` belong to the sandbox's internals.
+pub fn is_reserved_js_name(name: &str) -> bool {
+    name.starts_with("__") || RESERVED_JS_NAMES.contains(&name)
+}
+
 /// A tool with its owning server name attached.
 #[derive(Debug, Clone, Serialize)]
 pub struct CatalogEntry {
@@ -12,12 +38,127 @@ pub struct CatalogEntry {
     pub description: String,
     /// JSON Schema for the tool's input parameters (as a JSON value).
     pub input_schema: serde_json::Value,
+    /// Parameter names listed in the schema's `required` array.
+    pub required: Vec<String>,
+    /// Parameter names declared in `properties` but not required.
+    pub optional: Vec<String>,
+    /// Behavior hints declared by the upstream server (read-only, destructive, ...).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<ToolAnnotations>,
+}
+
+impl CatalogEntry {
+    /// Build an entry, deriving the required/optional param lists from the schema.
+    pub fn new(server: &str, name: &str, description: &str, input_schema: serde_json::Value) -> Self {
+        let (required, optional) = split_params(&input_schema);
+        Self {
+            server: server.to_string(),
+            name: name.to_string(),
+            description: description.to_string(),
+            input_schema,
+            required,
+            optional,
+            annotations: None,
+        }
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.annotations.as_ref().and_then(|a| a.read_only_hint) == Some(true)
+    }
+
+    fn is_destructive(&self) -> bool {
+        self.annotations.as_ref().and_then(|a| a.destructive_hint) == Some(true)
+            && !self.is_read_only()
+    }
+}
+
+/// A resource published by an upstream server, readable with `read_resource(uri)`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ResourceEntry {
+    /// Which upstream server publishes this resource.
+    pub server: String,
+    pub uri: String,
+    pub name: String,
+    pub description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+}
+
+/// A parameterized resource URI (RFC 6570) published by an upstream server.
+/// Expanded URIs are read with `read_resource(uri, server)`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ResourceTemplateEntry {
+    /// Which upstream server publishes this template.
+    pub server: String,
+    pub uri_template: String,
+    pub name: String,
+    pub description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+}
+
+/// A prompt template published by an upstream server, rendered with `get_prompt`.
+#[derive(Debug, Clone, Serialize)]
+pub struct PromptEntry {
+    /// Which upstream server publishes this prompt.
+    pub server: String,
+    pub name: String,
+    pub description: String,
+    /// Argument names the prompt requires.
+    pub required: Vec<String>,
+    /// Argument names the prompt accepts but doesn't require.
+    pub optional: Vec<String>,
+}
+
+/// Which tools get full type declarations.
+///
+/// Tools left out are "callable-only": they stay in the `tools` array and are
+/// reachable through the server's Proxy object, but aren't declared, which
+/// keeps the transpile input small for very large servers.
+#[derive(Debug, Clone, Default)]
+pub struct DeclarationFilter {
+    /// Tools named as "server.tool" that are callable-only.
+    pub callable_only: Vec<String>,
+    /// Max declared tools per server; tools past the limit are callable-only.
+    pub max_per_server: Option<usize>,
+}
+
+impl DeclarationFilter {
+    fn is_callable_only(&self, entry: &CatalogEntry) -> bool {
+        let qualified = format!("{}.{}", entry.server, entry.name);
+        self.callable_only.contains(&qualified)
+    }
 }
 
 /// Aggregated catalog of tools from all connected MCP servers.
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct Catalog {
     entries: Vec<CatalogEntry>,
+    /// Every connected server, including ones that expose no tools.
+    servers: Vec<String>,
+    /// Implementation name/version each server reported at initialize.
+    versions: std::collections::HashMap<String, ServerVersion>,
+    /// Resources from servers that support `resources/list`.
+    resources: Vec<ResourceEntry>,
+    /// Resource templates from servers that support `resources/templates/list`.
+    resource_templates: Vec<ResourceTemplateEntry>,
+    /// Prompts from servers that support `prompts/list`.
+    prompts: Vec<PromptEntry>,
+}
+
+/// Name and version an upstream server reported in its `serverInfo`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ServerVersion {
+    pub name: String,
+    pub version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+impl std::fmt::Display for ServerVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.name, self.version)
+    }
 }
 
 impl Catalog {
@@ -26,61 +167,334 @@ impl Catalog {
     }
 
     /// Register all tools from a given server.
+    /// The server is recorded as connected even if `tools` is empty. A tool
+    /// the server lists twice is kept once, as first listed.
     pub fn add_server_tools(&mut self, server_name: &str, tools: Vec<Tool>) {
+        if !self.servers.iter().any(|s| s == server_name) {
+            self.servers.push(server_name.to_string());
+        }
         for tool in tools {
-            self.entries.push(CatalogEntry {
+            if self.entries.iter().any(|e| e.server == server_name && e.name == tool.name) {
+                tracing::warn!(server = %server_name, tool = %tool.name, "server lists this tool twice, keeping the first");
+                continue;
+            }
+            let mut entry = CatalogEntry::new(
+                server_name,
+                &tool.name,
+                tool.description.as_deref().unwrap_or(""),
+                serde_json::to_value(&tool.input_schema).unwrap_or_default(),
+            );
+            entry.annotations = tool.annotations;
+            self.entries.push(entry);
+        }
+    }
+
+    /// Add everything in `other`, a catalog of servers not in this one.
+    pub fn extend(&mut self, other: Catalog) {
+        self.servers.extend(other.servers);
+        self.entries.extend(other.entries);
+        self.versions.extend(other.versions);
+        self.resources.extend(other.resources);
+        self.resource_templates.extend(other.resource_templates);
+        self.prompts.extend(other.prompts);
+    }
+
+    /// Replace tool descriptions from `overrides` ("server.tool" -> text). Text
+    /// starting with `+` is appended to the upstream description instead.
+    pub fn override_descriptions(&mut self, overrides: &HashMap<String, String>) {
+        if overrides.is_empty() {
+            return;
+        }
+        for entry in &mut self.entries {
+            let Some(text) = overrides.get(&format!("{}.{}", entry.server, entry.name)) else {
+                continue;
+            };
+            entry.description = match text.strip_prefix('+') {
+                Some(extra) if !entry.description.is_empty() => {
+                    format!("{} {}", entry.description.trim_end(), extra.trim_start())
+                }
+                Some(extra) => extra.trim_start().to_string(),
+                None => text.clone(),
+            };
+        }
+    }
+
+    /// Register the resources a server publishes.
+    pub fn add_server_resources(&mut self, server_name: &str, resources: Vec<Resource>) {
+        for resource in resources {
+            self.resources.push(ResourceEntry {
+                server: server_name.to_string(),
+                uri: resource.raw.uri,
+                name: resource.raw.name,
+                description: resource.raw.description.unwrap_or_default(),
+                mime_type: resource.raw.mime_type,
+            });
+        }
+    }
+
+    /// Get all resources.
+    pub fn resources(&self) -> &[ResourceEntry] {
+        &self.resources
+    }
+
+    /// Return all resources as a JSON array (for injection into the JS sandbox).
+    pub fn resources_json_value(&self) -> serde_json::Value {
+        serde_json::to_value(&self.resources).unwrap_or_default()
+    }
+
+    /// Keep only the resources matching `keep`.
+    pub fn retain_resources(&mut self, keep: impl Fn(&ResourceEntry) -> bool) {
+        self.resources.retain(|r| keep(r));
+    }
+
+    /// Register the resource templates a server publishes.
+    pub fn add_server_resource_templates(&mut self, server_name: &str, templates: Vec<ResourceTemplate>) {
+        for template in templates {
+            self.resource_templates.push(ResourceTemplateEntry {
                 server: server_name.to_string(),
-                name: tool.name.to_string(),
-                description: tool
-                    .description
-                    .as_deref()
-                    .unwrap_or("")
-                    .to_string(),
-                input_schema: serde_json::to_value(&tool.input_schema).unwrap_or_default(),
+                uri_template: template.raw.uri_template,
+                name: template.raw.name,
+                description: template.raw.description.unwrap_or_default(),
+                mime_type: template.raw.mime_type,
             });
         }
     }
 
+    /// Get all resource templates.
+    pub fn resource_templates(&self) -> &[ResourceTemplateEntry] {
+        &self.resource_templates
+    }
+
+    /// Return all resource templates as a JSON array (for injection into the JS sandbox).
+    pub fn resource_templates_json_value(&self) -> serde_json::Value {
+        serde_json::to_value(&self.resource_templates).unwrap_or_default()
+    }
+
+    /// Keep only the resource templates matching `keep`.
+    pub fn retain_resource_templates(&mut self, keep: impl Fn(&ResourceTemplateEntry) -> bool) {
+        self.resource_templates.retain(|t| keep(t));
+    }
+
+    /// Register the prompts a server publishes.
+    pub fn add_server_prompts(&mut self, server_name: &str, prompts: Vec<Prompt>) {
+        for prompt in prompts {
+            let (required, optional) = prompt
+                .arguments
+                .unwrap_or_default()
+                .into_iter()
+                .partition::<Vec<_>, _>(|arg| arg.required == Some(true));
+            self.prompts.push(PromptEntry {
+                server: server_name.to_string(),
+                name: prompt.name,
+                description: prompt.description.unwrap_or_default(),
+                required: required.into_iter().map(|arg| arg.name).collect(),
+                optional: optional.into_iter().map(|arg| arg.name).collect(),
+            });
+        }
+    }
+
+    /// Get all prompts.
+    pub fn prompts(&self) -> &[PromptEntry] {
+        &self.prompts
+    }
+
+    /// Return all prompts as a JSON array (for injection into the JS sandbox).
+    pub fn prompts_json_value(&self) -> serde_json::Value {
+        serde_json::to_value(&self.prompts).unwrap_or_default()
+    }
+
+    /// Keep only the prompts matching `keep`.
+    pub fn retain_prompts(&mut self, keep: impl Fn(&PromptEntry) -> bool) {
+        self.prompts.retain(|p| keep(p));
+    }
+
+    /// Record the `serverInfo` a server reported at initialize.
+    pub fn set_server_version(&mut self, server_name: &str, version: ServerVersion) {
+        self.versions.insert(server_name.to_string(), version);
+    }
+
+    /// The `serverInfo` a server reported, if any.
+    pub fn server_version(&self, server_name: &str) -> Option<&ServerVersion> {
+        self.versions.get(server_name)
+    }
+
+    /// Return every server with its description and tool count as a JSON
+    /// array, sorted by name (for injection into the JS sandbox).
+    pub fn servers_json_value(&self) -> serde_json::Value {
+        let mut servers: Vec<&String> = self.servers.iter().collect();
+        servers.sort();
+        let servers: Vec<serde_json::Value> = servers
+            .into_iter()
+            .map(|name| {
+                let description = self
+                    .versions
+                    .get(name)
+                    .and_then(|v| v.description.as_deref())
+                    .unwrap_or_default();
+                serde_json::json!({
+                    "name": name,
+                    "description": description,
+                    "tools": self.entries.iter().filter(|e| &e.server == name).count(),
+                })
+            })
+            .collect();
+        servers.into()
+    }
+
     /// Return all entries as a JSON array (for injection into the JS sandbox).
     pub fn to_json_value(&self) -> serde_json::Value {
         serde_json::to_value(&self.entries).unwrap_or_default()
     }
 
+    /// Like [`Catalog::to_json_value`], with each `input_schema` cut down to
+    /// its top-level property names and types (see [`Catalog::attach_schemas`]).
+    pub fn summarized_json_value(&self) -> serde_json::Value {
+        let entries = self.entries.iter().map(|entry| {
+            let mut value = serde_json::to_value(entry).unwrap_or_default();
+            value["input_schema"] = summarize_schema(&entry.input_schema);
+            value
+        });
+        serde_json::Value::Array(entries.collect())
+    }
+
+    /// Put the full `input_schema` back on every tool entry in `value`: any
+    /// object with an `input_schema` whose `server` and `name` are in the catalog.
+    pub fn attach_schemas(&self, value: &mut serde_json::Value) {
+        let schemas: HashMap<(&str, &str), &serde_json::Value> = self
+            .entries
+            .iter()
+            .map(|e| ((e.server.as_str(), e.name.as_str()), &e.input_schema))
+            .collect();
+        attach_schemas(value, &schemas);
+    }
+
     /// Get all entries.
     pub fn entries(&self) -> &[CatalogEntry] {
         &self.entries
     }
 
+    /// Keep only the entries matching `keep`. Servers that had entries and are
+    /// left with none are dropped; servers that never had any are kept.
+    pub fn retain(&mut self, keep: impl Fn(&CatalogEntry) -> bool) {
+        let had_entries: std::collections::HashSet<String> =
+            self.entries.iter().map(|e| e.server.clone()).collect();
+        self.entries.retain(|e| keep(e));
+        let entries = &self.entries;
+        self.servers
+            .retain(|s| !had_entries.contains(s) || entries.iter().any(|e| &e.server == s));
+    }
+
+    /// Keep only the servers matching `keep`, along with their tools,
+    /// resources, prompts and versions.
+    pub fn retain_servers(&mut self, keep: impl Fn(&str) -> bool) {
+        self.servers.retain(|s| keep(s));
+        self.entries.retain(|e| keep(&e.server));
+        self.resources.retain(|r| keep(&r.server));
+        self.resource_templates.retain(|t| keep(&t.server));
+        self.prompts.retain(|p| keep(&p.server));
+        self.versions.retain(|s, _| keep(s));
+    }
+
+    /// Names of all connected servers, in connection order.
+    pub fn servers(&self) -> &[String] {
+        &self.servers
+    }
+
+    /// The JS global each server's proxy is bound to, by server name.
+    ///
+    /// Hyphens become underscores. When several servers sanitize to the same
+    /// name (`my-server` and `my_server`), the first by name keeps it and the
+    /// others get the first free `_2`, `_3`, ... suffix. A server whose name is
+    /// taken by a sandbox global (`tools`, `resources`, ...) is suffixed the same way,
+    /// and one starting with `__` loses the leading underscores first.
+    pub fn js_names(&self) -> std::collections::BTreeMap<&str, String> {
+        let servers: std::collections::BTreeSet<&str> = self
+            .servers
+            .iter()
+            .chain(self.entries.iter().map(|e| &e.server))
+            .map(String::as_str)
+            .collect();
+        let mut taken: std::collections::HashSet<String> = servers.iter().map(|s| s.replace('-', "_")).collect();
+        let mut claimed = std::collections::HashSet::new();
+        let mut names = std::collections::BTreeMap::new();
+        for server in servers {
+            let base = server.replace('-', "_");
+            let name = if !is_reserved_js_name(&base) && claimed.insert(base.clone()) {
+                base
+            } else {
+                // `__` names belong to the sandbox's internals, suffixed or not.
+                let stem = match base.trim_start_matches('_') {
+                    _ if !base.starts_with("__") => base.as_str(),
+                    "" => "server",
+                    stem => stem,
+                };
+                let name = (2..)
+                    .map(|n| format!("{stem}_{n}"))
+                    .find(|candidate| !taken.contains(candidate) && !is_reserved_js_name(candidate))
+                    .unwrap();
+                taken.insert(name.clone());
+                name
+            };
+            names.insert(server, name);
+        }
+        names
+    }
+
     /// Generate TypeScript type declarations for all servers and their tools.
     ///
     /// Produces `declare const <server>: { ... }` blocks so the agent
     /// gets autocomplete-style hints when writing execute() code.
     pub fn type_declarations(&self) -> String {
+        self.type_declarations_filtered(&DeclarationFilter::default())
+    }
+
+    /// Like [`Catalog::type_declarations`], but only declares tools the filter documents.
+    pub fn type_declarations_filtered(&self, filter: &DeclarationFilter) -> String {
         let mut servers: std::collections::BTreeMap<&str, Vec<&CatalogEntry>> =
             std::collections::BTreeMap::new();
         for entry in &self.entries {
             servers.entry(&entry.server).or_default().push(entry);
         }
 
+        let js_names = self.js_names();
         let mut out = String::new();
 
         // tools array type
-        out.push_str("declare const tools: Array<{ server: string; name: string; description: string; input_schema: any }>;\n\n");
+        out.push_str("declare const servers: Array<{ name: string; description: string; tools: number }>;\n");
+        out.push_str("declare const tools: Array<{ server: string; name: string; description: string; input_schema: any; required: string[]; optional: string[]; annotations?: { title?: string; readOnlyHint?: boolean; destructiveHint?: boolean; idempotentHint?: boolean; openWorldHint?: boolean } }>;\n");
+        out.push_str("declare const resources: Array<{ server: string; uri: string; name: string; description: string; mime_type?: string }>;\n");
+        out.push_str("declare const resource_templates: Array<{ server: string; uri_template: string; name: string; description: string; mime_type?: string }>;\n");
+        out.push_str("declare function read_resource(uri: string, server?: string): Promise<any>;\n");
+        out.push_str("declare function subscribe_resource(uri: string, server?: string): Promise<any>;\n");
+        out.push_str("declare function unsubscribe_resource(uri: string, server?: string): Promise<any>;\n");
+        out.push_str("declare function resource_updates(): Promise<Array<{ server: string; uri: string }>>;\n");
+        out.push_str("declare const prompts: Array<{ server: string; name: string; description: string; required: string[]; optional: string[] }>;\n");
+        out.push_str("declare function get_prompt(server: string, name: string, args?: Record<string, string>): Promise<Array<{ role: string; content: any }>>;\n\n");
 
         for (server, tools) in &servers {
-            // Sanitize server names: hyphens become underscores (matches sandbox proxy names).
-            let js_name = server.replace('-', "_");
-            if !is_valid_js_ident(&js_name) {
+            // Same names as the sandbox proxies (see `js_names`).
+            let js_name = &js_names[server];
+            if !is_valid_js_ident(js_name) {
                 continue;
             }
 
             out.push_str(&format!("declare const {js_name}: {{\n"));
-            for tool in tools {
+            let documented = tools
+                .iter()
+                .filter(|t| !filter.is_callable_only(t))
+                .take(filter.max_per_server.unwrap_or(usize::MAX));
+            for tool in documented {
                 let params_type = schema_to_ts_params(&tool.input_schema);
                 // Sanitize description for JSDoc (escape */ sequences).
-                let desc = tool.description.replace('\n', " ").replace("*/", "* /");
-                if !desc.is_empty() {
-                    out.push_str(&format!("  /** {desc} */\n"));
+                let mut doc = tool.description.replace('\n', " ").replace("*/", "* /");
+                // Behavior hints as JSDoc tags, so cautious agents can tell reads from writes.
+                for (tag, set) in [("@readonly", tool.is_read_only()), ("@destructive", tool.is_destructive())] {
+                    if set {
+                        doc = format!("{doc} {tag}").trim_start().to_string();
+                    }
+                }
+                if !doc.is_empty() {
+                    out.push_str(&format!("  /** {doc} */\n"));
                 }
                 // Quote tool names that aren't valid identifiers.
                 let name_str = if is_valid_js_ident(&tool.name) {
@@ -96,25 +510,240 @@ impl Catalog {
         out
     }
 
+    /// Rank tools matching `query`, best first.
+    ///
+    /// Each whitespace-separated term scores 3 for a hit in the tool name and 1
+    /// for a hit in the description; tools with no hits are dropped. Weights then
+    /// boost read-only tools, demote destructive ones, favor servers early in
+    /// `priority`, and favor tools with a high `recency` (0.0–1.0, e.g. from
+    /// call history).
+    pub fn rank(
+        &self,
+        query: &str,
+        weights: &RankWeights,
+        priority: &[String],
+        recency: impl Fn(&CatalogEntry) -> f64,
+    ) -> Vec<&CatalogEntry> {
+        let terms: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+        let preference = |server: &str| {
+            priority
+                .iter()
+                .position(|p| p == server)
+                .map_or(0.0, |i| (priority.len() - i) as f64 / priority.len() as f64)
+        };
+
+        let mut scored: Vec<(f64, &CatalogEntry)> = self
+            .entries
+            .iter()
+            .filter_map(|entry| {
+                let name = entry.name.to_lowercase();
+                let desc = entry.description.to_lowercase();
+                let text: f64 = terms
+                    .iter()
+                    .map(|t| {
+                        if name.contains(t.as_str()) {
+                            3.0
+                        } else if desc.contains(t.as_str()) {
+                            1.0
+                        } else {
+                            0.0
+                        }
+                    })
+                    .sum();
+                if text == 0.0 && !terms.is_empty() {
+                    return None;
+                }
+
+                let mut score = text
+                    + weights.recent_use * recency(entry)
+                    + weights.server_priority * preference(&entry.server);
+                if entry.is_read_only() {
+                    score += weights.read_only;
+                }
+                if entry.is_destructive() {
+                    score += weights.destructive;
+                }
+                Some((score, entry))
+            })
+            .collect();
+
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored.into_iter().map(|(_, e)| e).collect()
+    }
+
+    /// Fuzzy tool search for pickers and the CLI, best match first.
+    ///
+    /// Every whitespace-separated term must match the tool name (exactly, as a
+    /// prefix, a substring or a subsequence like "lsis" for "list_issues"),
+    /// the server name or the description. Closer name matches score higher;
+    /// ties are broken by server and tool name.
+    pub fn search(&self, query: &str) -> Vec<&CatalogEntry> {
+        let terms: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+        let mut scored: Vec<(u32, &CatalogEntry)> = self
+            .entries
+            .iter()
+            .filter_map(|entry| {
+                let name = entry.name.to_lowercase();
+                let server = entry.server.to_lowercase();
+                let desc = entry.description.to_lowercase();
+                terms.iter().try_fold(0, |score, term| {
+                    let term_score = if name == *term {
+                        10
+                    } else if name.starts_with(term.as_str()) {
+                        6
+                    } else if name.contains(term.as_str()) {
+                        4
+                    } else if server.contains(term.as_str()) {
+                        3
+                    } else if is_subsequence(term, &name) {
+                        2
+                    } else if desc.contains(term.as_str()) {
+                        1
+                    } else {
+                        return None;
+                    };
+                    Some(score + term_score)
+                })
+                .map(|score| (score, entry))
+            })
+            .collect();
+
+        scored.sort_by(|a, b| {
+            b.0.cmp(&a.0)
+                .then_with(|| (&a.1.server, &a.1.name).cmp(&(&b.1.server, &b.1.name)))
+        });
+        scored.into_iter().map(|(_, e)| e).collect()
+    }
+
     /// Summarize the catalog for display.
+    /// Servers are listed by name; those that connected but expose no tools are
+    /// shown as "connected, 0 tools".
     pub fn summary(&self) -> String {
-        let mut servers: std::collections::HashMap<&str, usize> = std::collections::HashMap::new();
+        let mut servers: std::collections::BTreeMap<&str, usize> = std::collections::BTreeMap::new();
         for entry in &self.entries {
             *servers.entry(&entry.server).or_default() += 1;
         }
-        let parts: Vec<String> = servers
-            .iter()
-            .map(|(name, count)| format!("{name}: {count} tools"))
+        // Servers are added in connection order, which varies between runs.
+        let mut names: Vec<&String> = self.servers.iter().collect();
+        names.sort();
+        let parts: Vec<String> = names
+            .into_iter()
+            .map(|name| {
+                let label = match self.versions.get(name) {
+                    Some(version) => format!("{name} [{version}]"),
+                    None => name.clone(),
+                };
+                match servers.get(name.as_str()) {
+                    Some(count) => format!("{label}: {count} tools"),
+                    None => format!("{label}: connected, 0 tools"),
+                }
+            })
             .collect();
         format!("{} total tools ({})", self.entries.len(), parts.join(", "))
     }
 }
 
+/// Whether the chars of `needle` appear in `haystack` in order.
+fn is_subsequence(needle: &str, haystack: &str) -> bool {
+    let mut rest = haystack.chars();
+    needle.chars().all(|c| rest.any(|h| h == c))
+}
+
+/// `schema` with only its type, required list and each property's type.
+fn summarize_schema(schema: &serde_json::Value) -> serde_json::Value {
+    let mut summary = serde_json::Map::new();
+    for key in ["type", "required"] {
+        if let Some(value) = schema.get(key) {
+            summary.insert(key.to_string(), value.clone());
+        }
+    }
+    if let Some(properties) = schema.get("properties").and_then(|p| p.as_object()) {
+        let properties = properties
+            .iter()
+            .map(|(name, prop)| {
+                let kind = prop.get("type").map(|t| serde_json::json!({ "type": t }));
+                (name.clone(), kind.unwrap_or_else(|| serde_json::json!({})))
+            })
+            .collect();
+        summary.insert("properties".to_string(), serde_json::Value::Object(properties));
+    }
+    serde_json::Value::Object(summary)
+}
+
+fn attach_schemas(value: &mut serde_json::Value, schemas: &HashMap<(&str, &str), &serde_json::Value>) {
+    match value {
+        serde_json::Value::Array(items) => items.iter_mut().for_each(|item| attach_schemas(item, schemas)),
+        serde_json::Value::Object(object) => {
+            let key = match (object.get("server"), object.get("name")) {
+                (Some(serde_json::Value::String(server)), Some(serde_json::Value::String(name))) => {
+                    schemas.get(&(server.as_str(), name.as_str())).copied()
+                }
+                _ => None,
+            };
+            match key {
+                Some(schema) if object.contains_key("input_schema") => {
+                    object.insert("input_schema".to_string(), schema.clone());
+                }
+                _ => object.values_mut().for_each(|v| attach_schemas(v, schemas)),
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Split a schema's top-level properties into (required, optional) name lists.
+///
+/// Required names come from the schema's `required` array in declared order;
+/// optional names are the remaining `properties` keys.
+fn split_params(schema: &serde_json::Value) -> (Vec<String>, Vec<String>) {
+    let required: Vec<String> = schema
+        .get("required")
+        .and_then(|v| v.as_array())
+        .map(|a| a.iter().filter_map(|v| v.as_str().map(String::from)).collect())
+        .unwrap_or_default();
+
+    let optional = schema
+        .get("properties")
+        .and_then(|v| v.as_object())
+        .map(|props| {
+            props
+                .keys()
+                .filter(|k| !required.contains(k))
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_default();
+
+    (required, optional)
+}
+
 /// Convert a JSON Schema `input_schema` to a TypeScript-style parameter string.
 ///
 /// Given `{ "type": "object", "properties": { "title": { "type": "string" }, "width": { "type": "number" } }, "required": ["title"] }`,
 /// produces `title: string; width?: number`.
 fn schema_to_ts_params(schema: &serde_json::Value) -> String {
+    let mut refs = RefScope { root: schema, expanding: Vec::new() };
+    object_to_ts_params(schema, &mut refs)
+}
+
+/// Local `$ref`s (`#/$defs/...`, `#/definitions/...`) resolve against `root`.
+/// `expanding` holds the refs being inlined, so a cyclic one becomes `any`.
+struct RefScope<'a> {
+    root: &'a serde_json::Value,
+    expanding: Vec<&'a str>,
+}
+
+fn object_to_ts_params<'a>(schema: &'a serde_json::Value, refs: &mut RefScope<'a>) -> String {
+    if let Some(reference) = schema.get("$ref").and_then(|r| r.as_str()) {
+        return match resolve_ref(reference, refs) {
+            Some(target) => {
+                let params = object_to_ts_params(target, refs);
+                refs.expanding.pop();
+                params
+            }
+            None => String::new(),
+        };
+    }
     let Some(properties) = schema.get("properties").and_then(|v| v.as_object()) else {
         return String::new();
     };
@@ -127,7 +756,7 @@ fn schema_to_ts_params(schema: &serde_json::Value) -> String {
 
     let mut params = Vec::new();
     for (name, prop) in properties {
-        let ts_type = json_type_to_ts(prop);
+        let ts_type = json_type_to_ts(prop, refs);
         let optional = if required.contains(&name.as_str()) {
             ""
         } else {
@@ -145,8 +774,28 @@ fn schema_to_ts_params(schema: &serde_json::Value) -> String {
     params.join("; ")
 }
 
+/// The subschema `reference` points to, pushed onto `refs.expanding`; `None`
+/// if it isn't a local pointer, doesn't resolve or is already being expanded.
+fn resolve_ref<'a>(reference: &'a str, refs: &mut RefScope<'a>) -> Option<&'a serde_json::Value> {
+    if refs.expanding.contains(&reference) {
+        return None;
+    }
+    let target = refs.root.pointer(reference.strip_prefix('#')?)?;
+    refs.expanding.push(reference);
+    Some(target)
+}
+
 /// Map a JSON Schema type to a TypeScript type string.
-fn json_type_to_ts(schema: &serde_json::Value) -> String {
+fn json_type_to_ts<'a>(schema: &'a serde_json::Value, refs: &mut RefScope<'a>) -> String {
+    if let Some(reference) = schema.get("$ref").and_then(|r| r.as_str()) {
+        let Some(target) = resolve_ref(reference, refs) else {
+            return "any".to_string();
+        };
+        let ts = json_type_to_ts(target, refs);
+        refs.expanding.pop();
+        return ts;
+    }
+
     // Handle enum values
     if let Some(enum_vals) = schema.get("enum").and_then(|v| v.as_array()) {
         let literals: Vec<String> = enum_vals
@@ -159,11 +808,63 @@ fn json_type_to_ts(schema: &serde_json::Value) -> String {
         return literals.join(" | ");
     }
 
-    let type_str = schema
-        .get("type")
-        .and_then(|v| v.as_str())
-        .unwrap_or("any");
+    // oneOf/anyOf become unions and allOf an intersection. Members without
+    // a type of their own (e.g. only `required`) only constrain the schema
+    // around them, so those fall through to its `type`.
+    let union = ["oneOf", "anyOf"]
+        .iter()
+        .find_map(|key| schema.get(key).and_then(|v| v.as_array()));
+    if let Some(members) = union {
+        let types = member_types(members, refs);
+        if !types.is_empty() && !types.iter().any(|t| t == "any") {
+            return types.join(" | ");
+        }
+    }
+    if let Some(members) = schema.get("allOf").and_then(|v| v.as_array()) {
+        let types: Vec<String> = member_types(members, refs)
+            .into_iter()
+            .filter(|t| t != "any")
+            .map(|t| if t.contains(" | ") { format!("({t})") } else { t })
+            .collect();
+        if !types.is_empty() {
+            return types.join(" & ");
+        }
+    }
+
+    match schema.get("type") {
+        Some(serde_json::Value::String(type_str)) => type_name_to_ts(type_str, schema, refs),
+        // `"type": ["string", "null"]`
+        Some(serde_json::Value::Array(types)) => {
+            let mut names: Vec<String> = Vec::new();
+            for ts in types.iter().filter_map(|t| t.as_str()).map(|t| type_name_to_ts(t, schema, refs)) {
+                if !names.contains(&ts) {
+                    names.push(ts);
+                }
+            }
+            if names.is_empty() {
+                "any".to_string()
+            } else {
+                names.join(" | ")
+            }
+        }
+        _ => "any".to_string(),
+    }
+}
 
+/// TypeScript types of the `oneOf`/`anyOf`/`allOf` members, without duplicates.
+fn member_types<'a>(members: &'a [serde_json::Value], refs: &mut RefScope<'a>) -> Vec<String> {
+    let mut types: Vec<String> = Vec::new();
+    for member in members {
+        let ts = json_type_to_ts(member, refs);
+        if !types.contains(&ts) {
+            types.push(ts);
+        }
+    }
+    types
+}
+
+/// The TypeScript type for one JSON Schema `type` name of `schema`.
+fn type_name_to_ts<'a>(type_str: &str, schema: &'a serde_json::Value, refs: &mut RefScope<'a>) -> String {
     match type_str {
         "string" => "string".to_string(),
         "number" | "integer" => "number".to_string(),
@@ -171,7 +872,12 @@ fn json_type_to_ts(schema: &serde_json::Value) -> String {
         "null" => "null".to_string(),
         "array" => {
             if let Some(items) = schema.get("items") {
-                format!("{}[]", json_type_to_ts(items))
+                let item = json_type_to_ts(items, refs);
+                if item.contains(" | ") || item.contains(" & ") {
+                    format!("({item})[]")
+                } else {
+                    format!("{item}[]")
+                }
             } else {
                 "any[]".to_string()
             }
@@ -181,7 +887,7 @@ fn json_type_to_ts(schema: &serde_json::Value) -> String {
                 if props.is_empty() {
                     "Record<string, any>".to_string()
                 } else {
-                    let inner = schema_to_ts_params(schema);
+                    let inner = object_to_ts_params(schema, refs);
                     format!("{{ {inner} }}")
                 }
             } else {
@@ -210,12 +916,33 @@ mod tests {
     use super::*;
 
     fn make_entry(server: &str, name: &str, desc: &str, schema: serde_json::Value) -> CatalogEntry {
-        CatalogEntry {
-            server: server.to_string(),
-            name: name.to_string(),
-            description: desc.to_string(),
-            input_schema: schema,
-        }
+        CatalogEntry::new(server, name, desc, schema)
+    }
+
+    #[test]
+    fn test_required_optional_params() {
+        let entry = make_entry("github", "create_issue", "Create an issue", serde_json::json!({
+            "type": "object",
+            "properties": {
+                "repo": {"type": "string"},
+                "title": {"type": "string"},
+                "body": {"type": "string"},
+                "labels": {"type": "array", "items": {"type": "string"}}
+            },
+            "required": ["repo", "title"]
+        }));
+
+        assert_eq!(entry.required, vec!["repo", "title"]);
+        let mut optional = entry.optional.clone();
+        optional.sort();
+        assert_eq!(optional, vec!["body", "labels"]);
+
+        // The lists are exposed to agent code through the injected `tools` array.
+        let mut catalog = Catalog::new();
+        catalog.entries = vec![entry];
+        let json = catalog.to_json_value();
+        assert_eq!(json[0]["required"], serde_json::json!(["repo", "title"]));
+        assert!(json[0]["optional"].is_array());
     }
 
     #[test]
@@ -237,6 +964,319 @@ mod tests {
         assert!(decls.contains("url: string"), "decls: {decls}");
     }
 
+    #[test]
+    fn test_refs_are_inlined_in_declarations() {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "owner": { "$ref": "#/$defs/User" },
+                "tags": { "type": "array", "items": { "$ref": "#/definitions/Tag" } },
+                "missing": { "$ref": "#/$defs/Nope" },
+            },
+            "required": ["owner"],
+            "$defs": {
+                "User": {
+                    "type": "object",
+                    "properties": { "login": { "type": "string" }, "manager": { "$ref": "#/$defs/User" } },
+                    "required": ["login"],
+                },
+            },
+            "definitions": { "Tag": { "type": "string" } },
+        });
+        assert_eq!(
+            schema_to_ts_params(&schema),
+            "missing?: any; owner: { login: string; manager?: any }; tags?: string[]"
+        );
+    }
+
+    #[test]
+    fn test_composition_keywords_become_unions_and_intersections() {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "shape": {
+                    "oneOf": [
+                        {
+                            "type": "object",
+                            "properties": { "kind": { "enum": ["circle"] }, "radius": { "type": "number" } },
+                            "required": ["kind", "radius"],
+                        },
+                        {
+                            "type": "object",
+                            "properties": { "kind": { "enum": ["square"] }, "side": { "type": "number" } },
+                            "required": ["kind", "side"],
+                        },
+                    ],
+                },
+                "id": { "anyOf": [{ "type": "string" }, { "type": "integer" }] },
+                "note": { "type": ["string", "null"] },
+                "ids": { "type": "array", "items": { "type": ["string", "integer"] } },
+                "tagged": {
+                    "allOf": [
+                        { "type": "object", "properties": { "a": { "type": "string" } } },
+                        { "type": "object", "properties": { "b": { "type": "number" } } },
+                    ],
+                },
+            },
+            "required": ["shape"],
+        });
+        assert_eq!(
+            schema_to_ts_params(&schema),
+            "id?: string | number; ids?: (string | number)[]; note?: string | null; \
+             shape: { kind: \"circle\"; radius: number } | { kind: \"square\"; side: number }; \
+             tagged?: { a?: string } & { b?: number }"
+        );
+    }
+
+    #[test]
+    fn test_description_overrides() {
+        let schema = serde_json::json!({"type": "object", "properties": {}});
+        let mut catalog = Catalog::new();
+        catalog.entries = vec![
+            make_entry("github", "list_issues", "", schema.clone()),
+            make_entry("github", "search_code", "Search code.", schema.clone()),
+            make_entry("canva", "create_design", "Create a design", schema),
+        ];
+
+        let overrides = HashMap::from([
+            ("github.list_issues".to_string(), "List issues in a repository, newest first".to_string()),
+            ("github.search_code".to_string(), "+ Supports GitHub code search syntax".to_string()),
+        ]);
+        catalog.override_descriptions(&overrides);
+
+        let decls = catalog.type_declarations();
+        assert!(decls.contains("/** List issues in a repository, newest first */"), "decls: {decls}");
+        assert!(decls.contains("/** Search code. Supports GitHub code search syntax */"), "decls: {decls}");
+
+        let json = catalog.to_json_value();
+        assert_eq!(json[0]["description"], "List issues in a repository, newest first");
+        assert_eq!(json[1]["description"], "Search code. Supports GitHub code search syntax");
+        assert_eq!(json[2]["description"], "Create a design");
+        let ranked = catalog.rank("newest", &RankWeights::default(), &[], |_| 0.0);
+        assert_eq!(ranked[0].name, "list_issues");
+    }
+
+    #[test]
+    fn test_duplicate_tools_within_a_server_are_kept_once() {
+        let schema = std::sync::Arc::new(serde_json::Map::new());
+        let mut catalog = Catalog::new();
+        catalog.add_server_tools("jira", vec![
+            Tool::new("get_issue", "Get an issue", schema.clone()),
+            Tool::new("get_issue", "Get an issue (again)", schema.clone()),
+        ]);
+        catalog.add_server_tools("github", vec![Tool::new("get_issue", "Get an issue", schema)]);
+
+        let names: Vec<_> = catalog.entries().iter().map(|e| (e.server.as_str(), e.description.as_str())).collect();
+        assert_eq!(names, [("jira", "Get an issue"), ("github", "Get an issue")]);
+    }
+
+    #[test]
+    fn test_colliding_server_names_get_suffixes() {
+        let mut catalog = Catalog::new();
+        for server in ["my_server", "my-server", "my_server_2", "other"] {
+            catalog.add_server_tools(server, vec![]);
+        }
+        catalog.entries = vec![
+            make_entry("my-server", "a", "", serde_json::json!({})),
+            make_entry("my_server", "b", "", serde_json::json!({})),
+        ];
+
+        let names = catalog.js_names();
+        assert_eq!(names["my-server"], "my_server");
+        assert_eq!(names["my_server"], "my_server_3");
+        assert_eq!(names["my_server_2"], "my_server_2");
+        assert_eq!(names["other"], "other");
+
+        let decls = catalog.type_declarations();
+        assert!(decls.contains("declare const my_server: {\n  a(params"), "decls: {decls}");
+        assert!(decls.contains("declare const my_server_3: {\n  b(params"), "decls: {decls}");
+    }
+
+    #[test]
+    fn test_servers_named_like_globals_get_suffixes() {
+        let mut catalog = Catalog::new();
+        for server in ["resources", "servers", "resource-templates", "delete", "__call_tool", "__", "tools_2"] {
+            catalog.add_server_tools(server, vec![]);
+        }
+
+        let names = catalog.js_names();
+        assert_eq!(names["resources"], "resources_2");
+        assert_eq!(names["servers"], "servers_2");
+        assert_eq!(names["resource-templates"], "resource_templates_2");
+        assert_eq!(names["delete"], "delete_2");
+        assert_eq!(names["__call_tool"], "call_tool_2");
+        assert_eq!(names["__"], "server_2");
+        assert_eq!(names["tools_2"], "tools_2");
+    }
+
+    #[test]
+    fn test_empty_server_shown_as_connected() {
+        let mut catalog = Catalog::new();
+        catalog.add_server_tools("idle", vec![]);
+
+        assert_eq!(catalog.servers(), ["idle"]);
+        assert!(catalog.entries().is_empty());
+        assert_eq!(catalog.summary(), "0 total tools (idle: connected, 0 tools)");
+    }
+
+    #[test]
+    fn test_summary_lists_servers_alphabetically() {
+        let mut catalog = Catalog::new();
+        catalog.add_server_tools("figma", vec![]);
+        catalog.entries = vec![
+            make_entry("figma", "get_file", "", serde_json::json!({})),
+            make_entry("canva", "create_design", "", serde_json::json!({})),
+            make_entry("canva", "list_designs", "", serde_json::json!({})),
+        ];
+        catalog.servers.push("canva".to_string());
+
+        assert_eq!(catalog.summary(), "3 total tools (canva: 2 tools, figma: 1 tools)");
+    }
+
+    #[test]
+    fn test_rank_prefers_read_only_on_equal_match() {
+        let mut delete = make_entry("github", "delete_issue", "Delete an issue", serde_json::json!({}));
+        delete.annotations = Some(ToolAnnotations {
+            destructive_hint: Some(true),
+            ..Default::default()
+        });
+        let mut get = make_entry("github", "get_issue", "Fetch an issue", serde_json::json!({}));
+        get.annotations = Some(ToolAnnotations {
+            read_only_hint: Some(true),
+            ..Default::default()
+        });
+        let unrelated = make_entry("canva", "create_design", "Create a design", serde_json::json!({}));
+
+        let mut catalog = Catalog::new();
+        catalog.entries = vec![delete, get, unrelated];
+
+        let ranked = catalog.rank("issue", &RankWeights::default(), &[], |_| 0.0);
+        let names: Vec<&str> = ranked.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["get_issue", "delete_issue"]);
+
+        // Recent use outweighs the annotation boost.
+        let ranked = catalog.rank("issue", &RankWeights::default(), &[], |e| {
+            if e.name == "delete_issue" { 1.0 } else { 0.0 }
+        });
+        assert_eq!(ranked[0].name, "delete_issue");
+    }
+
+    #[test]
+    fn test_annotations_surface_in_json_and_declarations() {
+        let mut delete = make_entry("github", "delete_issue", "Delete an issue", serde_json::json!({}));
+        delete.annotations = Some(ToolAnnotations {
+            destructive_hint: Some(true),
+            ..Default::default()
+        });
+        let mut get = make_entry("github", "get_issue", "", serde_json::json!({}));
+        get.annotations = Some(ToolAnnotations {
+            read_only_hint: Some(true),
+            ..Default::default()
+        });
+        let plain = make_entry("github", "list_issues", "List issues", serde_json::json!({}));
+
+        let mut catalog = Catalog::new();
+        catalog.entries = vec![delete, get, plain];
+
+        let json = catalog.to_json_value();
+        assert_eq!(json[0]["annotations"]["destructiveHint"], true);
+        assert_eq!(json[1]["annotations"]["readOnlyHint"], true);
+        assert!(json[2].get("annotations").is_none());
+
+        let decls = catalog.type_declarations();
+        assert!(decls.contains("  /** Delete an issue @destructive */\n  delete_issue("), "decls: {decls}");
+        assert!(decls.contains("  /** @readonly */\n  get_issue("), "decls: {decls}");
+        assert!(decls.contains("  /** List issues */\n  list_issues("), "decls: {decls}");
+    }
+
+    #[test]
+    fn test_search_ranks_fuzzy_matches() {
+        let mut catalog = Catalog::new();
+        catalog.entries = vec![
+            make_entry("github", "list_issues", "List issues in a repository", serde_json::json!({})),
+            make_entry("github", "issue", "Fetch one issue", serde_json::json!({})),
+            make_entry("jira", "issues_search", "Search issues with JQL", serde_json::json!({})),
+            make_entry("canva", "create_design", "Create a design", serde_json::json!({})),
+        ];
+        let names = |query: &str| -> Vec<String> {
+            catalog.search(query).iter().map(|e| format!("{}.{}", e.server, e.name)).collect()
+        };
+
+        assert_eq!(names("issue"), ["github.issue", "jira.issues_search", "github.list_issues"]);
+        assert_eq!(names("lsis"), ["github.list_issues"]);
+        assert_eq!(names("jira issue"), ["jira.issues_search"]);
+        assert_eq!(names("repository"), ["github.list_issues"]);
+        assert!(names("stripe").is_empty());
+    }
+
+    #[test]
+    fn test_rank_prefers_priority_server_on_equal_match() {
+        let mut catalog = Catalog::new();
+        catalog.entries = vec![
+            make_entry("playwright", "navigate", "Open a URL", serde_json::json!({})),
+            make_entry("chrome", "navigate", "Open a URL", serde_json::json!({})),
+            make_entry("puppeteer", "navigate", "Open a URL", serde_json::json!({})),
+        ];
+        let priority = ["chrome".to_string(), "puppeteer".to_string()];
+
+        let ranked = catalog.rank("navigate", &RankWeights::default(), &priority, |_| 0.0);
+        let servers: Vec<&str> = ranked.iter().map(|e| e.server.as_str()).collect();
+        assert_eq!(servers, ["chrome", "puppeteer", "playwright"]);
+
+        // A better text match still wins over priority.
+        catalog.entries.push(make_entry("other", "navigate_page", "Navigate", serde_json::json!({})));
+        let ranked = catalog.rank("navigate page", &RankWeights::default(), &priority, |_| 0.0);
+        assert_eq!(ranked[0].server, "other");
+    }
+
+    #[test]
+    fn test_view_restricts_catalog_and_declarations() {
+        let mut catalog = Catalog::new();
+        catalog.entries = vec![
+            make_entry("github", "list_issues", "List issues", serde_json::json!({"type": "object"})),
+            make_entry("github", "delete_repo", "Delete a repo", serde_json::json!({"type": "object"})),
+            make_entry("canva", "create_design", "Create a design", serde_json::json!({"type": "object"})),
+            make_entry("stripe", "refund", "Refund a charge", serde_json::json!({"type": "object"})),
+        ];
+        catalog.servers = vec!["github".into(), "canva".into(), "stripe".into(), "figma".into(), "jira".into()];
+
+        let view = crate::config::ViewConfig {
+            servers: vec!["canva".into(), "figma".into()],
+            tools: vec!["github.list_issues".into()],
+        };
+        catalog.retain(|e| view.includes(&e.server, &e.name));
+        catalog.retain_servers(|s| view.touches_server(s));
+
+        let names: Vec<&str> = catalog.entries().iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["list_issues", "create_design"]);
+        // Tool-less (e.g. lazy) servers stay if the view names them.
+        assert_eq!(catalog.servers(), ["github", "canva", "figma"]);
+
+        let decls = catalog.type_declarations();
+        assert!(decls.contains("list_issues("), "decls: {decls}");
+        assert!(!decls.contains("delete_repo"), "decls: {decls}");
+        assert!(!decls.contains("declare const stripe"), "decls: {decls}");
+    }
+
+    #[test]
+    fn test_declaration_filter_callable_only() {
+        let mut catalog = Catalog::new();
+        catalog.entries = vec![
+            make_entry("jira", "get_issue", "Get an issue", serde_json::json!({"type": "object"})),
+            make_entry("jira", "bulk_migrate", "Migrate projects", serde_json::json!({"type": "object"})),
+            make_entry("jira", "list_boards", "List boards", serde_json::json!({"type": "object"})),
+        ];
+
+        let filter = DeclarationFilter {
+            callable_only: vec!["jira.bulk_migrate".to_string()],
+            max_per_server: Some(1),
+        };
+        let decls = catalog.type_declarations_filtered(&filter);
+        assert!(decls.contains("get_issue(params:"), "decls: {decls}");
+        assert!(!decls.contains("bulk_migrate"), "decls: {decls}");
+        assert!(!decls.contains("list_boards"), "decls: {decls}");
+    }
+
     #[test]
     fn test_type_declarations_hyphenated_params() {
         let mut catalog = Catalog::new();
@@ -1,208 +1,1801 @@
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result};
-use rmcp::model::{CallToolRequestParams, CallToolResult};
-use rmcp::service::RunningService;
+use rmcp::handler::client::ClientHandler;
+use rmcp::model::{
+    CallToolRequestParams, CallToolResult, ClientRequest, GetPromptRequestParams,
+    GetPromptResult, LoggingLevel, LoggingMessageNotificationParam, Prompt,
+    ReadResourceRequestParams, ReadResourceResult, Resource, ResourceTemplate,
+    ResourceUpdatedNotificationParam, SetLevelRequestParams, SubscribeRequestParams, Tool,
+    UnsubscribeRequestParams,
+};
+use rmcp::service::{NotificationContext, RunningService, ServiceError};
 use rmcp::transport::streamable_http_client::StreamableHttpClientTransportConfig;
 use rmcp::transport::ConfigureCommandExt;
-use rmcp::{RoleClient, ServiceExt};
+use rmcp::{Peer, RoleClient, ServiceExt};
 use tokio::process::Command;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, Semaphore};
 use tracing::info;
 
-use crate::catalog::Catalog;
-use crate::config::ServerConfig;
+use crate::catalog::{Catalog, ServerVersion};
+use crate::config::{self, BreakerPolicy, CachePolicy, RetryPolicy, ServerConfig, ServerOptions, ViewConfig};
+use crate::metrics::Metrics;
+use crate::secret::{redact, resolve_secret_async, Secrets};
 
-/// A handle to one connected upstream MCP server with its config for reconnection.
+/// Resource update notifications kept per connection until agent code takes
+/// them; older ones are dropped first.
+const MAX_PENDING_UPDATES: usize = 1000;
+
+/// Client-side handler for one upstream connection.
+/// Forwards the server's log notifications to tracing and queues the URIs of
+/// updated resources.
+#[derive(Debug, Clone)]
+pub struct UpstreamHandler {
+    server: String,
+    updated: Arc<std::sync::Mutex<std::collections::VecDeque<String>>>,
+}
+
+impl UpstreamHandler {
+    pub fn new(server: &str) -> Self {
+        Self {
+            server: server.to_string(),
+            updated: Default::default(),
+        }
+    }
+
+    /// URIs of resources updated since the last call, oldest first.
+    fn take_updates(&self) -> Vec<String> {
+        self.updated.lock().unwrap().drain(..).collect()
+    }
+}
+
+impl ClientHandler for UpstreamHandler {
+    async fn on_logging_message(
+        &self,
+        params: LoggingMessageNotificationParam,
+        _context: NotificationContext<RoleClient>,
+    ) {
+        let server = &self.server;
+        let logger = params.logger.as_deref().unwrap_or("");
+        let data = match &params.data {
+            serde_json::Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        match params.level {
+            LoggingLevel::Debug => tracing::debug!(target: "upstream", server = %server, logger, "{data}"),
+            LoggingLevel::Info | LoggingLevel::Notice => {
+                tracing::info!(target: "upstream", server = %server, logger, "{data}")
+            }
+            LoggingLevel::Warning => tracing::warn!(target: "upstream", server = %server, logger, "{data}"),
+            _ => tracing::error!(target: "upstream", server = %server, logger, "{data}"),
+        }
+    }
+
+    async fn on_resource_updated(
+        &self,
+        params: ResourceUpdatedNotificationParam,
+        _context: NotificationContext<RoleClient>,
+    ) {
+        info!(server = %self.server, uri = %params.uri, "resource updated");
+        let mut updated = self.updated.lock().unwrap();
+        if updated.len() == MAX_PENDING_UPDATES {
+            updated.pop_front();
+        }
+        updated.push_back(params.uri);
+    }
+}
+
+/// How long to wait for a server's MCP handshake when `connect_timeout_ms` is unset.
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// How long a health-check ping may take before the server counts as failed.
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A connected upstream MCP service.
+pub type UpstreamService = RunningService<RoleClient, UpstreamHandler>;
+
+/// A handle to one upstream MCP server with its config for (re)connection.
 struct UpstreamServer {
-    service: RunningService<RoleClient, ()>,
+    /// `None` until a lazily added server is first used.
+    service: Option<UpstreamService>,
     config: ServerConfig,
+    /// Bumped on every (re)connect attempt, so calls that failed on the same
+    /// connection reconnect it once between them.
+    generation: u64,
+}
+
+/// An upstream server plus the `max_concurrent` limit on calls to it.
+struct UpstreamSlot {
+    server: Mutex<UpstreamServer>,
+    limit: Option<Semaphore>,
+    call_timeout: Option<Duration>,
+    max_length: Option<usize>,
+    breaker: std::sync::Mutex<Breaker>,
+    /// Values resolved from the config's secret references, masked in errors.
+    secrets: Arc<Secrets>,
+}
+
+impl UpstreamSlot {
+    fn new(service: Option<UpstreamService>, config: ServerConfig, secrets: Arc<Secrets>) -> Self {
+        let limit = config.options().max_concurrent.map(|n| Semaphore::new(n.max(1)));
+        let call_timeout = config.options().call_timeout_ms.map(Duration::from_millis);
+        let max_length = config.options().max_length;
+        Self {
+            server: Mutex::new(UpstreamServer { service, config, generation: 0 }),
+            limit,
+            call_timeout,
+            max_length,
+            breaker: Default::default(),
+            secrets,
+        }
+    }
+}
+
+/// Recent transport failures of one server, and whether calls to it are
+/// refused until a cooldown ends.
+#[derive(Debug, Default)]
+struct Breaker {
+    failures: u32,
+    first_failure: Option<Instant>,
+    /// Set while open. Once it has passed the breaker is half-open: calls go
+    /// through, and the first failure opens it again.
+    open_until: Option<Instant>,
+}
+
+impl Breaker {
+    /// Time left until calls are let through again, if the breaker is open.
+    fn retry_in(&self, now: Instant) -> Option<Duration> {
+        self.open_until
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+    }
+
+    /// Count a transport failure; returns true if this opened the breaker.
+    fn record_failure(&mut self, policy: &BreakerPolicy, now: Instant) -> bool {
+        if policy.failures == 0 {
+            return false;
+        }
+        let half_open = self.open_until.is_some();
+        // `BreakerPolicy::validate` rejects bad values; never panic on them here.
+        let window = Duration::try_from_secs_f64(policy.window_secs).unwrap_or_default();
+        if self.first_failure.is_none_or(|first| now - first > window) {
+            self.failures = 0;
+            self.first_failure = Some(now);
+        }
+        self.failures += 1;
+        if half_open || self.failures >= policy.failures {
+            let cooldown = Duration::try_from_secs_f64(policy.cooldown_secs).unwrap_or_default();
+            self.open_until = now.checked_add(cooldown);
+            self.failures = 0;
+            self.first_failure = None;
+            return true;
+        }
+        false
+    }
+
+    fn record_success(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Call history for one tool, used to rank recently used tools higher.
+#[derive(Debug, Clone, Copy)]
+pub struct ToolUsage {
+    pub calls: u64,
+    pub last_used: Instant,
+}
+
+impl ToolUsage {
+    /// 1.0 right after a call, halving every five minutes.
+    pub fn recency(&self) -> f64 {
+        let minutes = self.last_used.elapsed().as_secs_f64() / 60.0;
+        0.5_f64.powf(minutes / 5.0)
+    }
+}
+
+/// Whether a server answered a health-check ping.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HealthStatus {
+    Connected,
+    Failed(String),
+    /// A lazily added server that hasn't been used yet.
+    NotConnected,
+    /// Calls fail fast after repeated transport failures; not pinged.
+    CircuitOpen { retry_in: Duration },
 }
 
+/// Result of pinging one server in [`ClientPool::health_check`].
+#[derive(Debug, Clone)]
+pub struct ServerHealth {
+    pub status: HealthStatus,
+    /// Round-trip time of the ping (or time until it failed).
+    pub latency: Duration,
+}
+
+/// A cached tool result and when it goes stale.
+struct CachedResult {
+    result: CallToolResult,
+    expires: Instant,
+}
+
+/// Tool calls in flight across all servers unless configured otherwise.
+pub const DEFAULT_MAX_CONCURRENT_CALLS: usize = 16;
+
+/// `User-Agent` sent to http, sse and ws servers that don't configure one.
+pub const DEFAULT_USER_AGENT: &str = concat!("cmcp/", env!("CARGO_PKG_VERSION"));
+
 /// Manages connections to all upstream MCP servers.
 pub struct ClientPool {
-    servers: HashMap<String, Mutex<UpstreamServer>>,
+    /// Locked only to look up, insert or list slots, never across an await.
+    servers: std::sync::RwLock<HashMap<String, Arc<UpstreamSlot>>>,
+    /// Bounds tool calls in flight across all servers; per-server
+    /// `max_concurrent` limits apply on top.
+    call_limit: Semaphore,
+    retry: RetryPolicy,
+    breaker: BreakerPolicy,
+    cache: CachePolicy,
+    /// Cached results keyed on server, tool and arguments. Dropped with the pool on reload.
+    results: std::sync::Mutex<HashMap<String, CachedResult>>,
+    /// Successful calls per "server.tool".
+    usage: std::sync::Mutex<HashMap<String, ToolUsage>>,
+    /// Tool list of every server connected so far, for building a later catalog.
+    known_tools: std::sync::Mutex<HashMap<String, Vec<Tool>>>,
+    /// Catalogs of lazy servers connected on first use, not yet taken by
+    /// [`ClientPool::take_lazily_connected`].
+    lazily_connected: std::sync::Mutex<Vec<Catalog>>,
+    /// Call counters, shared with the pools that replace this one on reload.
+    metrics: Arc<Metrics>,
+    /// Values from the `redact` config, masked along with each server's secrets.
+    redactions: Vec<String>,
+    /// Every sandbox's [`UpdateQueue`], dropped ones included until the next is added.
+    update_queues: std::sync::Mutex<Vec<std::sync::Weak<UpdateQueue>>>,
+}
+
+/// Resource updates waiting for one sandbox; see [`ClientPool::resource_update_queue`].
+#[derive(Debug)]
+pub struct UpdateQueue {
+    view: Option<ViewConfig>,
+    updates: std::sync::Mutex<std::collections::VecDeque<(String, String)>>,
+}
+
+impl UpdateQueue {
+    fn sees(&self, server: &str) -> bool {
+        self.view.as_ref().is_none_or(|view| view.touches_server(server))
+    }
+}
+
+/// A server still connecting when [`ClientPool::connect_within`] returned.
+pub struct PendingServer {
+    pub name: String,
+    pub(crate) config: ServerConfig,
+    pub(crate) connecting: tokio::task::JoinHandle<Result<(UpstreamService, Vec<Tool>)>>,
+    /// Filled in by `connecting` with the values its secret references resolved to.
+    pub(crate) secrets: Arc<Secrets>,
 }
 
 impl ClientPool {
-    /// Connect to all configured servers and build the tool catalog.
+    /// Connect to all configured servers concurrently and build the tool catalog.
+    ///
+    /// With `lazy`, nothing is connected up front: each server is connected on
+    /// first use, so a broken one only fails the calls made to it. Its catalog
+    /// entry has no tools until they are filled in from an earlier pool's
+    /// [`ClientPool::known_tools`]. Disabled servers are left out entirely.
     pub async fn connect(
         configs: HashMap<String, ServerConfig>,
+        lazy: bool,
     ) -> Result<(Self, Catalog)> {
+        let (pool, catalog, _) = Self::connect_within(configs, lazy, None).await?;
+        Ok((pool, catalog))
+    }
+
+    /// Like [`ClientPool::connect`], but waits at most `wait` for the servers.
+    /// Those still connecting then are returned as pending, to be added with
+    /// [`ClientPool::finish_pending`] once the pool is in use.
+    pub async fn connect_within(
+        configs: HashMap<String, ServerConfig>,
+        lazy: bool,
+        wait: Option<Duration>,
+    ) -> Result<(Self, Catalog, Vec<PendingServer>)> {
         let mut servers = HashMap::new();
         let mut catalog = Catalog::new();
+        let mut known_tools = HashMap::new();
+        let configs = configs.into_iter().filter(|(name, config)| {
+            if !config.is_enabled() {
+                info!(server = %name, "disabled, skipping");
+            }
+            config.is_enabled()
+        });
 
-        for (name, config) in configs {
-            match Self::connect_one(&name, &config).await {
+        if lazy {
+            for (name, config) in configs {
+                catalog.add_server_tools(&name, Vec::new());
+                servers.insert(name, UpstreamSlot::new(None, config, Default::default()));
+            }
+            return Ok((Self::from_slots(servers, known_tools), catalog, Vec::new()));
+        }
+
+        let connecting: Vec<PendingServer> = configs
+            .map(|(name, config)| {
+                let (task_name, task_config) = (name.clone(), config.clone());
+                let secrets = Arc::new(Secrets::default());
+                let task_secrets = secrets.clone();
+                let connecting =
+                    tokio::spawn(async move { Self::connect_one(&task_name, &task_config, &task_secrets).await });
+                PendingServer { name, config, connecting, secrets }
+            })
+            .collect();
+        let deadline = wait.map(|wait| tokio::time::Instant::now() + wait);
+        let mut pending = Vec::new();
+        for mut server in connecting {
+            let joined = match deadline {
+                Some(deadline) => match tokio::time::timeout_at(deadline, &mut server.connecting).await {
+                    Ok(joined) => joined,
+                    Err(_) => {
+                        info!(server = %server.name, "still connecting, its tools will be added when it is ready");
+                        pending.push(server);
+                        continue;
+                    }
+                },
+                None => (&mut server.connecting).await,
+            };
+            let PendingServer { name, config, secrets, .. } = server;
+            match joined.map_err(anyhow::Error::from).and_then(|connected| connected) {
                 Ok((service, tools)) => {
-                    info!(server = %name, tool_count = tools.len(), "connected");
-                    catalog.add_server_tools(&name, tools);
-                    servers.insert(
-                        name,
-                        Mutex::new(UpstreamServer { service, config }),
-                    );
+                    known_tools.insert(name.clone(), tools.clone());
+                    catalog_server(&mut catalog, &name, &service, tools).await;
+                    servers.insert(name, UpstreamSlot::new(Some(service), config, secrets));
                 }
                 Err(e) => {
-                    tracing::warn!(server = %name, error = %e, "failed to connect, skipping");
+                    tracing::warn!(server = %name, error = %redact(&e.to_string()), "failed to connect, skipping");
+                }
+            }
+        }
+
+        Ok((Self::from_slots(servers, known_tools), catalog, pending))
+    }
+
+    /// Wait for a server [`ClientPool::connect_within`] left pending and add it,
+    /// returning a catalog of just that server.
+    pub async fn finish_pending(&self, server: PendingServer) -> Result<Catalog> {
+        let (service, tools) = server
+            .connecting
+            .await?
+            .with_context(|| format!("could not connect to {}", server.name))?;
+        Ok(self.insert_service(&server.name, service, tools, server.config, server.secrets).await)
+    }
+
+    /// Like [`ClientPool::connect`], but servers whose config is unchanged
+    /// from `previous` keep their connection, breaker state and known tools
+    /// instead of being reconnected. The catalog only covers the servers
+    /// connected here; the names of the kept ones are returned with it.
+    pub async fn reconnect(
+        previous: &ClientPool,
+        configs: HashMap<String, ServerConfig>,
+        lazy: bool,
+    ) -> Result<(Self, Catalog, Vec<String>)> {
+        let mut kept = HashMap::new();
+        let mut changed = HashMap::new();
+        for (name, config) in configs {
+            match previous.slot(&name) {
+                Some(slot) if slot.server.lock().await.config.same_as(&config) => {
+                    kept.insert(name, slot);
                 }
+                _ => {
+                    changed.insert(name, config);
+                }
+            }
+        }
+
+        let (pool, catalog) = Self::connect(changed, lazy).await?;
+        let previous_tools = previous.known_tools();
+        let mut kept_names = Vec::with_capacity(kept.len());
+        for name in kept.keys() {
+            info!(server = %name, "config unchanged, keeping connection");
+            if let Some(tools) = previous_tools.get(name) {
+                pool.remember_tools(name, tools.clone());
             }
+            kept_names.push(name.clone());
+        }
+        pool.servers.write().unwrap().extend(kept);
+        Ok((pool, catalog, kept_names))
+    }
+
+    fn from_slots(
+        servers: HashMap<String, UpstreamSlot>,
+        known_tools: HashMap<String, Vec<Tool>>,
+    ) -> Self {
+        Self {
+            servers: std::sync::RwLock::new(servers.into_iter().map(|(name, slot)| (name, Arc::new(slot))).collect()),
+            call_limit: Semaphore::new(DEFAULT_MAX_CONCURRENT_CALLS),
+            retry: RetryPolicy::default(),
+            breaker: BreakerPolicy::default(),
+            cache: CachePolicy::default(),
+            results: Default::default(),
+            usage: Default::default(),
+            known_tools: std::sync::Mutex::new(known_tools),
+            lazily_connected: Default::default(),
+            metrics: Default::default(),
+            redactions: Vec::new(),
+            update_queues: Default::default(),
+        }
+    }
+
+    /// Build a pool around already-connected services, e.g. in-process test upstreams.
+    #[cfg(test)]
+    pub(crate) fn from_services(services: Vec<(String, UpstreamService, ServerConfig)>) -> Self {
+        let servers = services
+            .into_iter()
+            .map(|(name, service, config)| (name, UpstreamSlot::new(Some(service), config, Default::default())))
+            .collect();
+        Self::from_slots(servers, HashMap::new())
+    }
+
+    /// Add a server to a pool that is already in use, connecting it unless
+    /// `lazy`. Returns a catalog of just that server, to merge into the
+    /// pool's catalog.
+    pub async fn add_server(&self, name: &str, config: ServerConfig, lazy: bool) -> Result<Catalog> {
+        if self.slot(name).is_some() {
+            anyhow::bail!("server \"{name}\" is already connected");
+        }
+        if !config.is_enabled() {
+            anyhow::bail!("server \"{name}\" is disabled");
+        }
+        if lazy {
+            let mut catalog = Catalog::new();
+            catalog.add_server_tools(name, Vec::new());
+            self.insert_slot(name, UpstreamSlot::new(None, config, Default::default()));
+            return Ok(catalog);
+        }
+        let secrets = Arc::new(Secrets::default());
+        let (service, tools) = Self::connect_one(name, &config, &secrets)
+            .await
+            .with_context(|| format!("could not connect to {name}"))?;
+        Ok(self.insert_service(name, service, tools, config, secrets).await)
+    }
+
+    /// Add an already-connected server, returning a catalog of just that server.
+    pub(crate) async fn insert_service(
+        &self,
+        name: &str,
+        service: UpstreamService,
+        tools: Vec<Tool>,
+        config: ServerConfig,
+        secrets: Arc<Secrets>,
+    ) -> Catalog {
+        let mut catalog = Catalog::new();
+        self.known_tools.lock().unwrap().insert(name.to_string(), tools.clone());
+        catalog_server(&mut catalog, name, &service, tools).await;
+        self.insert_slot(name, UpstreamSlot::new(Some(service), config, secrets));
+        catalog
+    }
+
+    fn insert_slot(&self, name: &str, slot: UpstreamSlot) {
+        self.servers.write().unwrap().insert(name.to_string(), Arc::new(slot));
+    }
+
+    fn slot(&self, server_name: &str) -> Option<Arc<UpstreamSlot>> {
+        self.servers.read().unwrap().get(server_name).cloned()
+    }
+
+    /// Use `retry` for transport failures instead of the default policy.
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Fail calls to a server fast while it keeps failing, per `breaker`.
+    pub fn with_breaker(mut self, breaker: BreakerPolicy) -> Self {
+        self.breaker = breaker;
+        self
+    }
+
+    /// Allow at most `limit` tool calls in flight across all servers.
+    pub fn with_max_concurrent_calls(mut self, limit: usize) -> Self {
+        self.call_limit = Semaphore::new(limit.max(1));
+        self
+    }
+
+    /// Reuse results of the tools listed in `cache` until their TTL runs out.
+    pub fn with_cache(mut self, cache: CachePolicy) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Count calls in `metrics` instead of counters of the pool's own.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Also mask `values` in errors (the `redact` config). Values too short to
+    /// mask safely are skipped with a warning.
+    pub fn with_redactions(mut self, values: &[String]) -> Self {
+        let (kept, short): (Vec<_>, Vec<_>) =
+            values.iter().cloned().partition(|value| value.len() >= crate::secret::MIN_SECRET_LEN);
+        if !short.is_empty() {
+            tracing::warn!(
+                count = short.len(),
+                "ignoring redact entries shorter than {} characters",
+                crate::secret::MIN_SECRET_LEN
+            );
         }
+        self.redactions = kept;
+        self
+    }
 
-        Ok((Self { servers }, catalog))
+    /// Mask the `redact` config values and every server's resolved secrets in
+    /// `text`, besides what [`redact`] masks.
+    pub fn redact(&self, text: &str) -> String {
+        let mut secrets = self.redactions.clone();
+        for slot in self.servers.read().unwrap().values() {
+            secrets.extend(slot.secrets.values());
+        }
+        crate::secret::redact_with(text, secrets)
     }
 
-    /// Build the transport config for HTTP/SSE servers.
-    fn build_http_config(
+    /// Build the transport config for HTTP/SSE servers. With `auth = "oauth"`,
+    /// the stored token is refreshed here if it has expired.
+    async fn build_http_config(
+        name: &str,
         url: &str,
         auth: &Option<String>,
         headers: &HashMap<String, String>,
-    ) -> StreamableHttpClientTransportConfig {
-        let mut config = StreamableHttpClientTransportConfig::with_uri(url);
+        options: &ServerOptions,
+        secrets: &Secrets,
+    ) -> Result<StreamableHttpClientTransportConfig> {
+        let strict = options.strict_env == Some(true);
+        tracing::debug!(
+            server = %name,
+            url = %url,
+            headers = ?redacted_request_headers(auth, headers),
+            "opening HTTP connection"
+        );
+
+        let url = resolve_secret_async(url, strict, secrets).await?;
+        let mut config = StreamableHttpClientTransportConfig::with_uri(url.as_str());
 
         // Auth header (bearer token)
         if let Some(token) = auth {
-            let resolved = resolve_env(token);
+            let resolved = if config::oauth_scopes(token).is_some() {
+                let token = crate::oauth::access_token(name, &url).await?;
+                secrets.register(&token);
+                token
+            } else {
+                resolve_secret_async(token, strict, secrets).await?
+            };
             config = config.auth_header(resolved);
         }
 
-        // Custom headers
-        if !headers.is_empty() {
-            let mut header_map = HashMap::new();
-            for (k, v) in headers {
-                let resolved_v = resolve_env(v);
-                if let (Ok(name), Ok(value)) = (
-                    http::HeaderName::try_from(k.as_str()),
-                    http::HeaderValue::try_from(resolved_v.as_str()),
-                ) {
-                    header_map.insert(name, value);
-                }
+        // Custom headers, on top of the User-Agent
+        let mut header_map = HashMap::new();
+        let user_agent = options.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT);
+        let user_agent = http::HeaderValue::try_from(user_agent)
+            .with_context(|| format!("invalid user_agent \"{user_agent}\""))?;
+        header_map.insert(http::header::USER_AGENT, user_agent);
+        for (k, v) in headers {
+            let resolved_v = resolve_secret_async(v, strict, secrets).await?;
+            if let (Ok(name), Ok(value)) = (
+                http::HeaderName::try_from(k.as_str()),
+                http::HeaderValue::try_from(resolved_v.as_str()),
+            ) {
+                header_map.insert(name, value);
             }
-            config = config.custom_headers(header_map);
         }
+        config = config.custom_headers(header_map);
+
+        Ok(config)
+    }
 
-        config
+    /// Connect to one server, count its tools and disconnect again.
+    pub async fn check(name: &str, config: &ServerConfig) -> Result<usize> {
+        let (service, tools) = Self::connect_one(name, config, &Secrets::default())
+            .await
+            .with_context(|| format!("could not connect to {name}"))?;
+        let _ = service.cancel().await;
+        Ok(tools.len())
     }
 
+    /// Connect to one server and list its tools, registering the values of its
+    /// secret references in `secrets`. They are masked in the error, if any.
     async fn connect_one(
         name: &str,
         config: &ServerConfig,
-    ) -> Result<(RunningService<RoleClient, ()>, Vec<rmcp::model::Tool>)> {
+        secrets: &Secrets,
+    ) -> Result<(UpstreamService, Vec<rmcp::model::Tool>)> {
+        Self::connect_unmasked(name, config, secrets)
+            .await
+            .map_err(|e| anyhow::anyhow!(secrets.redact(&format!("{e:#}"))))
+    }
+
+    async fn connect_unmasked(
+        name: &str,
+        config: &ServerConfig,
+        secrets: &Secrets,
+    ) -> Result<(UpstreamService, Vec<rmcp::model::Tool>)> {
+        let handler = UpstreamHandler::new(name);
+        let timeout = config
+            .options()
+            .connect_timeout_ms
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_CONNECT_TIMEOUT);
+        let strict = config.options().strict_env == Some(true);
+        let handshake_timeout =
+            || anyhow::anyhow!("{name} did not complete the MCP handshake within {timeout:?}");
         let service = match config {
-            ServerConfig::Http { url, auth, headers } => {
-                let transport_config = Self::build_http_config(url, auth, headers);
-                let transport =
-                    rmcp::transport::StreamableHttpClientTransport::from_config(transport_config);
-                ().serve(transport)
+            ServerConfig::Http { url, auth, headers, options } => {
+                let transport_config =
+                    Self::build_http_config(name, url, auth, headers, options, secrets).await?;
+                let transport = crate::streamable_http::transport(transport_config);
+                tokio::time::timeout(timeout, handler.serve(transport))
                     .await
+                    .map_err(|_| handshake_timeout())?
                     .with_context(|| format!("HTTP connection to {name} failed"))?
             }
-            ServerConfig::Sse { url, auth, headers } => {
+            ServerConfig::Sse { url, auth, headers, options } => {
                 // SSE uses the same streamable HTTP transport — the protocol auto-negotiates.
-                let transport_config = Self::build_http_config(url, auth, headers);
-                let transport =
-                    rmcp::transport::StreamableHttpClientTransport::from_config(transport_config);
-                ().serve(transport)
+                let transport_config =
+                    Self::build_http_config(name, url, auth, headers, options, secrets).await?;
+                let transport = crate::streamable_http::transport(transport_config);
+                tokio::time::timeout(timeout, handler.serve(transport))
                     .await
+                    .map_err(|_| handshake_timeout())?
                     .with_context(|| format!("SSE connection to {name} failed"))?
             }
+            ServerConfig::Ws { url, auth, headers, options } => {
+                if auth.as_deref().and_then(config::oauth_scopes).is_some() {
+                    anyhow::bail!("{name}: OAuth is only supported for http and sse servers");
+                }
+                tracing::debug!(
+                    server = %name,
+                    url = %url,
+                    headers = ?redacted_request_headers(auth, headers),
+                    "opening WebSocket connection"
+                );
+                let url = resolve_secret_async(url, strict, secrets).await?;
+                let auth = match auth {
+                    Some(auth) => Some(resolve_secret_async(auth, strict, secrets).await?),
+                    None => None,
+                };
+                let mut resolved = HashMap::new();
+                for (k, v) in headers {
+                    resolved.insert(k.clone(), resolve_secret_async(v, strict, secrets).await?);
+                }
+                let mut headers = resolved;
+                if !headers.keys().any(|k| k.eq_ignore_ascii_case("user-agent")) {
+                    let user_agent = options.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT);
+                    headers.insert("User-Agent".to_string(), user_agent.to_string());
+                }
+                let handshake = async {
+                    let transport = crate::ws::connect(&url, auth, headers).await?;
+                    handler.serve(transport).await.map_err(anyhow::Error::from)
+                };
+                tokio::time::timeout(timeout, handshake)
+                    .await
+                    .map_err(|_| handshake_timeout())?
+                    .with_context(|| format!("WebSocket connection to {name} failed"))?
+            }
             ServerConfig::Stdio {
                 command,
                 args,
                 env,
+                ..
             } => {
+                let mut resolved_args = Vec::with_capacity(args.len());
+                for arg in args {
+                    resolved_args.push(resolve_secret_async(arg, strict, secrets).await?);
+                }
+                let mut resolved_env = Vec::with_capacity(env.len());
+                for (k, v) in env {
+                    resolved_env.push((k, resolve_secret_async(v, strict, secrets).await?));
+                }
+                let (args, env) = (resolved_args, resolved_env);
                 let transport = rmcp::transport::TokioChildProcess::new(
                     Command::new(command).configure(|cmd| {
-                        cmd.args(args);
-                        for (k, v) in env {
-                            cmd.env(k, resolve_env(v));
-                        }
+                        cmd.args(args).envs(env);
                     }),
-                )?;
-                ().serve(transport)
+                )
+                .with_context(|| format!("failed to start `{command}`"))?;
+                tokio::time::timeout(timeout, handler.serve(transport))
                     .await
+                    .map_err(|_| handshake_timeout())?
                     .with_context(|| format!("stdio connection to {name} failed"))?
             }
         };
 
+        apply_log_level(&service, name, config.options()).await;
+
         let tools_result = service.list_tools(Default::default()).await?;
         Ok((service, tools_result.tools))
     }
 
+    /// Ping every server concurrently. Never connects, reconnects or replaces a connection.
+    pub async fn health_check(&self) -> HashMap<String, ServerHealth> {
+        let slots: Vec<_> = self
+            .servers
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, slot)| (name.clone(), slot.clone()))
+            .collect();
+        let checks = slots.into_iter().map(|(name, slot)| async move {
+            if let Some(retry_in) = slot.breaker.lock().unwrap().retry_in(Instant::now()) {
+                let health = ServerHealth {
+                    status: HealthStatus::CircuitOpen { retry_in },
+                    latency: Duration::ZERO,
+                };
+                return (name, health);
+            }
+            let peer = slot.server.lock().await.service.as_ref().map(|s| s.peer().clone());
+            let Some(peer) = peer else {
+                let health = ServerHealth {
+                    status: HealthStatus::NotConnected,
+                    latency: Duration::ZERO,
+                };
+                return (name, health);
+            };
+            let start = Instant::now();
+            let ping = peer.send_request(ClientRequest::PingRequest(Default::default()));
+            let status = match tokio::time::timeout(HEALTH_CHECK_TIMEOUT, ping).await {
+                Ok(Ok(_)) => HealthStatus::Connected,
+                Ok(Err(e)) => HealthStatus::Failed(e.to_string()),
+                Err(_) => HealthStatus::Failed(format!("no response within {HEALTH_CHECK_TIMEOUT:?}")),
+            };
+            let health = ServerHealth {
+                status,
+                latency: start.elapsed(),
+            };
+            (name, health)
+        });
+        futures::future::join_all(checks).await.into_iter().collect()
+    }
+
+    /// Each server's name and whether it has a live connection, without
+    /// pinging it. A server whose circuit breaker is open counts as disconnected.
+    pub async fn connection_states(&self) -> Vec<(String, bool)> {
+        let slots: Vec<_> = self
+            .servers
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, slot)| (name.clone(), slot.clone()))
+            .collect();
+        let mut states = Vec::with_capacity(slots.len());
+        for (name, slot) in slots {
+            let open = slot.breaker.lock().unwrap().retry_in(Instant::now()).is_some();
+            let connected = !open && slot.server.lock().await.service.is_some();
+            states.push((name, connected));
+        }
+        states.sort();
+        states
+    }
+
+    /// List the resources a server publishes; empty if it doesn't support resources.
+    pub async fn list_resources(&self, server_name: &str) -> Result<Vec<Resource>> {
+        let slot = self
+            .slot(server_name)
+            .with_context(|| format!("no server named '{server_name}'"))?;
+        let peer = self.connected_peer(server_name, &slot).await?;
+        list_server_resources(&peer).await
+    }
+
+    /// Read one resource from a specific upstream server.
+    pub async fn read_resource(&self, server_name: &str, uri: &str) -> Result<ReadResourceResult> {
+        let slot = self
+            .slot(server_name)
+            .with_context(|| format!("no server named '{server_name}'"))?;
+        let peer = self.connected_peer(server_name, &slot).await?;
+        let params = ReadResourceRequestParams {
+            meta: None,
+            uri: uri.to_string(),
+        };
+        peer.read_resource(params)
+            .await
+            .with_context(|| format!("reading {uri} from {server_name} failed"))
+    }
+
+    /// List the resource templates a server publishes; empty if it doesn't support resources.
+    pub async fn list_resource_templates(&self, server_name: &str) -> Result<Vec<ResourceTemplate>> {
+        let slot = self
+            .slot(server_name)
+            .with_context(|| format!("no server named '{server_name}'"))?;
+        let peer = self.connected_peer(server_name, &slot).await?;
+        list_server_resource_templates(&peer).await
+    }
+
+    /// Ask a server to notify us when the resource at `uri` changes; see
+    /// [`ClientPool::take_resource_updates`]. Subscriptions don't survive a reconnect.
+    pub async fn subscribe_resource(&self, server_name: &str, uri: &str) -> Result<()> {
+        let slot = self
+            .slot(server_name)
+            .with_context(|| format!("no server named '{server_name}'"))?;
+        let peer = self.connected_peer(server_name, &slot).await?;
+        let params = SubscribeRequestParams {
+            meta: None,
+            uri: uri.to_string(),
+        };
+        peer.subscribe(params)
+            .await
+            .with_context(|| format!("subscribing to {uri} on {server_name} failed"))
+    }
+
+    /// Stop update notifications for `uri`.
+    pub async fn unsubscribe_resource(&self, server_name: &str, uri: &str) -> Result<()> {
+        let slot = self
+            .slot(server_name)
+            .with_context(|| format!("no server named '{server_name}'"))?;
+        let peer = self.connected_peer(server_name, &slot).await?;
+        let params = UnsubscribeRequestParams {
+            meta: None,
+            uri: uri.to_string(),
+        };
+        peer.unsubscribe(params)
+            .await
+            .with_context(|| format!("unsubscribing from {uri} on {server_name} failed"))
+    }
+
+    /// A queue for the resource updates of the servers `view` touches (all
+    /// without one), for [`ClientPool::take_resource_updates`]. Each sandbox
+    /// has its own, so one can't take another's updates.
+    pub fn resource_update_queue(&self, view: Option<ViewConfig>) -> Arc<UpdateQueue> {
+        let queue = Arc::new(UpdateQueue {
+            view,
+            updates: Default::default(),
+        });
+        let mut queues = self.update_queues.lock().unwrap();
+        queues.retain(|queue| queue.strong_count() > 0);
+        queues.push(Arc::downgrade(&queue));
+        queue
+    }
+
+    /// (server, uri) of every subscribed resource updated since the last call
+    /// for `queue`. Only the servers `queue` can see are drained; their updates
+    /// also go to the other queues that can see them.
+    pub async fn take_resource_updates(&self, queue: &UpdateQueue) -> Vec<(String, String)> {
+        let slots: Vec<_> = self
+            .servers
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(name, _)| queue.sees(name))
+            .map(|(name, slot)| (name.clone(), slot.clone()))
+            .collect();
+        for (name, slot) in slots {
+            let updates = match &slot.server.lock().await.service {
+                Some(service) => service.service().take_updates(),
+                None => continue,
+            };
+            if updates.is_empty() {
+                continue;
+            }
+            let queues: Vec<_> = self.update_queues.lock().unwrap().iter().filter_map(|q| q.upgrade()).collect();
+            for other in queues.iter().filter(|other| other.sees(&name)) {
+                let mut pending = other.updates.lock().unwrap();
+                for uri in &updates {
+                    if pending.len() == MAX_PENDING_UPDATES {
+                        pending.pop_front();
+                    }
+                    pending.push_back((name.clone(), uri.clone()));
+                }
+            }
+        }
+        let mut updates: Vec<_> = queue.updates.lock().unwrap().drain(..).collect();
+        updates.sort();
+        updates
+    }
+
+    /// List the prompts a server publishes; empty if it doesn't support prompts.
+    pub async fn list_prompts(&self, server_name: &str) -> Result<Vec<Prompt>> {
+        let slot = self
+            .slot(server_name)
+            .with_context(|| format!("no server named '{server_name}'"))?;
+        let peer = self.connected_peer(server_name, &slot).await?;
+        list_server_prompts(&peer).await
+    }
+
+    /// Render one prompt on a specific upstream server with `arguments` (a JSON object).
+    pub async fn get_prompt(
+        &self,
+        server_name: &str,
+        prompt_name: &str,
+        arguments: serde_json::Value,
+    ) -> Result<GetPromptResult> {
+        let slot = self
+            .slot(server_name)
+            .with_context(|| format!("no server named '{server_name}'"))?;
+        let peer = self.connected_peer(server_name, &slot).await?;
+        let params = GetPromptRequestParams {
+            meta: None,
+            name: prompt_name.to_string(),
+            arguments: arguments.as_object().cloned(),
+        };
+        peer.get_prompt(params)
+            .await
+            .with_context(|| format!("prompt {server_name}.{prompt_name} failed"))
+    }
+
+    /// A peer for the server in `slot`, connecting it first if it was added lazily.
+    /// The slot stays locked while connecting, so concurrent first calls share one attempt.
+    async fn connected_peer(&self, server_name: &str, slot: &UpstreamSlot) -> Result<Peer<RoleClient>> {
+        Ok(self.connected_peer_at(server_name, slot).await?.0)
+    }
+
+    /// Like [`ClientPool::connected_peer`], plus the connection's generation.
+    async fn connected_peer_at(&self, server_name: &str, slot: &UpstreamSlot) -> Result<(Peer<RoleClient>, u64)> {
+        let mut upstream = slot.server.lock().await;
+        if let Some(service) = &upstream.service {
+            return Ok((service.peer().clone(), upstream.generation));
+        }
+        let (service, tools) = Self::connect_one(server_name, &upstream.config, &slot.secrets)
+            .await
+            .with_context(|| format!("connecting to {server_name} failed"))?;
+        info!(server = %server_name, tool_count = tools.len(), "connected on first use");
+        self.known_tools.lock().unwrap().insert(server_name.to_string(), tools.clone());
+        let mut catalog = Catalog::new();
+        catalog_server(&mut catalog, server_name, &service, tools).await;
+        self.lazily_connected.lock().unwrap().push(catalog);
+        let peer = service.peer().clone();
+        upstream.service = Some(service);
+        upstream.generation += 1;
+        Ok((peer, upstream.generation))
+    }
+
+    /// The server's `max_length` for its tool results, if it sets one.
+    pub fn max_length(&self, server_name: &str) -> Option<usize> {
+        self.slot(server_name)?.max_length
+    }
+
+    /// Tool list of every server this pool has connected, by server name.
+    pub fn known_tools(&self) -> HashMap<String, Vec<Tool>> {
+        self.known_tools.lock().unwrap().clone()
+    }
+
+    /// Catalogs of the lazy servers connected since the last call, one per server.
+    pub(crate) fn take_lazily_connected(&self) -> Vec<Catalog> {
+        std::mem::take(&mut *self.lazily_connected.lock().unwrap())
+    }
+
+    /// Carry a tool list over from an earlier pool, unless this pool has its own.
+    pub(crate) fn remember_tools(&self, server_name: &str, tools: Vec<Tool>) {
+        self.known_tools
+            .lock()
+            .unwrap()
+            .entry(server_name.to_string())
+            .or_insert(tools);
+    }
+
+    /// Usage histogram for tools called through this pool, keyed by "server.tool".
+    pub fn usage(&self) -> HashMap<String, ToolUsage> {
+        self.usage.lock().unwrap().clone()
+    }
+
+    fn record_usage(&self, server_name: &str, tool_name: &str) {
+        let mut usage = self.usage.lock().unwrap();
+        let entry = usage
+            .entry(format!("{server_name}.{tool_name}"))
+            .or_insert(ToolUsage {
+                calls: 0,
+                last_used: Instant::now(),
+            });
+        entry.calls += 1;
+        entry.last_used = Instant::now();
+    }
+
     /// Call a tool on a specific upstream server.
-    /// If the connection is dead, attempts one reconnect.
+    /// Transport failures are retried per the pool's [`RetryPolicy`], reconnecting
+    /// between attempts; errors returned by the server are not retried, and
+    /// timeouts only for tools annotated `idempotentHint: true`. A call
+    /// that outlasts the server's `call_timeout_ms` fails without a retry.
+    /// While the server's circuit breaker is open, calls fail right away.
+    /// Tools with a TTL in the pool's [`CachePolicy`] are answered from cache while fresh.
+    #[tracing::instrument(name = "call_tool", skip_all, fields(server = %server_name, tool = %tool_name))]
     pub async fn call_tool(
         &self,
         server_name: &str,
         tool_name: &str,
         arguments: serde_json::Value,
     ) -> Result<CallToolResult> {
-        let upstream_mutex = self
-            .servers
+        let ttl = self.cache.ttl(server_name, tool_name);
+        let key = format!("{server_name}.{tool_name}:{arguments}");
+        if ttl.is_some()
+            && let Some(cached) = self.cached_result(&key)
+        {
+            self.record_usage(server_name, tool_name);
+            return Ok(cached);
+        }
+
+        let start = Instant::now();
+        let result = self.call_tool_inner(server_name, tool_name, arguments).await;
+        let failed = result.as_ref().map_or(true, |r| r.is_error == Some(true));
+        let (server_label, tool_label) = self.metric_labels(server_name, tool_name);
+        self.metrics.record_call(server_label, tool_label, start.elapsed(), failed);
+        if let Ok(result) = &result {
+            self.record_usage(server_name, tool_name);
+            if let Some(ttl) = ttl
+                && result.is_error != Some(true)
+            {
+                self.store_result(key, result.clone(), ttl);
+            }
+        }
+        result
+    }
+
+    fn cached_result(&self, key: &str) -> Option<CallToolResult> {
+        let results = self.results.lock().unwrap();
+        let cached = results.get(key)?;
+        (cached.expires > Instant::now()).then(|| cached.result.clone())
+    }
+
+    fn store_result(&self, key: String, result: CallToolResult, ttl: Duration) {
+        let now = Instant::now();
+        let mut results = self.results.lock().unwrap();
+        results.retain(|_, cached| cached.expires > now);
+        results.insert(
+            key,
+            CachedResult {
+                result,
+                expires: now + ttl,
+            },
+        );
+    }
+
+    /// `server_name` and `tool_name` as metric labels, with names this pool
+    /// doesn't know replaced by [`crate::metrics::UNKNOWN`].
+    fn metric_labels<'a>(&self, server_name: &'a str, tool_name: &'a str) -> (&'a str, &'a str) {
+        use crate::metrics::UNKNOWN;
+        if self.slot(server_name).is_none() {
+            return (UNKNOWN, UNKNOWN);
+        }
+        let known_tools = self.known_tools.lock().unwrap();
+        let known = known_tools
+            .get(server_name)
+            .is_some_and(|tools| tools.iter().any(|tool| tool.name == tool_name));
+        (server_name, if known { tool_name } else { UNKNOWN })
+    }
+
+    /// Whether the server annotated `tool_name` with `idempotentHint: true`.
+    fn is_idempotent(&self, server_name: &str, tool_name: &str) -> bool {
+        let known_tools = self.known_tools.lock().unwrap();
+        known_tools
             .get(server_name)
+            .and_then(|tools| tools.iter().find(|tool| tool.name == tool_name))
+            .and_then(|tool| tool.annotations.as_ref()?.idempotent_hint)
+            .unwrap_or(false)
+    }
+
+    /// Count a transport failure against `slot`'s circuit breaker.
+    fn record_failure(&self, server_name: &str, slot: &UpstreamSlot) {
+        if slot.breaker.lock().unwrap().record_failure(&self.breaker, Instant::now()) {
+            tracing::warn!(
+                server = %server_name,
+                cooldown_secs = self.breaker.cooldown_secs,
+                "circuit opened after repeated transport failures"
+            );
+        }
+    }
+
+    async fn call_tool_inner(
+        &self,
+        server_name: &str,
+        tool_name: &str,
+        arguments: serde_json::Value,
+    ) -> Result<CallToolResult> {
+        let slot = self
+            .slot(server_name)
             .with_context(|| format!("no server named '{server_name}'"))?;
+        let slot = slot.as_ref();
+
+        // Take the server's own permit first, so calls queued on one busy
+        // server don't hold global permits other servers could use.
+        let _permit = match &slot.limit {
+            Some(limit) => Some(limit.acquire().await?),
+            None => None,
+        };
+        let _global_permit = self.call_limit.acquire().await?;
 
-        let mut upstream = upstream_mutex.lock().await;
+        let params = CallToolRequestParams {
+            meta: crate::telemetry::trace_meta(),
+            name: tool_name.to_string().into(),
+            arguments: arguments.as_object().cloned(),
+            task: None,
+        };
 
-        let tool_name_owned = tool_name.to_string();
+        let mut attempt = 1;
+        loop {
+            if slot.breaker.lock().unwrap().retry_in(Instant::now()).is_some() {
+                anyhow::bail!("circuit open for {server_name}");
+            }
 
-        // First attempt
-        let result = upstream
-            .service
-            .call_tool(CallToolRequestParams {
-                meta: None,
-                name: tool_name_owned.clone().into(),
-                arguments: arguments.as_object().cloned(),
-                task: None,
-            })
-            .await;
-
-        match result {
-            Ok(r) => return Ok(r),
-            Err(first_err) => {
-                // Try to reconnect once
-                tracing::warn!(
-                    server = %server_name,
-                    error = %first_err,
-                    "tool call failed, attempting reconnect"
-                );
+            // Call through a cloned peer so concurrent calls to one server don't
+            // serialize on the lock; it is only held again to reconnect.
+            let (peer, generation) = match self.connected_peer_at(server_name, slot).await {
+                Ok(connected) => connected,
+                Err(e) => {
+                    self.record_failure(server_name, slot);
+                    return Err(e);
+                }
+            };
 
-                match Self::connect_one(server_name, &upstream.config).await {
-                    Ok((new_service, _tools)) => {
-                        upstream.service = new_service;
-
-                        // Retry the tool call
-                        let retry = upstream
-                            .service
-                            .call_tool(CallToolRequestParams {
-                                meta: None,
-                                name: tool_name_owned.into(),
-                                arguments: arguments.as_object().cloned(),
-                                task: None,
-                            })
-                            .await
-                            .with_context(|| {
-                                format!("tool call {server_name}.{tool_name} failed after reconnect")
-                            })?;
-
-                        Ok(retry)
-                    }
-                    Err(reconnect_err) => {
-                        anyhow::bail!(
-                            "tool call {server_name}.{tool_name} failed: {first_err}; reconnect also failed: {reconnect_err}"
-                        );
+            let call = peer.call_tool(params.clone());
+            let outcome = match slot.call_timeout {
+                Some(limit) => tokio::time::timeout(limit, call).await.map_err(|_| {
+                    anyhow::anyhow!("tool call {server_name}.{tool_name} timed out after {limit:?}")
+                })?,
+                None => call.await,
+            };
+            let err = match outcome {
+                Ok(result) => {
+                    slot.breaker.lock().unwrap().record_success();
+                    return Ok(result);
+                }
+                Err(e) => e,
+            };
+            if !is_transport_error(&err) {
+                slot.breaker.lock().unwrap().record_success();
+                anyhow::bail!("tool call {server_name}.{tool_name} failed: {err}");
+            }
+            self.record_failure(server_name, slot);
+            if !is_retryable(&err, self.is_idempotent(server_name, tool_name)) {
+                anyhow::bail!("tool call {server_name}.{tool_name} failed: {err} (not retried, the tool isn't marked idempotent)");
+            }
+            if attempt >= self.retry.attempts {
+                anyhow::bail!("tool call {server_name}.{tool_name} failed after {attempt} attempts: {err}");
+            }
+
+            let delay = self.retry.delay(attempt);
+            tracing::warn!(
+                server = %server_name,
+                error = %self.redact(&err.to_string()),
+                attempt,
+                delay_ms = delay.as_millis() as u64,
+                "tool call failed, reconnecting"
+            );
+            tokio::time::sleep(delay).await;
+
+            // Calls that failed together reconnect once: whoever gets the lock
+            // first reconnects, the others find the generation moved on.
+            let mut upstream = slot.server.lock().await;
+            if upstream.generation == generation {
+                upstream.generation += 1;
+                match Self::connect_one(server_name, &upstream.config, &slot.secrets).await {
+                    Ok((new_service, tools)) => {
+                        self.known_tools.lock().unwrap().insert(server_name.to_string(), tools);
+                        upstream.service = Some(new_service);
                     }
+                    Err(e) => tracing::warn!(server = %server_name, error = %self.redact(&e.to_string()), "reconnect failed"),
                 }
             }
+            drop(upstream);
+            attempt += 1;
+        }
+    }
+}
+
+/// Whether a call that failed with `err` may be sent again. A timed-out
+/// request may still have run, so only `idempotent` tools are retried then.
+fn is_retryable(err: &ServiceError, idempotent: bool) -> bool {
+    match err {
+        ServiceError::Timeout { .. } => idempotent,
+        err => is_transport_error(err),
+    }
+}
+
+/// Whether a failed call looks like a broken connection worth reconnecting for,
+/// as opposed to an error the server returned for this request.
+fn is_transport_error(err: &ServiceError) -> bool {
+    matches!(
+        err,
+        ServiceError::TransportSend(_) | ServiceError::TransportClosed | ServiceError::Timeout { .. }
+    )
+}
+
+/// All resources a server publishes, or none if it didn't advertise the capability.
+async fn list_server_resources(peer: &Peer<RoleClient>) -> Result<Vec<Resource>> {
+    let supported = peer
+        .peer_info()
+        .is_some_and(|info| info.capabilities.resources.is_some());
+    if !supported {
+        return Ok(Vec::new());
+    }
+    Ok(peer.list_all_resources().await?)
+}
+
+/// All resource templates a server publishes, or none if it didn't advertise resources.
+async fn list_server_resource_templates(peer: &Peer<RoleClient>) -> Result<Vec<ResourceTemplate>> {
+    let supported = peer
+        .peer_info()
+        .is_some_and(|info| info.capabilities.resources.is_some());
+    if !supported {
+        return Ok(Vec::new());
+    }
+    Ok(peer.list_all_resource_templates().await?)
+}
+
+/// All prompts a server publishes, or none if it didn't advertise the capability.
+async fn list_server_prompts(peer: &Peer<RoleClient>) -> Result<Vec<Prompt>> {
+    let supported = peer
+        .peer_info()
+        .is_some_and(|info| info.capabilities.prompts.is_some());
+    if !supported {
+        return Ok(Vec::new());
+    }
+    Ok(peer.list_all_prompts().await?)
+}
+
+/// Add a freshly connected server's tools, resources, prompts and version to `catalog`.
+async fn catalog_server(catalog: &mut Catalog, name: &str, service: &UpstreamService, tools: Vec<Tool>) {
+    let version = server_version(service);
+    info!(
+        server = %name,
+        tool_count = tools.len(),
+        upstream = %version.as_ref().map(ToString::to_string).unwrap_or_default(),
+        "connected"
+    );
+    catalog.add_server_tools(name, tools);
+    match list_server_resources(service).await {
+        Ok(resources) => catalog.add_server_resources(name, resources),
+        Err(e) => {
+            tracing::warn!(server = %name, error = %e, "failed to list resources");
+        }
+    }
+    match list_server_resource_templates(service).await {
+        Ok(templates) => catalog.add_server_resource_templates(name, templates),
+        // Some servers publish resources but not the templates method.
+        Err(e) => {
+            tracing::debug!(server = %name, error = %e, "failed to list resource templates");
+        }
+    }
+    match list_server_prompts(service).await {
+        Ok(prompts) => catalog.add_server_prompts(name, prompts),
+        Err(e) => {
+            tracing::warn!(server = %name, error = %e, "failed to list prompts");
+        }
+    }
+    if let Some(version) = version {
+        catalog.set_server_version(name, version);
+    }
+}
+
+/// The implementation name and version a server reported at initialize.
+pub fn server_version(service: &UpstreamService) -> Option<ServerVersion> {
+    service.peer_info().map(|info| ServerVersion {
+        name: info.server_info.name.clone(),
+        version: info.server_info.version.clone(),
+        description: info.server_info.description.clone(),
+    })
+}
+
+/// Send the configured `logging/setLevel` request, if the server supports logging.
+/// Failures are logged and otherwise ignored — logging is best-effort.
+pub async fn apply_log_level(service: &UpstreamService, name: &str, options: &ServerOptions) {
+    let Some(level_str) = &options.log_level else {
+        return;
+    };
+
+    let supports_logging = service
+        .peer_info()
+        .is_some_and(|info| info.capabilities.logging.is_some());
+    if !supports_logging {
+        tracing::debug!(server = %name, "server does not advertise logging, skipping setLevel");
+        return;
+    }
+
+    let level: LoggingLevel = match serde_json::from_value(serde_json::Value::String(level_str.clone())) {
+        Ok(level) => level,
+        Err(_) => {
+            tracing::warn!(server = %name, level = %level_str, "unknown log_level, skipping setLevel");
+            return;
         }
+    };
+
+    if let Err(e) = service
+        .set_level(SetLevelRequestParams { meta: None, level })
+        .await
+    {
+        tracing::warn!(server = %name, error = %e, "logging/setLevel failed");
     }
+}
+
+/// Header name fragments whose values are always redacted in logs.
+const REDACTED_HEADER_PATTERNS: &[&str] = &["auth", "token", "secret", "key", "cookie", "session"];
 
+/// Build the request headers as they should appear in logs.
+///
+/// The bearer token and any header whose name matches a redaction pattern
+/// are replaced with `***`. Every request-logging path goes through here.
+fn redacted_request_headers(
+    auth: &Option<String>,
+    headers: &HashMap<String, String>,
+) -> Vec<(String, String)> {
+    let mut out: Vec<(String, String)> = headers
+        .iter()
+        .map(|(k, v)| {
+            let lower = k.to_ascii_lowercase();
+            let value = if REDACTED_HEADER_PATTERNS.iter().any(|p| lower.contains(p)) {
+                "***".to_string()
+            } else {
+                v.clone()
+            };
+            (k.clone(), value)
+        })
+        .collect();
+    if auth.is_some() {
+        out.push(("Authorization".to_string(), "Bearer ***".to_string()));
+    }
+    out.sort();
+    out
 }
 
-/// Resolve "env:VAR_NAME" references to environment variable values.
-fn resolve_env(value: &str) -> String {
-    if let Some(var) = value.strip_prefix("env:") {
-        std::env::var(var).unwrap_or_default()
-    } else {
-        value.to_string()
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{header_checking_server, in_process_upstream, stand_in_config, CountingServer};
+
+    use std::sync::Arc;
+
+    use rmcp::model::{Implementation, ServerCapabilities, ServerInfo};
+    use rmcp::service::RequestContext;
+    use rmcp::{ErrorData as McpError, RoleServer, ServerHandler};
+
+    /// Mock upstream that advertises logging and records the requested level.
+    #[derive(Clone, Default)]
+    struct MockLoggingServer {
+        level: Arc<std::sync::Mutex<Option<LoggingLevel>>>,
+    }
+
+    impl ServerHandler for MockLoggingServer {
+        async fn set_level(
+            &self,
+            request: SetLevelRequestParams,
+            _context: RequestContext<RoleServer>,
+        ) -> Result<(), McpError> {
+            *self.level.lock().unwrap() = Some(request.level);
+            Ok(())
+        }
+
+        fn get_info(&self) -> ServerInfo {
+            ServerInfo {
+                capabilities: ServerCapabilities::builder().enable_logging().build(),
+                server_info: Implementation {
+                    name: "mock-upstream".to_string(),
+                    version: "1.2.3".to_string(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_log_level_sent_after_connect() {
+        let mock = MockLoggingServer::default();
+        let (service, _) = in_process_upstream("mock", mock.clone()).await;
+        let options = ServerOptions {
+            log_level: Some("debug".to_string()),
+            ..Default::default()
+        };
+        apply_log_level(&service, "mock", &options).await;
+
+        assert_eq!(*mock.level.lock().unwrap(), Some(LoggingLevel::Debug));
+    }
+
+    #[tokio::test]
+    async fn test_server_version_captured() {
+        let (service, _) = in_process_upstream("mock", MockLoggingServer::default()).await;
+        let version = server_version(&service).unwrap();
+        assert_eq!(version.to_string(), "mock-upstream 1.2.3");
+
+        let mut catalog = Catalog::new();
+        catalog.add_server_tools("mock", Vec::new());
+        catalog.set_server_version("mock", version);
+        assert_eq!(catalog.server_version("mock").unwrap().version, "1.2.3");
+        assert_eq!(catalog.summary(), "0 total tools (mock [mock-upstream 1.2.3]: connected, 0 tools)");
+    }
+
+    async fn counting_pool(max_concurrent: Option<usize>) -> (ClientPool, CountingServer) {
+        let mock = CountingServer::default();
+        let (service, _) = in_process_upstream("mock", mock.clone()).await;
+        let config = stand_in_config(ServerOptions {
+            max_concurrent,
+            ..Default::default()
+        });
+        (ClientPool::from_services(vec![("mock".to_string(), service, config)]), mock)
+    }
+
+    #[tokio::test]
+    async fn test_max_concurrent_limits_in_flight_calls() {
+        use std::sync::atomic::Ordering;
+
+        let (pool, mock) = counting_pool(Some(2)).await;
+        let calls = (0..6).map(|_| pool.call_tool("mock", "work", serde_json::json!({})));
+        for result in futures::future::join_all(calls).await {
+            result.unwrap();
+        }
+        assert_eq!(mock.peak.load(Ordering::SeqCst), 2);
+
+        // Without a limit, calls to one server run concurrently.
+        let (pool, mock) = counting_pool(None).await;
+        let calls = (0..6).map(|_| pool.call_tool("mock", "work", serde_json::json!({})));
+        futures::future::join_all(calls).await;
+        assert!(mock.peak.load(Ordering::SeqCst) > 2);
+    }
+
+    #[tokio::test]
+    async fn test_stalled_handshake_times_out_and_is_skipped() {
+        // Accepts connections but never answers.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                held.push(socket);
+            }
+        });
+
+        let config = ServerConfig::Http {
+            url: format!("http://{addr}/mcp"),
+            auth: None,
+            headers: HashMap::new(),
+            options: ServerOptions {
+                connect_timeout_ms: Some(200),
+                ..Default::default()
+            },
+        };
+        let start = Instant::now();
+        let (_pool, catalog) = ClientPool::connect(HashMap::from([("stalled".to_string(), config)]), false)
+            .await
+            .unwrap();
+
+        assert!(catalog.servers().is_empty());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_http_accept_header_defaults_and_overrides() {
+        let config = |addr, accept: Option<&str>| ServerConfig::Http {
+            url: format!("http://{addr}/mcp"),
+            auth: None,
+            headers: accept
+                .map(|a| HashMap::from([("Accept".to_string(), a.to_string())]))
+                .unwrap_or_default(),
+            options: ServerOptions::default(),
+        };
+        let strict = header_checking_server("accept", crate::streamable_http::DEFAULT_ACCEPT).await;
+        let json_only = header_checking_server("accept", "application/json").await;
+
+        let servers = HashMap::from([
+            ("strict".to_string(), config(strict, None)),
+            ("json_only".to_string(), config(json_only, Some("application/json"))),
+            ("rejected".to_string(), config(json_only, None)),
+        ]);
+        let (_pool, catalog) = ClientPool::connect(servers, false).await.unwrap();
+
+        let mut connected = catalog.servers().to_vec();
+        connected.sort();
+        assert_eq!(connected, ["json_only", "strict"]);
+    }
+
+    #[tokio::test]
+    async fn test_user_agent_reaches_http_server() {
+        let config = |addr, user_agent: Option<&str>| ServerConfig::Http {
+            url: format!("http://{addr}/mcp"),
+            auth: None,
+            headers: HashMap::new(),
+            options: ServerOptions {
+                user_agent: user_agent.map(String::from),
+                ..Default::default()
+            },
+        };
+        let default = header_checking_server("user-agent", DEFAULT_USER_AGENT).await;
+        let custom = header_checking_server("user-agent", "acme-gateway/2").await;
+
+        let servers = HashMap::from([
+            ("default".to_string(), config(default, None)),
+            ("custom".to_string(), config(custom, Some("acme-gateway/2"))),
+            ("rejected".to_string(), config(custom, None)),
+        ]);
+        let (_pool, catalog) = ClientPool::connect(servers, false).await.unwrap();
+
+        let mut connected = catalog.servers().to_vec();
+        connected.sort();
+        assert_eq!(connected, ["custom", "default"]);
+    }
+
+    #[tokio::test]
+    async fn test_health_check_reports_live_and_dead_servers() {
+        let config = || ServerConfig::Stdio {
+            command: "true".to_string(),
+            args: Vec::new(),
+            env: HashMap::new(),
+            options: Default::default(),
+        };
+
+        let (live, _) = in_process_upstream("live", MockLoggingServer::default()).await;
+
+        let (client_io, server_io) = tokio::io::duplex(4096);
+        let server = tokio::spawn(async move {
+            MockLoggingServer::default().serve(server_io).await.unwrap()
+        });
+        let dead = UpstreamHandler::new("dead").serve(client_io).await.unwrap();
+        server.await.unwrap().cancel().await.unwrap();
+
+        let pool = ClientPool::from_services(vec![
+            ("live".to_string(), live, config()),
+            ("dead".to_string(), dead, config()),
+        ]);
+        let health = pool.health_check().await;
+
+        assert_eq!(health["live"].status, HealthStatus::Connected);
+        assert!(matches!(health["dead"].status, HealthStatus::Failed(_)));
+        assert!(health["live"].latency < HEALTH_CHECK_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn test_cached_result_skips_upstream_until_expired() {
+        use std::sync::atomic::Ordering;
+
+        let (pool, mock) = counting_pool(None).await;
+        let pool = pool.with_cache(CachePolicy {
+            ttl_secs: HashMap::from([("mock.list".to_string(), 0.2)]),
+        });
+        let args = || serde_json::json!({ "owner": "me" });
+
+        pool.call_tool("mock", "list", args()).await.unwrap();
+        pool.call_tool("mock", "list", args()).await.unwrap();
+        assert_eq!(mock.calls.load(Ordering::SeqCst), 1);
+
+        // Different arguments and uncached tools always go upstream.
+        pool.call_tool("mock", "list", serde_json::json!({ "owner": "you" })).await.unwrap();
+        pool.call_tool("mock", "work", serde_json::json!({})).await.unwrap();
+        pool.call_tool("mock", "work", serde_json::json!({})).await.unwrap();
+        assert_eq!(mock.calls.load(Ordering::SeqCst), 4);
+
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        pool.call_tool("mock", "list", args()).await.unwrap();
+        assert_eq!(mock.calls.load(Ordering::SeqCst), 5);
+        assert_eq!(pool.usage()["mock.list"].calls, 4);
+    }
+
+    #[tokio::test]
+    async fn test_lazy_server_fails_only_when_used() {
+        let config = ServerConfig::Stdio {
+            command: "cmcp-test-no-such-binary".to_string(),
+            args: Vec::new(),
+            env: HashMap::new(),
+            options: Default::default(),
+        };
+        let (pool, catalog) = ClientPool::connect(HashMap::from([("broken".to_string(), config)]), true)
+            .await
+            .unwrap();
+
+        assert_eq!(catalog.servers(), ["broken"]);
+        assert_eq!(pool.health_check().await["broken"].status, HealthStatus::NotConnected);
+
+        let err = pool.call_tool("broken", "work", serde_json::json!({})).await.unwrap_err();
+        assert!(err.to_string().contains("connecting to broken failed"), "err: {err}");
+        assert!(pool.known_tools().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_check_connects_or_explains() {
+        let addr = header_checking_server("accept", crate::streamable_http::DEFAULT_ACCEPT).await;
+        let http = ServerConfig::Http {
+            url: format!("http://{addr}/mcp"),
+            auth: None,
+            headers: HashMap::new(),
+            options: ServerOptions::default(),
+        };
+        assert_eq!(ClientPool::check("picky", &http).await.unwrap(), 1);
+
+        let missing = ServerConfig::Stdio {
+            command: "cmcp-test-no-such-command".to_string(),
+            args: Vec::new(),
+            env: HashMap::new(),
+            options: Default::default(),
+        };
+        let err = ClientPool::check("missing", &missing).await.unwrap_err();
+        let message = format!("{err:#}");
+        assert!(message.contains("could not connect to missing"), "{message}");
+        assert!(message.contains("failed to start `cmcp-test-no-such-command`"), "{message}");
+    }
+
+    #[tokio::test]
+    async fn test_disabled_server_is_not_connected() {
+        let config = |enabled| ServerConfig::Stdio {
+            command: "cmcp-test-no-such-binary".to_string(),
+            args: Vec::new(),
+            env: HashMap::new(),
+            options: ServerOptions { enabled, ..Default::default() },
+        };
+        let configs = || HashMap::from([("on".to_string(), config(None)), ("off".to_string(), config(Some(false)))]);
+
+        let (pool, catalog) = ClientPool::connect(configs(), true).await.unwrap();
+        assert_eq!(catalog.servers(), ["on"]);
+        let err = pool.call_tool("off", "work", serde_json::json!({})).await.unwrap_err();
+        assert_eq!(err.to_string(), "no server named 'off'");
+
+        let (_, catalog) = ClientPool::connect(configs(), false).await.unwrap();
+        assert!(catalog.servers().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_circuit_opens_after_repeated_failures() {
+        let config = ServerConfig::Stdio {
+            command: "cmcp-test-no-such-binary".to_string(),
+            args: Vec::new(),
+            env: HashMap::new(),
+            options: Default::default(),
+        };
+        let (pool, _) = ClientPool::connect(HashMap::from([("down".to_string(), config)]), true)
+            .await
+            .unwrap();
+        let pool = pool.with_breaker(BreakerPolicy {
+            failures: 3,
+            window_secs: 30.0,
+            cooldown_secs: 0.3,
+        });
+        let call = || pool.call_tool("down", "work", serde_json::json!({}));
+
+        for _ in 0..3 {
+            let err = call().await.unwrap_err();
+            assert!(err.to_string().contains("connecting to down failed"), "err: {err}");
+        }
+        let err = call().await.unwrap_err();
+        assert_eq!(err.to_string(), "circuit open for down");
+        assert!(matches!(
+            pool.health_check().await["down"].status,
+            HealthStatus::CircuitOpen { retry_in } if retry_in <= Duration::from_millis(300)
+        ));
+
+        // After the cooldown one call probes the server; its failure reopens the breaker.
+        tokio::time::sleep(Duration::from_millis(350)).await;
+        let err = call().await.unwrap_err();
+        assert!(err.to_string().contains("connecting to down failed"), "err: {err}");
+        let err = call().await.unwrap_err();
+        assert_eq!(err.to_string(), "circuit open for down");
+    }
+
+    #[test]
+    fn test_breaker_rejects_unusable_durations() {
+        let policy = BreakerPolicy { cooldown_secs: 1e300, ..Default::default() };
+        let err = policy.validate().unwrap_err();
+        assert!(err.to_string().starts_with("circuit_breaker.cooldown_secs must be"), "{err}");
+        let policy = BreakerPolicy { window_secs: f64::NAN, ..Default::default() };
+        assert!(policy.validate().is_err());
+
+        // Used anyway, they don't panic.
+        let policy = BreakerPolicy { failures: 1, window_secs: -1.0, cooldown_secs: 1e300 };
+        let mut breaker = Breaker::default();
+        breaker.record_failure(&policy, Instant::now());
+        breaker.record_failure(&policy, Instant::now());
+    }
+
+    #[test]
+    fn test_timeouts_retried_only_for_idempotent_tools() {
+        let timeout = ServiceError::Timeout { timeout: Duration::from_secs(1) };
+        assert!(!is_retryable(&timeout, false));
+        assert!(is_retryable(&timeout, true));
+        assert!(is_retryable(&ServiceError::TransportClosed, false));
+        let server_error = ServiceError::McpError(McpError::invalid_params("bad input", None));
+        assert!(!is_retryable(&server_error, true));
+    }
+
+    #[test]
+    fn test_retry_delay_doubles() {
+        let policy = RetryPolicy { attempts: 4, base_delay_ms: 100 };
+        let delays: Vec<_> = (1..4).map(|n| policy.delay(n).as_millis()).collect();
+        assert_eq!(delays, [100, 200, 400]);
+    }
+
+    #[tokio::test]
+    async fn test_server_error_not_retried() {
+        use std::sync::atomic::Ordering;
+
+        #[derive(Clone, Default)]
+        struct FailingServer {
+            calls: Arc<std::sync::atomic::AtomicUsize>,
+        }
+
+        impl ServerHandler for FailingServer {
+            async fn call_tool(
+                &self,
+                _request: CallToolRequestParams,
+                _context: RequestContext<RoleServer>,
+            ) -> Result<CallToolResult, McpError> {
+                self.calls.fetch_add(1, Ordering::SeqCst);
+                Err(McpError::invalid_params("bad input", None))
+            }
+
+            fn get_info(&self) -> ServerInfo {
+                ServerInfo {
+                    capabilities: ServerCapabilities::builder().enable_tools().build(),
+                    ..Default::default()
+                }
+            }
+        }
+
+        let mock = FailingServer::default();
+        let (service, config) = in_process_upstream("mock", mock.clone()).await;
+        let pool = ClientPool::from_services(vec![("mock".to_string(), service, config)]);
+
+        let err = pool.call_tool("mock", "work", serde_json::json!({})).await.unwrap_err();
+        assert!(err.to_string().contains("bad input"), "err: {err}");
+        assert_eq!(mock.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_transport_failure_retried_with_backoff() {
+        let (client_io, server_io) = tokio::io::duplex(4096);
+        let server = tokio::spawn(async move {
+            MockLoggingServer::default().serve(server_io).await.unwrap()
+        });
+        let service = UpstreamHandler::new("gone").serve(client_io).await.unwrap();
+        // Kill the upstream so every call fails at the transport level.
+        server.await.unwrap().cancel().await.unwrap();
+
+        let config = ServerConfig::Stdio {
+            command: "cmcp-test-no-such-binary".to_string(),
+            args: Vec::new(),
+            env: HashMap::new(),
+            options: Default::default(),
+        };
+        let pool = ClientPool::from_services(vec![("gone".to_string(), service, config)])
+            .with_retry(RetryPolicy { attempts: 3, base_delay_ms: 20 });
+
+        let start = Instant::now();
+        let err = pool.call_tool("gone", "work", serde_json::json!({})).await.unwrap_err();
+        assert!(err.to_string().contains("after 3 attempts"), "err: {err}");
+        assert!(start.elapsed() >= Duration::from_millis(60));
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_failures_reconnect_once() {
+        let (client_io, server_io) = tokio::io::duplex(4096);
+        let server = tokio::spawn(async move {
+            MockLoggingServer::default().serve(server_io).await.unwrap()
+        });
+        let service = UpstreamHandler::new("gone").serve(client_io).await.unwrap();
+        server.await.unwrap().cancel().await.unwrap();
+
+        // Each reconnect appends a line, then stalls the handshake until it times out.
+        let launches = std::env::temp_dir().join(format!("cmcp-reconnects-{}", std::process::id()));
+        let _ = std::fs::remove_file(&launches);
+        let config = ServerConfig::Stdio {
+            command: "sh".to_string(),
+            args: vec!["-c".to_string(), format!("echo >> {}; sleep 5", launches.display())],
+            env: HashMap::new(),
+            options: ServerOptions {
+                connect_timeout_ms: Some(200),
+                ..Default::default()
+            },
+        };
+        let pool = ClientPool::from_services(vec![("gone".to_string(), service, config)])
+            .with_retry(RetryPolicy { attempts: 2, base_delay_ms: 10 });
+
+        let calls = (0..5).map(|_| pool.call_tool("gone", "work", serde_json::json!({})));
+        for result in futures::future::join_all(calls).await {
+            assert!(result.is_err());
+        }
+        let reconnects = std::fs::read_to_string(&launches).unwrap_or_default().lines().count();
+        assert_eq!(reconnects, 1);
+        let _ = std::fs::remove_file(&launches);
+    }
+
+    #[test]
+    fn test_logged_headers_omit_token() {
+        let auth = Some("sk-live-abc123".to_string());
+        let headers = HashMap::from([
+            ("X-Api-Key".to_string(), "key-456".to_string()),
+            ("X-Session-Id".to_string(), "sess-789".to_string()),
+            ("Accept-Language".to_string(), "en".to_string()),
+        ]);
+
+        let logged = format!("{:?}", redacted_request_headers(&auth, &headers));
+        assert!(!logged.contains("sk-live-abc123"), "logged: {logged}");
+        assert!(!logged.contains("key-456"), "logged: {logged}");
+        assert!(!logged.contains("sess-789"), "logged: {logged}");
+        assert!(logged.contains("Bearer ***"), "logged: {logged}");
+        assert!(logged.contains("\"en\""), "logged: {logged}");
     }
 }
@@ -1,5 +1,7 @@
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
@@ -34,13 +36,512 @@ impl Scope {
     }
 }
 
+/// A config file layer in the merge order used by [`Config::load_merged`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigLayer {
+    /// ~/.config/code-mode-mcp/config.toml
+    User,
+    /// .cmcp.toml in the current directory
+    Project,
+    /// .cmcp.<env>.toml selected by `CMCP_ENV`
+    Environment,
+    /// Path passed with --config
+    Explicit,
+}
+
+impl std::fmt::Display for ConfigLayer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigLayer::User => write!(f, "user"),
+            ConfigLayer::Project => write!(f, "project"),
+            ConfigLayer::Environment => write!(f, "environment"),
+            ConfigLayer::Explicit => write!(f, "explicit"),
+        }
+    }
+}
+
+/// A server definition that was overridden by a higher-priority layer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shadowed {
+    pub server: String,
+    /// The layer whose definition is in effect.
+    pub winner: ConfigLayer,
+    /// The lower-priority layer whose definition was replaced.
+    pub shadowed: ConfigLayer,
+}
+
+/// Where each server in a merged config came from.
+#[derive(Debug, Default)]
+pub struct MergeReport {
+    /// Winning layer for every server name.
+    pub origins: HashMap<String, ConfigLayer>,
+    /// Definitions replaced by a later layer, in merge order.
+    pub shadowed: Vec<Shadowed>,
+}
+
 /// Top-level configuration.
 #[derive(Debug, Deserialize, Serialize, Default)]
 pub struct Config {
+    #[serde(default, skip_serializing_if = "SandboxConfig::is_empty")]
+    pub sandbox: SandboxConfig,
+
+    #[serde(default)]
+    pub servers: HashMap<String, ServerConfig>,
+
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub views: HashMap<String, ViewConfig>,
+
+    /// Alternative server sets (`[profiles.<name>.servers.<server>]`), used
+    /// instead of the top-level servers when selected with `--profile`.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub profiles: HashMap<String, Profile>,
+
+    /// Options applied to every server that doesn't set them itself (`[defaults]`).
+    #[serde(default, skip_serializing_if = "ServerOptions::is_empty")]
+    pub defaults: ServerOptions,
+
+    #[serde(default, skip_serializing_if = "TelemetryConfig::is_empty")]
+    pub telemetry: TelemetryConfig,
+}
+
+/// OpenTelemetry export (`[telemetry]`), used when built with the `otel` feature.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct TelemetryConfig {
+    /// OTLP/HTTP traces endpoint, e.g. "http://localhost:4318/v1/traces".
+    /// Spans are only exported when this is set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub otlp_endpoint: Option<String>,
+    /// `service.name` reported with every span (default: "cmcp").
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service_name: Option<String>,
+}
+
+impl TelemetryConfig {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Overlay fields set in `other`.
+    pub fn merge(&mut self, other: TelemetryConfig) {
+        if other.otlp_endpoint.is_some() {
+            self.otlp_endpoint = other.otlp_endpoint;
+        }
+        if other.service_name.is_some() {
+            self.service_name = other.service_name;
+        }
+    }
+}
+
+/// A named set of servers (`[profiles.<name>]`).
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct Profile {
     #[serde(default)]
     pub servers: HashMap<String, ServerConfig>,
 }
 
+/// A named subset of servers and tools (`[views.<name>]`), selected with `cmcp serve --view`.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct ViewConfig {
+    /// Servers whose tools are all included.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub servers: Vec<String>,
+    /// Individual tools included as "server.tool".
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<String>,
+}
+
+impl ViewConfig {
+    /// Whether the tool `server.tool` is a member of this view.
+    pub fn includes(&self, server: &str, tool: &str) -> bool {
+        self.servers.iter().any(|s| s == server)
+            || self.tools.iter().any(|t| t.split_once('.') == Some((server, tool)))
+    }
+
+    /// Whether any of this view's members live on `server`.
+    pub fn touches_server(&self, server: &str) -> bool {
+        self.servers.iter().any(|s| s == server)
+            || self.tools.iter().any(|t| t.split_once('.').is_some_and(|(s, _)| s == server))
+    }
+}
+
+/// Settings for the JS sandbox (`[sandbox]` table).
+/// Unset fields fall back to defaults; when merging, later layers win per field.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct SandboxConfig {
+    /// Tracing level for `console.*` output: "error", "warn", "info" (default),
+    /// "debug", "trace", or "off" to suppress it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub console_level: Option<String>,
+    /// Prefix shown before console messages (default: "js").
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub console_prefix: Option<String>,
+    /// Active view, set at runtime by [`Config::select_view`]. Not read from config files.
+    #[serde(skip)]
+    pub view: Option<ViewConfig>,
+    /// Extra strings to mask in error messages, on top of resolved secrets
+    /// (see [`crate::client::ClientPool::redact`]).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redact: Vec<String>,
+    /// Tools ("server.tool") that are callable but left out of type declarations.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub callable_only: Vec<String>,
+    /// Max tools per server that get type declarations; the rest are callable-only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_declared_tools: Option<usize>,
+    /// Wall-clock limit for one `search`/`execute` call, in seconds (default: 60).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<f64>,
+    /// At startup, wait at most this long for servers to connect, in ms
+    /// (default: until all are done). Servers still connecting are added to
+    /// the catalog when they are ready.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_wait_ms: Option<u64>,
+    /// How many QuickJS interrupt polls (one roughly every 10,000 bytecode
+    /// operations) pass between deadline checks (default: 1, every poll).
+    /// Higher values cost less in tight loops but stop a runaway one later.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interrupt_check_interval: Option<u32>,
+    /// Time budget shared by all tool calls of one `execute`, in seconds. Each
+    /// call gets what earlier calls left; once it runs out, calls fail.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub call_deadline_secs: Option<f64>,
+    /// How oversized `search`/`execute` responses are cut to `max_length`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub truncation: Option<Truncation>,
+    /// What `truncation = "text"` puts in place of the omitted output:
+    /// "prose" (default), "marker", or a template using `{omitted}`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub truncation_notice: Option<TruncationNotice>,
+    /// Max nesting depth of tool results and call results (default: 64).
+    /// Deeper JSON is rejected instead of risking a stack overflow.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_json_depth: Option<usize>,
+    /// Images extracted from one `execute` result (default: 20). Further
+    /// images are dropped, leaving a note in their place.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_images: Option<usize>,
+    /// Repair malformed JSON in tool result text (trailing commas, raw control
+    /// characters) before handing it to agent code. Off by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repair_json: Option<bool>,
+    /// Hand tool results to `execute` code as the full `CallToolResult`
+    /// instead of its `structuredContent` or text. Off by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_results: Option<bool>,
+    /// Resolve successful tool calls in `execute` to `{ data, meta }`, where
+    /// `meta` is the result's `_meta` (e.g. pagination cursors). Off by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result_meta: Option<bool>,
+    /// Make tool calls in `execute` throw an `Error` when the upstream call
+    /// fails or returns `isError: true`, instead of resolving to `{ error }`
+    /// or the error result. Off by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub throw_on_error: Option<bool>,
+    /// Cut each `input_schema` in the `tools` array down to its top-level
+    /// property names and types. Tool entries a `search` returns get their
+    /// full schema back. Off by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summarize_schemas: Option<bool>,
+    /// Weights for `cmcp.rank()` (`[sandbox.ranking]`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ranking: Option<RankWeights>,
+    /// Expose an `anyServer` global in `execute`: `anyServer.tool(args)` calls
+    /// `tool` on the highest-priority server that has it. Off by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub any_server: Option<bool>,
+    /// Preferred servers, highest priority first. `anyServer` routes to them
+    /// (the rest follow alphabetically) and `cmcp.rank()` ranks their tools higher.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub server_priority: Vec<String>,
+    /// Retries for tool calls that fail at the transport level (`[sandbox.retry]`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<RetryPolicy>,
+    /// When to stop calling a server that keeps failing (`[sandbox.circuit_breaker]`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub circuit_breaker: Option<BreakerPolicy>,
+    /// Per-tool TTLs for reusing results across calls (`[sandbox.cache]`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache: Option<CachePolicy>,
+    /// Max tool calls in flight across all servers (default: 16); further
+    /// calls queue. Per-server `max_concurrent` limits apply on top.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrent_calls: Option<usize>,
+    /// Replacement descriptions for poorly documented tools ("server.tool" ->
+    /// text); text starting with `+` is appended instead.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub tool_descriptions: HashMap<String, String>,
+    /// Extra global names for servers in `execute` ("old-api" -> "new-api"),
+    /// so code written against a renamed server keeps working.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub server_aliases: HashMap<String, String>,
+    /// Vetted `execute` snippets by id (id -> TypeScript), run with their
+    /// parameters as the `params` global.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub templates: HashMap<String, String>,
+    /// Make `execute` run only `templates`, rejecting arbitrary code.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub templates_only: Option<bool>,
+}
+
+/// How oversized responses are cut down to `max_length`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Truncation {
+    /// Cut the pretty-printed text at a line boundary and append a notice.
+    #[default]
+    Text,
+    /// Cap arrays, objects and strings so the result stays valid JSON.
+    Json,
+}
+
+/// The notice appended to text cut by `truncation = "text"`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum TruncationNotice {
+    /// A sentence telling the agent how much was cut and how to avoid it.
+    #[default]
+    Prose,
+    /// `{"__cmcp_truncated": {"omitted_chars": N}}`, for code that parses output.
+    Marker,
+    /// Custom text, with `{omitted}` replaced by the number of chars cut.
+    /// It must contain that placeholder, so a misspelled name isn't taken
+    /// for a template.
+    Template(String),
+}
+
+impl TruncationNotice {
+    pub fn render(&self, omitted: usize) -> String {
+        match self {
+            Self::Prose => format!(
+                "[truncated — {omitted} chars omitted. Use your code to extract only the data you need, or increase max_length.]"
+            ),
+            Self::Marker => serde_json::json!({ "__cmcp_truncated": { "omitted_chars": omitted } }).to_string(),
+            Self::Template(template) => template.replace("{omitted}", &omitted.to_string()),
+        }
+    }
+}
+
+impl TryFrom<String> for TruncationNotice {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "prose" => Ok(Self::Prose),
+            "marker" => Ok(Self::Marker),
+            _ if value.contains("{omitted}") => Ok(Self::Template(value)),
+            _ => Err(format!(
+                "unknown truncation_notice \"{value}\": use \"prose\", \"marker\" or a template containing {{omitted}}"
+            )),
+        }
+    }
+}
+
+impl From<TruncationNotice> for String {
+    fn from(notice: TruncationNotice) -> Self {
+        match notice {
+            TruncationNotice::Prose => "prose".to_string(),
+            TruncationNotice::Marker => "marker".to_string(),
+            TruncationNotice::Template(template) => template,
+        }
+    }
+}
+
+/// A `*_secs` setting as a [`Duration`]. Negative and NaN values, and ones
+/// too large to add to the current time, are config errors rather than panics.
+pub fn duration_secs(setting: &str, secs: f64) -> Result<Duration> {
+    Duration::try_from_secs_f64(secs)
+        .ok()
+        .filter(|duration| Instant::now().checked_add(*duration).is_some())
+        .with_context(|| format!("{setting} must be a non-negative number of seconds, got {secs}"))
+}
+
+impl SandboxConfig {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Overlay `other` on top of `self`: fields set in `other` win.
+    pub fn merge(&mut self, other: SandboxConfig) {
+        if other.console_level.is_some() {
+            self.console_level = other.console_level;
+        }
+        if other.console_prefix.is_some() {
+            self.console_prefix = other.console_prefix;
+        }
+        self.redact.extend(other.redact);
+        self.callable_only.extend(other.callable_only);
+        if other.max_declared_tools.is_some() {
+            self.max_declared_tools = other.max_declared_tools;
+        }
+        if other.timeout_secs.is_some() {
+            self.timeout_secs = other.timeout_secs;
+        }
+        if other.connect_wait_ms.is_some() {
+            self.connect_wait_ms = other.connect_wait_ms;
+        }
+        if other.interrupt_check_interval.is_some() {
+            self.interrupt_check_interval = other.interrupt_check_interval;
+        }
+        if other.call_deadline_secs.is_some() {
+            self.call_deadline_secs = other.call_deadline_secs;
+        }
+        if other.truncation.is_some() {
+            self.truncation = other.truncation;
+        }
+        if other.truncation_notice.is_some() {
+            self.truncation_notice = other.truncation_notice;
+        }
+        if other.max_json_depth.is_some() {
+            self.max_json_depth = other.max_json_depth;
+        }
+        if other.max_images.is_some() {
+            self.max_images = other.max_images;
+        }
+        if other.repair_json.is_some() {
+            self.repair_json = other.repair_json;
+        }
+        if other.raw_results.is_some() {
+            self.raw_results = other.raw_results;
+        }
+        if other.result_meta.is_some() {
+            self.result_meta = other.result_meta;
+        }
+        if other.throw_on_error.is_some() {
+            self.throw_on_error = other.throw_on_error;
+        }
+        if other.summarize_schemas.is_some() {
+            self.summarize_schemas = other.summarize_schemas;
+        }
+        if other.ranking.is_some() {
+            self.ranking = other.ranking;
+        }
+        if other.any_server.is_some() {
+            self.any_server = other.any_server;
+        }
+        if !other.server_priority.is_empty() {
+            self.server_priority = other.server_priority;
+        }
+        if other.retry.is_some() {
+            self.retry = other.retry;
+        }
+        if other.circuit_breaker.is_some() {
+            self.circuit_breaker = other.circuit_breaker;
+        }
+        if other.cache.is_some() {
+            self.cache = other.cache;
+        }
+        if other.max_concurrent_calls.is_some() {
+            self.max_concurrent_calls = other.max_concurrent_calls;
+        }
+        self.tool_descriptions.extend(other.tool_descriptions);
+        self.server_aliases.extend(other.server_aliases);
+        self.templates.extend(other.templates);
+        if other.templates_only.is_some() {
+            self.templates_only = other.templates_only;
+        }
+    }
+}
+
+/// Weights for [`crate::catalog::Catalog::rank`] on top of the text match score (`[sandbox.ranking]`).
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RankWeights {
+    /// Added for tools annotated `readOnlyHint: true`.
+    pub read_only: f64,
+    /// Added for tools annotated `destructiveHint: true` (negative to demote).
+    pub destructive: f64,
+    /// Multiplied by the tool's recency (1.0 = just used, decaying toward 0).
+    pub recent_use: f64,
+    /// Added in full for tools on the first server in `server_priority`,
+    /// scaled down for each later one; unlisted servers get nothing.
+    pub server_priority: f64,
+}
+
+impl Default for RankWeights {
+    fn default() -> Self {
+        Self {
+            read_only: 0.5,
+            destructive: -0.5,
+            recent_use: 1.5,
+            server_priority: 1.0,
+        }
+    }
+}
+
+/// How tool calls that fail at the transport level are retried (`[sandbox.retry]`).
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Total attempts per call, including the first.
+    pub attempts: u32,
+    /// Delay before the first retry; doubled for each one after.
+    pub base_delay_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            base_delay_ms: 200,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Backoff before retrying after failed attempt number `attempt` (1-based).
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u64.saturating_pow(attempt.saturating_sub(1));
+        Duration::from_millis(self.base_delay_ms.saturating_mul(factor))
+    }
+}
+
+/// When a failing server stops getting calls (`[sandbox.circuit_breaker]`).
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BreakerPolicy {
+    /// Transport failures within `window_secs` that open the breaker (0 disables it).
+    pub failures: u32,
+    pub window_secs: f64,
+    /// How long calls fail fast before the server is tried again.
+    pub cooldown_secs: f64,
+}
+
+impl BreakerPolicy {
+    /// Reject windows and cooldowns that are not a usable number of seconds.
+    pub fn validate(&self) -> Result<()> {
+        duration_secs("circuit_breaker.window_secs", self.window_secs)?;
+        duration_secs("circuit_breaker.cooldown_secs", self.cooldown_secs)?;
+        Ok(())
+    }
+}
+
+impl Default for BreakerPolicy {
+    fn default() -> Self {
+        Self {
+            failures: 5,
+            window_secs: 30.0,
+            cooldown_secs: 30.0,
+        }
+    }
+}
+
+/// Which tool results are reused across calls (`[sandbox.cache]`).
+/// Nothing is cached unless its tool is listed.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CachePolicy {
+    /// Seconds a result stays fresh, per "server.tool".
+    pub ttl_secs: HashMap<String, f64>,
+}
+
+impl CachePolicy {
+    /// How long results of `server_name.tool_name` are kept, if they are cached at all.
+    pub fn ttl(&self, server_name: &str, tool_name: &str) -> Option<Duration> {
+        let secs = *self.ttl_secs.get(&format!("{server_name}.{tool_name}"))?;
+        Duration::try_from_secs_f64(secs).ok().filter(|ttl| !ttl.is_zero())
+    }
+}
+
 /// Configuration for a single upstream MCP server.
 #[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(tag = "transport")]
@@ -48,23 +549,41 @@ pub enum ServerConfig {
     #[serde(rename = "http")]
     Http {
         url: String,
-        /// Bearer token (without "Bearer " prefix).
+        /// Bearer token (without "Bearer " prefix), or `oauth[:scopes]` to use
+        /// tokens from `cmcp auth`.
         #[serde(default, skip_serializing_if = "Option::is_none")]
         auth: Option<String>,
         /// Custom HTTP headers sent with every request.
         #[serde(default, skip_serializing_if = "HashMap::is_empty")]
         headers: HashMap<String, String>,
+        #[serde(flatten)]
+        options: ServerOptions,
     },
 
     #[serde(rename = "sse")]
     Sse {
         url: String,
-        /// Bearer token (without "Bearer " prefix).
+        /// Bearer token (without "Bearer " prefix), or `oauth[:scopes]`.
         #[serde(default, skip_serializing_if = "Option::is_none")]
         auth: Option<String>,
         /// Custom HTTP headers.
         #[serde(default, skip_serializing_if = "HashMap::is_empty")]
         headers: HashMap<String, String>,
+        #[serde(flatten)]
+        options: ServerOptions,
+    },
+
+    #[serde(rename = "ws")]
+    Ws {
+        url: String,
+        /// Bearer token (without "Bearer " prefix), sent on the upgrade request.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        auth: Option<String>,
+        /// Custom HTTP headers sent on the upgrade request.
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        headers: HashMap<String, String>,
+        #[serde(flatten)]
+        options: ServerOptions,
     },
 
     #[serde(rename = "stdio")]
@@ -74,11 +593,197 @@ pub enum ServerConfig {
         args: Vec<String>,
         #[serde(default, skip_serializing_if = "HashMap::is_empty")]
         env: HashMap<String, String>,
+        #[serde(flatten)]
+        options: ServerOptions,
     },
 }
 
+/// Settings shared by every transport, stored inline in the server's table.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct ServerOptions {
+    /// MCP log level requested from the server after connecting
+    /// (debug, info, notice, warning, error, critical, alert, emergency).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_level: Option<String>,
+    /// Max tool calls in flight to this server; further calls queue.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrent: Option<usize>,
+    /// How long to wait for the MCP handshake before skipping the server (default: 15000).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_timeout_ms: Option<u64>,
+    /// Fail a single tool call that gets no reply within this many ms (default: no limit).
+    #[serde(default, alias = "timeout_ms", skip_serializing_if = "Option::is_none")]
+    pub call_timeout_ms: Option<u64>,
+    /// Truncation budget in characters for this server's tool results, used
+    /// unless the request passes its own `max_length`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_length: Option<usize>,
+    /// `User-Agent` sent to http, sse and ws servers (default: `cmcp/<version>`).
+    /// A `User-Agent` in `headers` takes precedence.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    /// `false` keeps the server in the config without connecting to it
+    /// (`cmcp disable`). Unset means enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    /// Fail to connect when an `env:VAR` or `${VAR}` reference is unset,
+    /// rather than using an empty value or leaving `${VAR}` as written.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strict_env: Option<bool>,
+}
+
+impl ServerOptions {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Overlay `other` on top of `self`: fields set in `other` win.
+    pub fn merge(&mut self, other: ServerOptions) {
+        if other.log_level.is_some() {
+            self.log_level = other.log_level;
+        }
+        if other.max_concurrent.is_some() {
+            self.max_concurrent = other.max_concurrent;
+        }
+        if other.connect_timeout_ms.is_some() {
+            self.connect_timeout_ms = other.connect_timeout_ms;
+        }
+        if other.call_timeout_ms.is_some() {
+            self.call_timeout_ms = other.call_timeout_ms;
+        }
+        if other.max_length.is_some() {
+            self.max_length = other.max_length;
+        }
+        if other.user_agent.is_some() {
+            self.user_agent = other.user_agent;
+        }
+        if other.enabled.is_some() {
+            self.enabled = other.enabled;
+        }
+        if other.strict_env.is_some() {
+            self.strict_env = other.strict_env;
+        }
+    }
+
+    /// Fill fields this server leaves unset from `defaults`.
+    pub fn fill_from(&mut self, defaults: &ServerOptions) {
+        let mut filled = defaults.clone();
+        filled.merge(std::mem::take(self));
+        *self = filled;
+    }
+}
+
+/// Whether `url` uses the `ws://` or `wss://` scheme.
+pub fn is_websocket_url(url: &str) -> bool {
+    matches!(url_scheme(url).as_deref(), Some("ws" | "wss"))
+}
+
+/// The lowercased scheme of `s` if it looks like `scheme://...`.
+fn url_scheme(s: &str) -> Option<String> {
+    let (scheme, _) = s.split_once("://")?;
+    let mut chars = scheme.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+    valid.then(|| scheme.to_ascii_lowercase())
+}
+
+/// Guess the transport for `cmcp add` from its first positional argument.
+///
+/// Returns the transport name and, when the guess may be wrong, a hint
+/// recommending an explicit `--transport`.
+pub fn detect_transport(first: &str) -> (&'static str, Option<String>) {
+    match url_scheme(first).as_deref() {
+        Some("http" | "https") => ("http", None),
+        Some("ws" | "wss") => (
+            "ws",
+            Some(format!(
+                "\"{first}\" is a WebSocket URL, using --transport ws. \
+                 If the server speaks streamable HTTP or SSE, use an http(s):// URL with --transport http or sse."
+            )),
+        ),
+        Some(scheme) => (
+            "stdio",
+            Some(format!(
+                "\"{first}\" has unsupported URL scheme \"{scheme}://\", treating it as a command. \
+                 Pass --transport http, sse, ws or stdio to choose explicitly."
+            )),
+        ),
+        None if looks_like_bare_host(first) => (
+            "stdio",
+            Some(format!(
+                "\"{first}\" looks like a URL without a scheme, treating it as a command. \
+                 Add https:// or pass --transport stdio to silence this."
+            )),
+        ),
+        None => ("stdio", None),
+    }
+}
+
+/// `example.com/mcp` or `localhost:8080`: a host with a path or port but no
+/// scheme, which is almost never a command name.
+fn looks_like_bare_host(s: &str) -> bool {
+    if s.starts_with(['.', '/', '~', '-']) || s.contains(char::is_whitespace) {
+        return false;
+    }
+    let host = s.split('/').next().unwrap_or(s);
+    let (name, port) = match host.rsplit_once(':') {
+        Some((name, port)) => (name, Some(port)),
+        None => (host, None),
+    };
+    let has_port = port.is_some_and(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_digit()));
+    let dotted = name.split('.').count() > 1 && name.split('.').all(|l| !l.is_empty());
+    (name == "localhost" && (has_port || s.contains('/')))
+        || (dotted && (has_port || s.contains('/')) && !name.ends_with(".js") && !name.ends_with(".py"))
+}
+
+impl ServerConfig {
+    /// Transport-independent settings for this server.
+    pub fn options(&self) -> &ServerOptions {
+        match self {
+            Self::Http { options, .. }
+            | Self::Sse { options, .. }
+            | Self::Ws { options, .. }
+            | Self::Stdio { options, .. } => options,
+        }
+    }
+
+    pub fn options_mut(&mut self) -> &mut ServerOptions {
+        match self {
+            Self::Http { options, .. }
+            | Self::Sse { options, .. }
+            | Self::Ws { options, .. }
+            | Self::Stdio { options, .. } => options,
+        }
+    }
+
+    /// Whether the server should be connected (see [`ServerOptions::enabled`]).
+    pub fn is_enabled(&self) -> bool {
+        self.options().enabled != Some(false)
+    }
+
+    /// Whether two configs for a server would connect the same way.
+    pub fn same_as(&self, other: &ServerConfig) -> bool {
+        serde_json::to_value(self).ok() == serde_json::to_value(other).ok()
+    }
+
+    /// Names of the fields, as written in a config file, whose values differ
+    /// between the two configs, sorted.
+    pub fn differing_fields(&self, other: &ServerConfig) -> Vec<String> {
+        let fields = |config: &ServerConfig| match serde_json::to_value(config) {
+            Ok(serde_json::Value::Object(map)) => map,
+            _ => serde_json::Map::new(),
+        };
+        let (a, b) = (fields(self), fields(other));
+        let mut names: Vec<String> = a.keys().chain(b.keys()).filter(|k| a.get(*k) != b.get(*k)).cloned().collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
 impl Config {
     /// Load config from a specific path, falling back to defaults if the file doesn't exist.
+    /// `.json` files are read as JSON, anything else as TOML.
     pub fn load_from(path: &PathBuf) -> Result<Self> {
         if !path.exists() {
             return Ok(Self::default());
@@ -87,8 +792,13 @@ impl Config {
         let content = std::fs::read_to_string(path)
             .with_context(|| format!("failed to read config from {}", path.display()))?;
 
-        toml::from_str(&content)
-            .with_context(|| format!("failed to parse config from {}", path.display()))
+        if is_json_path(path) {
+            serde_json::from_str(&content)
+                .with_context(|| format!("failed to parse config from {}", path.display()))
+        } else {
+            toml::from_str(&content)
+                .with_context(|| format!("failed to parse config from {}", path.display()))
+        }
     }
 
     /// Load config, falling back to defaults if the file doesn't exist.
@@ -100,43 +810,131 @@ impl Config {
         Self::load_from(&path)
     }
 
-    /// Load merged config: user config as base, then overlay project and explicit configs.
+    /// Load merged config: user config as base, then overlay project, environment and explicit configs.
     /// Later configs override earlier ones with the same server name.
-    /// Priority (lowest to highest): user → project (.cmcp.toml) → explicit_path
+    /// Priority (lowest to highest): user → project (.cmcp.toml) → environment (.cmcp.<CMCP_ENV>.toml) → explicit_path
     pub fn load_merged(explicit_path: Option<&PathBuf>) -> Result<Self> {
-        // Always start with user config as the base.
-        let user_path = default_config_path()?;
-        let mut merged = Self::load_from(&user_path)?;
+        Ok(Self::load_merged_with_report(explicit_path)?.0)
+    }
+
+    /// Like [`Config::load_merged`], but also reports which layer each server came from
+    /// and which definitions were shadowed by a higher-priority layer.
+    ///
+    /// With an [`active_profile`], every layer contributes that profile's
+    /// servers instead of its top-level ones.
+    pub fn load_merged_with_report(explicit_path: Option<&PathBuf>) -> Result<(Self, MergeReport)> {
+        Self::load_merged_from(default_config_path(), explicit_path)
+    }
+
+    /// Merge on top of `user_path`. Without a user config path (no HOME in a
+    /// container, say) the other layers are still loaded.
+    fn load_merged_from(
+        user_path: Result<PathBuf>,
+        explicit_path: Option<&PathBuf>,
+    ) -> Result<(Self, MergeReport)> {
+        // Start with user config as the base.
+        let mut layers = Vec::new();
+        match user_path {
+            Ok(path) => layers.push((ConfigLayer::User, path)),
+            Err(e) if explicit_path.is_some() => tracing::debug!(error = %e, "skipping user config"),
+            Err(e) => tracing::warn!(error = %e, "skipping user config"),
+        }
 
         // Overlay project config (.cmcp.toml) if it exists.
         let project_path = project_config_path();
         if project_path.exists() {
-            let project = Self::load_from(&project_path)?;
-            for (name, config) in project.servers {
-                merged.servers.insert(name, config);
+            layers.push((ConfigLayer::Project, project_path));
+        }
+
+        // Overlay the environment-specific project config if CMCP_ENV is set.
+        if let Some(env_path) = env_config_path() {
+            if env_path.exists() {
+                layers.push((ConfigLayer::Environment, env_path));
+            } else {
+                tracing::warn!(path = %env_path.display(), "CMCP_ENV is set but the overlay does not exist");
             }
         }
 
         // Overlay explicit config (e.g. .cas/proxy.toml) if provided.
         if let Some(p) = explicit_path {
-            let explicit = Self::load_from(p)?;
-            for (name, config) in explicit.servers {
+            layers.push((ConfigLayer::Explicit, p.clone()));
+        }
+
+        Self::merge_layers(&layers, active_profile().as_deref())
+    }
+
+    /// Merge config files in priority order (lowest first), tracking server origins.
+    /// With `profile`, each file's servers come from that profile.
+    fn merge_layers(layers: &[(ConfigLayer, PathBuf)], profile: Option<&str>) -> Result<(Self, MergeReport)> {
+        let mut merged = Self::default();
+        let mut report = MergeReport::default();
+        let mut profile_found = false;
+
+        for (layer, path) in layers {
+            let mut cfg = Self::load_from(path)?;
+            // A checked-out repo must not be able to loosen the template lockdown.
+            if matches!(layer, ConfigLayer::Project | ConfigLayer::Environment)
+                && (!cfg.sandbox.templates.is_empty() || cfg.sandbox.templates_only.is_some())
+            {
+                tracing::warn!(path = %path.display(), "ignoring templates and templates_only in a project config, set them in the user config or with --config");
+                cfg.sandbox.templates.clear();
+                cfg.sandbox.templates_only = None;
+            }
+            merged.sandbox.merge(cfg.sandbox);
+            merged.defaults.merge(cfg.defaults);
+            merged.telemetry.merge(cfg.telemetry);
+            merged.views.extend(cfg.views);
+            let servers = match profile {
+                Some(name) => match cfg.profiles.remove(name) {
+                    Some(profile) => {
+                        profile_found = true;
+                        profile.servers
+                    }
+                    None => HashMap::new(),
+                },
+                None => cfg.servers,
+            };
+            for (name, config) in servers {
+                if let Some(previous) = report.origins.insert(name.clone(), *layer) {
+                    report.shadowed.push(Shadowed {
+                        server: name.clone(),
+                        winner: *layer,
+                        shadowed: previous,
+                    });
+                }
                 merged.servers.insert(name, config);
             }
         }
 
-        Ok(merged)
+        if let Some(name) = profile
+            && !profile_found
+        {
+            anyhow::bail!("profile \"{name}\" not found. Define its servers under [profiles.{name}.servers.<name>]");
+        }
+
+        for config in merged.servers.values_mut() {
+            config.options_mut().fill_from(&merged.defaults);
+        }
+
+        Ok((merged, report))
     }
 
     /// Save config to a specific path, creating parent dirs as needed.
+    /// The format follows the extension, as in [`Config::load_from`].
     pub fn save_to(&self, path: &PathBuf) -> Result<()> {
         if let Some(parent) = path.parent() {
             std::fs::create_dir_all(parent)
                 .with_context(|| format!("failed to create {}", parent.display()))?;
         }
 
-        let content = toml::to_string_pretty(self)
-            .context("failed to serialize config")?;
+        let content = if is_json_path(path) {
+            serde_json::to_string_pretty(self)
+                .context("failed to serialize config")?
+                + "\n"
+        } else {
+            toml::to_string_pretty(self)
+                .context("failed to serialize config")?
+        };
 
         std::fs::write(path, content)
             .with_context(|| format!("failed to write config to {}", path.display()))
@@ -151,6 +949,19 @@ impl Config {
         self.save_to(&path)
     }
 
+    /// Restrict this config to the named view: servers outside it are dropped,
+    /// and the view is recorded so the catalog and sandbox can filter tools.
+    pub fn select_view(&mut self, name: &str) -> Result<()> {
+        let view = self
+            .views
+            .get(name)
+            .cloned()
+            .with_context(|| format!("no view named \"{name}\""))?;
+        self.servers.retain(|server, _| view.touches_server(server));
+        self.sandbox.view = Some(view);
+        Ok(())
+    }
+
     pub fn add_server(&mut self, name: String, config: ServerConfig) {
         self.servers.insert(name, config);
     }
@@ -160,29 +971,376 @@ impl Config {
     }
 }
 
+/// The user config file: `CMCP_CONFIG` if set, otherwise `config.toml` in
+/// the platform config directory.
 pub fn default_config_path() -> Result<PathBuf> {
-    let config_dir = dirs_config_dir().context("could not determine config directory")?;
+    user_config_path(|key| std::env::var_os(key))
+}
+
+fn user_config_path(env: impl Fn(&str) -> Option<OsString>) -> Result<PathBuf> {
+    if let Some(path) = env("CMCP_CONFIG").filter(|p| !p.is_empty()) {
+        return Ok(PathBuf::from(path));
+    }
+    let config_dir = config_dir_from(env).context(
+        "could not determine the user config directory (HOME is not set). \
+         Set CMCP_CONFIG to a config file path, pass --config <path>, or use --scope project",
+    )?;
     Ok(config_dir.join("code-mode-mcp").join("config.toml"))
 }
 
+/// Where OAuth tokens for `server` are stored: always under the platform
+/// config directory, even when `CMCP_CONFIG` points into a project that
+/// might be committed.
+pub fn oauth_token_path(server: &str) -> Result<PathBuf> {
+    oauth_token_path_from(server, |key| std::env::var_os(key))
+}
+
+fn oauth_token_path_from(server: &str, env: impl Fn(&str) -> Option<OsString>) -> Result<PathBuf> {
+    let config_dir = config_dir_from(env)
+        .context("could not determine the user config directory for OAuth tokens (HOME is not set)")?;
+    Ok(config_dir.join("code-mode-mcp").join("oauth").join(format!("{server}.json")))
+}
+
+/// Scopes to request if `auth` selects OAuth (`"oauth"` or `"oauth:scope1 scope2"`).
+pub fn oauth_scopes(auth: &str) -> Option<Vec<String>> {
+    let rest = auth.strip_prefix("oauth")?;
+    if rest.is_empty() {
+        return Some(Vec::new());
+    }
+    let scopes = rest.strip_prefix(':')?;
+    Some(
+        scopes
+            .split([' ', ','])
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+            .collect(),
+    )
+}
+
+/// Set by `--profile`; takes precedence over `CMCP_PROFILE`.
+static PROFILE: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+
+/// Select the profile for the rest of this process.
+pub fn set_profile(name: &str) {
+    let _ = PROFILE.set(name.to_string());
+}
+
+/// The selected profile (`--profile`, else `CMCP_PROFILE`), or `None` for
+/// the top-level servers.
+pub fn active_profile() -> Option<String> {
+    PROFILE
+        .get()
+        .cloned()
+        .or_else(|| std::env::var("CMCP_PROFILE").ok())
+        .filter(|name| !name.is_empty())
+}
+
 /// Project-scoped config: .cmcp.toml in the current directory.
 pub fn project_config_path() -> PathBuf {
     PathBuf::from(".cmcp.toml")
 }
 
-fn dirs_config_dir() -> Option<PathBuf> {
+/// Environment overlay: .cmcp.<env>.toml for `CMCP_ENV=<env>`, if set.
+pub fn env_config_path() -> Option<PathBuf> {
+    std::env::var("CMCP_ENV")
+        .ok()
+        .filter(|env| !env.is_empty())
+        .map(|env| PathBuf::from(format!(".cmcp.{env}.toml")))
+}
+
+fn is_json_path(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+}
+
+fn config_dir_from(env: impl Fn(&str) -> Option<OsString>) -> Option<PathBuf> {
+    let env = |key: &str| env(key).filter(|v| !v.is_empty());
     #[cfg(target_os = "macos")]
     {
-        std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config"))
+        env("HOME").map(|h| PathBuf::from(h).join(".config"))
     }
     #[cfg(target_os = "linux")]
     {
-        std::env::var_os("XDG_CONFIG_HOME")
+        env("XDG_CONFIG_HOME")
             .map(PathBuf::from)
-            .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))
+            .or_else(|| env("HOME").map(|h| PathBuf::from(h).join(".config")))
     }
     #[cfg(target_os = "windows")]
     {
-        std::env::var_os("APPDATA").map(PathBuf::from)
+        env("APPDATA").map(PathBuf::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_transport_hints_on_websocket_url() {
+        let (transport, hint) = detect_transport("ws://localhost:9000/mcp");
+        assert_eq!(transport, "ws");
+        assert!(hint.unwrap().contains("--transport"));
+
+        let (transport, hint) = detect_transport("WSS://example.com/mcp");
+        assert_eq!(transport, "ws");
+        assert!(hint.is_some());
+
+        let (transport, hint) = detect_transport("grpc://example.com");
+        assert_eq!(transport, "stdio");
+        assert!(hint.unwrap().contains("unsupported URL scheme \"grpc://\""));
+
+        let (transport, hint) = detect_transport("example.com/mcp");
+        assert_eq!(transport, "stdio");
+        assert!(hint.is_some());
+    }
+
+    #[test]
+    fn test_explicit_config_loads_without_home() {
+        let no_env = |_: &str| None;
+        let err = user_config_path(no_env).unwrap_err();
+        assert!(err.to_string().contains("CMCP_CONFIG"), "{err}");
+
+        let dir = std::env::temp_dir().join(format!("cmcp-nohome-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let explicit = dir.join("proxy.toml");
+        std::fs::write(&explicit, "[servers.github]\ntransport = \"http\"\nurl = \"https://gh.example\"\n").unwrap();
+
+        let (merged, report) = Config::load_merged_from(user_config_path(no_env), Some(&explicit)).unwrap();
+        assert!(merged.servers.contains_key("github"));
+        assert_eq!(report.origins["github"], ConfigLayer::Explicit);
+
+        // CMCP_CONFIG stands in for the user config when HOME is missing.
+        let from_env = user_config_path(|key| (key == "CMCP_CONFIG").then(|| explicit.clone().into()));
+        assert_eq!(from_env.unwrap(), explicit);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_oauth_tokens_stay_out_of_cmcp_config_dir() {
+        let env = |key: &str| match key {
+            "CMCP_CONFIG" => Some("/work/app/.cmcp/config.toml".into()),
+            "HOME" => Some("/home/me".into()),
+            "XDG_CONFIG_HOME" | "APPDATA" => Some("/home/me/.config".into()),
+            _ => None,
+        };
+        let path = oauth_token_path_from("canva", env).unwrap();
+        assert_eq!(path, Path::new("/home/me/.config/code-mode-mcp/oauth/canva.json"));
+        assert!(oauth_token_path_from("canva", |_| None).is_err());
+    }
+
+    #[test]
+    fn test_oauth_scopes() {
+        assert_eq!(oauth_scopes("oauth"), Some(vec![]));
+        assert_eq!(oauth_scopes("oauth:read write"), Some(vec!["read".to_string(), "write".to_string()]));
+        assert_eq!(oauth_scopes("oauth:design:read,design:write"), Some(vec!["design:read".to_string(), "design:write".to_string()]));
+        assert_eq!(oauth_scopes("oauthtoken123"), None);
+        assert_eq!(oauth_scopes("env:TOKEN"), None);
+    }
+
+    #[test]
+    fn test_detect_transport_picks_stdio_for_commands() {
+        for cmd in ["npx", "httpie-mcp", "http-server", "uvx", "./server.py", "server.js", "python3.12", "/usr/bin/mcp"] {
+            assert_eq!(detect_transport(cmd), ("stdio", None), "{cmd}");
+        }
+        assert_eq!(detect_transport("HTTPS://mcp.example.com"), ("http", None));
+    }
+
+    fn sample_config() -> Config {
+        let mut cfg = Config::default();
+        cfg.add_server("canva".into(), ServerConfig::Http {
+            url: "https://mcp.canva.com/mcp".into(),
+            auth: Some("env:CANVA_TOKEN".into()),
+            headers: HashMap::from([("X-Custom".into(), "value".into())]),
+            options: ServerOptions { call_timeout_ms: Some(20_000), ..Default::default() },
+        });
+        cfg.add_server("github".into(), ServerConfig::Stdio {
+            command: "npx".into(),
+            args: vec!["-y".into(), "@modelcontextprotocol/server-github".into()],
+            env: HashMap::from([("GITHUB_TOKEN".into(), "env:GITHUB_TOKEN".into())]),
+            options: ServerOptions { max_concurrent: Some(2), ..Default::default() },
+        });
+        cfg.add_server("live".into(), ServerConfig::Ws {
+            url: "wss://live.example.com/mcp".into(),
+            auth: None,
+            headers: HashMap::new(),
+            options: ServerOptions::default(),
+        });
+        cfg
+    }
+
+    fn assert_round_trips(file: &str) {
+        let dir = std::env::temp_dir().join(format!("cmcp-format-{}-{file}", std::process::id()));
+        let path = dir.join(file);
+        let cfg = sample_config();
+        cfg.save_to(&path).unwrap();
+        let loaded = Config::load_from(&path).unwrap();
+        assert_eq!(serde_json::to_value(&loaded).unwrap(), serde_json::to_value(&cfg).unwrap());
+        assert!(matches!(&loaded.servers["github"], ServerConfig::Stdio { command, .. } if command == "npx"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_toml_config_round_trips() {
+        assert_round_trips("config.toml");
+    }
+
+    #[test]
+    fn test_json_config_round_trips() {
+        assert_round_trips("config.json");
+
+        let cfg: Config = serde_json::from_str(
+            r#"{"servers": {"api": {"transport": "sse", "url": "https://api.example/sse", "log_level": "warning"}}}"#,
+        )
+        .unwrap();
+        assert!(matches!(&cfg.servers["api"], ServerConfig::Sse { url, .. } if url == "https://api.example/sse"));
+        assert_eq!(cfg.servers["api"].options().log_level.as_deref(), Some("warning"));
+    }
+
+    #[test]
+    fn test_per_server_limits() {
+        let cfg: Config = toml::from_str(
+            "[servers.logs]\ntransport = \"http\"\nurl = \"https://logs.example\"\nmax_length = 200000\ntimeout_ms = 90000\n",
+        )
+        .unwrap();
+        let options = cfg.servers["logs"].options();
+        assert_eq!(options.max_length, Some(200_000));
+        assert_eq!(options.call_timeout_ms, Some(90_000));
+    }
+
+    #[test]
+    fn test_merge_reports_project_shadowing_user() {
+        let dir = std::env::temp_dir().join(format!("cmcp-merge-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let user = dir.join("user.toml");
+        let project = dir.join("project.toml");
+        std::fs::write(&user, "[servers.github]\ntransport = \"http\"\nurl = \"https://user.example\"\n\n[servers.canva]\ntransport = \"http\"\nurl = \"https://canva.example\"\n").unwrap();
+        std::fs::write(&project, "[servers.github]\ntransport = \"http\"\nurl = \"https://project.example\"\n").unwrap();
+
+        let (merged, report) = Config::merge_layers(&[
+            (ConfigLayer::User, user),
+            (ConfigLayer::Project, project),
+        ], None)
+        .unwrap();
+
+        assert!(matches!(&merged.servers["github"], ServerConfig::Http { url, .. } if url == "https://project.example"));
+        assert_eq!(report.origins["github"], ConfigLayer::Project);
+        assert_eq!(report.origins["canva"], ConfigLayer::User);
+        assert_eq!(
+            report.shadowed,
+            vec![Shadowed {
+                server: "github".to_string(),
+                winner: ConfigLayer::Project,
+                shadowed: ConfigLayer::User,
+            }]
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_project_config_cannot_change_templates() {
+        let dir = std::env::temp_dir().join(format!("cmcp-templates-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let user = dir.join("user.toml");
+        let project = dir.join("project.toml");
+        let explicit = dir.join("explicit.toml");
+        std::fs::write(&user, "[sandbox]\ntemplates_only = true\n\n[sandbox.templates]\nlist = \"return 1;\"\n").unwrap();
+        std::fs::write(&project, "[sandbox]\ntemplates_only = false\n\n[sandbox.templates]\nlist = \"return 2;\"\nextra = \"return 3;\"\n").unwrap();
+        std::fs::write(&explicit, "[sandbox.templates]\nextra = \"return 4;\"\n").unwrap();
+
+        let (merged, _) = Config::merge_layers(&[
+            (ConfigLayer::User, user.clone()),
+            (ConfigLayer::Project, project.clone()),
+        ], None)
+        .unwrap();
+        assert_eq!(merged.sandbox.templates_only, Some(true));
+        assert_eq!(merged.sandbox.templates, HashMap::from([("list".to_string(), "return 1;".to_string())]));
+
+        let (merged, _) = Config::merge_layers(&[
+            (ConfigLayer::User, user),
+            (ConfigLayer::Project, project),
+            (ConfigLayer::Explicit, explicit),
+        ], None)
+        .unwrap();
+        assert_eq!(merged.sandbox.templates["extra"], "return 4;");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_profile_replaces_top_level_servers() {
+        let dir = std::env::temp_dir().join(format!("cmcp-profile-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let user = dir.join("user.toml");
+        let project = dir.join("project.toml");
+        std::fs::write(&user, "[servers.blog]\ntransport = \"http\"\nurl = \"https://blog.example\"\n\n[profiles.work.servers.jira]\ntransport = \"http\"\nurl = \"https://jira.example\"\n\n[profiles.work.servers.github]\ntransport = \"http\"\nurl = \"https://github.com\"\n").unwrap();
+        std::fs::write(&project, "[profiles.work.servers.github]\ntransport = \"http\"\nurl = \"https://ghe.example\"\n").unwrap();
+        let layers = [(ConfigLayer::User, user), (ConfigLayer::Project, project)];
+
+        let (default, _) = Config::merge_layers(&layers, None).unwrap();
+        assert_eq!(default.servers.keys().collect::<Vec<_>>(), ["blog"]);
+
+        let (work, report) = Config::merge_layers(&layers, Some("work")).unwrap();
+        let mut names: Vec<_> = work.servers.keys().cloned().collect();
+        names.sort();
+        assert_eq!(names, ["github", "jira"]);
+        assert!(matches!(&work.servers["github"], ServerConfig::Http { url, .. } if url == "https://ghe.example"));
+        assert_eq!(report.origins["github"], ConfigLayer::Project);
+
+        let err = Config::merge_layers(&layers, Some("home")).unwrap_err();
+        assert!(err.to_string().contains("profile \"home\" not found"), "{err}");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_env_overlay_wins_over_project() {
+        let dir = std::env::temp_dir().join(format!("cmcp-env-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let base = dir.join(".cmcp.toml");
+        let dev = dir.join(".cmcp.dev.toml");
+        std::fs::write(&base, "[sandbox]\ntimeout_secs = 60\n\n[servers.api]\ntransport = \"http\"\nurl = \"https://api.example\"\n\n[servers.docs]\ntransport = \"http\"\nurl = \"https://docs.example\"\n").unwrap();
+        std::fs::write(&dev, "[sandbox]\ntimeout_secs = 5\n\n[servers.api]\ntransport = \"http\"\nurl = \"http://localhost:3000\"\n").unwrap();
+
+        let (merged, report) = Config::merge_layers(&[
+            (ConfigLayer::Project, base),
+            (ConfigLayer::Environment, dev),
+        ], None)
+        .unwrap();
+
+        assert!(matches!(&merged.servers["api"], ServerConfig::Http { url, .. } if url == "http://localhost:3000"));
+        assert!(matches!(&merged.servers["docs"], ServerConfig::Http { url, .. } if url == "https://docs.example"));
+        assert_eq!(merged.sandbox.timeout_secs, Some(5.0));
+        assert_eq!(report.origins["api"], ConfigLayer::Environment);
+        assert_eq!(report.origins["docs"], ConfigLayer::Project);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_defaults_fill_unset_server_options() {
+        let dir = std::env::temp_dir().join(format!("cmcp-defaults-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let user = dir.join("user.toml");
+        std::fs::write(&user, "[defaults]\nconnect_timeout_ms = 5000\n\n[servers.slow]\ntransport = \"http\"\nurl = \"https://slow.example\"\nconnect_timeout_ms = 60000\n\n[servers.fast]\ntransport = \"http\"\nurl = \"https://fast.example\"\n").unwrap();
+
+        let (merged, _) = Config::merge_layers(&[(ConfigLayer::User, user)], None).unwrap();
+
+        assert_eq!(merged.servers["slow"].options().connect_timeout_ms, Some(60_000));
+        assert_eq!(merged.servers["fast"].options().connect_timeout_ms, Some(5_000));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_truncation_notice_names_and_templates() {
+        let parse = |notice: &str| toml::from_str::<SandboxConfig>(&format!("truncation_notice = {notice:?}"));
+        assert_eq!(parse("marker").unwrap().truncation_notice, Some(TruncationNotice::Marker));
+        assert_eq!(
+            parse("<cut {omitted}>").unwrap().truncation_notice,
+            Some(TruncationNotice::Template("<cut {omitted}>".to_string()))
+        );
+        let err = parse("markers").unwrap_err().to_string();
+        assert!(err.contains("unknown truncation_notice \"markers\""), "{err}");
     }
 }
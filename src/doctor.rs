@@ -0,0 +1,216 @@
+//! `cmcp doctor`: check the environment cmcp runs in and say how to fix
+//! what's wrong.
+
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use cmcp_core::config::{self, ServerConfig};
+use cmcp_core::secret::resolve_secret;
+
+/// Name cmcp registers itself under in Claude and Codex.
+const REGISTERED_NAME: &str = "code-mode-mcp";
+
+struct Check {
+    label: String,
+    /// `None` when the check passed, otherwise how to fix it.
+    problem: Option<String>,
+    /// Whether a failure makes `cmcp doctor` exit non-zero.
+    critical: bool,
+}
+
+impl Check {
+    fn pass(label: impl Into<String>) -> Self {
+        Self { label: label.into(), problem: None, critical: true }
+    }
+
+    fn fail(label: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self { label: label.into(), problem: Some(hint.into()), critical: true }
+    }
+
+    fn optional(mut self) -> Self {
+        self.critical = false;
+        self
+    }
+}
+
+/// Run every check, print the checklist and fail if a critical check failed.
+pub fn run(config_path: Option<&PathBuf>) -> Result<()> {
+    let mut checks = config_checks(config_path);
+    match config::Config::load_merged(config_path) {
+        Ok(cfg) => {
+            checks.push(Check::pass("Config loads"));
+            let mut servers: Vec<_> = cfg.servers.iter().filter(|(_, c)| c.is_enabled()).collect();
+            servers.sort_by(|a, b| a.0.cmp(b.0));
+            for (name, server) in servers {
+                checks.extend(server_checks(name, server));
+            }
+        }
+        Err(e) => checks.push(Check::fail("Config does not load", format!("{e:#}"))),
+    }
+    checks.push(claude_check());
+    checks.push(codex_check());
+
+    for check in &checks {
+        match &check.problem {
+            None => println!("✓ {}", check.label),
+            Some(hint) => println!("✗ {}\n    {hint}", check.label),
+        }
+    }
+    let failed = checks.iter().filter(|c| c.critical && c.problem.is_some()).count();
+    if failed > 0 {
+        anyhow::bail!("{failed} critical check(s) failed");
+    }
+    Ok(())
+}
+
+/// The config path resolves and cmcp can write to it.
+fn config_checks(config_path: Option<&PathBuf>) -> Vec<Check> {
+    let path = match config_path.cloned().map_or_else(config::default_config_path, Ok) {
+        Ok(path) => path,
+        Err(e) => return vec![Check::fail("Config path not found", format!("{e:#}"))],
+    };
+    let check = match writable(&path) {
+        Ok(()) => Check::pass(format!("Config path {} is writable", path.display())),
+        Err(e) => Check::fail(
+            format!("Config path {} is not writable", path.display()),
+            format!("{e}. Fix its permissions or pass --config <path>"),
+        ),
+    };
+    vec![check]
+}
+
+/// Whether `path` can be written, or created in its nearest existing directory.
+fn writable(path: &Path) -> std::io::Result<()> {
+    if path.exists() {
+        return std::fs::OpenOptions::new().append(true).open(path).map(drop);
+    }
+    let dir = path
+        .ancestors()
+        .skip(1)
+        .find(|dir| dir.is_dir())
+        .unwrap_or(Path::new("."));
+    let probe = dir.join(format!(".cmcp-doctor-{}", std::process::id()));
+    std::fs::File::create(&probe)?;
+    std::fs::remove_file(&probe)
+}
+
+/// The server's command is on PATH and all its secret references resolve.
+fn server_checks(name: &str, server: &ServerConfig) -> Vec<Check> {
+    let mut checks = Vec::new();
+    let mut references: Vec<(String, &String)> = Vec::new();
+    match server {
+        ServerConfig::Http { url, auth, headers, .. }
+        | ServerConfig::Sse { url, auth, headers, .. }
+        | ServerConfig::Ws { url, auth, headers, .. } => {
+            references.push(("url".to_string(), url));
+            if let Some(auth) = auth.as_ref().filter(|a| config::oauth_scopes(a).is_none()) {
+                references.push(("auth".to_string(), auth));
+            }
+            references.extend(headers.iter().map(|(k, v)| (format!("header {k}"), v)));
+        }
+        ServerConfig::Stdio { command, args, env, .. } => {
+            checks.push(match find_command(command, std::env::var_os("PATH").as_deref()) {
+                Some(_) => Check::pass(format!("{name}: `{command}` found")),
+                None => Check::fail(
+                    format!("{name}: `{command}` not found"),
+                    "Install it or set `command` to its full path",
+                ),
+            });
+            references.extend(args.iter().map(|a| ("args".to_string(), a)));
+            references.extend(env.iter().map(|(k, v)| (format!("env {k}"), v)));
+        }
+    }
+    references.sort();
+
+    let failures: Vec<String> = references
+        .iter()
+        .filter_map(|(field, value)| resolve_secret(value, true, &Default::default()).err().map(|e| format!("{field}: {e:#}")))
+        .collect();
+    checks.push(if failures.is_empty() {
+        Check::pass(format!("{name}: secrets and variables resolve"))
+    } else {
+        Check::fail(format!("{name}: unresolved secrets or variables"), failures.join("\n    "))
+    });
+    checks
+}
+
+/// Where `command` would be found, searching `path` unless it names a file.
+fn find_command(command: &str, path: Option<&OsStr>) -> Option<PathBuf> {
+    let candidate = Path::new(command);
+    if candidate.components().count() > 1 {
+        return candidate.is_file().then(|| candidate.to_path_buf());
+    }
+    let extensions: &[&str] = if cfg!(windows) { &["", ".exe", ".cmd", ".bat"] } else { &[""] };
+    std::env::split_paths(path?).find_map(|dir| {
+        extensions
+            .iter()
+            .map(|ext| dir.join(format!("{command}{ext}")))
+            .find(|file| file.is_file())
+    })
+}
+
+fn claude_check() -> Check {
+    if find_command("claude", std::env::var_os("PATH").as_deref()).is_none() {
+        return Check::fail("Claude CLI not found", "Skip this if you don't use Claude").optional();
+    }
+    let registered = std::process::Command::new("claude")
+        .args(["mcp", "get", REGISTERED_NAME])
+        .env_remove("CLAUDECODE")
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .is_ok_and(|s| s.success());
+    if registered {
+        Check::pass("Registered with Claude")
+    } else {
+        Check::fail("Not registered with Claude", "Run: cmcp install --target claude").optional()
+    }
+}
+
+fn codex_check() -> Check {
+    let Some(home) = std::env::var_os("HOME") else {
+        return Check::fail("Codex config not found", "HOME is not set").optional();
+    };
+    let codex_config = PathBuf::from(home).join(".codex").join("config.toml");
+    let registered = std::fs::read_to_string(&codex_config)
+        .is_ok_and(|content| content.contains(&format!("[mcp_servers.{REGISTERED_NAME}]")));
+    if registered {
+        Check::pass("Registered with Codex")
+    } else {
+        Check::fail("Not registered with Codex", "Run: cmcp install --target codex").optional()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_command_searches_path() {
+        let dir = std::env::temp_dir().join(format!("cmcp-doctor-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let tool = dir.join("mcp-tool");
+        std::fs::write(&tool, "").unwrap();
+
+        let path = std::env::join_paths([Path::new("/nonexistent"), dir.as_path()]).unwrap();
+        assert_eq!(find_command("mcp-tool", Some(&path)), Some(tool.clone()));
+        assert_eq!(find_command("missing-tool", Some(&path)), None);
+        assert_eq!(find_command(tool.to_str().unwrap(), None), Some(tool));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_server_checks_report_unresolved_references() {
+        let server = ServerConfig::Stdio {
+            command: "definitely-not-installed-cmcp".to_string(),
+            args: vec!["--token=${CMCP_DOCTOR_TEST_UNSET}".to_string()],
+            env: Default::default(),
+            options: Default::default(),
+        };
+        let checks = server_checks("broken", &server);
+        let problems: Vec<_> = checks.iter().filter_map(|c| c.problem.as_deref()).collect();
+        assert_eq!(problems.len(), 2, "{problems:?}");
+        assert!(problems[1].contains("CMCP_DOCTOR_TEST_UNSET is not set"), "{problems:?}");
+    }
+}
@@ -1,9 +1,10 @@
 use std::collections::HashMap;
+use std::io::{BufRead, Write};
 use std::path::PathBuf;
 
 use anyhow::{Context, Result};
 
-use cmcp_core::config::ServerConfig;
+use cmcp_core::config::{ServerConfig, is_websocket_url};
 
 /// A discovered MCP server from an external source.
 #[derive(Debug)]
@@ -11,6 +12,8 @@ pub struct ImportedServer {
     pub name: String,
     pub config: ServerConfig,
     pub source: ImportSource,
+    /// Project the server was defined under (Claude's per-project `projects.*.mcpServers`).
+    pub project: Option<String>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -28,6 +31,29 @@ impl std::fmt::Display for ImportSource {
     }
 }
 
+impl ImportedServer {
+    /// One-line transport summary, e.g. `http  https://mcp.canva.com/mcp`.
+    pub fn transport_summary(&self) -> String {
+        transport_summary(&self.config)
+    }
+
+    /// Transport summary plus the project the server came from, if any.
+    pub fn display_summary(&self) -> String {
+        match &self.project {
+            Some(project) => format!("{} (project {project})", self.transport_summary()),
+            None => self.transport_summary(),
+        }
+    }
+}
+
+/// What to do with one discovered server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportAction {
+    Add,
+    Update,
+    Skip,
+}
+
 /// Scan all known config locations and return discovered servers.
 pub fn discover(source_filter: Option<ImportSource>) -> Result<Vec<ImportedServer>> {
     let mut servers = Vec::new();
@@ -47,12 +73,13 @@ pub fn discover(source_filter: Option<ImportSource>) -> Result<Vec<ImportedServe
 
 fn discover_claude_code() -> Result<Vec<ImportedServer>> {
     let mut servers = Vec::new();
-    let home = home_dir()?;
 
     // User-scoped: ~/.claude.json
-    let user_config = home.join(".claude.json");
-    if user_config.exists() {
-        servers.extend(parse_claude_code_json(&user_config)?);
+    if let Some(home) = home_dir() {
+        let user_config = home.join(".claude.json");
+        if user_config.exists() {
+            servers.extend(parse_claude_code_json(&user_config)?);
+        }
     }
 
     // Project-scoped: .mcp.json (current directory)
@@ -71,23 +98,44 @@ fn parse_claude_code_json(path: &PathBuf) -> Result<Vec<ImportedServer>> {
     let root: serde_json::Value = serde_json::from_str(&content)
         .with_context(|| format!("failed to parse {}", path.display()))?;
 
-    let Some(mcp_servers) = root.get("mcpServers").and_then(|v| v.as_object()) else {
-        return Ok(Vec::new());
-    };
+    Ok(parse_claude_code_root(&root))
+}
 
-    let mut servers = Vec::new();
+/// Collect servers from top-level `mcpServers` and from every `projects.<path>.mcpServers`.
+/// Names are deduped: top-level definitions win, then the first project that defines a name.
+fn parse_claude_code_root(root: &serde_json::Value) -> Vec<ImportedServer> {
+    let mut servers: Vec<ImportedServer> = Vec::new();
+
+    let projects = root
+        .get("projects")
+        .and_then(|v| v.as_object())
+        .into_iter()
+        .flatten()
+        .map(|(path, project)| (Some(path.as_str()), project));
+
+    for (project, scope) in std::iter::once((None, root)).chain(projects) {
+        let Some(mcp_servers) = scope.get("mcpServers").and_then(|v| v.as_object()) else {
+            continue;
+        };
 
-    for (name, value) in mcp_servers {
-        match parse_claude_code_server(name, value) {
-            Ok(Some(server)) => servers.push(server),
-            Ok(None) => {} // unsupported transport, skip
-            Err(e) => {
-                eprintln!("  warning: skipping {name}: {e}");
+        for (name, value) in mcp_servers {
+            if servers.iter().any(|s| &s.name == name) {
+                continue;
+            }
+            match parse_claude_code_server(name, value) {
+                Ok(Some(mut server)) => {
+                    server.project = project.map(String::from);
+                    servers.push(server);
+                }
+                Ok(None) => {} // unsupported transport, skip
+                Err(e) => {
+                    eprintln!("  warning: skipping {name}: {e}");
+                }
             }
         }
     }
 
-    Ok(servers)
+    servers
 }
 
 fn parse_claude_code_server(
@@ -121,7 +169,7 @@ fn parse_claude_code_server(
 
             let env = parse_json_string_map(obj.get("env"));
 
-            ServerConfig::Stdio { command, args, env }
+            ServerConfig::Stdio { command, args, env, options: Default::default() }
         }
         "http" => {
             let url = obj
@@ -135,7 +183,7 @@ fn parse_claude_code_server(
             // Extract auth from Authorization header if present.
             let (auth, headers) = extract_auth_header(headers);
 
-            ServerConfig::Http { url, auth, headers }
+            ServerConfig::Http { url, auth, headers, options: Default::default() }
         }
         "sse" => {
             let url = obj
@@ -147,9 +195,21 @@ fn parse_claude_code_server(
             let headers = parse_json_string_map(obj.get("headers"));
             let (auth, headers) = extract_auth_header(headers);
 
-            ServerConfig::Sse { url, auth, headers }
+            ServerConfig::Sse { url, auth, headers, options: Default::default() }
+        }
+        "ws" => {
+            let url = obj
+                .get("url")
+                .and_then(|v| v.as_str())
+                .context("missing url")?
+                .to_string();
+
+            let headers = parse_json_string_map(obj.get("headers"));
+            let (auth, headers) = extract_auth_header(headers);
+
+            ServerConfig::Ws { url, auth, headers, options: Default::default() }
         }
-        // Skip internal types: ws, sse-ide, ws-ide, sdk, claudeai-proxy
+        // Skip internal types: sse-ide, ws-ide, sdk, claudeai-proxy
         _ => return Ok(None),
     };
 
@@ -157,6 +217,7 @@ fn parse_claude_code_server(
         name: name.to_string(),
         config,
         source: ImportSource::ClaudeCode,
+        project: None,
     }))
 }
 
@@ -164,12 +225,13 @@ fn parse_claude_code_server(
 
 fn discover_codex() -> Result<Vec<ImportedServer>> {
     let mut servers = Vec::new();
-    let home = home_dir()?;
 
     // User-scoped: ~/.codex/config.toml
-    let user_config = home.join(".codex").join("config.toml");
-    if user_config.exists() {
-        servers.extend(parse_codex_toml(&user_config)?);
+    if let Some(home) = home_dir() {
+        let user_config = home.join(".codex").join("config.toml");
+        if user_config.exists() {
+            servers.extend(parse_codex_toml(&user_config)?);
+        }
     }
 
     // Project-scoped: .codex/config.toml
@@ -222,7 +284,7 @@ fn parse_codex_server(name: &str, value: &toml::Value) -> Result<Option<Imported
     let has_command = table.get("command").is_some();
 
     let config = if has_url {
-        // Streamable HTTP
+        // Streamable HTTP, or WebSocket for ws:// URLs
         let url = table
             .get("url")
             .and_then(|v| v.as_str())
@@ -252,24 +314,27 @@ fn parse_codex_server(name: &str, value: &toml::Value) -> Result<Option<Imported
             }
         }
 
-        ServerConfig::Http { url, auth, headers }
+        if is_websocket_url(&url) {
+            ServerConfig::Ws { url, auth, headers, options: Default::default() }
+        } else {
+            ServerConfig::Http { url, auth, headers, options: Default::default() }
+        }
     } else if has_command {
-        // Stdio
-        let command = table
-            .get("command")
-            .and_then(|v| v.as_str())
-            .context("missing command")?
-            .to_string();
-
-        let args = table
-            .get("args")
-            .and_then(|v| v.as_array())
-            .map(|a| {
-                a.iter()
-                    .filter_map(|v| v.as_str().map(String::from))
-                    .collect()
-            })
-            .unwrap_or_default();
+        // Stdio. `command` is a string, or an array of command + leading args.
+        let strings = |v: &toml::Value| -> Option<Vec<String>> {
+            v.as_array()
+                .map(|a| a.iter().filter_map(|v| v.as_str().map(String::from)).collect())
+        };
+        let command_value = &table["command"];
+        let (command, mut args) = match command_value.as_str() {
+            Some(command) => (command.to_string(), Vec::new()),
+            None => {
+                let mut parts = strings(command_value).unwrap_or_default().into_iter();
+                let command = parts.next().context("missing command")?;
+                (command, parts.collect())
+            }
+        };
+        args.extend(table.get("args").and_then(strings).unwrap_or_default());
 
         let mut env = parse_toml_string_map(table.get("env"));
 
@@ -282,7 +347,7 @@ fn parse_codex_server(name: &str, value: &toml::Value) -> Result<Option<Imported
             }
         }
 
-        ServerConfig::Stdio { command, args, env }
+        ServerConfig::Stdio { command, args, env, options: Default::default() }
     } else {
         anyhow::bail!("server has neither 'url' nor 'command'");
     };
@@ -291,11 +356,220 @@ fn parse_codex_server(name: &str, value: &toml::Value) -> Result<Option<Imported
         name: name.to_string(),
         config,
         source: ImportSource::Codex,
+        project: None,
     }))
 }
 
+// ── Dry-run output ───────────────────────────────────────────────────
+
+/// Machine-readable formats for `cmcp import --dry-run --format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlanFormat {
+    Toml,
+    Json,
+}
+
+impl PlanFormat {
+    pub fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "toml" => Ok(Self::Toml),
+            "json" => Ok(Self::Json),
+            other => anyhow::bail!("unknown format \"{other}\". Use: toml or json"),
+        }
+    }
+}
+
+/// Render the server configs an import would write, as a config file's `servers` table.
+pub fn render_plan<'a>(
+    servers: impl IntoIterator<Item = &'a ImportedServer>,
+    format: PlanFormat,
+) -> Result<String> {
+    #[derive(serde::Serialize)]
+    struct Plan<'a> {
+        servers: std::collections::BTreeMap<&'a str, &'a ServerConfig>,
+    }
+
+    let plan = Plan {
+        servers: servers
+            .into_iter()
+            .map(|s| (s.name.as_str(), &s.config))
+            .collect(),
+    };
+    match format {
+        PlanFormat::Toml => toml::to_string_pretty(&plan).context("failed to render TOML"),
+        PlanFormat::Json => serde_json::to_string_pretty(&plan).context("failed to render JSON"),
+    }
+}
+
+// ── Diff ─────────────────────────────────────────────────────────────
+
+/// A discovered server and the fields where it differs from cmcp's config.
+pub type SourceDiff<'a> = (&'a ImportedServer, Vec<String>);
+
+/// How the cmcp config compares to the servers discovered elsewhere.
+///
+/// Each server name lands in exactly one part, so the counts add up to the
+/// number of distinct names even when a name is found in several sources.
+#[derive(Debug, Default)]
+pub struct ConfigDiff<'a> {
+    /// Configured in cmcp but not found in any source.
+    pub only_cmcp: Vec<(&'a str, &'a ServerConfig)>,
+    /// Found in one or more sources but not configured in cmcp, with every
+    /// source it was found in.
+    pub only_source: Vec<(&'a str, Vec<&'a ImportedServer>)>,
+    /// Configured in cmcp and different in at least one source: the cmcp
+    /// config, then each source's server with the fields that differ
+    /// (none when that source matches).
+    pub changed: Vec<(&'a str, &'a ServerConfig, Vec<SourceDiff<'a>>)>,
+    /// Names configured in cmcp that match in every source they were found in.
+    pub same: usize,
+}
+
+/// Compare cmcp's `servers` with `discovered`, each part sorted by name.
+pub fn diff<'a>(servers: &'a HashMap<String, ServerConfig>, discovered: &'a [ImportedServer]) -> ConfigDiff<'a> {
+    let mut by_name: std::collections::BTreeMap<&str, Vec<&ImportedServer>> = std::collections::BTreeMap::new();
+    for server in discovered {
+        by_name.entry(&server.name).or_default().push(server);
+    }
+
+    let mut diff = ConfigDiff::default();
+    for (name, found) in by_name {
+        let Some(config) = servers.get(name) else {
+            diff.only_source.push((name, found));
+            continue;
+        };
+        let sources: Vec<_> = found
+            .into_iter()
+            .map(|server| (server, config.differing_fields(&server.config)))
+            .collect();
+        if sources.iter().all(|(server, _)| config.same_as(&server.config)) {
+            diff.same += 1;
+        } else {
+            diff.changed.push((name, config, sources));
+        }
+    }
+    diff.only_cmcp = servers
+        .iter()
+        .filter(|(name, _)| !discovered.iter().any(|s| &s.name == *name))
+        .map(|(name, config)| (name.as_str(), config))
+        .collect();
+    diff.only_cmcp.sort_by_key(|(name, _)| *name);
+    diff
+}
+
+/// Render a diff for `cmcp diff`: `-` only in cmcp, `+` only in a source,
+/// `~` in both with different configs. Only field names are shown, so env
+/// values and tokens don't end up on the terminal.
+pub fn render_diff(diff: &ConfigDiff) -> String {
+    let mut out = String::new();
+    if !diff.only_cmcp.is_empty() {
+        out.push_str("Only in cmcp:\n");
+        for (name, config) in &diff.only_cmcp {
+            out.push_str(&format!("  - {name:<20} {}\n", transport_summary(config)));
+        }
+    }
+    if !diff.only_source.is_empty() {
+        out.push_str("Only in source:\n");
+        for (name, found) in &diff.only_source {
+            for (i, server) in found.iter().enumerate() {
+                let name = if i == 0 { name } else { "" };
+                out.push_str(&format!("  + {name:<20} {:<8} {}\n", server.source.to_string(), server.display_summary()));
+            }
+        }
+    }
+    if !diff.changed.is_empty() {
+        out.push_str("Different:\n");
+        for (name, config, sources) in &diff.changed {
+            for (i, (server, fields)) in sources.iter().enumerate() {
+                let name = if i == 0 { name } else { "" };
+                let status = if config.same_as(&server.config) {
+                    "matches".to_string()
+                } else {
+                    format!("differs in {}", fields.join(", "))
+                };
+                out.push_str(&format!("  ~ {name:<20} {:<8} {status}\n", server.source.to_string()));
+            }
+            out.push_str(&format!("      {:<8} {}\n", "cmcp:", transport_summary(config)));
+            for (server, _) in sources.iter().filter(|(server, _)| !config.same_as(&server.config)) {
+                out.push_str(&format!("      {:<8} {}\n", format!("{}:", server.source), server.display_summary()));
+            }
+        }
+    }
+    out.push_str(&format!(
+        "{} only in cmcp, {} only in source, {} different, {} identical",
+        diff.only_cmcp.len(),
+        diff.only_source.len(),
+        diff.changed.len(),
+        diff.same
+    ));
+    out
+}
+
+// ── Interactive ──────────────────────────────────────────────────────
+
+/// Ask what to do with each discovered server.
+///
+/// New servers default to add and existing ones to skip when the answer is
+/// empty. End of input skips everything not yet answered.
+pub fn prompt_actions(
+    servers: &[ImportedServer],
+    exists: impl Fn(&str) -> bool,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> Result<Vec<ImportAction>> {
+    let mut actions = Vec::with_capacity(servers.len());
+
+    for server in servers {
+        let exists = exists(&server.name);
+        let (choices, default) = if exists {
+            ("already exists — [u]pdate / [S]kip", ImportAction::Skip)
+        } else {
+            ("[A]dd / [s]kip", ImportAction::Add)
+        };
+
+        let action = loop {
+            write!(
+                output,
+                "{} ({}) {}\n  {choices}? ",
+                server.name,
+                server.source,
+                server.display_summary()
+            )?;
+            output.flush()?;
+
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                writeln!(output)?;
+                break ImportAction::Skip;
+            }
+            match (line.trim().to_lowercase().as_str(), exists) {
+                ("", _) => break default,
+                ("s" | "skip", _) => break ImportAction::Skip,
+                ("a" | "add", false) => break ImportAction::Add,
+                ("u" | "update", true) => break ImportAction::Update,
+                (other, _) => writeln!(output, "  unrecognized answer \"{other}\"")?,
+            }
+        };
+        actions.push(action);
+    }
+
+    Ok(actions)
+}
+
 // ── Helpers ──────────────────────────────────────────────────────────
 
+/// One-line transport summary, e.g. `http  https://mcp.canva.com/mcp`.
+fn transport_summary(config: &ServerConfig) -> String {
+    match config {
+        ServerConfig::Http { url, .. } => format!("http  {url}"),
+        ServerConfig::Sse { url, .. } => format!("sse   {url}"),
+        ServerConfig::Ws { url, .. } => format!("ws    {url}"),
+        ServerConfig::Stdio { command, args, .. } => {
+            format!("stdio {} {}", command, args.join(" "))
+        }
+    }
+}
+
 fn parse_json_string_map(value: Option<&serde_json::Value>) -> HashMap<String, String> {
     let mut map = HashMap::new();
     if let Some(obj) = value.and_then(|v| v.as_object()) {
@@ -340,8 +614,229 @@ fn extract_auth_header(mut headers: HashMap<String, String>) -> (Option<String>,
     (auth, headers)
 }
 
-fn home_dir() -> Result<PathBuf> {
-    std::env::var_os("HOME")
-        .map(PathBuf::from)
-        .context("HOME not set")
+/// The home directory, if any. Without one only project-scoped configs are
+/// scanned.
+fn home_dir() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME").filter(|h| !h.is_empty()).map(PathBuf::from);
+    if home.is_none() {
+        tracing::warn!("HOME is not set; skipping user-scoped Claude and Codex configs");
+    }
+    home
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stdio_server(name: &str) -> ImportedServer {
+        ImportedServer {
+            name: name.to_string(),
+            config: ServerConfig::Stdio {
+                command: "npx".to_string(),
+                args: vec![name.to_string()],
+                env: HashMap::new(),
+                options: Default::default(),
+            },
+            source: ImportSource::ClaudeCode,
+            project: None,
+        }
+    }
+
+    #[test]
+    fn test_claude_project_servers_discovered() {
+        let root = serde_json::json!({
+            "mcpServers": {
+                "github": { "command": "npx", "args": ["server-github"] },
+            },
+            "projects": {
+                "/home/me/app": {
+                    "mcpServers": {
+                        "github": { "command": "shadowed" },
+                        "postgres": { "type": "http", "url": "http://localhost:5432/mcp" },
+                    },
+                },
+                "/home/me/site": {
+                    "mcpServers": { "postgres": { "command": "shadowed" } },
+                },
+                "/home/me/empty": {},
+            },
+        });
+
+        let servers = parse_claude_code_root(&root);
+        let found: Vec<_> = servers
+            .iter()
+            .map(|s| (s.name.as_str(), s.project.as_deref()))
+            .collect();
+        assert_eq!(found, [("github", None), ("postgres", Some("/home/me/app"))]);
+        assert!(matches!(&servers[0].config, ServerConfig::Stdio { command, .. } if command == "npx"));
+    }
+
+    #[test]
+    fn test_websocket_servers_imported() {
+        let root = serde_json::json!({
+            "mcpServers": {
+                "live": {
+                    "type": "ws",
+                    "url": "wss://live.example.com/mcp",
+                    "headers": { "Authorization": "Bearer abc" },
+                },
+            },
+        });
+        let servers = parse_claude_code_root(&root);
+        assert!(matches!(
+            &servers[0].config,
+            ServerConfig::Ws { auth: Some(token), .. } if token == "abc"
+        ));
+        assert_eq!(servers[0].transport_summary(), "ws    wss://live.example.com/mcp");
+
+        let codex: toml::Value = toml::from_str(r#"url = "ws://localhost:9000""#).unwrap();
+        let server = parse_codex_server("local", &codex).unwrap().unwrap();
+        assert!(matches!(server.config, ServerConfig::Ws { .. }));
+    }
+
+    #[test]
+    fn test_codex_command_array() {
+        let codex: toml::Value = toml::from_str(r#"command = ["npx", "-y", "@modelcontextprotocol/server-github"]"#).unwrap();
+        let server = parse_codex_server("github", &codex).unwrap().unwrap();
+        assert!(matches!(
+            &server.config,
+            ServerConfig::Stdio { command, args, .. }
+                if command == "npx" && args == &["-y", "@modelcontextprotocol/server-github"]
+        ));
+
+        // Separate args follow the ones from the array.
+        let codex: toml::Value = toml::from_str("command = [\"uvx\", \"mcp-server-git\"]\nargs = [\"--repo\", \".\"]").unwrap();
+        let server = parse_codex_server("git", &codex).unwrap().unwrap();
+        assert!(matches!(
+            &server.config,
+            ServerConfig::Stdio { command, args, .. } if command == "uvx" && args == &["mcp-server-git", "--repo", "."]
+        ));
+
+        let codex: toml::Value = toml::from_str("command = []").unwrap();
+        assert!(parse_codex_server("empty", &codex).is_err());
+    }
+
+    #[test]
+    fn test_dry_run_json_plan() {
+        let servers = [
+            stdio_server("github"),
+            ImportedServer {
+                name: "canva".to_string(),
+                config: ServerConfig::Http {
+                    url: "https://mcp.canva.com/mcp".to_string(),
+                    auth: Some("env:CANVA_TOKEN".to_string()),
+                    headers: HashMap::new(),
+                    options: Default::default(),
+                },
+                source: ImportSource::Codex,
+                project: None,
+            },
+        ];
+
+        let json: serde_json::Value =
+            serde_json::from_str(&render_plan(&servers, PlanFormat::Json).unwrap()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "servers": {
+                    "canva": { "transport": "http", "url": "https://mcp.canva.com/mcp", "auth": "env:CANVA_TOKEN" },
+                    "github": { "transport": "stdio", "command": "npx", "args": ["github"] },
+                }
+            })
+        );
+
+        let toml = render_plan(&servers, PlanFormat::Toml).unwrap();
+        assert!(toml.contains("[servers.github]"), "toml: {toml}");
+    }
+
+    #[test]
+    fn test_diff_against_discovered_servers() {
+        let mut servers: HashMap<String, ServerConfig> = ["github", "linear", "local"]
+            .into_iter()
+            .map(|name| (name.to_string(), stdio_server(name).config))
+            .collect();
+        if let Some(ServerConfig::Stdio { env, .. }) = servers.get_mut("linear") {
+            env.insert("LINEAR_TOKEN".to_string(), "secret-value".to_string());
+        }
+        let mut codex_github = stdio_server("github");
+        codex_github.source = ImportSource::Codex;
+        let discovered = [stdio_server("linear"), stdio_server("canva"), codex_github];
+
+        let diff = diff(&servers, &discovered);
+        assert_eq!(diff.only_cmcp.iter().map(|(name, _)| *name).collect::<Vec<_>>(), ["local"]);
+        assert_eq!(diff.only_source.iter().map(|(name, _)| *name).collect::<Vec<_>>(), ["canva"]);
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].0, "linear");
+        assert_eq!(diff.changed[0].2[0].1, ["env"]);
+        assert_eq!(diff.same, 1);
+
+        let text = render_diff(&diff);
+        assert!(text.contains("  - local                stdio npx local"), "text: {text}");
+        assert!(text.contains("  + canva                claude   stdio npx canva"), "text: {text}");
+        assert!(text.contains("  ~ linear               claude   differs in env"), "text: {text}");
+        assert!(text.ends_with("1 only in cmcp, 1 only in source, 1 different, 1 identical"), "text: {text}");
+        assert!(!text.contains("secret-value"), "text: {text}");
+    }
+
+    #[test]
+    fn test_diff_groups_a_name_found_in_several_sources() {
+        let servers: HashMap<String, ServerConfig> =
+            [("github".to_string(), stdio_server("github").config)].into_iter().collect();
+        let from_codex = |mut server: ImportedServer| {
+            server.source = ImportSource::Codex;
+            server
+        };
+        let mut codex_github = from_codex(stdio_server("github"));
+        if let ServerConfig::Stdio { args, .. } = &mut codex_github.config {
+            args.push("--verbose".to_string());
+        }
+        let discovered = [
+            stdio_server("canva"),
+            from_codex(stdio_server("canva")),
+            stdio_server("github"),
+            codex_github,
+        ];
+
+        let diff = diff(&servers, &discovered);
+        assert_eq!(diff.only_source.len(), 1);
+        assert_eq!(diff.only_source[0].1.len(), 2);
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].2.iter().map(|(_, fields)| fields.len()).collect::<Vec<_>>(), [0, 1]);
+        assert_eq!(diff.same, 0);
+
+        let text = render_diff(&diff);
+        assert!(text.contains("  + canva                claude   stdio npx canva\n  +                      codex    stdio npx canva\n"), "text: {text}");
+        assert!(text.contains("  ~ github               claude   matches\n  ~                      codex    differs in args\n"), "text: {text}");
+        assert!(text.contains("      codex:   stdio npx github --verbose\n"), "text: {text}");
+        assert!(!text.contains("      claude:"), "text: {text}");
+        assert!(text.ends_with("0 only in cmcp, 1 only in source, 1 different, 0 identical"), "text: {text}");
+    }
+
+    #[test]
+    fn test_prompt_actions_scripted() {
+        let servers: Vec<_> = ["github", "canva", "linear", "figma", "jira"]
+            .into_iter()
+            .map(stdio_server)
+            .collect();
+        // github: add, canva: bogus then skip, linear (exists): update,
+        // figma: default add, jira: input runs out.
+        let mut input = "a\nwhat\ns\nu\n\n".as_bytes();
+        let mut output = Vec::new();
+
+        let actions = prompt_actions(&servers, |name| name == "linear", &mut input, &mut output).unwrap();
+
+        assert_eq!(
+            actions,
+            [
+                ImportAction::Add,
+                ImportAction::Skip,
+                ImportAction::Update,
+                ImportAction::Add,
+                ImportAction::Skip,
+            ]
+        );
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("linear (claude) stdio npx linear"), "output: {output}");
+        assert!(output.contains("unrecognized answer \"what\""), "output: {output}");
+    }
 }
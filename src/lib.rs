@@ -6,23 +6,40 @@
 pub mod catalog;
 pub mod client;
 pub mod config;
+pub mod lint;
+pub mod metrics;
+pub mod oauth;
 pub mod sandbox;
+pub mod secret;
+pub mod streamable_http;
+pub mod telemetry;
 pub mod transpile;
+pub mod ws;
+
+#[cfg(test)]
+mod test_support;
 
 use std::collections::HashMap;
 use std::sync::Arc;
 
 use anyhow::Result;
-use tokio::sync::Mutex;
+use rmcp::model::CallToolResult;
+use tokio::sync::{Mutex, watch};
 
 use catalog::Catalog;
 use client::ClientPool;
-use config::ServerConfig;
-use sandbox::Sandbox;
+use config::{SandboxConfig, ServerConfig, Truncation, TruncationNotice, ViewConfig};
+use sandbox::{ConsoleLine, ExecuteOptions, RecordedCall, Sandbox};
 
 /// Default max response length in characters (~10k tokens).
 const DEFAULT_MAX_LENGTH: usize = 40_000;
 
+/// Default number of images extracted from one result (`[sandbox] max_images`).
+const DEFAULT_MAX_IMAGES: usize = 20;
+
+/// Max characters kept per preserved error when a response is truncated.
+const MAX_PRESERVED_ERROR_LEN: usize = 500;
+
 /// Image data extracted from an MCP tool response.
 #[derive(Debug, Clone)]
 pub struct ImageData {
@@ -37,6 +54,19 @@ pub struct ExecuteResult {
     pub text: String,
     /// Extracted image content blocks.
     pub images: Vec<ImageData>,
+    /// Console output from the agent code.
+    pub logs: Vec<ConsoleLine>,
+    /// Tool calls made by the agent code, in order, when recording was requested.
+    pub calls: Vec<RecordedCall>,
+}
+
+/// Search result with the console output of the filter code.
+#[derive(Debug)]
+pub struct SearchResult {
+    /// The (possibly truncated) search result.
+    pub value: serde_json::Value,
+    /// Console output from the agent code.
+    pub logs: Vec<ConsoleLine>,
 }
 
 /// Mutable state that gets replaced atomically on reload.
@@ -44,84 +74,394 @@ pub struct ExecuteResult {
 /// reference for tool calls, but we retain ownership for lifecycle management.
 struct ProxyState {
     sandbox: Sandbox,
+    /// Recursion limit when walking results for images.
+    max_json_depth: usize,
+    /// Images extracted per result; later ones are dropped.
+    max_images: usize,
+    truncation: Truncation,
+    truncation_notice: TruncationNotice,
+    /// Applied to the catalog of servers added later.
+    tool_descriptions: HashMap<String, String>,
+    /// Active view; direct tool calls outside it are rejected.
+    view: Option<ViewConfig>,
     catalog: Arc<Catalog>,
-    _pool: Arc<ClientPool>,
+    /// The catalog as the servers reported it, before descriptions and the
+    /// view are applied; carried over for servers kept across a reload.
+    upstream_catalog: Catalog,
+    pool: Arc<ClientPool>,
 }
 
 /// The core proxy engine that manages upstream MCP server connections
 /// and executes agent-written TypeScript code against them.
 pub struct ProxyEngine {
-    state: Mutex<ProxyState>,
+    /// Shared with tasks that add servers still connecting at startup.
+    state: Arc<Mutex<ProxyState>>,
+    /// The pool of `state`, readable without waiting for a running execute.
+    pool: watch::Sender<Arc<ClientPool>>,
+    /// False while servers are still being connected in the background.
+    connected: watch::Receiver<bool>,
+    /// Connect each server on first use instead of up front.
+    lazy: bool,
+    /// Kept across reloads; every pool counts its calls here.
+    metrics: Arc<metrics::Metrics>,
+    /// Tasks adding servers still connecting at startup; aborted on reload.
+    pending: std::sync::Mutex<Vec<tokio::task::AbortHandle>>,
 }
 
 impl ProxyEngine {
     /// Create a ProxyEngine from a map of server configs.
     /// Connects to all configured servers and builds the tool catalog.
     /// Servers that fail to connect are skipped with a warning.
-    pub async fn from_configs(servers: HashMap<String, ServerConfig>) -> Result<Self> {
-        let state = ProxyState::new(servers).await?;
-        Ok(Self {
-            state: Mutex::new(state),
-        })
+    ///
+    /// With `lazy`, servers are connected on first use instead, and only calls
+    /// to a broken server fail (see [`ClientPool::connect`]).
+    ///
+    /// With `connect_wait_ms`, returns once that much time has passed even if
+    /// some servers are still connecting; they join the catalog when ready.
+    pub async fn from_configs(
+        servers: HashMap<String, ServerConfig>,
+        sandbox_config: SandboxConfig,
+        lazy: bool,
+    ) -> Result<Self> {
+        let wait = sandbox_config.connect_wait_ms.map(std::time::Duration::from_millis);
+        let (pool, catalog, pending) = ClientPool::connect_within(servers, lazy, wait).await?;
+        let metrics = Arc::new(metrics::Metrics::default());
+        let state = ProxyState::assemble(pool, catalog, &sandbox_config, &metrics).await?;
+        let engine = Self {
+            pool: watch::channel(state.pool.clone()).0,
+            state: Arc::new(Mutex::new(state)),
+            connected: watch::channel(true).1,
+            lazy,
+            metrics,
+            pending: Default::default(),
+        };
+        engine.add_pending(pending).await;
+        Ok(engine)
+    }
+
+    /// Add each of `pending` to the pool and catalog in the background, once
+    /// it has connected.
+    async fn add_pending(&self, pending: Vec<client::PendingServer>) {
+        if pending.is_empty() {
+            return;
+        }
+        let pool = self.pool();
+        let mut tasks = self.pending.lock().unwrap();
+        for server in pending {
+            let (state, pool) = (self.state.clone(), pool.clone());
+            tasks.push(server.connecting.abort_handle());
+            let task = tokio::spawn(async move {
+                let name = server.name.clone();
+                let added = match pool.finish_pending(server).await {
+                    Ok(added) => added,
+                    Err(e) => {
+                        tracing::warn!(server = %name, error = %pool.redact(&e.to_string()), "failed to connect, skipping");
+                        return;
+                    }
+                };
+                match merge_catalog(&state, &pool, added).await {
+                    Ok(()) => tracing::info!(server = %name, "added to the catalog"),
+                    Err(e) => {
+                        tracing::warn!(server = %name, error = %pool.redact(&e.to_string()), "failed to add server, skipping")
+                    }
+                }
+            });
+            tasks.push(task.abort_handle());
+        }
+    }
+
+    /// Like [`ProxyEngine::from_configs`], but returns right away with no servers
+    /// and connects them in a background task. `search` and `execute` wait
+    /// until that task finishes.
+    pub async fn connect_in_background(
+        servers: HashMap<String, ServerConfig>,
+        sandbox_config: SandboxConfig,
+    ) -> Result<Arc<Self>> {
+        let metrics = Arc::new(metrics::Metrics::default());
+        let state = ProxyState::new(HashMap::new(), &sandbox_config, false, &metrics).await?;
+        let (done, connected) = watch::channel(false);
+        let engine = Arc::new(Self {
+            pool: watch::channel(state.pool.clone()).0,
+            state: Arc::new(Mutex::new(state)),
+            connected,
+            lazy: false,
+            metrics,
+            pending: Default::default(),
+        });
+        let background = engine.clone();
+        tokio::spawn(async move {
+            if let Err(e) = background.reload(servers, sandbox_config).await {
+                tracing::warn!(error = %background.redact(&e.to_string()), "background connect failed, serving no servers");
+            }
+            let _ = done.send(true);
+        });
+        Ok(engine)
+    }
+
+    /// Lock the state, first merging in lazy servers that connected since.
+    async fn state(&self) -> tokio::sync::MutexGuard<'_, ProxyState> {
+        let mut state = self.state.lock().await;
+        state.merge_lazily_connected();
+        state
+    }
+
+    fn pool(&self) -> Arc<ClientPool> {
+        self.pool.borrow().clone()
+    }
+
+    /// Mask the current servers' secrets and the `redact` config values in
+    /// `text` (see [`ClientPool::redact`]).
+    pub fn redact(&self, text: &str) -> String {
+        self.pool().redact(text)
+    }
+
+    /// Wait until servers connecting in the background are done (connected or skipped).
+    pub async fn wait_until_connected(&self) {
+        let mut connected = self.connected.clone();
+        let _ = connected.wait_for(|done| *done).await;
     }
 
     /// Execute a search query — agent TypeScript code that filters the tool catalog.
-    pub async fn search(&self, code: &str, max_length: Option<usize>) -> Result<serde_json::Value> {
+    #[tracing::instrument(name = "search", skip_all)]
+    pub async fn search(&self, code: &str, max_length: Option<usize>) -> Result<SearchResult> {
         let max_len = max_length.unwrap_or(DEFAULT_MAX_LENGTH);
-        let state = self.state.lock().await;
-        let result = state.sandbox.search(code).await?;
+        self.wait_until_connected().await;
+        let state = self.state().await;
+        let (result, logs) = state.sandbox.search_with_logs(code).await?;
         let text = serde_json::to_string_pretty(&result)?;
-        let truncated = truncate_response(text, max_len);
-        serde_json::from_str(&truncated).or(Ok(serde_json::Value::String(truncated)))
+        let value = match state.truncation {
+            Truncation::Json if text.len() > max_len => truncate_json(&result, max_len),
+            Truncation::Json => result,
+            Truncation::Text => {
+                let truncated = truncate_response_with(text, max_len, &state.truncation_notice);
+                serde_json::from_str(&truncated).unwrap_or(serde_json::Value::String(truncated))
+            }
+        };
+        Ok(SearchResult { value, logs })
     }
 
     /// Execute tool-calling code — agent TypeScript that calls tools across servers.
     ///
     /// Extracts image content blocks from the JSON result before truncation,
-    /// so binary data is preserved intact.
+    /// so binary data is preserved intact. The response is cut to the request's
+    /// `max_length`, else the largest `max_length` of the servers called, else
+    /// the default.
     pub async fn execute(&self, code: &str, max_length: Option<usize>) -> Result<ExecuteResult> {
-        let max_len = max_length.unwrap_or(DEFAULT_MAX_LENGTH);
-        let state = self.state.lock().await;
-        let mut result = state.sandbox.execute(code).await?;
+        let options = ExecuteOptions {
+            max_length,
+            ..Default::default()
+        };
+        self.execute_with_options(code, &options).await
+    }
 
-        // Extract images before truncation so base64 data isn't corrupted.
-        let images = extract_images(&mut result);
+    /// Like [`ProxyEngine::execute`]; with `options.record`, the tool calls the
+    /// code made are returned in [`ExecuteResult::calls`].
+    #[tracing::instrument(name = "execute", skip_all)]
+    pub async fn execute_with_options(&self, code: &str, options: &ExecuteOptions) -> Result<ExecuteResult> {
+        self.wait_until_connected().await;
+        let state = self.state().await;
+        let execution = state.sandbox.execute_with_options(code, options).await?;
+        finish_execution(&state, execution, options)
+    }
 
-        let text = serde_json::to_string_pretty(&result)?;
-        let truncated = truncate_response(text, max_len);
+    /// Run the `[sandbox.templates]` entry `id` with `params`; see
+    /// [`sandbox::Sandbox::execute_template`].
+    #[tracing::instrument(name = "execute_template", skip(self, params, options))]
+    pub async fn execute_template(
+        &self,
+        id: &str,
+        params: &serde_json::Value,
+        options: &ExecuteOptions,
+    ) -> Result<ExecuteResult> {
+        self.wait_until_connected().await;
+        let state = self.state().await;
+        let execution = state.sandbox.execute_template(id, params, options).await?;
+        finish_execution(&state, execution, options)
+    }
 
+    /// Call one tool directly, without going through the sandbox.
+    ///
+    /// A single text block comes back as its text (pretty-printed if it is
+    /// JSON), anything else as the full result. Images are extracted but
+    /// nothing is truncated. A result flagged as an error is returned as `Err`.
+    pub async fn call_tool(
+        &self,
+        server: &str,
+        tool: &str,
+        arguments: serde_json::Value,
+    ) -> Result<ExecuteResult> {
+        self.wait_until_connected().await;
+        let state = self.state().await;
+        let result = Self::call_upstream(&state, server, tool, arguments).await?;
+        let text_block = match result.content.as_slice() {
+            [block] if result.structured_content.is_none() => block.as_text(),
+            _ => None,
+        };
+        let mut value = match text_block {
+            Some(block) => serde_json::from_str(&block.text)
+                .unwrap_or_else(|_| serde_json::Value::String(block.text.clone())),
+            None => serde_json::to_value(&result)?,
+        };
+        let render = |value: serde_json::Value| match value {
+            serde_json::Value::String(text) => Ok(text),
+            value => serde_json::to_string_pretty(&value),
+        };
+        if result.is_error == Some(true) {
+            anyhow::bail!("{server}.{tool} returned an error: {}", render(value)?);
+        }
+        let images = extract_images(&mut value, state.max_json_depth, state.max_images);
+        let text = render(value)?;
         Ok(ExecuteResult {
-            text: truncated,
+            text,
             images,
+            logs: Vec::new(),
+            calls: Vec::new(),
         })
     }
 
+    /// Like [`ProxyEngine::call_tool`], but returns the whole result as sent by
+    /// the server, error or not, with image data swapped for placeholders.
+    pub async fn call_tool_result(
+        &self,
+        server: &str,
+        tool: &str,
+        arguments: serde_json::Value,
+    ) -> Result<(CallToolResult, Vec<ImageData>)> {
+        self.wait_until_connected().await;
+        let state = self.state().await;
+        let result = Self::call_upstream(&state, server, tool, arguments).await?;
+        let mut value = serde_json::to_value(&result)?;
+        let images = extract_images(&mut value, state.max_json_depth, state.max_images);
+        Ok((serde_json::from_value(value)?, images))
+    }
+
+    async fn call_upstream(
+        state: &ProxyState,
+        server: &str,
+        tool: &str,
+        arguments: serde_json::Value,
+    ) -> Result<CallToolResult> {
+        if let Some(view) = &state.view
+            && !view.includes(server, tool)
+        {
+            anyhow::bail!("tool {server}.{tool} is not in this view");
+        }
+        let mut result = state.pool.call_tool(server, tool, arguments).await?;
+        sandbox::sanitize_text_content(&mut result);
+        Ok(result)
+    }
+
     /// Reload the proxy with a new set of server configs.
-    /// Reconnects to all servers and rebuilds the catalog and sandbox.
-    pub async fn reload(&self, servers: HashMap<String, ServerConfig>) -> Result<()> {
-        let new_state = ProxyState::new(servers).await?;
+    ///
+    /// Only servers whose config changed are reconnected and removed ones are
+    /// disconnected; the others keep their connections and catalog entries.
+    /// The sandbox is rebuilt, and cached tool results are dropped with the old pool.
+    pub async fn reload(
+        &self,
+        servers: HashMap<String, ServerConfig>,
+        sandbox_config: SandboxConfig,
+    ) -> Result<()> {
+        // Servers still connecting from startup are connected anew below if
+        // still configured, rather than added to the pool being replaced.
+        for task in self.pending.lock().unwrap().drain(..) {
+            task.abort();
+        }
+        let (pool, upstream_catalog) = {
+            let state = self.state().await;
+            (state.pool.clone(), state.upstream_catalog.clone())
+        };
+        let new_state =
+            ProxyState::reconnect(&pool, upstream_catalog, servers, &sandbox_config, self.lazy, &self.metrics)
+                .await?;
         let mut state = self.state.lock().await;
-        *state = new_state;
+        self.pool.send_replace(new_state.pool.clone());
+        let old_state = std::mem::replace(&mut *state, new_state);
+        drop(state);
+        old_state.sandbox.shutdown().await;
         Ok(())
     }
 
+    /// Connect one more server and add its tools to the catalog. Other
+    /// connections, cached results and the sandbox runtime are kept, unlike
+    /// with [`ProxyEngine::reload`].
+    pub async fn add_server(&self, name: &str, config: ServerConfig) -> Result<()> {
+        self.wait_until_connected().await;
+        let pool = self.pool();
+        let added = pool.add_server(name, config, self.lazy).await?;
+        merge_catalog(&self.state, &pool, added).await
+    }
+
     /// Get a summary of the connected servers and tools.
     pub async fn summary(&self) -> String {
-        let state = self.state.lock().await;
+        let state = self.state().await;
         state.catalog.summary()
     }
 
+    /// Get the `serverInfo` (name, version) each connected server reported, by server name.
+    pub async fn server_versions(&self) -> std::collections::BTreeMap<String, catalog::ServerVersion> {
+        let state = self.state().await;
+        state
+            .catalog
+            .servers()
+            .iter()
+            .filter_map(|name| {
+                let version = state.catalog.server_version(name)?;
+                Some((name.clone(), version.clone()))
+            })
+            .collect()
+    }
+
+    /// Ping every connected server and report whether it answered, with latency.
+    /// Does not reconnect or otherwise change connection state.
+    pub async fn server_health(&self) -> HashMap<String, client::ServerHealth> {
+        let pool = self.pool();
+        pool.health_check().await
+    }
+
+    /// Tools matching `query` by fuzzy name/description search, best first
+    /// (see [`Catalog::search`]).
+    pub async fn search_tools(&self, query: &str) -> Vec<catalog::CatalogEntry> {
+        self.wait_until_connected().await;
+        let state = self.state().await;
+        state.catalog.search(query).into_iter().cloned().collect()
+    }
+
+    /// Check agent code against the tool schemas without running it
+    /// (see [`lint::lint`]).
+    pub async fn lint(&self, code: &str) -> Vec<lint::Diagnostic> {
+        self.wait_until_connected().await;
+        let state = self.state().await;
+        lint::lint(code, &state.catalog)
+    }
+
+    /// Counters for `/metrics`; the server records hot reloads here.
+    pub fn metrics(&self) -> &metrics::Metrics {
+        &self.metrics
+    }
+
+    /// All counters plus the current connection states, in the Prometheus
+    /// text format (see [`metrics::Metrics::render`]).
+    pub async fn metrics_text(&self) -> String {
+        let pool = self.pool();
+        self.metrics.render(&pool.connection_states().await)
+    }
+
     /// Get the number of tools in the catalog.
     pub async fn tool_count(&self) -> usize {
-        let state = self.state.lock().await;
+        let state = self.state().await;
         state.catalog.entries().len()
     }
 
     /// Get tool names grouped by server, sorted alphabetically.
+    /// Connected servers with no tools map to an empty list.
     pub async fn catalog_entries_by_server(&self) -> std::collections::BTreeMap<String, Vec<String>> {
-        let state = self.state.lock().await;
-        let mut servers: std::collections::BTreeMap<String, Vec<String>> =
-            std::collections::BTreeMap::new();
+        let state = self.state().await;
+        let mut servers: std::collections::BTreeMap<String, Vec<String>> = state
+            .catalog
+            .servers()
+            .iter()
+            .map(|name| (name.clone(), Vec::new()))
+            .collect();
         for entry in state.catalog.entries() {
             servers
                 .entry(entry.server.clone())
@@ -133,30 +473,324 @@ impl ProxyEngine {
 }
 
 impl ProxyState {
-    async fn new(servers: HashMap<String, ServerConfig>) -> Result<Self> {
-        let (pool, catalog) = ClientPool::connect(servers).await?;
+    async fn new(
+        servers: HashMap<String, ServerConfig>,
+        sandbox_config: &SandboxConfig,
+        lazy: bool,
+        metrics: &Arc<metrics::Metrics>,
+    ) -> Result<Self> {
+        let (pool, catalog) = ClientPool::connect(servers, lazy).await?;
+        Self::assemble(pool, catalog, sandbox_config, metrics).await
+    }
+
+    /// Like [`ProxyState::new`], keeping `previous`'s connections to servers
+    /// whose config is unchanged. `upstream_catalog` is the catalog that went
+    /// with `previous`; the tools `previous` has seen stand in for the tool
+    /// lists of lazy servers that haven't been connected yet.
+    async fn reconnect(
+        previous: &ClientPool,
+        mut upstream_catalog: Catalog,
+        servers: HashMap<String, ServerConfig>,
+        sandbox_config: &SandboxConfig,
+        lazy: bool,
+        metrics: &Arc<metrics::Metrics>,
+    ) -> Result<Self> {
+        let (pool, mut catalog, kept) = ClientPool::reconnect(previous, servers, lazy).await?;
+        upstream_catalog.retain_servers(|s| kept.iter().any(|k| k == s));
+        catalog.extend(upstream_catalog);
+        if lazy {
+            for (name, tools) in previous.known_tools() {
+                let listed = catalog.entries().iter().any(|e| e.server == name);
+                if catalog.servers().contains(&name) && !listed {
+                    catalog.add_server_tools(&name, tools.clone());
+                    pool.remember_tools(&name, tools);
+                }
+            }
+        }
+        Self::assemble(pool, catalog, sandbox_config, metrics).await
+    }
+
+    /// Apply the sandbox config to a connected pool and its catalog.
+    async fn assemble(
+        pool: ClientPool,
+        mut catalog: Catalog,
+        sandbox_config: &SandboxConfig,
+        metrics: &Arc<metrics::Metrics>,
+    ) -> Result<Self> {
+        let breaker = sandbox_config.circuit_breaker.unwrap_or_default();
+        breaker.validate()?;
+        let pool = pool
+            .with_metrics(metrics.clone())
+            .with_redactions(&sandbox_config.redact)
+            .with_retry(sandbox_config.retry.unwrap_or_default())
+            .with_breaker(breaker)
+            .with_cache(sandbox_config.cache.clone().unwrap_or_default())
+            .with_max_concurrent_calls(
+                sandbox_config
+                    .max_concurrent_calls
+                    .unwrap_or(client::DEFAULT_MAX_CONCURRENT_CALLS),
+            );
+        let upstream_catalog = catalog.clone();
+        shape_catalog(&mut catalog, &sandbox_config.tool_descriptions, sandbox_config.view.as_ref());
         let catalog = Arc::new(catalog);
         let pool = Arc::new(pool);
-        let sandbox = Sandbox::new(pool.clone(), catalog.clone()).await?;
+        let sandbox = Sandbox::new(pool.clone(), catalog.clone(), sandbox_config).await?;
         Ok(Self {
             sandbox,
+            max_json_depth: sandbox_config
+                .max_json_depth
+                .unwrap_or(sandbox::DEFAULT_MAX_JSON_DEPTH),
+            max_images: sandbox_config.max_images.unwrap_or(DEFAULT_MAX_IMAGES),
+            truncation: sandbox_config.truncation.unwrap_or_default(),
+            truncation_notice: sandbox_config.truncation_notice.clone().unwrap_or_default(),
+            tool_descriptions: sandbox_config.tool_descriptions.clone(),
+            view: sandbox_config.view.clone(),
             catalog,
-            _pool: pool,
+            upstream_catalog,
+            pool,
         })
     }
 }
 
+/// Extract images from an `execute` result and truncate the rest to the
+/// request's, the called servers' or the default `max_length`.
+fn finish_execution(state: &ProxyState, execution: sandbox::Execution, options: &ExecuteOptions) -> Result<ExecuteResult> {
+    let max_len = options
+        .max_length
+        .or(execution.server_max_length)
+        .unwrap_or(DEFAULT_MAX_LENGTH);
+    let (mut result, logs) = (execution.value, execution.logs);
+
+    // Extract images before truncation so base64 data isn't corrupted.
+    let (mut images, mut seen) = (Vec::new(), 0);
+    extract_images_recursive(&mut result, &mut images, &mut seen, state.max_images, state.max_json_depth);
+
+    // Recorded results get the same treatment, their images numbered after the
+    // result's, and share the budget so `record` can't bypass it.
+    let mut calls = execution.calls;
+    let share = max_len / calls.len().max(1);
+    for call in &mut calls {
+        extract_images_recursive(&mut call.result, &mut images, &mut seen, state.max_images, state.max_json_depth);
+        if serde_json::to_string_pretty(&call.result).is_ok_and(|text| text.len() > share) {
+            call.result = truncate_json(&call.result, share);
+        }
+    }
+
+    let text = serde_json::to_string_pretty(&result)?;
+    let truncated = match state.truncation {
+        Truncation::Json if text.len() > max_len => {
+            serde_json::to_string_pretty(&truncate_json(&result, max_len))?
+        }
+        Truncation::Json => text,
+        Truncation::Text => truncate_preserving_errors(&result, text, max_len, &state.truncation_notice),
+    };
+
+    Ok(ExecuteResult {
+        text: truncated,
+        images,
+        logs,
+        calls,
+    })
+}
+
+/// Swap in the current catalog plus `added`, the catalog of servers just added to `pool`.
+async fn merge_catalog(state: &Mutex<ProxyState>, pool: &Arc<ClientPool>, added: Catalog) -> Result<()> {
+    let mut state = state.lock().await;
+    if !Arc::ptr_eq(&state.pool, pool) {
+        anyhow::bail!("the proxy was reloaded while the server was being added");
+    }
+    state.merge(added);
+    Ok(())
+}
+
+impl ProxyState {
+    /// Swap in the current catalog plus `added`, replacing what it had for
+    /// `added`'s servers (a lazy server's empty placeholder, say).
+    fn merge(&mut self, mut added: Catalog) {
+        let servers = added.servers().to_vec();
+        let kept = |server: &str| !servers.iter().any(|s| s == server);
+        self.upstream_catalog.retain_servers(kept);
+        self.upstream_catalog.extend(added.clone());
+        shape_catalog(&mut added, &self.tool_descriptions, self.view.as_ref());
+        let mut catalog = Catalog::clone(&self.catalog);
+        catalog.retain_servers(kept);
+        catalog.extend(added);
+        let catalog = Arc::new(catalog);
+        self.sandbox.set_catalog(catalog.clone());
+        self.catalog = catalog;
+    }
+
+    /// Merge the catalogs of lazy servers that connected on first use.
+    fn merge_lazily_connected(&mut self) {
+        for added in self.pool.take_lazily_connected() {
+            self.merge(added);
+        }
+    }
+}
+
+/// Apply description overrides and the active view to a freshly built catalog.
+fn shape_catalog(catalog: &mut Catalog, tool_descriptions: &HashMap<String, String>, view: Option<&ViewConfig>) {
+    catalog.override_descriptions(tool_descriptions);
+    if let Some(view) = view {
+        catalog.retain(|e| view.includes(&e.server, &e.name));
+        catalog.retain_servers(|s| view.touches_server(s));
+        catalog.retain_resources(|r| view.touches_server(&r.server));
+        catalog.retain_resource_templates(|t| view.touches_server(&t.server));
+        catalog.retain_prompts(|p| view.touches_server(&p.server));
+    }
+}
+
 /// Truncate a response to `max_len` characters, appending a notice if truncated.
 pub fn truncate_response(text: String, max_len: usize) -> String {
+    truncate_response_with(text, max_len, &TruncationNotice::Prose)
+}
+
+/// Like [`truncate_response`], with `notice` describing what was cut.
+pub fn truncate_response_with(text: String, max_len: usize, notice: &TruncationNotice) -> String {
     if max_len == 0 || text.len() <= max_len {
         return text;
     }
+    let mut max_len = max_len;
+    while !text.is_char_boundary(max_len) {
+        max_len -= 1;
+    }
     let cut = text[..max_len].rfind('\n').unwrap_or(max_len);
     let truncated = &text[..cut];
     let remaining = text.len() - cut;
-    format!(
-        "{truncated}\n\n[truncated — {remaining} chars omitted. Use your code to extract only the data you need, or increase max_length.]"
-    )
+    format!("{truncated}\n\n{}", notice.render(remaining))
+}
+
+/// Shrink `value` until its pretty-printed form fits `max_len`, keeping it valid JSON.
+///
+/// Arrays and objects keep their first N entries and strings their first N
+/// characters, halving N until the output fits (or N reaches zero). The result
+/// carries a `_truncated: true` marker — inserted into a top-level object, or
+/// wrapping any other value as `{ "_truncated": true, "value": ... }`.
+pub fn truncate_json(value: &serde_json::Value, max_len: usize) -> serde_json::Value {
+    let mut limit = max_len;
+    loop {
+        let mut capped = cap_json(value, limit);
+        match &mut capped {
+            serde_json::Value::Object(map) => {
+                map.insert("_truncated".to_string(), serde_json::Value::Bool(true));
+            }
+            other => {
+                *other = serde_json::json!({ "_truncated": true, "value": other.take() });
+            }
+        }
+        let fits = serde_json::to_string_pretty(&capped).map_or(true, |t| t.len() <= max_len);
+        if fits || limit == 0 {
+            return capped;
+        }
+        limit /= 2;
+    }
+}
+
+/// Copy `value` keeping at most `limit` entries per array/object and `limit` chars per string.
+fn cap_json(value: &serde_json::Value, limit: usize) -> serde_json::Value {
+    match value {
+        serde_json::Value::String(s) => {
+            let len = s.chars().count();
+            if len <= limit {
+                return value.clone();
+            }
+            let kept: String = s.chars().take(limit).collect();
+            serde_json::Value::String(format!("{kept}… [{} chars omitted]", len - limit))
+        }
+        serde_json::Value::Array(arr) => {
+            let mut out: Vec<_> = arr.iter().take(limit).map(|v| cap_json(v, limit)).collect();
+            if arr.len() > limit {
+                out.push(serde_json::Value::String(format!("[… {} more items]", arr.len() - limit)));
+            }
+            serde_json::Value::Array(out)
+        }
+        serde_json::Value::Object(map) => {
+            let mut out: serde_json::Map<_, _> = map
+                .iter()
+                .take(limit)
+                .map(|(k, v)| (k.clone(), cap_json(v, limit)))
+                .collect();
+            if map.len() > limit {
+                out.insert("_omitted_keys".to_string(), serde_json::json!(map.len() - limit));
+            }
+            serde_json::Value::Object(out)
+        }
+        other => other.clone(),
+    }
+}
+
+/// Truncate like [`truncate_response`], but keep `error`/`isError` fields visible.
+///
+/// Any error indicators in `value` that were cut off by truncation are appended
+/// after the truncation notice, so a failed call in a large fan-out isn't hidden.
+pub fn truncate_preserving_errors(
+    value: &serde_json::Value,
+    text: String,
+    max_len: usize,
+    notice: &TruncationNotice,
+) -> String {
+    if max_len == 0 || text.len() <= max_len {
+        return text;
+    }
+    let mut truncated = truncate_response_with(text, max_len, notice);
+
+    let mut errors = Vec::new();
+    collect_errors(value, "$", &mut errors);
+
+    let mut lost: Vec<String> = Vec::new();
+    for (path, err) in errors {
+        let mut err_text = match err {
+            serde_json::Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        if truncated.contains(&err_text) {
+            continue;
+        }
+        if err_text.len() > MAX_PRESERVED_ERROR_LEN {
+            let mut cut = MAX_PRESERVED_ERROR_LEN;
+            while !err_text.is_char_boundary(cut) {
+                cut -= 1;
+            }
+            err_text.truncate(cut);
+            err_text.push_str("...");
+        }
+        lost.push(format!("  {path}: {err_text}"));
+    }
+
+    if !lost.is_empty() {
+        truncated.push_str("\n\n[errors in omitted output]\n");
+        truncated.push_str(&lost.join("\n"));
+    }
+    truncated
+}
+
+/// Recursively collect `error` fields and `isError: true` objects with their JSON paths.
+fn collect_errors<'a>(
+    value: &'a serde_json::Value,
+    path: &str,
+    out: &mut Vec<(String, &'a serde_json::Value)>,
+) {
+    match value {
+        serde_json::Value::Object(map) => {
+            if let Some(err) = map.get("error").filter(|e| !e.is_null()) {
+                out.push((format!("{path}.error"), err));
+            }
+            if map.get("isError").and_then(|v| v.as_bool()) == Some(true) {
+                out.push((path.to_string(), map.get("content").unwrap_or(value)));
+            }
+            for (k, v) in map {
+                if k != "error" {
+                    collect_errors(v, &format!("{path}.{k}"), out);
+                }
+            }
+        }
+        serde_json::Value::Array(arr) => {
+            for (i, item) in arr.iter().enumerate() {
+                collect_errors(item, &format!("{path}[{i}]"), out);
+            }
+        }
+        _ => {}
+    }
 }
 
 /// Recursively walk a JSON value and extract MCP image content blocks.
@@ -164,13 +798,25 @@ pub fn truncate_response(text: String, max_len: usize) -> String {
 /// Looks for objects matching `{"type": "image", "data": "...", "mimeType": "..."}`.
 /// Extracted images are removed from the JSON (data replaced with a placeholder)
 /// so the remaining text can be safely truncated without corrupting binary data.
-fn extract_images(value: &mut serde_json::Value) -> Vec<ImageData> {
+/// Nothing below `max_depth` levels is visited. Past `max_images`, images are
+/// dropped and their data replaced with a note instead.
+fn extract_images(value: &mut serde_json::Value, max_depth: usize, max_images: usize) -> Vec<ImageData> {
     let mut images = Vec::new();
-    extract_images_recursive(value, &mut images);
+    let mut seen = 0;
+    extract_images_recursive(value, &mut images, &mut seen, max_images, max_depth);
     images
 }
 
-fn extract_images_recursive(value: &mut serde_json::Value, images: &mut Vec<ImageData>) {
+fn extract_images_recursive(
+    value: &mut serde_json::Value,
+    images: &mut Vec<ImageData>,
+    seen: &mut usize,
+    max_images: usize,
+    depth_left: usize,
+) {
+    if depth_left == 0 {
+        return;
+    }
     match value {
         serde_json::Value::Object(map) => {
             // Check if this object is an MCP image content block.
@@ -186,27 +832,657 @@ fn extract_images_recursive(value: &mut serde_json::Value, images: &mut Vec<Imag
                         .and_then(|v| v.as_str())
                         .map(String::from),
                 ) {
-                    let idx = images.len();
-                    images.push(ImageData { data, mime_type });
+                    let idx = *seen;
+                    *seen += 1;
                     // Replace the data with a placeholder to keep the JSON structure
                     // but avoid truncating the base64 blob.
-                    map.insert(
-                        "data".to_string(),
-                        serde_json::Value::String(format!("[image #{idx} extracted]")),
-                    );
+                    let placeholder = if images.len() < max_images {
+                        images.push(ImageData { data, mime_type });
+                        format!("[image #{idx} extracted]")
+                    } else {
+                        format!("[image #{idx} omitted: only the first {max_images} images are returned]")
+                    };
+                    map.insert("data".to_string(), serde_json::Value::String(placeholder));
                 }
             }
 
             // Recurse into all values.
             for v in map.values_mut() {
-                extract_images_recursive(v, images);
+                extract_images_recursive(v, images, seen, max_images, depth_left - 1);
             }
         }
         serde_json::Value::Array(arr) => {
             for item in arr.iter_mut() {
-                extract_images_recursive(item, images);
+                extract_images_recursive(item, images, seen, max_images, depth_left - 1);
             }
         }
         _ => {}
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Mock upstream: `echo` returns its arguments as JSON text, `snapshot`
+    /// returns a caption and an image, `fail` returns an error result.
+    #[derive(Clone)]
+    struct EchoServer;
+
+    impl rmcp::ServerHandler for EchoServer {
+        async fn call_tool(
+            &self,
+            request: rmcp::model::CallToolRequestParams,
+            context: rmcp::service::RequestContext<rmcp::RoleServer>,
+        ) -> Result<rmcp::model::CallToolResult, rmcp::ErrorData> {
+            use rmcp::model::{CallToolResult, Content};
+            Ok(match request.name.as_ref() {
+                "echo" => {
+                    let args = serde_json::Value::Object(request.arguments.unwrap_or_default());
+                    CallToolResult::success(vec![Content::text(args.to_string())])
+                }
+                "snapshot" => CallToolResult::success(vec![
+                    Content::text("page"),
+                    Content::image("iVBORw0=", "image/png"),
+                ]),
+                // Two pages, linked by a `_meta` cursor.
+                "page" => {
+                    let next = request.arguments.as_ref().and_then(|a| a.get("cursor")).is_none();
+                    let mut result = CallToolResult::success(vec![Content::text(if next { "[1,2]" } else { "[3]" })]);
+                    if next {
+                        let meta = serde_json::json!({ "nextCursor": "p2" });
+                        result.meta = Some(rmcp::model::Meta(meta.as_object().unwrap().clone()));
+                    }
+                    result
+                }
+                "traceparent" => {
+                    let traceparent = context.meta.0.get("traceparent").and_then(|t| t.as_str());
+                    CallToolResult::success(vec![Content::text(traceparent.unwrap_or("none"))])
+                }
+                _ => CallToolResult::error(vec![Content::text("no such tool")]),
+            })
+        }
+
+        fn get_info(&self) -> rmcp::model::ServerInfo {
+            rmcp::model::ServerInfo {
+                capabilities: rmcp::model::ServerCapabilities::builder().enable_tools().build(),
+                ..Default::default()
+            }
+        }
+    }
+
+    async fn echo_engine(sandbox_config: SandboxConfig) -> ProxyEngine {
+        echo_engine_with_options(sandbox_config, Default::default()).await
+    }
+
+    async fn echo_engine_with_options(sandbox_config: SandboxConfig, options: config::ServerOptions) -> ProxyEngine {
+        let (service, _) = test_support::in_process_upstream("mock", EchoServer).await;
+        let config = test_support::stand_in_config(options);
+        let pool = ClientPool::from_services(vec![("mock".to_string(), service, config)]);
+        let mut catalog = Catalog::new();
+        catalog.add_server_tools("mock", Vec::new());
+        let metrics = Arc::new(metrics::Metrics::default());
+        let state = ProxyState::assemble(pool, catalog, &sandbox_config, &metrics).await.unwrap();
+        ProxyEngine {
+            pool: watch::channel(state.pool.clone()).0,
+            state: Arc::new(Mutex::new(state)),
+            connected: watch::channel(true).1,
+            lazy: false,
+            metrics,
+            pending: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_slow_server_joins_the_catalog_after_startup() {
+        let engine = echo_engine(SandboxConfig::default()).await;
+        let connecting = tokio::spawn(async {
+            tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+            let (service, _) = test_support::in_process_upstream("slow", EchoServer).await;
+            let schema: rmcp::model::JsonObject = serde_json::from_value(serde_json::json!({ "type": "object" }))?;
+            let tools = vec![rmcp::model::Tool::new("echo", "Echo the arguments", Arc::new(schema))];
+            Ok((service, tools))
+        });
+        let slow = client::PendingServer {
+            name: "slow".to_string(),
+            config: ServerConfig::Stdio {
+                command: "true".to_string(),
+                args: Vec::new(),
+                env: HashMap::new(),
+                options: Default::default(),
+            },
+            connecting,
+            secrets: Default::default(),
+        };
+        engine.add_pending(vec![slow]).await;
+        assert_eq!(engine.tool_count().await, 0);
+
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while engine.tool_count().await == 0 {
+            assert!(std::time::Instant::now() < deadline, "slow server was never added");
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        let result = engine.execute("return (await slow.echo({ n: 1 })).n;", None).await.unwrap();
+        assert_eq!(result.text, "1");
+    }
+
+    #[tokio::test]
+    async fn test_reload_cancels_servers_still_connecting() {
+        let engine = echo_engine(SandboxConfig::default()).await;
+        let connecting = tokio::spawn(async {
+            tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+            anyhow::bail!("never connects")
+        });
+        let watch = connecting.abort_handle();
+        let slow = client::PendingServer {
+            name: "slow".to_string(),
+            config: ServerConfig::Stdio {
+                command: "true".to_string(),
+                args: Vec::new(),
+                env: HashMap::new(),
+                options: Default::default(),
+            },
+            connecting,
+            secrets: Default::default(),
+        };
+        engine.add_pending(vec![slow]).await;
+
+        engine.reload(HashMap::new(), SandboxConfig::default()).await.unwrap();
+        tokio::task::yield_now().await;
+        assert!(watch.is_finished());
+        assert!(engine.pending.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_reloads_do_not_grow_tasks_or_threads() {
+        fn threads() -> usize {
+            let status = std::fs::read_to_string("/proc/self/status").unwrap_or_default();
+            status.lines().find_map(|l| l.strip_prefix("Threads:")).map_or(0, |n| n.trim().parse().unwrap())
+        }
+        let engine = echo_engine(SandboxConfig::default()).await;
+        let servers = HashMap::from([("mock".to_string(), test_support::stand_in_config(Default::default()))]);
+        let reload = async || {
+            // Leave a timer and a tool call running into the reload.
+            let code = "cmcp.sleep(60000); mock.echo({ n: 1 }); return 1;";
+            engine.execute(code, None).await.unwrap();
+            engine.reload(servers.clone(), SandboxConfig::default()).await.unwrap();
+        };
+        for _ in 0..5 {
+            reload().await;
+        }
+        let metrics = tokio::runtime::Handle::current().metrics();
+        let (tasks, threads_before) = (metrics.num_alive_tasks(), threads());
+
+        for _ in 0..50 {
+            reload().await;
+        }
+        tokio::task::yield_now().await;
+        assert!(metrics.num_alive_tasks() <= tasks + 2, "{tasks} tasks grew to {}", metrics.num_alive_tasks());
+        assert!(threads() <= threads_before + 2, "{threads_before} threads grew to {}", threads());
+    }
+
+    #[tokio::test]
+    async fn test_reload_keeps_unchanged_connections() {
+        let engine = echo_engine(SandboxConfig::default()).await;
+        let mock = ServerConfig::Stdio {
+            command: "true".to_string(),
+            args: Vec::new(),
+            env: HashMap::new(),
+            options: Default::default(),
+        };
+        let broken = ServerConfig::Stdio {
+            command: "cmcp-test-no-such-command".to_string(),
+            args: Vec::new(),
+            env: HashMap::new(),
+            options: Default::default(),
+        };
+        let servers = HashMap::from([("mock".to_string(), mock), ("broken".to_string(), broken)]);
+        let sandbox_config = SandboxConfig {
+            timeout_secs: Some(30.0),
+            ..Default::default()
+        };
+        engine.reload(servers, sandbox_config).await.unwrap();
+
+        // `true` is no MCP server, so a reconnect would have dropped mock.
+        let result = engine
+            .execute(
+                "return [servers.map(s => s.name), await mock.echo({ n: 1 })];",
+                None,
+            )
+            .await
+            .unwrap();
+        let value: serde_json::Value = serde_json::from_str(&result.text).unwrap();
+        assert_eq!(value, serde_json::json!([["mock"], { "n": 1 }]));
+
+        // A removed server is disconnected.
+        engine.reload(HashMap::new(), SandboxConfig::default()).await.unwrap();
+        let result = engine.execute("return [servers.length, typeof mock];", None).await.unwrap();
+        let value: serde_json::Value = serde_json::from_str(&result.text).unwrap();
+        assert_eq!(value, serde_json::json!([0, "undefined"]));
+    }
+
+    #[tokio::test]
+    async fn test_add_server_keeps_existing_connections() {
+        let engine = echo_engine(SandboxConfig::default()).await;
+        let first = engine.execute("return (await mock.echo({ n: 1 })).n;", None).await.unwrap();
+        assert_eq!(first.text, "1");
+        let pool = engine.state.lock().await.pool.clone();
+
+        let (service, config) = test_support::in_process_upstream("docs", EchoServer).await;
+        let schema: rmcp::model::JsonObject = serde_json::from_value(serde_json::json!({ "type": "object" })).unwrap();
+        let tools = vec![rmcp::model::Tool::new("echo", "Echo the arguments", Arc::new(schema))];
+        let added = pool.insert_service("docs", service, tools, config.clone(), Default::default()).await;
+        merge_catalog(&engine.state, &pool, added).await.unwrap();
+
+        // Same pool, so the existing upstream session was not reconnected.
+        assert!(Arc::ptr_eq(&engine.state.lock().await.pool, &pool));
+        let result = engine
+            .execute(
+                "return [servers.map(s => s.name), tools.map(t => `${t.server}.${t.name}`), \
+                 await docs.echo({ n: 2 }), await mock.echo({ n: 3 })];",
+                None,
+            )
+            .await
+            .unwrap();
+        let value: serde_json::Value = serde_json::from_str(&result.text).unwrap();
+        assert_eq!(value, serde_json::json!([["docs", "mock"], ["docs.echo"], { "n": 2 }, { "n": 3 }]));
+
+        let err = engine.add_server("docs", config).await.unwrap_err();
+        assert_eq!(err.to_string(), "server \"docs\" is already connected");
+    }
+
+    #[tokio::test]
+    async fn test_call_tool_directly() {
+        let engine = echo_engine(SandboxConfig::default()).await;
+
+        let result = engine
+            .call_tool("mock", "echo", serde_json::json!({ "query": "rust" }))
+            .await
+            .unwrap();
+        assert_eq!(result.text, "{\n  \"query\": \"rust\"\n}");
+        assert!(result.images.is_empty());
+
+        let result = engine.call_tool("mock", "snapshot", serde_json::json!({})).await.unwrap();
+        assert_eq!(result.images.len(), 1);
+        assert_eq!(result.images[0].mime_type, "image/png");
+        assert!(result.text.contains("[image #0 extracted]"), "{}", result.text);
+
+        let err = engine.call_tool("mock", "fail", serde_json::json!({})).await.unwrap_err();
+        assert_eq!(err.to_string(), "mock.fail returned an error: no such tool");
+
+        let view = config::ViewConfig {
+            servers: Vec::new(),
+            tools: vec!["mock.echo".to_string()],
+        };
+        let engine = echo_engine(SandboxConfig { view: Some(view), ..Default::default() }).await;
+        let err = engine.call_tool("mock", "snapshot", serde_json::json!({})).await.unwrap_err();
+        assert_eq!(err.to_string(), "tool mock.snapshot is not in this view");
+    }
+
+    #[tokio::test]
+    async fn test_redactions_are_rebuilt_on_reload() {
+        let sandbox_config = SandboxConfig {
+            redact: vec!["tenant-4242".to_string(), "abc".to_string()],
+            ..Default::default()
+        };
+        let engine = echo_engine(sandbox_config).await;
+        assert_eq!(engine.redact("tenant-4242 said abc"), "[redacted] said abc");
+
+        engine.reload(HashMap::new(), SandboxConfig::default()).await.unwrap();
+        assert_eq!(engine.redact("tenant-4242 said abc"), "tenant-4242 said abc");
+    }
+
+    #[tokio::test]
+    async fn test_metrics_do_not_wait_for_execute() {
+        let engine = Arc::new(echo_engine(SandboxConfig::default()).await);
+        let running = engine.clone();
+        let execute = tokio::spawn(async move { running.execute("await cmcp.sleep(2000); return 1;", None).await });
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        let scrape = tokio::time::timeout(std::time::Duration::from_millis(500), engine.metrics_text()).await;
+        assert!(scrape.is_ok(), "metrics waited for the running execute");
+        execute.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_metrics_collapse_unknown_tools() {
+        let engine = echo_engine(SandboxConfig::default()).await;
+        let schema: rmcp::model::JsonObject = serde_json::from_value(serde_json::json!({ "type": "object" })).unwrap();
+        engine.pool().remember_tools("mock", vec![rmcp::model::Tool::new("echo", "Echo the arguments", Arc::new(schema))]);
+
+        engine
+            .execute(
+                r#"
+                await mock.echo({});
+                for (let i = 0; i < 3; i++) await __call_tool("mock", "random" + i, "{}");
+                await __call_tool("nowhere", "echo", "{}");
+                return 1;
+                "#,
+                None,
+            )
+            .await
+            .unwrap();
+        let text = engine.metrics_text().await;
+        assert!(text.contains(r#"server="mock",tool="echo""#), "{text}");
+        assert!(text.contains(r#"server="mock",tool="unknown""#), "{text}");
+        assert!(text.contains(r#"server="unknown",tool="unknown""#), "{text}");
+        assert!(!text.contains("random"), "{text}");
+    }
+
+    #[tokio::test]
+    async fn test_call_tool_result_keeps_the_whole_result() {
+        let engine = echo_engine(SandboxConfig::default()).await;
+
+        let (result, images) = engine.call_tool_result("mock", "snapshot", serde_json::json!({})).await.unwrap();
+        assert_eq!(images.len(), 1);
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["content"][1]["data"], "[image #0 extracted]");
+
+        // Errors come back as results for the caller to inspect.
+        let (result, _) = engine.call_tool_result("mock", "fail", serde_json::json!({})).await.unwrap();
+        assert_eq!(result.is_error, Some(true));
+    }
+
+    #[tokio::test]
+    async fn test_failed_calls_throw_when_configured() {
+        let code = r#"
+            const outcome = async (call) => {
+                try { return { value: await call }; } catch (e) { return { thrown: e.message, tool: e.tool }; }
+            };
+            return [
+                await outcome(mock.fail({})),
+                await outcome(mock.echo({ n: 1 })),
+                await outcome(Promise.all([mock.echo({ n: 2 }), mock.fail({})])),
+                await outcome(mock.echo({ error: "a field, not a failure" })),
+                await outcome(__server_proxy("gone").anything({})),
+            ];
+        "#;
+
+        let engine = echo_engine(SandboxConfig {
+            throw_on_error: Some(true),
+            ..Default::default()
+        })
+        .await;
+        let result: serde_json::Value = serde_json::from_str(&engine.execute(code, None).await.unwrap().text).unwrap();
+        assert_eq!(result[0], serde_json::json!({ "thrown": "no such tool", "tool": "fail" }));
+        assert_eq!(result[1]["value"], serde_json::json!({ "n": 1 }));
+        assert_eq!(result[2], serde_json::json!({ "thrown": "no such tool", "tool": "fail" }));
+        assert_eq!(result[3]["value"], serde_json::json!({ "error": "a field, not a failure" }));
+        assert_eq!(result[4], serde_json::json!({ "thrown": "no server named 'gone'", "tool": "anything" }));
+
+        // By default the error result is handed back as is.
+        let engine = echo_engine(SandboxConfig::default()).await;
+        let result: serde_json::Value = serde_json::from_str(&engine.execute(code, None).await.unwrap().text).unwrap();
+        assert_eq!(result[0]["value"]["isError"], true);
+        assert_eq!(result[3]["value"], serde_json::json!({ "error": "a field, not a failure" }));
+        assert_eq!(result[4]["value"], serde_json::json!({ "error": "no server named 'gone'" }));
+    }
+
+    #[tokio::test]
+    async fn test_lazy_server_joins_the_catalog_on_first_use() {
+        let addr = test_support::header_checking_server("accept", streamable_http::DEFAULT_ACCEPT).await;
+        let config = ServerConfig::Http {
+            url: format!("http://{addr}/mcp"),
+            auth: None,
+            headers: HashMap::new(),
+            options: Default::default(),
+        };
+        let servers = HashMap::from([("picky".to_string(), config)]);
+        let engine = ProxyEngine::from_configs(servers, SandboxConfig::default(), true).await.unwrap();
+        assert_eq!(engine.tool_count().await, 0);
+
+        engine.execute("await picky.ping({}); return 1;", None).await.unwrap();
+        assert_eq!(engine.catalog_entries_by_server().await["picky"], ["ping"]);
+        let found = engine.search("return tools.map(t => t.name);", None).await.unwrap();
+        assert_eq!(found.value, serde_json::json!(["ping"]));
+    }
+
+    #[tokio::test]
+    async fn test_templates_only_runs_known_templates() {
+        let engine = echo_engine(SandboxConfig {
+            templates: HashMap::from([(
+                "echo_repo".to_string(),
+                "return await mock.echo({ repo: params.repo, open: true });".to_string(),
+            )]),
+            templates_only: Some(true),
+            ..Default::default()
+        })
+        .await;
+        let options = ExecuteOptions::default();
+
+        // Parameters arrive as data, so code in them is never run.
+        let params = serde_json::json!({ "repo": "\"}); globalThis.pwned = 1; ({\"" });
+        let result = engine.execute_template("echo_repo", &params, &options).await.unwrap();
+        let value: serde_json::Value = serde_json::from_str(&result.text).unwrap();
+        assert_eq!(value, serde_json::json!({ "repo": params["repo"], "open": true }));
+
+        let err = engine.execute_template("drop_tables", &params, &options).await.unwrap_err();
+        assert_eq!(err.to_string(), "unknown template \"drop_tables\" (available: echo_repo)");
+        let err = engine.execute("return 1;", None).await.unwrap_err();
+        assert_eq!(err.to_string(), "only templates can be executed here (available: echo_repo)");
+    }
+
+    #[tokio::test]
+    async fn test_result_meta_cursor_reaches_agent_code() {
+        let code = r#"
+            const items = [];
+            let cursor;
+            do {
+                const { data, meta } = await mock.page(cursor ? { cursor } : {});
+                items.push(...data);
+                cursor = meta?.nextCursor;
+            } while (cursor);
+            return [items, await mock.fail({})];
+        "#;
+        let engine = echo_engine(SandboxConfig {
+            result_meta: Some(true),
+            ..Default::default()
+        })
+        .await;
+        let result: serde_json::Value = serde_json::from_str(&engine.execute(code, None).await.unwrap().text).unwrap();
+        assert_eq!(result[0], serde_json::json!([1, 2, 3]));
+        // Errors keep their usual shape.
+        assert_eq!(result[1]["isError"], true);
+
+        let engine = echo_engine(SandboxConfig::default()).await;
+        let result = engine.execute("return await mock.page({});", None).await.unwrap();
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&result.text).unwrap(), serde_json::json!([1, 2]));
+    }
+
+    #[tokio::test]
+    async fn test_execute_records_calls_in_order() {
+        let engine = echo_engine(SandboxConfig::default()).await;
+        let code = r#"
+            const a = await mock.echo({ n: 1 });
+            const [b, c] = await Promise.all([mock.echo({ n: 2 }), mock.fail({})]);
+            return a;
+        "#;
+        let options = ExecuteOptions {
+            record: true,
+            ..Default::default()
+        };
+        let result = engine.execute_with_options(code, &options).await.unwrap();
+
+        let summary: Vec<_> = result
+            .calls
+            .iter()
+            .map(|c| (c.tool.as_str(), c.params.clone()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("echo", serde_json::json!({ "n": 1 })),
+                ("echo", serde_json::json!({ "n": 2 })),
+                ("fail", serde_json::json!({})),
+            ]
+        );
+        assert!(result.calls.iter().all(|c| c.server == "mock"));
+        assert_eq!(result.calls[0].result, serde_json::json!({ "n": 1 }));
+        assert_eq!(result.calls[2].result["isError"], true);
+
+        // Nothing is recorded unless asked for.
+        assert!(engine.execute(code, None).await.unwrap().calls.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_recorded_results_are_extracted_and_truncated() {
+        let engine = echo_engine(SandboxConfig::default()).await;
+        let code = r#"
+            const shot = await mock.snapshot({});
+            await mock.echo({ text: "x".repeat(5000) });
+            return shot.content[1].data;
+        "#;
+        let options = ExecuteOptions {
+            max_length: Some(1000),
+            record: true,
+        };
+        let result = engine.execute_with_options(code, &options).await.unwrap();
+
+        // The recorded image is extracted after the result's own.
+        assert_eq!(result.images.len(), 1);
+        assert_eq!(result.calls[0].result["content"][1]["data"], "[image #0 extracted]");
+        assert!(result.text.contains("iVBORw0="), "{}", result.text);
+
+        // Each recorded result gets a share of max_length.
+        let recorded = serde_json::to_string_pretty(&result.calls[1].result).unwrap();
+        assert!(recorded.len() <= 500, "{recorded}");
+        assert_eq!(result.calls[1].result["_truncated"], true);
+    }
+
+    #[tokio::test]
+    async fn test_server_max_length_yields_to_request_max_length() {
+        let options = config::ServerOptions {
+            max_length: Some(100),
+            ..Default::default()
+        };
+        let engine = echo_engine_with_options(SandboxConfig::default(), options).await;
+
+        // The result reaches the code whole; the server's budget cuts the response.
+        let code = r#"const r = await mock.echo({ text: "x".repeat(500) }); return [typeof r, r.text.length, "y".repeat(1000)];"#;
+        let result = engine.execute(code, None).await.unwrap();
+        assert!(result.text.starts_with("[\n  \"object\",\n  500,"), "{}", result.text);
+        assert!(result.text.contains("[truncated"), "{}", result.text);
+        assert!(result.text.len() < 300, "{}", result.text);
+
+        // A request-level max_length takes precedence over both.
+        let result = engine.execute(code, Some(50_000)).await.unwrap();
+        assert!(result.text.starts_with("[\n  \"object\""), "{}", result.text);
+        assert!(!result.text.contains("[truncated"), "{}", result.text);
+    }
+
+    #[cfg(feature = "otel")]
+    #[tokio::test]
+    async fn test_execute_exports_call_tool_spans() {
+        use opentelemetry::KeyValue;
+        use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider};
+        use tracing_subscriber::prelude::*;
+
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder().with_simple_exporter(exporter.clone()).build();
+        let subscriber = tracing_subscriber::registry().with(telemetry::layer_for(&provider));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let engine = echo_engine(SandboxConfig::default()).await;
+        let result = engine.execute("return await mock.traceparent({});", None).await.unwrap();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let span = |name: &str| spans.iter().find(|s| s.name == name).unwrap_or_else(|| panic!("no {name} span"));
+        let (execute, call) = (span("execute"), span("call_tool"));
+        assert_eq!(call.parent_span_id, execute.span_context.span_id());
+        assert!(call.attributes.contains(&KeyValue::new("server", "mock")));
+        assert!(call.attributes.contains(&KeyValue::new("tool", "traceparent")));
+
+        // The upstream server received the call span's context in `_meta`.
+        let expected = format!("00-{}-{}-01", call.span_context.trace_id(), call.span_context.span_id());
+        assert_eq!(result.text, format!("\"{expected}\""));
+    }
+
+    #[test]
+    fn test_truncation_keeps_nested_error() {
+        let mut items: Vec<serde_json::Value> = (0..500)
+            .map(|i| serde_json::json!({ "id": i, "title": format!("item number {i}") }))
+            .collect();
+        items.push(serde_json::json!({ "error": "no server named 'linear'" }));
+        let value = serde_json::json!({ "results": items });
+
+        let text = serde_json::to_string_pretty(&value).unwrap();
+        let out = truncate_preserving_errors(&value, text, 2_000, &TruncationNotice::Prose);
+
+        assert!(out.contains("[truncated"), "output: {out}");
+        assert!(out.contains("$.results[500].error: no server named 'linear'"), "output: {out}");
+    }
+
+    #[test]
+    fn test_extract_images_stops_at_max_depth() {
+        let image = serde_json::json!({ "type": "image", "data": "AAAA", "mimeType": "image/png" });
+        let mut shallow = serde_json::json!({ "content": [image.clone()] });
+        assert_eq!(extract_images(&mut shallow, 8, DEFAULT_MAX_IMAGES).len(), 1);
+
+        let mut deep = image;
+        for _ in 0..20 {
+            deep = serde_json::json!([deep]);
+        }
+        assert!(extract_images(&mut deep, 8, DEFAULT_MAX_IMAGES).is_empty());
+    }
+
+    #[test]
+    fn test_extract_images_stops_at_max_images() {
+        let image = |i: usize| serde_json::json!({ "type": "image", "data": format!("IMG{i}"), "mimeType": "image/png" });
+        let mut value = serde_json::json!({ "content": (0..5).map(image).collect::<Vec<_>>() });
+
+        let images = extract_images(&mut value, 8, 3);
+        let data: Vec<_> = images.iter().map(|i| i.data.as_str()).collect();
+        assert_eq!(data, ["IMG0", "IMG1", "IMG2"]);
+        assert_eq!(value["content"][2]["data"], "[image #2 extracted]");
+        assert_eq!(
+            value["content"][4]["data"],
+            "[image #4 omitted: only the first 3 images are returned]"
+        );
+    }
+
+    #[test]
+    fn test_json_truncation_stays_parseable() {
+        let items: Vec<serde_json::Value> = (0..1_000)
+            .map(|i| serde_json::json!({ "id": i, "title": format!("item number {i}"), "body": "x".repeat(200) }))
+            .collect();
+        let value = serde_json::json!(items);
+
+        let out = serde_json::to_string_pretty(&truncate_json(&value, 2_000)).unwrap();
+        assert!(out.len() <= 2_000, "len: {}", out.len());
+
+        let parsed: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(parsed["_truncated"], serde_json::json!(true));
+        let kept = parsed["value"].as_array().unwrap();
+        assert_eq!(kept[0]["id"], serde_json::json!(0));
+        assert!(kept.last().unwrap().as_str().unwrap().contains("more items"));
+    }
+
+    #[test]
+    fn test_truncation_marker_for_json_result() {
+        let items: Vec<serde_json::Value> = (0..200).map(|i| serde_json::json!({ "id": i })).collect();
+        let value = serde_json::json!({ "results": items });
+        let text = serde_json::to_string_pretty(&value).unwrap();
+        let total = text.len();
+
+        let out = truncate_preserving_errors(&value, text, 500, &TruncationNotice::Marker);
+        let (kept, marker) = out.rsplit_once("\n\n").unwrap();
+        let marker: serde_json::Value = serde_json::from_str(marker).unwrap();
+        assert_eq!(
+            marker,
+            serde_json::json!({ "__cmcp_truncated": { "omitted_chars": total - kept.len() } })
+        );
+        assert!(!out.contains("[truncated"), "output: {out}");
+
+        let template = TruncationNotice::Template("<cut {omitted}>".to_string());
+        let out = truncate_response_with("a\nb\nc".to_string(), 3, &template);
+        assert_eq!(out, "a\n\n<cut 4>");
+    }
+
+    #[test]
+    fn test_truncation_on_multibyte_boundary() {
+        // Each "🎨" is 4 bytes, so a cut at 10 lands mid-codepoint.
+        let text = format!("ab{}", "🎨".repeat(100));
+        let out = truncate_response(text, 10);
+        assert!(out.starts_with("ab🎨🎨\n\n[truncated"), "output: {out}");
+    }
+}
@@ -0,0 +1,281 @@
+//! Check agent code against the tool schemas before running it.
+//!
+//! `transpile::ts_to_js` only strips types, so `canva.create_design({ titl: "x" })`
+//! transpiles fine and fails upstream. This parses the code with oxc and checks
+//! the one shape that matters most: a call on a server proxy with an object
+//! literal argument. It reports unknown tools, properties the schema doesn't
+//! declare (like TypeScript's excess property check against the generated
+//! declarations) and missing required properties. Anything it can't see
+//! through (spreads, computed keys, non-literal arguments) is left alone, and
+//! so is a name the code declares itself, which shadows the proxy.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use oxc::allocator::Allocator;
+use oxc::ast::AstKind;
+use oxc::ast::ast::{Argument, CallExpression, Expression, ObjectExpression, ObjectPropertyKind};
+use oxc::parser::Parser;
+use oxc::semantic::{Scoping, SemanticBuilder};
+use oxc::span::SourceType;
+
+use crate::catalog::{Catalog, CatalogEntry};
+
+/// One problem found in agent code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// 1-based line of the call.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Lint `code` against the tools in `catalog`. Code that doesn't parse
+/// gets no diagnostics; running it reports the syntax error.
+pub fn lint(code: &str, catalog: &Catalog) -> Vec<Diagnostic> {
+    let js_names = catalog.js_names();
+    let mut servers: HashMap<&str, Vec<&CatalogEntry>> = HashMap::new();
+    for entry in catalog.entries() {
+        servers.entry(js_names[entry.server.as_str()].as_str()).or_default().push(entry);
+    }
+
+    // Wrapped the way the sandbox runs it, so top-level `await`, `return`
+    // and `yield` parse.
+    let allocator = Allocator::default();
+    let prefixes = ["async function __agent__() {\n", "async function* __agent__() {\n"];
+    let Some((prefix, program)) = prefixes.into_iter().find_map(|prefix| {
+        let source = allocator.alloc_str(&format!("{prefix}{code}\n}}"));
+        let parsed = Parser::new(&allocator, source, SourceType::ts()).parse();
+        parsed.errors.is_empty().then_some((prefix, parsed.program))
+    }) else {
+        return Vec::new();
+    };
+    let semantic = SemanticBuilder::new().build(&program).semantic;
+
+    let mut diagnostics = Vec::new();
+    for node in semantic.nodes().iter() {
+        let AstKind::CallExpression(call) = node.kind() else {
+            continue;
+        };
+        let Some((ident, tool)) = proxy_call(call, semantic.scoping()) else {
+            continue;
+        };
+        let Some(tools) = servers.get(ident) else {
+            continue;
+        };
+        let offset = call.span.start as usize - prefix.len();
+        let line = code[..offset].matches('\n').count() + 1;
+        let call_name = format!("{ident}.{tool}");
+        let Some(entry) = tools.iter().find(|t| t.name == tool) else {
+            let message = with_suggestion(
+                format!("{call_name}: no tool \"{tool}\" on {ident}"),
+                tool,
+                tools.iter().map(|t| t.name.as_str()),
+            );
+            diagnostics.push((offset, Diagnostic { line, message }));
+            continue;
+        };
+        if let Some(keys) = call_keys(call) {
+            diagnostics.extend(check_keys(entry, &keys).into_iter().map(|message| {
+                let message = format!("{call_name}: {message}");
+                (offset, Diagnostic { line, message })
+            }));
+        }
+    }
+    diagnostics.sort_by_key(|(offset, _)| *offset);
+    diagnostics.into_iter().map(|(_, d)| d).collect()
+}
+
+/// `server.tool(...)` or `server["tool"](...)` where `server` is a global,
+/// as `(server, tool)`.
+fn proxy_call<'a>(call: &CallExpression<'a>, scoping: &Scoping) -> Option<(&'a str, &'a str)> {
+    let member = call.callee.get_member_expr()?;
+    let Expression::Identifier(object) = member.object() else {
+        return None;
+    };
+    // Resolved to a declaration in the code, so not the proxy.
+    if scoping.get_reference(object.reference_id()).symbol_id().is_some() {
+        return None;
+    }
+    let tool = member.static_property_name().filter(|t| !t.is_empty())?;
+    Some((object.name.as_str(), tool))
+}
+
+/// The keys of a call's argument if it has none or an object literal.
+fn call_keys(call: &CallExpression) -> Option<ObjectKeys> {
+    match call.arguments.first() {
+        None => Some(ObjectKeys { names: Vec::new(), complete: true }),
+        Some(Argument::ObjectExpression(object)) => Some(object_keys(object)),
+        _ => None,
+    }
+}
+
+fn object_keys(object: &ObjectExpression) -> ObjectKeys {
+    let mut keys = ObjectKeys { names: Vec::new(), complete: true };
+    for property in &object.properties {
+        let name = match property {
+            ObjectPropertyKind::ObjectProperty(property) => property.key.static_name(),
+            ObjectPropertyKind::SpreadProperty(_) => None,
+        };
+        match name {
+            Some(name) => keys.names.push(name.into_owned()),
+            // Spread (`...rest`) or computed key (`[name]`).
+            None => keys.complete = false,
+        }
+    }
+    keys
+}
+
+/// Problems with the literal keys passed to `entry`.
+fn check_keys(entry: &CatalogEntry, keys: &ObjectKeys) -> Vec<String> {
+    let mut problems = Vec::new();
+    let schema = &entry.input_schema;
+    let open = matches!(schema.get("additionalProperties"), Some(v) if v != &serde_json::Value::Bool(false));
+    if let Some(properties) = schema.get("properties").and_then(|p| p.as_object())
+        && !open
+    {
+        for key in keys.names.iter().filter(|k| !properties.contains_key(k.as_str())) {
+            problems.push(with_suggestion(
+                format!("unknown property \"{key}\""),
+                key,
+                properties.keys().map(String::as_str),
+            ));
+        }
+    }
+    if keys.complete {
+        for required in entry.required.iter().filter(|r| !keys.names.contains(r)) {
+            problems.push(format!("missing required property \"{required}\""));
+        }
+    }
+    problems
+}
+
+/// Append "did you mean ...?" when one of `candidates` is a likely typo target.
+fn with_suggestion<'a>(message: String, name: &str, candidates: impl Iterator<Item = &'a str>) -> String {
+    let closest = candidates
+        .map(|c| (edit_distance(name, c), c))
+        .filter(|(d, _)| *d <= 2.max(name.len() / 3))
+        .min();
+    match closest {
+        Some((_, c)) => format!("{message} (did you mean \"{c}\"?)"),
+        None => message,
+    }
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Top-level keys of an object literal argument.
+struct ObjectKeys {
+    names: Vec<String>,
+    /// False when a spread or computed key may add keys we can't see.
+    complete: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn catalog() -> Catalog {
+        let mut catalog = Catalog::new();
+        let design = serde_json::json!({
+            "type": "object",
+            "properties": { "title": { "type": "string" }, "width": { "type": "number" } },
+            "required": ["title"],
+        });
+        let search = serde_json::json!({
+            "type": "object",
+            "properties": { "query": { "type": "string" } },
+            "additionalProperties": true,
+        });
+        let tool = |name: &str, schema: serde_json::Value| {
+            serde_json::from_value(serde_json::json!({ "name": name, "inputSchema": schema })).unwrap()
+        };
+        catalog.add_server_tools("canva", vec![tool("create_design", design), tool("search-designs", search)]);
+        catalog
+    }
+
+    fn messages(code: &str) -> Vec<String> {
+        lint(code, &catalog()).iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_wrong_property_is_flagged() {
+        let code = "const d = await canva.create_design({ titl: \"x\" });\nreturn d;";
+        assert_eq!(
+            messages(code),
+            [
+                "line 1: canva.create_design: unknown property \"titl\" (did you mean \"title\"?)",
+                "line 1: canva.create_design: missing required property \"title\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_valid_and_opaque_calls_pass() {
+        let code = r#"
+// canva.create_design({ bogus: 1 }) in a comment is ignored
+const note = "canva.create_design({ bogus: 1 })";
+await canva.create_design({ title: `a, ${b}`, width: f(1, { x: 2 }) });
+await canva.create_design({ ...defaults, width: 3 });
+await canva.create_design(params);
+await canva["search-designs"]({ query: "q", page: 2 });
+return other.canva.create_design({ bogus: 1 });
+"#;
+        assert_eq!(messages(code), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_regex_and_template_calls_are_checked() {
+        let code = r#"
+const quoted = /"/.test(input);
+await canva.create_design({ titl: "x" });
+return `${await canva.create_design({ title: "y", hieght: 2 })}`;
+"#;
+        assert_eq!(
+            messages(code),
+            [
+                "line 3: canva.create_design: unknown property \"titl\" (did you mean \"title\"?)",
+                "line 3: canva.create_design: missing required property \"title\"",
+                "line 4: canva.create_design: unknown property \"hieght\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_local_shadowing_a_server_is_ignored() {
+        let code = r#"
+const canva = { create_design: (o) => o };
+canva.create_design({ anything: 1 });
+function draw(canva) { return canva.bogus(); }
+return draw(canva);
+"#;
+        assert_eq!(messages(code), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_unknown_tool_is_flagged() {
+        let code = "\n\nawait canva.create_desing({ title: \"x\" });";
+        assert_eq!(
+            messages(code),
+            ["line 3: canva.create_desing: no tool \"create_desing\" on canva (did you mean \"create_design\"?)"]
+        );
+    }
+}
@@ -1,13 +1,19 @@
+mod doctor;
 mod import;
+mod probe;
 mod server;
 
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
+use base64::Engine;
+use clap::{CommandFactory, Parser, Subcommand};
 use cmcp_core::config;
 use cmcp_core::config::ServerConfig;
+use cmcp_core::sandbox::console_logs;
+use cmcp_core::secret;
 use rmcp::transport::stdio;
 use rmcp::ServiceExt;
 use tracing::info;
@@ -24,6 +30,11 @@ struct Cli {
     #[arg(short, long, global = true)]
     config: Option<PathBuf>,
 
+    /// Use the servers of this profile (`[profiles.<name>]`) instead of the
+    /// top-level ones. Defaults to $CMCP_PROFILE.
+    #[arg(long, global = true)]
+    profile: Option<String>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -35,14 +46,17 @@ enum Commands {
     /// Examples:
     ///   cmcp add canva https://mcp.canva.com/mcp
     ///   cmcp add canva https://mcp.canva.com/mcp --auth env:CANVA_TOKEN
+    ///   cmcp add canva https://mcp.canva.com/mcp --auth oauth
     ///   cmcp add --transport stdio github -- npx -y @modelcontextprotocol/server-github
     ///   cmcp add -e GITHUB_TOKEN=env:GITHUB_TOKEN --transport stdio github -- npx -y @modelcontextprotocol/server-github
     Add {
-        /// Transport type (http, stdio, sse). Defaults to http if a URL is given, stdio otherwise.
+        /// Transport type (http, stdio, sse, ws). Defaults to http for URLs (ws for ws:// and
+        /// wss://), stdio otherwise.
         #[arg(short, long)]
         transport: Option<String>,
 
-        /// Bearer auth token for http/sse (use "env:VAR" to read from environment).
+        /// Bearer auth token for http/sse/ws (use "env:VAR" to read from environment,
+        /// "keychain:service/account" from the OS keychain).
         #[arg(short, long)]
         auth: Option<String>,
 
@@ -58,6 +72,10 @@ enum Commands {
         #[arg(long, default_value = "local")]
         scope: String,
 
+        /// Connect to the server first and only save it if that works.
+        #[arg(long)]
+        check: bool,
+
         /// Server name (e.g. "canva", "github", "filesystem")
         name: String,
 
@@ -76,12 +94,71 @@ enum Commands {
         scope: String,
     },
 
+    /// Turn a server back on after `cmcp disable`.
+    Enable {
+        /// Server name to enable
+        name: String,
+
+        /// Scope: "local" (default), "user", or "project".
+        #[arg(long, default_value = "local")]
+        scope: String,
+    },
+
+    /// Keep a server in the config but stop connecting to it.
+    Disable {
+        /// Server name to disable
+        name: String,
+
+        /// Scope: "local" (default), "user", or "project".
+        #[arg(long, default_value = "local")]
+        scope: String,
+    },
+
     /// List configured servers and their tools.
     #[command(alias = "ls")]
     List {
         /// Only show server names (don't connect to fetch tools)
         #[arg(short, long)]
         short: bool,
+        /// Print a JSON array of servers (with their tools unless --short).
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Find tools by name or description, best match first.
+    ///
+    /// Examples:
+    ///   cmcp search issue
+    ///   cmcp search github lsis     # fuzzy: matches github.list_issues
+    Search {
+        /// Words that must each match a tool's name, server or description.
+        #[arg(required = true)]
+        query: Vec<String>,
+        /// Show at most this many matches.
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+    },
+
+    /// Connect to every enabled server (or just NAME) and report whether it
+    /// works, without starting the MCP server. Exits non-zero on any failure.
+    Test {
+        /// Server to test (default: all enabled servers)
+        name: Option<String>,
+    },
+
+    /// Check the config, server commands, secrets and Claude/Codex
+    /// registration, with a hint for each problem found.
+    Doctor,
+
+    /// Print a shell completion script.
+    ///
+    /// Examples:
+    ///   cmcp completions bash > ~/.local/share/bash-completion/completions/cmcp
+    ///   cmcp completions zsh > "${fpath[1]}/_cmcp"
+    ///   cmcp completions fish > ~/.config/fish/completions/cmcp.fish
+    Completions {
+        /// Shell to generate completions for.
+        shell: clap_complete::Shell,
     },
 
     /// Install cmcp into Claude and/or Codex.
@@ -123,6 +200,28 @@ enum Commands {
         /// Overwrite existing servers with the same name.
         #[arg(long)]
         force: bool,
+
+        /// Ask whether to add, update, or skip each discovered server.
+        #[arg(short, long)]
+        interactive: bool,
+
+        /// With --dry-run, print the configs that would be written as "toml" or "json".
+        #[arg(long)]
+        format: Option<String>,
+    },
+
+    /// Show how cmcp's servers differ from those configured in Claude or Codex.
+    ///
+    /// Lists servers only in cmcp, only in the source, and in both with
+    /// different configs, without changing anything.
+    ///
+    /// Examples:
+    ///   cmcp diff                      # against all sources
+    ///   cmcp diff --from claude        # only against Claude
+    Diff {
+        /// Source to compare with: "claude", "codex", or omit for all.
+        #[arg(short, long)]
+        from: Option<String>,
     },
 
     /// Uninstall cmcp from Claude and/or Codex.
@@ -156,13 +255,136 @@ enum Commands {
         args: Vec<String>,
     },
 
+    /// Call one tool directly and print its result, for scripting and debugging.
+    ///
+    /// Only the named server is connected. Images in the result are saved to
+    /// --out-dir as <server>-<tool>-<n>.<ext>.
+    ///
+    /// Example:
+    ///   cmcp exec --server github --tool list_issues --args '{"repo": "rust-lang/rust"}'
+    ///   cmcp exec --server github --tool list_issues --full
+    Exec {
+        /// Server name.
+        #[arg(long)]
+        server: String,
+
+        /// Tool name.
+        #[arg(long)]
+        tool: String,
+
+        /// Tool arguments as a JSON object.
+        #[arg(long, default_value = "{}")]
+        args: String,
+
+        /// Directory to save images to.
+        #[arg(long, default_value = ".")]
+        out_dir: PathBuf,
+
+        /// Print the whole result (content, structuredContent, isError) as JSON,
+        /// including when the tool reports an error.
+        #[arg(long)]
+        full: bool,
+    },
+
+    /// Run a TypeScript file in the same sandbox `execute` uses, connected to
+    /// the configured servers, and print its result. Console output goes to stderr.
+    ///
+    /// Example:
+    ///   cmcp run script.ts
+    ///   echo 'return tools.length;' | cmcp run --search -
+    Run {
+        /// The file to run, or "-" for stdin.
+        file: PathBuf,
+
+        /// Run it as a `search` over the tool catalog instead.
+        #[arg(long)]
+        search: bool,
+
+        /// Max response length in characters (default: 40000).
+        #[arg(long)]
+        max_length: Option<usize>,
+
+        /// Directory to save images to.
+        #[arg(long, default_value = ".")]
+        out_dir: PathBuf,
+    },
+
+    /// Check a TypeScript file's tool calls against the connected servers'
+    /// schemas without running it: unknown tools, unknown properties and
+    /// missing required properties. Exits non-zero if anything is found.
+    ///
+    /// Example:
+    ///   cmcp lint script.ts
+    Lint {
+        /// The file to check, or "-" for stdin.
+        file: PathBuf,
+    },
+
+    /// Authorize an HTTP server configured with `--auth oauth` in the browser.
+    ///
+    /// Tokens are stored in the config directory, next to config.toml, and
+    /// refreshed automatically when they expire.
+    Auth {
+        /// Server name.
+        name: String,
+    },
+
+    /// Manage tokens stored in the OS keychain.
+    Secret {
+        #[command(subcommand)]
+        action: SecretAction,
+    },
+
     /// Start the MCP server (used internally by Claude).
-    Serve,
+    Serve {
+        /// Only expose the servers/tools of this view (from `[views.<name>]` in config).
+        #[arg(long)]
+        view: Option<String>,
+
+        /// Answer the MCP handshake right away and connect servers in the background.
+        /// The first search/execute waits for the connections to finish.
+        #[arg(long)]
+        no_connect: bool,
+
+        /// Connect each server only when one of its tools is first called, so a
+        /// broken server doesn't slow startup. Implies --no-connect.
+        #[arg(long)]
+        lazy: bool,
+
+        /// Also answer `GET /healthz` on this address (e.g. 0.0.0.0:8081): 200 with
+        /// the connected-server count, 503 while none are connected. `GET /metrics`
+        /// there serves Prometheus metrics.
+        #[arg(long, value_name = "ADDR")]
+        probe: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum SecretAction {
+    /// Store a server's bearer token in the OS keychain and point its `auth` at it.
+    ///
+    /// Example:
+    ///   cmcp secret set github ghp_xxx
+    ///   op read op://dev/github/token | cmcp secret set github
+    Set {
+        /// Server name (also the keychain account).
+        server: String,
+
+        /// The token. Omit to read it from stdin, keeping it out of shell history.
+        token: Option<String>,
+
+        /// Scope of the config to update: "local" (default), "user", or "project".
+        #[arg(long, default_value = "local")]
+        scope: String,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
+    if let Some(profile) = &cli.profile {
+        config::set_profile(profile);
+    }
 
     match cli.command {
         Commands::Add {
@@ -171,19 +393,44 @@ async fn main() -> Result<()> {
             headers,
             envs,
             scope,
+            check,
             name,
             args,
-        } => cmd_add(cli.config.as_ref(), transport, auth, headers, envs, &scope, name, args),
+        } => {
+            // OAuth servers can't be checked before `cmcp auth` has run.
+            let check = check.then_some(auth.as_deref().and_then(config::oauth_scopes).is_none());
+            let server_config = parse_server_args(transport, auth, headers, envs, &args)?;
+            cmd_add(cli.config.as_ref(), &scope, check, name, server_config).await
+        }
 
         Commands::Remove { name, scope } => cmd_remove(cli.config.as_ref(), &name, &scope),
 
-        Commands::List { short } => cmd_list(cli.config.as_ref(), short).await,
+        Commands::Enable { name, scope } => cmd_set_enabled(cli.config.as_ref(), &name, &scope, true),
+
+        Commands::Disable { name, scope } => cmd_set_enabled(cli.config.as_ref(), &name, &scope, false),
+
+        Commands::List { short, json } => cmd_list(cli.config.as_ref(), short, json).await,
+
+        Commands::Search { query, limit } => cmd_search(cli.config.as_ref(), &query.join(" "), limit).await,
+
+        Commands::Test { name } => cmd_test(cli.config.as_ref(), name.as_deref()).await,
+
+        Commands::Doctor => doctor::run(cli.config.as_ref()),
+
+        Commands::Completions { shell } => {
+            clap_complete::generate(shell, &mut Cli::command(), "cmcp", &mut std::io::stdout());
+            Ok(())
+        }
 
         Commands::Import {
             from,
             dry_run,
             force,
-        } => cmd_import(cli.config.as_ref(), from, dry_run, force),
+            interactive,
+            format,
+        } => cmd_import(cli.config.as_ref(), from, dry_run, force, interactive, format),
+
+        Commands::Diff { from } => cmd_diff(cli.config.as_ref(), from.as_deref()),
 
         Commands::Install { target, scope } => cmd_install(cli.config.as_ref(), target.as_deref(), &scope),
 
@@ -193,25 +440,61 @@ async fn main() -> Result<()> {
 
         Commands::Codex { args } => cmd_passthrough_codex(cli.config.as_ref(), &args),
 
-        Commands::Serve => cmd_serve(cli.config.as_ref()).await,
+        Commands::Exec {
+            server,
+            tool,
+            args,
+            out_dir,
+            full,
+        } => cmd_exec(cli.config.as_ref(), &server, &tool, &args, &out_dir, full).await,
+
+        Commands::Run {
+            file,
+            search,
+            max_length,
+            out_dir,
+        } => cmd_run(cli.config.as_ref(), &file, search, max_length, &out_dir).await,
+
+        Commands::Lint { file } => cmd_lint(cli.config.as_ref(), &file).await,
+
+        Commands::Auth { name } => cmd_auth(cli.config.as_ref(), &name).await,
+
+        Commands::Secret {
+            action: SecretAction::Set { server, token, scope },
+        } => cmd_secret_set(cli.config.as_ref(), &server, token, &scope),
+
+        Commands::Serve {
+            view,
+            no_connect,
+            lazy,
+            probe,
+        } => {
+            cmd_serve(cli.config.as_ref(), view, no_connect, lazy, probe.as_deref()).await
+        }
     }
 }
 
-fn cmd_add(
+/// Save `server_config` as `name`. `check` is `None` without `--check`,
+/// `Some(false)` when the server can't be checked yet (OAuth).
+async fn cmd_add(
     config_path: Option<&PathBuf>,
-    transport: Option<String>,
-    auth: Option<String>,
-    headers: Vec<String>,
-    envs: Vec<String>,
     scope: &str,
+    check: Option<bool>,
     name: String,
-    args: Vec<String>,
+    server_config: ServerConfig,
 ) -> Result<()> {
     let scope = config::Scope::from_str(scope)?;
     let path = resolve_config_path(config_path, scope)?;
     let mut cfg = config::Config::load_from(&path)?;
 
-    let server_config = parse_server_args(transport, auth, headers, envs, &args)?;
+    if check == Some(false) {
+        println!("Skipping --check: authorize first with `cmcp auth {name}`");
+    } else if check == Some(true) {
+        let tools = cmcp_core::client::ClientPool::check(&name, &server_config)
+            .await
+            .with_context(|| format!("\"{name}\" was not saved. Fix the error, or add it without --check"))?;
+        println!("Connected to \"{name}\": {tools} tools");
+    }
 
     let already_exists = cfg.servers.contains_key(&name);
     cfg.add_server(name.clone(), server_config);
@@ -303,16 +586,15 @@ fn parse_server_args(
     // Use explicitly provided --transport, or one extracted from trailing args, or auto-detect.
     let transport = transport
         .or(trailing_transport)
-        .unwrap_or_else(|| {
-            if let Some(first) = args.first() {
-                if first.starts_with("http://") || first.starts_with("https://") {
-                    "http".to_string()
-                } else {
-                    "stdio".to_string()
+        .unwrap_or_else(|| match args.first() {
+            Some(first) => {
+                let (transport, hint) = config::detect_transport(first);
+                if let Some(hint) = hint {
+                    eprintln!("hint: {hint}");
                 }
-            } else {
-                "http".to_string()
+                transport.to_string()
             }
+            None => "http".to_string(),
         });
 
     match transport.as_str() {
@@ -325,6 +607,7 @@ fn parse_server_args(
                 url,
                 auth,
                 headers: parse_headers(&headers),
+                options: Default::default(),
             })
         }
         "sse" => {
@@ -336,6 +619,19 @@ fn parse_server_args(
                 url,
                 auth,
                 headers: parse_headers(&headers),
+                options: Default::default(),
+            })
+        }
+        "ws" => {
+            let url = args
+                .first()
+                .context("missing URL. Usage: cmcp add --transport ws <name> <url>")?
+                .clone();
+            Ok(ServerConfig::Ws {
+                url,
+                auth,
+                headers: parse_headers(&headers),
+                options: Default::default(),
             })
         }
         "stdio" => {
@@ -356,9 +652,10 @@ fn parse_server_args(
                 command,
                 args: cmd_args,
                 env: parse_envs(&envs),
+                options: Default::default(),
             })
         }
-        other => anyhow::bail!("unknown transport \"{other}\". Use: http, stdio, or sse"),
+        other => anyhow::bail!("unknown transport \"{other}\". Use: http, stdio, sse, or ws"),
     }
 }
 
@@ -376,9 +673,244 @@ fn cmd_remove(config_path: Option<&PathBuf>, name: &str, scope: &str) -> Result<
     Ok(())
 }
 
-async fn cmd_list(config_path: Option<&PathBuf>, short: bool) -> Result<()> {
+fn cmd_set_enabled(config_path: Option<&PathBuf>, name: &str, scope: &str, enabled: bool) -> Result<()> {
+    let scope = config::Scope::from_str(scope)?;
+    let path = resolve_config_path(config_path, scope)?;
+    let mut cfg = config::Config::load_from(&path)?;
+    // Enabled is the default, so drop the flag unless [defaults] disables servers.
+    let flag = match enabled {
+        true => (cfg.defaults.enabled == Some(false)).then_some(true),
+        false => Some(false),
+    };
+    let server = cfg
+        .servers
+        .get_mut(name)
+        .with_context(|| format!("server \"{name}\" not found in {}", path.display()))?;
+    server.options_mut().enabled = flag;
+    cfg.save_to(&path)?;
+
+    let action = if enabled { "Enabled" } else { "Disabled" };
+    println!("{action} server \"{name}\"");
+    Ok(())
+}
+
+async fn cmd_exec(
+    config_path: Option<&PathBuf>,
+    server: &str,
+    tool: &str,
+    args: &str,
+    out_dir: &Path,
+    full: bool,
+) -> Result<()> {
+    let arguments = parse_tool_args(args)?;
+    let engine = single_server_engine(config_path, server).await?;
+    if !full {
+        let result = engine.call_tool(server, tool, arguments).await?;
+        println!("{}", result.text);
+        return save_images(&result.images, out_dir, server, tool);
+    }
+
+    let (result, images) = engine.call_tool_result(server, tool, arguments).await?;
+
+    println!("{}", serde_json::to_string_pretty(&result)?);
+    save_images(&images, out_dir, server, tool)?;
+    if result.is_error == Some(true) {
+        anyhow::bail!("{server}.{tool} returned an error");
+    }
+    Ok(())
+}
+
+async fn cmd_run(
+    config_path: Option<&PathBuf>,
+    file: &Path,
+    search: bool,
+    max_length: Option<usize>,
+    out_dir: &Path,
+) -> Result<()> {
+    let code = read_code(file)?;
+    let cfg = config::Config::load_merged(config_path)?;
+
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_env_filter(EnvFilter::from_default_env())
+        .init();
+
+    let engine = cmcp_core::ProxyEngine::from_configs(cfg.servers, cfg.sandbox, false).await?;
+    if search {
+        let result = engine.search(&code, max_length).await.inspect_err(|e| print_console(console_logs(e)))?;
+        print_console(&result.logs);
+        println!("{}", serde_json::to_string_pretty(&result.value)?);
+    } else {
+        let result = engine.execute(&code, max_length).await.inspect_err(|e| print_console(console_logs(e)))?;
+        print_console(&result.logs);
+        println!("{}", result.text);
+        save_images(&result.images, out_dir, "run", "result")?;
+    }
+    Ok(())
+}
+
+async fn cmd_lint(config_path: Option<&PathBuf>, file: &Path) -> Result<()> {
+    let code = read_code(file)?;
+    let cfg = config::Config::load_merged(config_path)?;
+
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_env_filter(EnvFilter::from_default_env())
+        .init();
+
+    let engine = cmcp_core::ProxyEngine::from_configs(cfg.servers, cfg.sandbox, false).await?;
+    let diagnostics = engine.lint(&code).await;
+    for diagnostic in &diagnostics {
+        println!("{diagnostic}");
+    }
+    if !diagnostics.is_empty() {
+        anyhow::bail!("{} problem(s) found", diagnostics.len());
+    }
+    Ok(())
+}
+
+/// Read agent code from `file`, or stdin for "-".
+fn read_code(file: &Path) -> Result<String> {
+    if file == Path::new("-") {
+        std::io::read_to_string(std::io::stdin()).context("failed to read code from stdin")
+    } else {
+        std::fs::read_to_string(file).with_context(|| format!("failed to read {}", file.display()))
+    }
+}
+
+fn print_console(logs: &[cmcp_core::sandbox::ConsoleLine]) {
+    for line in logs {
+        eprintln!("{line}");
+    }
+}
+
+fn parse_tool_args(args: &str) -> Result<serde_json::Value> {
+    let arguments: serde_json::Value = serde_json::from_str(args).context("--args must be a JSON object")?;
+    if !arguments.is_object() {
+        anyhow::bail!("--args must be a JSON object");
+    }
+    Ok(arguments)
+}
+
+/// An engine with just `server` from the merged config, connected lazily so a
+/// failed connection is reported as the error of the call.
+async fn single_server_engine(config_path: Option<&PathBuf>, server: &str) -> Result<cmcp_core::ProxyEngine> {
     let cfg = config::Config::load_merged(config_path)?;
+    let server_config = cfg
+        .servers
+        .get(server)
+        .with_context(|| format!("server \"{server}\" not found"))?
+        .clone();
+    if !server_config.is_enabled() {
+        anyhow::bail!("server \"{server}\" is disabled. Run: cmcp enable {server}");
+    }
+
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_env_filter(EnvFilter::from_default_env())
+        .init();
 
+    let servers = HashMap::from([(server.to_string(), server_config)]);
+    cmcp_core::ProxyEngine::from_configs(servers, cfg.sandbox, true).await
+}
+
+/// Save images as `<server>-<tool>-<n>.<ext>` in `out_dir`.
+fn save_images(images: &[cmcp_core::ImageData], out_dir: &Path, server: &str, tool: &str) -> Result<()> {
+    for (i, image) in images.iter().enumerate() {
+        let ext = match image.mime_type.as_str() {
+            "image/png" => "png",
+            "image/jpeg" => "jpg",
+            "image/gif" => "gif",
+            "image/webp" => "webp",
+            "image/svg+xml" => "svg",
+            _ => "bin",
+        };
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(&image.data)
+            .with_context(|| format!("image #{i} is not valid base64"))?;
+        let path = out_dir.join(format!("{server}-{tool}-{i}.{ext}"));
+        std::fs::write(&path, bytes).with_context(|| format!("failed to write {}", path.display()))?;
+        eprintln!("Saved image #{i} to {}", path.display());
+    }
+    Ok(())
+}
+
+async fn cmd_auth(config_path: Option<&PathBuf>, name: &str) -> Result<()> {
+    let cfg = config::Config::load_merged(config_path)?;
+    let server = cfg
+        .servers
+        .get(name)
+        .with_context(|| format!("server \"{name}\" not found"))?;
+    let (ServerConfig::Http { url, auth, .. } | ServerConfig::Sse { url, auth, .. }) = server else {
+        anyhow::bail!("OAuth is only supported for http and sse servers");
+    };
+    let scopes = auth.as_deref().and_then(config::oauth_scopes).with_context(|| {
+        format!("\"{name}\" does not use OAuth. Re-add it with: cmcp add {name} {url} --auth oauth")
+    })?;
+
+    let url = secret::resolve_secret_async(url, server.options().strict_env == Some(true), &Default::default()).await?;
+    cmcp_core::oauth::authorize(name, &url, &scopes).await?;
+    println!("Authorized \"{name}\". Token stored in {}", config::oauth_token_path(name)?.display());
+    Ok(())
+}
+
+fn cmd_secret_set(config_path: Option<&PathBuf>, server: &str, token: Option<String>, scope: &str) -> Result<()> {
+    let token = match token {
+        Some(token) => token,
+        None => {
+            let mut line = String::new();
+            std::io::stdin().read_line(&mut line).context("failed to read the token from stdin")?;
+            line.trim().to_string()
+        }
+    };
+    if token.is_empty() {
+        anyhow::bail!("the token is empty");
+    }
+    let scope_name = scope;
+    let scope = config::Scope::from_str(scope)?;
+    let path = resolve_config_path(config_path, scope)?;
+    let mut cfg = config::Config::load_from(&path)?;
+    let Some(entry) = cfg.servers.get_mut(server) else {
+        anyhow::bail!(
+            "server \"{server}\" not found in the {scope_name} config ({}). Pass the --scope it was added with",
+            path.display()
+        );
+    };
+
+    secret::keychain_set(secret::KEYCHAIN_SERVICE, server, &token)?;
+    let reference = secret::keychain_ref(server);
+    println!("Stored the token in the keychain as {reference}");
+    match entry {
+        ServerConfig::Http { auth, .. } | ServerConfig::Sse { auth, .. } | ServerConfig::Ws { auth, .. } => {
+            *auth = Some(reference.clone());
+            cfg.save_to(&path)?;
+            println!("\"{server}\" now uses auth = \"{reference}\"");
+        }
+        ServerConfig::Stdio { .. } => {
+            println!("Reference it from \"{server}\"'s env, e.g. -e TOKEN={reference}");
+        }
+    }
+    Ok(())
+}
+
+async fn cmd_list(config_path: Option<&PathBuf>, short: bool, json: bool) -> Result<()> {
+    let cfg = config::Config::load_merged(config_path)?;
+
+    if json {
+        let catalog = if short { None } else { Some(list_catalog(cfg.servers.clone()).await?) };
+        let mut servers: Vec<_> = cfg.servers.iter().collect();
+        servers.sort_by(|a, b| a.0.cmp(b.0));
+        let servers: Vec<_> = servers
+            .into_iter()
+            .map(|(name, server)| server_json(name, server, catalog.as_ref()))
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&servers)?);
+        return Ok(());
+    }
+
+    if let Some(profile) = config::active_profile() {
+        println!("Profile: {profile}\n");
+    }
     if cfg.servers.is_empty() {
         println!("No servers configured. Add one with: cmcp add <name> <url>");
         return Ok(());
@@ -389,24 +921,33 @@ async fn cmd_list(config_path: Option<&PathBuf>, short: bool) -> Result<()> {
             let transport_info = match server_config {
                 ServerConfig::Http { url, .. } => format!("http  {url}"),
                 ServerConfig::Sse { url, .. } => format!("sse   {url}"),
+                ServerConfig::Ws { url, .. } => format!("ws    {url}"),
                 ServerConfig::Stdio { command, args, .. } => {
                     format!("stdio {} {}", command, args.join(" "))
                 }
             };
-            println!("  {name:20} {transport_info}");
+            if server_config.is_enabled() {
+                println!("  {name:20} {transport_info}");
+            } else {
+                println!("{}", dim(&format!("  {name:20} {transport_info} (disabled)")));
+            }
         }
         return Ok(());
     }
 
-    // Full listing: connect and show tools
-    tracing_subscriber::fmt()
-        .with_writer(std::io::stderr)
-        .with_env_filter(EnvFilter::from_default_env())
-        .init();
-
-    let (_pool, catalog) = cmcp_core::client::ClientPool::connect(cfg.servers).await?;
+    let mut disabled: Vec<String> = cfg
+        .servers
+        .iter()
+        .filter(|(_, c)| !c.is_enabled())
+        .map(|(name, _)| name.clone())
+        .collect();
+    disabled.sort();
+    let catalog = list_catalog(cfg.servers).await?;
 
     println!("{}\n", catalog.summary());
+    if !disabled.is_empty() {
+        println!("{}\n", dim(&format!("Disabled: {}", disabled.join(", "))));
+    }
     for entry in catalog.entries() {
         println!("  {}.{}", entry.server, entry.name);
         if !entry.description.is_empty() {
@@ -422,20 +963,160 @@ async fn cmd_list(config_path: Option<&PathBuf>, short: bool) -> Result<()> {
     Ok(())
 }
 
+async fn cmd_search(config_path: Option<&PathBuf>, query: &str, limit: usize) -> Result<()> {
+    let cfg = config::Config::load_merged(config_path)?;
+
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_env_filter(EnvFilter::from_default_env())
+        .init();
+
+    let engine = cmcp_core::ProxyEngine::from_configs(cfg.servers, cfg.sandbox, false).await?;
+    let matches = engine.search_tools(query).await;
+    if matches.is_empty() {
+        println!("No tools match \"{query}\"");
+        return Ok(());
+    }
+    for entry in matches.iter().take(limit) {
+        println!("  {}.{}", entry.server, entry.name);
+        if !entry.description.is_empty() {
+            let desc: String = entry.description.chars().take(100).collect();
+            let ellipsis = if desc.len() < entry.description.len() { "..." } else { "" };
+            println!("    {desc}{ellipsis}");
+        }
+    }
+    if matches.len() > limit {
+        println!("{}", dim(&format!("  ... {} more (use --limit)", matches.len() - limit)));
+    }
+    Ok(())
+}
+
+async fn cmd_test(config_path: Option<&PathBuf>, name: Option<&str>) -> Result<()> {
+    let cfg = config::Config::load_merged(config_path)?;
+    let mut servers: Vec<(String, ServerConfig)> = match name {
+        Some(name) => {
+            let server = cfg
+                .servers
+                .get(name)
+                .with_context(|| format!("server \"{name}\" not found"))?;
+            vec![(name.to_string(), server.clone())]
+        }
+        None => cfg.servers.into_iter().filter(|(_, c)| c.is_enabled()).collect(),
+    };
+    if servers.is_empty() {
+        println!("No servers to test. Add one with: cmcp add <name> <url>");
+        return Ok(());
+    }
+    servers.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let checks = servers.iter().map(|(name, server)| async move {
+        let start = std::time::Instant::now();
+        let result = cmcp_core::client::ClientPool::check(name, server).await;
+        (name, result, start.elapsed().as_millis())
+    });
+    let results = futures::future::join_all(checks).await;
+
+    let width = servers.iter().map(|(name, _)| name.len()).max().unwrap_or(0).max(6);
+    println!("{:width$}  {:6}  {:>5}  {:>6}", "SERVER", "STATUS", "TOOLS", "MS");
+    for (name, result, ms) in &results {
+        match result {
+            Ok(tools) => println!("{name:width$}  {:6}  {tools:>5}  {ms:>6}", "ok"),
+            Err(_) => println!("{name:width$}  {:6}  {:>5}  {ms:>6}", "FAIL", "-"),
+        }
+    }
+
+    let failed: Vec<_> = results.iter().filter(|(_, result, _)| result.is_err()).collect();
+    if failed.is_empty() {
+        return Ok(());
+    }
+    println!();
+    for (_, result, _) in &failed {
+        if let Err(e) = result {
+            println!("{e:#}");
+        }
+    }
+    anyhow::bail!("{} of {} servers failed", failed.len(), results.len())
+}
+
+/// Connect to every enabled server for a full `cmcp list`.
+async fn list_catalog(servers: HashMap<String, ServerConfig>) -> Result<cmcp_core::catalog::Catalog> {
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_env_filter(EnvFilter::from_default_env())
+        .init();
+    let (_pool, catalog) = cmcp_core::client::ClientPool::connect(servers, false).await?;
+    Ok(catalog)
+}
+
+/// One server in `cmcp list --json`. `tool_count` and `tools` come from
+/// `catalog` and are left out with `--short` or when the server didn't connect.
+fn server_json(name: &str, server: &ServerConfig, catalog: Option<&cmcp_core::catalog::Catalog>) -> serde_json::Value {
+    let mut entry = match server {
+        ServerConfig::Http { url, .. } => serde_json::json!({ "transport": "http", "url": url }),
+        ServerConfig::Sse { url, .. } => serde_json::json!({ "transport": "sse", "url": url }),
+        ServerConfig::Ws { url, .. } => serde_json::json!({ "transport": "ws", "url": url }),
+        ServerConfig::Stdio { command, args, .. } => {
+            serde_json::json!({ "transport": "stdio", "command": command, "args": args })
+        }
+    };
+    entry["name"] = name.into();
+    entry["enabled"] = server.is_enabled().into();
+    if let Some(catalog) = catalog
+        && catalog.servers().iter().any(|s| s == name)
+    {
+        let tools: Vec<_> = catalog
+            .entries()
+            .iter()
+            .filter(|e| e.server == name)
+            .map(|e| serde_json::json!({ "name": e.name, "description": e.description, "input_schema": e.input_schema }))
+            .collect();
+        entry["tool_count"] = tools.len().into();
+        entry["tools"] = tools.into();
+    }
+    entry
+}
+
+/// Dim `text` when stdout is a terminal.
+fn dim(text: &str) -> String {
+    if std::io::stdout().is_terminal() {
+        format!("\x1b[2m{text}\x1b[0m")
+    } else {
+        text.to_string()
+    }
+}
+
+fn parse_import_source(from: Option<&str>) -> Result<Option<import::ImportSource>> {
+    match from {
+        Some("claude" | "claude-code") => Ok(Some(import::ImportSource::ClaudeCode)),
+        Some("codex" | "openai") => Ok(Some(import::ImportSource::Codex)),
+        Some(other) => anyhow::bail!(
+            "unknown source \"{other}\". Use: claude, codex, or omit for all"
+        ),
+        None => Ok(None),
+    }
+}
+
+fn cmd_diff(config_path: Option<&PathBuf>, from: Option<&str>) -> Result<()> {
+    let discovered = import::discover(parse_import_source(from)?)?;
+    let cfg = config::Config::load(config_path)?;
+    println!("{}", import::render_diff(&import::diff(&cfg.servers, &discovered)));
+    Ok(())
+}
+
 fn cmd_import(
     config_path: Option<&PathBuf>,
     from: Option<String>,
     dry_run: bool,
     force: bool,
+    interactive: bool,
+    format: Option<String>,
 ) -> Result<()> {
-    let source_filter = match from.as_deref() {
-        Some("claude" | "claude-code") => Some(import::ImportSource::ClaudeCode),
-        Some("codex" | "openai") => Some(import::ImportSource::Codex),
-        Some(other) => anyhow::bail!(
-            "unknown source \"{other}\". Use: claude, codex, or omit for all"
-        ),
-        None => None,
-    };
+    let format = format.as_deref().map(import::PlanFormat::from_str).transpose()?;
+    if format.is_some() && !dry_run {
+        anyhow::bail!("--format is only supported with --dry-run");
+    }
+
+    let source_filter = parse_import_source(from.as_deref())?;
 
     let discovered = import::discover(source_filter)?;
 
@@ -455,40 +1136,65 @@ fn cmd_import(
     let mut skipped = 0;
     let mut updated = 0;
 
-    for server in &discovered {
-        let exists = cfg.servers.contains_key(&server.name);
+    // Without a terminal to prompt on, fall back to the flag-driven import.
+    let actions = if interactive && std::io::stdin().is_terminal() {
+        import::prompt_actions(
+            &discovered,
+            |name| cfg.servers.contains_key(name),
+            &mut std::io::stdin().lock(),
+            &mut std::io::stdout(),
+        )?
+    } else {
+        discovered
+            .iter()
+            .map(|server| match (cfg.servers.contains_key(&server.name), force) {
+                (true, false) => import::ImportAction::Skip,
+                (true, true) => import::ImportAction::Update,
+                (false, _) => import::ImportAction::Add,
+            })
+            .collect()
+    };
+
+    // With --format the plan is printed as config instead of a table.
+    let table = dry_run && format.is_none();
+    let mut planned = Vec::new();
 
-        let transport_info = match &server.config {
-            ServerConfig::Http { url, .. } => format!("http  {url}"),
-            ServerConfig::Sse { url, .. } => format!("sse   {url}"),
-            ServerConfig::Stdio { command, args, .. } => {
-                format!("stdio {} {}", command, args.join(" "))
-            }
-        };
+    for (server, action) in discovered.iter().zip(actions) {
+        let transport_info = server.display_summary();
+        if action != import::ImportAction::Skip {
+            planned.push(server);
+        }
 
-        if exists && !force {
-            if dry_run {
-                println!("  skip  {:<20} {:<12} {} (already exists)", server.name, server.source, transport_info);
+        match action {
+            import::ImportAction::Skip => {
+                if table {
+                    let note = if cfg.servers.contains_key(&server.name) { " (already exists)" } else { "" };
+                    println!("  skip  {:<20} {:<12} {}{note}", server.name, server.source, transport_info);
+                }
+                skipped += 1;
             }
-            skipped += 1;
-        } else if exists && force {
-            if dry_run {
-                println!("  update {:<19} {:<12} {}", server.name, server.source, transport_info);
-            } else {
-                cfg.add_server(server.name.clone(), server.config.clone());
+            import::ImportAction::Update => {
+                if table {
+                    println!("  update {:<19} {:<12} {}", server.name, server.source, transport_info);
+                } else if !dry_run {
+                    cfg.add_server(server.name.clone(), server.config.clone());
+                }
+                updated += 1;
             }
-            updated += 1;
-        } else {
-            if dry_run {
-                println!("  add   {:<20} {:<12} {}", server.name, server.source, transport_info);
-            } else {
-                cfg.add_server(server.name.clone(), server.config.clone());
+            import::ImportAction::Add => {
+                if table {
+                    println!("  add   {:<20} {:<12} {}", server.name, server.source, transport_info);
+                } else if !dry_run {
+                    cfg.add_server(server.name.clone(), server.config.clone());
+                }
+                added += 1;
             }
-            added += 1;
         }
     }
 
-    if dry_run {
+    if let Some(format) = format {
+        print!("{}", import::render_plan(planned, format)?);
+    } else if dry_run {
         println!();
         println!("Dry run: {} to add, {} to update, {} to skip", added, updated, skipped);
         println!("Run without --dry-run to apply.");
@@ -496,7 +1202,7 @@ fn cmd_import(
         cfg.save(config_path)?;
         let path = config_path
             .cloned()
-            .unwrap_or_else(|| config::default_config_path().unwrap());
+            .map_or_else(config::default_config_path, Ok)?;
 
         if added > 0 || updated > 0 {
             println!("Imported {} server(s) ({} added, {} updated, {} skipped)", added + updated, added, updated, skipped);
@@ -515,7 +1221,7 @@ fn cmd_install(config_path: Option<&PathBuf>, target: Option<&str>, scope: &str)
 
     let config_path = config_path
         .cloned()
-        .unwrap_or_else(|| config::default_config_path().unwrap());
+        .map_or_else(config::default_config_path, Ok)?;
 
     let install_claude = target.is_none() || matches!(target, Some("claude"));
     let install_codex = target.is_none() || matches!(target, Some("codex" | "openai"));
@@ -864,12 +1570,20 @@ fn cmd_passthrough_codex(config_path: Option<&PathBuf>, raw_args: &[String]) ->
         .context("missing server name")?
         .clone();
 
-    let server_config = if let Some(url) = url {
+    let server_config = if let Some(url) = url.as_ref().filter(|u| config::is_websocket_url(u)) {
+        ServerConfig::Ws {
+            url: url.clone(),
+            auth,
+            headers: HashMap::new(),
+            options: Default::default(),
+        }
+    } else if let Some(url) = url {
         // HTTP server
         ServerConfig::Http {
             url,
             auth,
             headers: HashMap::new(),
+            options: Default::default(),
         }
     } else {
         // Stdio server — remaining positional args are command + args
@@ -884,6 +1598,7 @@ fn cmd_passthrough_codex(config_path: Option<&PathBuf>, raw_args: &[String]) ->
             command,
             args,
             env: envs,
+            options: Default::default(),
         }
     };
 
@@ -900,29 +1615,76 @@ fn cmd_passthrough_codex(config_path: Option<&PathBuf>, raw_args: &[String]) ->
 
     let path = config_path
         .cloned()
-        .unwrap_or_else(|| config::default_config_path().unwrap());
+        .map_or_else(config::default_config_path, Ok)?;
     println!("Config: {}", path.display());
     Ok(())
 }
 
-async fn cmd_serve(config_path: Option<&PathBuf>) -> Result<()> {
-    tracing_subscriber::fmt()
-        .with_writer(std::io::stderr)
-        .with_env_filter(EnvFilter::from_default_env())
-        .init();
+async fn cmd_serve(
+    config_path: Option<&PathBuf>,
+    view: Option<String>,
+    no_connect: bool,
+    lazy: bool,
+    probe: Option<&str>,
+) -> Result<()> {
+    let (mut cfg, report) = config::Config::load_merged_with_report(config_path)?;
+    init_serve_tracing(&cfg.telemetry)?;
 
-    let cfg = config::Config::load_merged(config_path)?;
+    if let Some(name) = &view {
+        cfg.select_view(name)?;
+        info!(view = %name, "serving view");
+    }
+
+    for s in &report.shadowed {
+        info!(
+            server = %s.server,
+            "{} config overrides the {} config definition",
+            s.winner,
+            s.shadowed
+        );
+    }
 
     info!(
         server_count = cfg.servers.len(),
+        background = no_connect,
+        lazy,
         "connecting to upstream servers (user + project configs merged)"
     );
 
-    let server = crate::server::CodeModeServer::new(cfg.servers, config_path.cloned()).await?;
+    let server = crate::server::CodeModeServer::new(cfg, config_path.cloned(), view, no_connect, lazy).await?;
+    if let Some(addr) = probe {
+        let addr = crate::probe::spawn(addr, server.engine()).await?;
+        info!(%addr, "health probe listening on /healthz and /metrics");
+    }
 
     info!("starting MCP server on stdio (hot-reload enabled)");
     let service = server.serve(stdio()).await?;
     service.waiting().await?;
 
+    #[cfg(feature = "otel")]
+    cmcp_core::telemetry::shutdown();
+    Ok(())
+}
+
+/// Log to stderr and, when built with the `otel` feature, export spans as
+/// configured under `[telemetry]`.
+fn init_serve_tracing(telemetry: &config::TelemetryConfig) -> Result<()> {
+    use tracing_subscriber::prelude::*;
+
+    let fmt = tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_filter(EnvFilter::from_default_env());
+    #[cfg(feature = "otel")]
+    tracing_subscriber::registry()
+        .with(fmt)
+        .with(cmcp_core::telemetry::layer(telemetry)?)
+        .init();
+    #[cfg(not(feature = "otel"))]
+    {
+        tracing_subscriber::registry().with(fmt).init();
+        if telemetry.otlp_endpoint.is_some() {
+            tracing::warn!("[telemetry] is configured, but cmcp was built without the otel feature");
+        }
+    }
     Ok(())
 }
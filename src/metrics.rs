@@ -0,0 +1,168 @@
+//! Counters for `cmcp serve`, rendered in the Prometheus text format.
+//!
+//! One [`Metrics`] lives as long as the [`crate::ProxyEngine`] and is handed
+//! to every pool it builds, so counters keep counting across hot reloads.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Label for calls to servers or tools that aren't in the catalog, so agent
+/// code can't grow the label set without bound.
+pub const UNKNOWN: &str = "unknown";
+
+/// Upper bounds (seconds) of the tool call latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Calls, errors and latencies of one tool.
+#[derive(Debug, Default, Clone)]
+struct ToolMetrics {
+    calls: u64,
+    errors: u64,
+    /// Calls per latency bucket, not cumulative; the last one is `+Inf`.
+    buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    seconds: f64,
+}
+
+/// Process-wide counters exposed on `/metrics`.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Keyed by (server, tool).
+    tools: Mutex<BTreeMap<(String, String), ToolMetrics>>,
+    reloads: AtomicU64,
+    failed_reloads: AtomicU64,
+}
+
+impl Metrics {
+    /// Count one upstream tool call. `failed` covers both transport errors
+    /// and results the server flagged with `isError`.
+    pub fn record_call(&self, server: &str, tool: &str, latency: Duration, failed: bool) {
+        let seconds = latency.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        let mut tools = self.tools.lock().unwrap();
+        let metrics = tools.entry((server.to_string(), tool.to_string())).or_default();
+        metrics.calls += 1;
+        metrics.errors += u64::from(failed);
+        metrics.buckets[bucket] += 1;
+        metrics.seconds += seconds;
+    }
+
+    /// Count one config hot reload.
+    pub fn record_reload(&self, succeeded: bool) {
+        let counter = if succeeded { &self.reloads } else { &self.failed_reloads };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Render everything in the Prometheus text exposition format, with
+    /// `servers` (name, connected) as the connection gauge.
+    pub fn render(&self, servers: &[(String, bool)]) -> String {
+        let tools = self.tools.lock().unwrap().clone();
+        let mut out = String::new();
+
+        header(&mut out, "cmcp_tool_calls_total", "counter", "Upstream tool calls.");
+        for ((server, tool), m) in &tools {
+            let _ = writeln!(out, "cmcp_tool_calls_total{{{}}} {}", tool_labels(server, tool), m.calls);
+        }
+        header(&mut out, "cmcp_tool_errors_total", "counter", "Upstream tool calls that failed or returned an error result.");
+        for ((server, tool), m) in &tools {
+            let _ = writeln!(out, "cmcp_tool_errors_total{{{}}} {}", tool_labels(server, tool), m.errors);
+        }
+        header(&mut out, "cmcp_tool_call_duration_seconds", "histogram", "Upstream tool call latency.");
+        for ((server, tool), m) in &tools {
+            let labels = tool_labels(server, tool);
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(&m.buckets) {
+                cumulative += count;
+                let _ = writeln!(out, "cmcp_tool_call_duration_seconds_bucket{{{labels},le=\"{bound}\"}} {cumulative}");
+            }
+            let _ = writeln!(out, "cmcp_tool_call_duration_seconds_bucket{{{labels},le=\"+Inf\"}} {}", m.calls);
+            let _ = writeln!(out, "cmcp_tool_call_duration_seconds_sum{{{labels}}} {}", m.seconds);
+            let _ = writeln!(out, "cmcp_tool_call_duration_seconds_count{{{labels}}} {}", m.calls);
+        }
+
+        header(&mut out, "cmcp_reloads_total", "counter", "Config hot reloads, by result.");
+        let _ = writeln!(out, "cmcp_reloads_total{{result=\"ok\"}} {}", self.reloads.load(Ordering::Relaxed));
+        let _ = writeln!(out, "cmcp_reloads_total{{result=\"error\"}} {}", self.failed_reloads.load(Ordering::Relaxed));
+
+        header(&mut out, "cmcp_server_connected", "gauge", "Whether an upstream server is connected (1) or not (0).");
+        for (server, connected) in servers {
+            let _ = writeln!(out, "cmcp_server_connected{{server=\"{}\"}} {}", escape(server), u8::from(*connected));
+        }
+        header(&mut out, "cmcp_servers_connected", "gauge", "Connected upstream servers.");
+        let connected = servers.iter().filter(|(_, c)| *c).count();
+        let _ = writeln!(out, "cmcp_servers_connected {connected}");
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn tool_labels(server: &str, tool: &str) -> String {
+    format!("server=\"{}\",tool=\"{}\"", escape(server), escape(tool))
+}
+
+/// Escape a label value: backslash, double quote and newline.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse exposition text into `name{labels}` -> value, failing on malformed lines.
+    fn parse(text: &str) -> BTreeMap<String, f64> {
+        text.lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| {
+                let (series, value) = line.rsplit_once(' ').unwrap_or_else(|| panic!("bad line: {line}"));
+                let name_end = series.find('{').unwrap_or(series.len());
+                assert!(
+                    series[..name_end].chars().all(|c| c.is_ascii_alphanumeric() || c == '_'),
+                    "bad metric name: {line}"
+                );
+                if name_end < series.len() {
+                    assert!(series.ends_with('}'), "unterminated labels: {line}");
+                }
+                (series.to_string(), value.parse().unwrap_or_else(|_| panic!("bad value: {line}")))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_render_parses_and_names_every_metric() {
+        let metrics = Metrics::default();
+        metrics.record_call("github", "list_issues", Duration::from_millis(20), false);
+        metrics.record_call("github", "list_issues", Duration::from_secs(30), true);
+        metrics.record_reload(true);
+        metrics.record_reload(false);
+        metrics.record_reload(true);
+        let servers = [("github".to_string(), true), ("jira".to_string(), false)];
+
+        let text = metrics.render(&servers);
+        let series = parse(&text);
+        let labels = "server=\"github\",tool=\"list_issues\"";
+        assert_eq!(series[&format!("cmcp_tool_calls_total{{{labels}}}")], 2.0);
+        assert_eq!(series[&format!("cmcp_tool_errors_total{{{labels}}}")], 1.0);
+        assert_eq!(series[&format!("cmcp_tool_call_duration_seconds_bucket{{{labels},le=\"0.01\"}}")], 0.0);
+        assert_eq!(series[&format!("cmcp_tool_call_duration_seconds_bucket{{{labels},le=\"0.025\"}}")], 1.0);
+        assert_eq!(series[&format!("cmcp_tool_call_duration_seconds_bucket{{{labels},le=\"10\"}}")], 1.0);
+        assert_eq!(series[&format!("cmcp_tool_call_duration_seconds_bucket{{{labels},le=\"+Inf\"}}")], 2.0);
+        assert_eq!(series[&format!("cmcp_tool_call_duration_seconds_count{{{labels}}}")], 2.0);
+        assert_eq!(series["cmcp_reloads_total{result=\"ok\"}"], 2.0);
+        assert_eq!(series["cmcp_reloads_total{result=\"error\"}"], 1.0);
+        assert_eq!(series["cmcp_server_connected{server=\"jira\"}"], 0.0);
+        assert_eq!(series["cmcp_servers_connected"], 1.0);
+        for name in ["cmcp_tool_calls_total", "cmcp_tool_call_duration_seconds", "cmcp_reloads_total"] {
+            assert!(text.contains(&format!("# TYPE {name} ")), "{text}");
+        }
+    }
+}
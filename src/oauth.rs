@@ -0,0 +1,461 @@
+//! OAuth 2.0 for HTTP/SSE servers configured with `auth = "oauth"`.
+//!
+//! `cmcp auth <server>` runs MCP OAuth discovery, dynamic client registration
+//! and the authorization-code flow with PKCE through rmcp, catching the
+//! redirect on a loopback port. Tokens are kept per server under the config
+//! directory (never in `config.toml`) and refreshed on connect once expired.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use oauth2::TokenResponse;
+use rmcp::transport::auth::{
+    AuthError, AuthorizationManager, AuthorizationMetadata, CredentialStore, OAuthTokenResponse, StoredCredentials,
+};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::config;
+
+/// Client name sent during dynamic client registration.
+const CLIENT_NAME: &str = "cmcp";
+
+/// Refresh this long before the token actually expires.
+const EXPIRY_SKEW: Duration = Duration::from_secs(60);
+
+/// How long `cmcp auth` waits for the browser to come back.
+const CALLBACK_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// One lock per server, so concurrent connects refresh its token only once.
+static REFRESHING: LazyLock<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>> = LazyLock::new(Default::default);
+
+/// Tokens for one server, stored as JSON in the config directory.
+#[derive(Debug, Clone)]
+pub struct FileCredentialStore {
+    path: PathBuf,
+    /// Secret from dynamic registration, saved alongside the tokens.
+    client_secret: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredFile {
+    credentials: StoredCredentials,
+    /// Unix time the token response was received; `expires_in` counts from here.
+    saved_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    client_secret: Option<String>,
+}
+
+impl FileCredentialStore {
+    pub fn for_server(server: &str) -> Result<Self> {
+        Ok(Self {
+            path: config::oauth_token_path(server)?,
+            client_secret: None,
+        })
+    }
+
+    fn with_client_secret(self, client_secret: Option<String>) -> Self {
+        Self { client_secret, ..self }
+    }
+
+    async fn read(&self) -> Result<Option<StoredFile>> {
+        match tokio::fs::read_to_string(&self.path).await {
+            Ok(content) => Ok(Some(serde_json::from_str(&content).with_context(|| {
+                format!("failed to parse {}", self.path.display())
+            })?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("failed to read {}", self.path.display())),
+        }
+    }
+
+    async fn write(&self, credentials: StoredCredentials) -> Result<()> {
+        let stored = StoredFile {
+            credentials,
+            saved_at: unix_now(),
+            client_secret: self.client_secret.clone(),
+        };
+        let content = serde_json::to_string_pretty(&stored)?;
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        write_private(&self.path, content.as_bytes())
+            .await
+            .with_context(|| format!("failed to write {}", self.path.display()))
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn store_error(e: anyhow::Error) -> AuthError {
+    AuthError::InternalError(format!("token store: {e:#}"))
+}
+
+#[async_trait::async_trait]
+impl CredentialStore for FileCredentialStore {
+    async fn load(&self) -> Result<Option<StoredCredentials>, AuthError> {
+        Ok(self.read().await.map_err(store_error)?.map(|stored| stored.credentials))
+    }
+
+    async fn save(&self, credentials: StoredCredentials) -> Result<(), AuthError> {
+        self.write(credentials).await.map_err(store_error)
+    }
+
+    async fn clear(&self) -> Result<(), AuthError> {
+        match tokio::fs::remove_file(&self.path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(store_error(e.into())),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl StoredFile {
+    /// Whether the access token has expired (or is about to).
+    fn expired(&self) -> bool {
+        let Some(expires_in) = self.credentials.token_response.as_ref().and_then(|t| t.expires_in()) else {
+            return false;
+        };
+        let age = Duration::from_secs(unix_now().saturating_sub(self.saved_at));
+        age + EXPIRY_SKEW >= expires_in
+    }
+}
+
+/// Write `content` readable only by the current user.
+async fn write_private(path: &PathBuf, content: &[u8]) -> std::io::Result<()> {
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path).await?;
+    // `mode` only applies when the file is created; tighten older files too.
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600)).await?;
+    file.write_all(content).await?;
+    file.flush().await
+}
+
+/// An access token for `server`, refreshed first if it has expired.
+pub async fn access_token(server: &str, url: &str) -> Result<String> {
+    let store = FileCredentialStore::for_server(server)?;
+    let lock = REFRESHING.lock().unwrap().entry(server.to_string()).or_default().clone();
+    let _refreshing = lock.lock().await;
+
+    // Read under the lock: a concurrent connect may have just refreshed it.
+    let not_authorized = || anyhow::anyhow!("{server} is not authorized yet. Run: cmcp auth {server}");
+    let stored = store.read().await?.ok_or_else(not_authorized)?;
+    let token = stored.credentials.token_response.as_ref().ok_or_else(not_authorized)?;
+    if !stored.expired() {
+        return Ok(token.access_token().secret().clone());
+    }
+
+    tracing::info!(server = %server, "OAuth access token expired, refreshing");
+    let metadata = AuthorizationManager::new(url)
+        .await?
+        .discover_metadata()
+        .await
+        .with_context(|| format!("OAuth discovery for {server} failed"))?;
+    let refreshed = refresh(server, url, &metadata, &stored).await?;
+
+    let access_token = refreshed.access_token().secret().clone();
+    store
+        .with_client_secret(stored.client_secret.clone())
+        .write(StoredCredentials {
+            client_id: stored.credentials.client_id.clone(),
+            token_response: Some(refreshed),
+            granted_scopes: stored.credentials.granted_scopes.clone(),
+        })
+        .await?;
+    Ok(access_token)
+}
+
+/// Exchange the refresh token in `stored` at the token endpoint.
+async fn refresh(server: &str, url: &str, metadata: &AuthorizationMetadata, stored: &StoredFile) -> Result<OAuthTokenResponse> {
+    let token = stored.credentials.token_response.as_ref().context("no stored token")?;
+    let refresh_token = token
+        .refresh_token()
+        .with_context(|| format!("{server}'s access token expired and it has no refresh token. Run: cmcp auth {server}"))?
+        .clone();
+
+    let mut request = reqwest::Client::new()
+        .post(&metadata.token_endpoint)
+        .header(http::header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .header(http::header::ACCEPT, "application/json");
+    // Authenticate the way the code exchange did: HTTP Basic unless the
+    // server only accepts the secret in the body.
+    let client_id = &stored.credentials.client_id;
+    let secret = stored.client_secret.as_ref();
+    let basic = secret.filter(|_| !secret_in_body(metadata));
+    if let Some(secret) = basic {
+        request = request.basic_auth(client_id, Some(secret));
+    }
+    let body = {
+        let mut form = url::form_urlencoded::Serializer::new(String::new());
+        form.append_pair("grant_type", "refresh_token")
+            .append_pair("refresh_token", refresh_token.secret())
+            .append_pair("resource", url);
+        if basic.is_none() {
+            form.append_pair("client_id", client_id);
+            if let Some(secret) = secret {
+                form.append_pair("client_secret", secret);
+            }
+        }
+        form.finish()
+    };
+
+    let response = request
+        .body(body)
+        .send()
+        .await
+        .with_context(|| format!("token refresh for {server} failed"))?;
+    let status = response.status();
+    if !status.is_success() {
+        anyhow::bail!("token refresh for {server} was rejected ({status}). Run: cmcp auth {server}");
+    }
+    let mut refreshed: OAuthTokenResponse = serde_json::from_slice(&response.bytes().await?)
+        .with_context(|| format!("token refresh for {server} returned an invalid response"))?;
+    // Servers that don't rotate refresh tokens omit it from the response.
+    if refreshed.refresh_token().is_none() {
+        refreshed.set_refresh_token(Some(refresh_token));
+    }
+    Ok(refreshed)
+}
+
+/// Whether the token endpoint takes `client_secret_post` but not `client_secret_basic`.
+fn secret_in_body(metadata: &AuthorizationMetadata) -> bool {
+    let methods = metadata
+        .additional_fields
+        .get("token_endpoint_auth_methods_supported")
+        .and_then(|v| v.as_array());
+    let supports = |method: &str| methods.is_some_and(|m| m.iter().any(|m| m.as_str() == Some(method)));
+    supports("client_secret_post") && !supports("client_secret_basic")
+}
+
+/// Run the interactive browser flow for `server` and store the tokens.
+pub async fn authorize(server: &str, url: &str, scopes: &[String]) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .context("failed to listen for the OAuth redirect")?;
+    let redirect_uri = format!("http://127.0.0.1:{}/callback", listener.local_addr()?.port());
+
+    let mut manager = AuthorizationManager::new(url).await?;
+    let metadata = manager
+        .discover_metadata()
+        .await
+        .with_context(|| format!("{server} does not advertise OAuth support"))?;
+    manager.set_metadata(metadata);
+
+    // Register here rather than through `AuthorizationSession` to keep the
+    // client secret, which refreshes need later.
+    let client = manager
+        .register_client(CLIENT_NAME, &redirect_uri)
+        .await
+        .context("dynamic client registration failed")?;
+    manager.set_credential_store(FileCredentialStore::for_server(server)?.with_client_secret(client.client_secret));
+    let scopes: Vec<&str> = scopes.iter().map(String::as_str).collect();
+    let auth_url = manager.get_authorization_url(&scopes).await?;
+
+    println!("Opening your browser to authorize \"{server}\". If it doesn't open, visit:\n\n  {auth_url}\n");
+    open_browser(&auth_url);
+
+    let (code, state) = tokio::time::timeout(CALLBACK_TIMEOUT, accept_callback(&listener))
+        .await
+        .map_err(|_| anyhow::anyhow!("timed out waiting for the browser to authorize {server}. Run: cmcp auth {server}"))??;
+    manager
+        .exchange_code_for_token(&code, &state)
+        .await
+        .context("token exchange failed")?;
+    Ok(())
+}
+
+/// Serve a single redirect on `listener` and return its `code` and `state`.
+async fn accept_callback(listener: &tokio::net::TcpListener) -> Result<(String, String)> {
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        let mut buf = vec![0u8; 8192];
+        let n = stream.read(&mut buf).await?;
+        let request = String::from_utf8_lossy(&buf[..n]);
+        let Some(target) = request.lines().next().and_then(|line| line.split(' ').nth(1)) else {
+            continue;
+        };
+        // Browsers also ask for /favicon.ico; only the callback path counts.
+        if !target.starts_with("/callback") {
+            let _ = stream.write_all(b"HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\n\r\n").await;
+            continue;
+        }
+
+        let result = parse_callback(target);
+        let body = match &result {
+            Ok(_) => "Authorized. You can close this tab and return to the terminal.".to_string(),
+            Err(e) => format!("Authorization failed: {e}"),
+        };
+        let response = format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: text/plain; charset=utf-8\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+            body.len()
+        );
+        let _ = stream.write_all(response.as_bytes()).await;
+        return result;
+    }
+}
+
+/// Extract `code` and `state` from the redirect's request target.
+fn parse_callback(target: &str) -> Result<(String, String)> {
+    let url = url::Url::parse(&format!("http://127.0.0.1{target}")).context("malformed redirect")?;
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.into_owned())
+    };
+    if let Some(error) = param("error") {
+        let detail = param("error_description").map(|d| format!(": {d}")).unwrap_or_default();
+        anyhow::bail!("{error}{detail}");
+    }
+    let code = param("code").context("redirect is missing the authorization code")?;
+    let state = param("state").context("redirect is missing the state parameter")?;
+    Ok((code, state))
+}
+
+fn open_browser(url: &str) {
+    #[cfg(target_os = "macos")]
+    let result = std::process::Command::new("open").arg(url).spawn();
+    #[cfg(target_os = "windows")]
+    let result = std::process::Command::new("cmd").args(["/C", "start", "", url]).spawn();
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    let result = std::process::Command::new("xdg-open").arg(url).spawn();
+    if let Err(e) = result {
+        tracing::debug!(error = %e, "could not open a browser");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_callback() {
+        let (code, state) = parse_callback("/callback?code=abc%2F123&state=xyz").unwrap();
+        assert_eq!(code, "abc/123");
+        assert_eq!(state, "xyz");
+
+        let err = parse_callback("/callback?error=access_denied&error_description=User+said+no").unwrap_err();
+        assert_eq!(err.to_string(), "access_denied: User said no");
+
+        assert!(parse_callback("/callback?state=xyz").is_err());
+    }
+
+    fn stored(token: serde_json::Value, saved_at: u64, client_secret: Option<&str>) -> StoredFile {
+        StoredFile {
+            credentials: StoredCredentials {
+                client_id: "cmcp-client".to_string(),
+                token_response: Some(serde_json::from_value(token).unwrap()),
+                granted_scopes: vec!["read".to_string()],
+            },
+            saved_at,
+            client_secret: client_secret.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_expired() {
+        let token = serde_json::json!({ "access_token": "a", "token_type": "bearer", "expires_in": 3600 });
+        assert!(!stored(token.clone(), unix_now(), None).expired());
+        // Within the skew of expiring counts as expired.
+        assert!(stored(token.clone(), unix_now() - 3600 + 30, None).expired());
+        assert!(stored(token, unix_now() - 7200, None).expired());
+
+        let forever = serde_json::json!({ "access_token": "a", "token_type": "bearer" });
+        assert!(!stored(forever, 0, None).expired());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_file_store_round_trip() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("cmcp-oauth-{}.json", std::process::id()));
+        // A token file left readable by an older version gets tightened on write.
+        std::fs::write(&path, "{}").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        let store = FileCredentialStore { path: path.clone(), client_secret: Some("s3cret".to_string()) };
+        let token = serde_json::json!({ "access_token": "a", "token_type": "bearer", "refresh_token": "r" });
+        let credentials = stored(token, 0, None).credentials;
+        store.save(credentials.clone()).await.unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+        let loaded = store.load().await.unwrap().unwrap();
+        assert_eq!(serde_json::to_value(&loaded).unwrap(), serde_json::to_value(&credentials).unwrap());
+        assert_eq!(store.read().await.unwrap().unwrap().client_secret.as_deref(), Some("s3cret"));
+
+        store.clear().await.unwrap();
+        assert!(store.load().await.unwrap().is_none());
+        store.clear().await.unwrap();
+    }
+
+    /// A token endpoint that answers one request with `response` and hands
+    /// back the raw request it got.
+    async fn token_endpoint(response: serde_json::Value) -> (String, tokio::sync::oneshot::Receiver<String>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/token", listener.local_addr().unwrap());
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 4096];
+            // The form body is the last thing sent; stop once it has arrived.
+            while !String::from_utf8_lossy(&request).contains("grant_type") {
+                let n = socket.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            let body = response.to_string();
+            let reply = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{body}",
+                body.len()
+            );
+            socket.write_all(reply.as_bytes()).await.unwrap();
+            let _ = tx.send(String::from_utf8_lossy(&request).into_owned());
+        });
+        (url, rx)
+    }
+
+    fn metadata(token_endpoint: &str, auth_methods: serde_json::Value) -> AuthorizationMetadata {
+        serde_json::from_value(serde_json::json!({
+            "authorization_endpoint": "http://127.0.0.1/authorize",
+            "token_endpoint": token_endpoint,
+            "token_endpoint_auth_methods_supported": auth_methods,
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_refresh_sends_client_secret() {
+        let expired = serde_json::json!({
+            "access_token": "old", "token_type": "bearer", "expires_in": 60, "refresh_token": "keep-me",
+        });
+        let fresh = serde_json::json!({ "access_token": "new", "token_type": "bearer", "expires_in": 3600 });
+
+        let (url, request) = token_endpoint(fresh.clone()).await;
+        let stored = stored(expired.clone(), 0, Some("s3cret"));
+        let refreshed = refresh("docs", "https://mcp.example", &metadata(&url, serde_json::json!([])), &stored)
+            .await
+            .unwrap();
+        assert_eq!(refreshed.access_token().secret(), "new");
+        // The server didn't rotate it, so the old refresh token is kept.
+        assert_eq!(refreshed.refresh_token().unwrap().secret(), "keep-me");
+        let request = request.await.unwrap().to_ascii_lowercase();
+        assert!(request.contains("authorization: basic"), "{request}");
+        assert!(request.contains("refresh_token=keep-me"), "{request}");
+
+        let (url, request) = token_endpoint(fresh).await;
+        let methods = serde_json::json!(["client_secret_post"]);
+        refresh("docs", "https://mcp.example", &metadata(&url, methods), &stored).await.unwrap();
+        let request = request.await.unwrap();
+        assert!(request.contains("client_id=cmcp-client&client_secret=s3cret"), "{request}");
+    }
+}
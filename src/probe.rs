@@ -0,0 +1,150 @@
+//! `GET /healthz` for orchestrators and `GET /metrics` for Prometheus, served
+//! next to the MCP transport by `cmcp serve --probe <addr>`.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use cmcp_core::ProxyEngine;
+use cmcp_core::client::{HealthStatus, ServerHealth};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+/// Bind `addr` and answer health probes until the process exits.
+pub async fn spawn(addr: &str, engine: Arc<ProxyEngine>) -> Result<std::net::SocketAddr> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("failed to bind the health probe to {addr}"))?;
+    let local = listener.local_addr()?;
+    tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            let engine = engine.clone();
+            tokio::spawn(async move {
+                if let Err(e) = answer(socket, &engine).await {
+                    tracing::debug!(error = %e, "health probe request failed");
+                }
+            });
+        }
+    });
+    Ok(local)
+}
+
+/// Answer one request; the connection is closed afterwards.
+async fn answer(socket: tokio::net::TcpStream, engine: &ProxyEngine) -> Result<()> {
+    let mut socket = BufReader::new(socket);
+    let mut request_line = String::new();
+    socket.read_line(&mut request_line).await?;
+    // Drain the headers; the request has no body we care about.
+    let mut line = String::new();
+    while socket.read_line(&mut line).await? > 2 {
+        line.clear();
+    }
+
+    let (status, content_type, body) = match request_line.split_whitespace().take(2).collect::<Vec<_>>()[..] {
+        ["GET", "/healthz"] => {
+            let (status, body) = health(&engine.server_health().await);
+            (status, "application/json", body.to_string())
+        }
+        ["GET", "/metrics"] => (200, "text/plain; version=0.0.4", engine.metrics_text().await),
+        _ => (404, "application/json", serde_json::json!({ "error": "not found" }).to_string()),
+    };
+    let reason = match status {
+        200 => "OK",
+        404 => "Not Found",
+        _ => "Service Unavailable",
+    };
+    let response = format!(
+        "HTTP/1.1 {status} {reason}\r\ncontent-type: {content_type}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    socket.get_mut().write_all(response.as_bytes()).await?;
+    Ok(())
+}
+
+/// Status code and payload for `/healthz`: 503 while no server is connected.
+fn health(servers: &HashMap<String, ServerHealth>) -> (u16, serde_json::Value) {
+    let connected = servers
+        .values()
+        .filter(|h| h.status == HealthStatus::Connected)
+        .count();
+    let statuses: serde_json::Map<String, serde_json::Value> = servers
+        .iter()
+        .map(|(name, h)| {
+            let status = match h.status {
+                HealthStatus::Connected => "connected",
+                HealthStatus::Failed(_) => "failed",
+                HealthStatus::NotConnected => "not_connected",
+                HealthStatus::CircuitOpen { .. } => "circuit_open",
+            };
+            (name.clone(), status.into())
+        })
+        .collect();
+    let (code, status) = if connected > 0 { (200, "ok") } else { (503, "unavailable") };
+    let body = serde_json::json!({
+        "status": status,
+        "connected": connected,
+        "servers": statuses,
+    });
+    (code, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use tokio::io::AsyncReadExt;
+
+    #[test]
+    fn test_health_counts_connected_servers() {
+        let server = |status| ServerHealth {
+            status,
+            latency: Duration::ZERO,
+        };
+        let servers = HashMap::from([
+            ("github".to_string(), server(HealthStatus::Connected)),
+            ("jira".to_string(), server(HealthStatus::Failed("timed out".to_string()))),
+        ]);
+
+        let (code, body) = health(&servers);
+        assert_eq!(code, 200);
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "status": "ok",
+                "connected": 1,
+                "servers": { "github": "connected", "jira": "failed" },
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_healthz_is_unavailable_without_servers() {
+        let engine = ProxyEngine::from_configs(HashMap::new(), Default::default(), false)
+            .await
+            .unwrap();
+        let addr = spawn("127.0.0.1:0", Arc::new(engine)).await.unwrap();
+
+        let get = |path: &'static str| async move {
+            let mut socket = tokio::net::TcpStream::connect(addr).await.unwrap();
+            let request = format!("GET {path} HTTP/1.1\r\nhost: localhost\r\n\r\n");
+            socket.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            socket.read_to_string(&mut response).await.unwrap();
+            response
+        };
+
+        let response = get("/healthz").await;
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable"), "{response}");
+        let body: serde_json::Value = serde_json::from_str(response.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        assert_eq!(body, serde_json::json!({ "status": "unavailable", "connected": 0, "servers": {} }));
+
+        let response = get("/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        assert!(response.contains("\r\n\r\n# HELP cmcp_tool_calls_total "), "{response}");
+        assert!(response.contains("\ncmcp_servers_connected 0\n"), "{response}");
+
+        assert!(get("/mcp").await.starts_with("HTTP/1.1 404 Not Found"));
+    }
+}
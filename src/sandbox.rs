@@ -1,20 +1,161 @@
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
 use rquickjs::context::EvalOptions;
-use rquickjs::prelude::Async;
-use rquickjs::{AsyncContext, AsyncRuntime, CatchResultExt, Function, Promise, Value, async_with};
-use crate::catalog::Catalog;
+use rquickjs::prelude::{Async, Opt};
+use rmcp::model::{CallToolResult, RawContent, ReadResourceResult, ResourceContents};
+use rquickjs::{AsyncContext, AsyncRuntime, CatchResultExt, Ctx, Function, Promise, Value, async_with};
+use tokio::sync::oneshot;
+use tracing::Level;
+
+use crate::catalog::{Catalog, DeclarationFilter};
 use crate::client::ClientPool;
+use crate::config::{RankWeights, SandboxConfig, ViewConfig, duration_secs};
 use crate::transpile;
 
+/// Default wall-clock limit for one `search`/`execute` call.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Default max nesting depth for tool results and call results.
+pub const DEFAULT_MAX_JSON_DEPTH: usize = 64;
+
+/// Errors from running agent code that callers may want to tell apart.
+#[derive(Debug, thiserror::Error)]
+pub enum SandboxError {
+    /// The code ran past the configured wall-clock limit and was interrupted.
+    #[error("timed out after {}s", .0.as_secs_f64())]
+    Timeout(Duration),
+    /// The returned value nests deeper than the configured limit.
+    #[error("result nests deeper than {0} levels")]
+    TooDeep(usize),
+}
+
+/// A failed `search`/`execute` and the console output it produced first.
+/// Displays as `error`; see [`console_logs`].
+#[derive(Debug)]
+pub struct LoggedError {
+    pub error: anyhow::Error,
+    pub logs: Vec<ConsoleLine>,
+}
+
+impl std::fmt::Display for LoggedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            write!(f, "{:#}", self.error)
+        } else {
+            write!(f, "{}", self.error)
+        }
+    }
+}
+
+impl std::error::Error for LoggedError {}
+
+/// The console output captured before `error`, if agent code logged any.
+pub fn console_logs(error: &anyhow::Error) -> &[ConsoleLine] {
+    error.downcast_ref::<LoggedError>().map_or(&[], |e| &e.logs)
+}
+
+/// Attach what is in `logs` to `error`, leaving it as is if nothing was logged.
+fn with_logs(error: anyhow::Error, logs: &ConsoleBuffer) -> anyhow::Error {
+    let logs = std::mem::take(&mut *logs.lock().unwrap());
+    if logs.is_empty() {
+        return error;
+    }
+    LoggedError { error, logs }.into()
+}
+
+/// Wall-clock deadline shared with the QuickJS interrupt handler, so CPU-bound
+/// loops are broken rather than merely abandoned.
+#[derive(Clone, Default)]
+struct Deadline(Arc<std::sync::Mutex<Option<Instant>>>);
+
+impl Deadline {
+    fn arm(&self, at: Instant) {
+        *self.0.lock().unwrap() = Some(at);
+    }
+
+    fn disarm(&self) {
+        *self.0.lock().unwrap() = None;
+    }
+
+    fn expired(&self) -> bool {
+        self.0.lock().unwrap().is_some_and(|at| Instant::now() >= at)
+    }
+}
+
 /// JS sandbox that executes agent-written code with proxied MCP tool calls.
+///
+/// One sandbox serves one client session, so state kept here (such as the
+/// previous execute result) is per session.
+///
+/// Every `search`/`execute` runs in a fresh context on a shared runtime, so
+/// globals set by one call are not visible to the next.
 pub struct Sandbox {
-    #[allow(dead_code)]
     rt: AsyncRuntime,
-    ctx: AsyncContext,
+    console: ConsoleRouting,
+    weights: RankWeights,
     pool: Arc<ClientPool>,
     catalog: Arc<Catalog>,
+    /// Value returned by the previous successful `execute()`, exposed as `__last_result`.
+    last_result: std::sync::Mutex<serde_json::Value>,
+    /// Which tools get type declarations; the rest are callable-only.
+    declarations: DeclarationFilter,
+    /// Active view; tool calls outside it are rejected.
+    view: Option<ViewConfig>,
+    /// Resource updates for `resource_updates()`, limited to the view's servers.
+    resource_updates: Arc<crate::client::UpdateQueue>,
+    /// Whether to repair malformed JSON in tool result text.
+    repair_json: bool,
+    /// Hand tool results to agent code as full `CallToolResult`s; see [`call_result_to_json`].
+    raw_results: bool,
+    /// Resolve successful tool calls to `{ data, meta }`, with the result's `_meta`.
+    result_meta: bool,
+    /// Whether `tools` carries summarized schemas; see [`Catalog::attach_schemas`].
+    summarize_schemas: bool,
+    /// Wall-clock limit for one call.
+    timeout: Duration,
+    /// Time all tool calls of one `execute` may take together.
+    call_deadline: Option<Duration>,
+    deadline: Deadline,
+    /// Max nesting depth of tool results and call results.
+    max_json_depth: usize,
+    /// Servers preferred by `cmcp.rank()` and `anyServer`, highest first.
+    server_priority: Vec<String>,
+    /// Server priority for the `anyServer` global; `None` when it is disabled.
+    any_server: Option<Vec<String>>,
+    /// Extra globals in `execute` routing to another server's proxy.
+    server_aliases: HashMap<String, String>,
+    /// Whether failed tool calls reject instead of resolving to the error.
+    throw_on_error: bool,
+    /// Vetted `execute` snippets by id; see [`Sandbox::execute_template`].
+    templates: HashMap<String, String>,
+    /// Whether `execute` refuses anything but `templates`.
+    templates_only: bool,
+    /// Recently transpiled agent code, so retried snippets skip oxc.
+    transpiled: TranspileCache,
+    /// Catalog-derived setup shared by every call. Reset along with the
+    /// catalog, so it never outlives it.
+    setup: std::sync::OnceLock<CatalogSetup>,
+}
+
+/// Everything injected into a context that depends only on the catalog,
+/// built on first use instead of on every `search`/`execute`.
+struct CatalogSetup {
+    /// The catalog's type declarations, transpiled once. They are all ambient
+    /// (`declare const ...`), so this is normally empty.
+    declarations_js: String,
+    /// `servers`, `tools`, `resources`, `resource_templates` and `prompts` as
+    /// JSON, parsed into globals.
+    servers_json: String,
+    tools_json: String,
+    resources_json: String,
+    resource_templates_json: String,
+    prompts_json: String,
+    /// Server proxies and helpers prepended to `execute` code: one short line
+    /// per server on top of a fixed block, however many tools there are.
+    execute_prelude: String,
 }
 
 fn eval_opts() -> EvalOptions {
@@ -28,7 +169,61 @@ fn eval_opts() -> EvalOptions {
     opts
 }
 
-/// JS code that defines console.log/warn/error/info, writing to __stderr.
+/// One `console.*` message captured during a `search`/`execute` call.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct ConsoleLine {
+    /// `LOG`, `INFO`, `WARN`, `ERROR`, or `DEBUG`.
+    pub level: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for ConsoleLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.level, self.message)
+    }
+}
+
+/// Console messages captured during one call.
+type ConsoleBuffer = Arc<std::sync::Mutex<Vec<ConsoleLine>>>;
+
+/// Where `console.*` output from agent code goes: a tracing level and message prefix.
+#[derive(Debug, Clone)]
+struct ConsoleRouting {
+    /// `None` suppresses console output entirely.
+    level: Option<Level>,
+    prefix: String,
+}
+
+impl ConsoleRouting {
+    fn from_config(config: &SandboxConfig) -> Result<Self> {
+        let level = match config.console_level.as_deref().unwrap_or("info") {
+            "off" | "none" => None,
+            other => Some(other.parse::<Level>().map_err(|_| {
+                anyhow::anyhow!(
+                    "unknown console_level \"{other}\". Use: error, warn, info, debug, trace, or off"
+                )
+            })?),
+        };
+        let prefix = config.console_prefix.clone().unwrap_or_else(|| "js".to_string());
+        Ok(Self { level, prefix })
+    }
+
+    /// Emit one console message through tracing under the `js` target.
+    fn emit(&self, msg: &str) {
+        let prefix = &self.prefix;
+        match self.level {
+            Some(Level::ERROR) => tracing::error!(target: "js", "[{prefix}] {msg}"),
+            Some(Level::WARN) => tracing::warn!(target: "js", "[{prefix}] {msg}"),
+            Some(Level::INFO) => tracing::info!(target: "js", "[{prefix}] {msg}"),
+            Some(Level::DEBUG) => tracing::debug!(target: "js", "[{prefix}] {msg}"),
+            Some(Level::TRACE) => tracing::trace!(target: "js", "[{prefix}] {msg}"),
+            None => {}
+        }
+    }
+}
+
+/// JS code that defines console.log/warn/error/info, writing to __stderr
+/// (captured for the caller and routed through tracing).
 const CONSOLE_SHIM: &str = r#"
 const console = {
   _write(level, args) {
@@ -36,7 +231,7 @@ const console = {
       if (typeof a === 'string') return a;
       try { return JSON.stringify(a); } catch { return String(a); }
     }).join(' ');
-    __stderr(level + ': ' + msg);
+    __stderr(level, msg);
   },
   log(...args)   { this._write('LOG', args); },
   info(...args)  { this._write('INFO', args); },
@@ -46,17 +241,210 @@ const console = {
 };
 "#;
 
+/// JS helper that drains generator results into an array.
+///
+/// Agent code may return an async iterator (e.g. an async generator object),
+/// or `yield` values directly; either way the yielded chunks are collected.
+/// Promises left in the result (`return ids.map(id => github.get_issue({ id }))`
+/// without an `await`) are awaited too, instead of stringifying as `{}`.
+const DRAIN_SHIM: &str = r#"
+async function __drain(value) {
+  if (value != null && typeof value[Symbol.asyncIterator] === 'function') {
+    const out = [];
+    for await (const chunk of value) out.push(chunk);
+    value = out;
+  } else if (Object.prototype.toString.call(value) === '[object Generator]') {
+    value = Array.from(value);
+  }
+  value = await value;
+  return __hasPending(value) ? __settle(value, new Set()) : value;
+}
+function __isThenable(v) {
+  return v != null && (typeof v === 'object' || typeof v === 'function') && typeof v.then === 'function';
+}
+// Only arrays and plain objects are walked; class instances stringify as they are.
+function __isPlain(v) {
+  if (v === null || typeof v !== 'object') return false;
+  const proto = Object.getPrototypeOf(v);
+  return Array.isArray(v) || proto === Object.prototype || proto === null;
+}
+// Iterative, so deeply nested results don't overflow the stack.
+function __hasPending(value) {
+  const seen = new Set();
+  const stack = [value];
+  while (stack.length > 0) {
+    const v = stack.pop();
+    if (__isThenable(v)) return true;
+    if (!__isPlain(v) || seen.has(v)) continue;
+    seen.add(v);
+    for (const child of Object.values(v)) stack.push(child);
+  }
+  return false;
+}
+// A cycle is left in place for JSON.stringify to report.
+async function __settle(value, seen) {
+  value = await value;
+  if (!__isPlain(value) || seen.has(value)) return value;
+  seen.add(value);
+  if (Array.isArray(value)) return Promise.all(value.map(v => __settle(v, seen)));
+  const entries = await Promise.all(Object.entries(value).map(async ([k, v]) => [k, await __settle(v, seen)]));
+  return Object.fromEntries(entries);
+}
+"#;
+
+/// Standard library of agent helpers, exposed as the `cmcp` global.
+const PRELUDE: &str = r#"
+const cmcp = Object.freeze({
+  sleep(ms) {
+    return __sleep(ms);
+  },
+  // Call fn until it resolves, retrying up to `retries` times with exponential backoff.
+  async retry(fn, { retries = 3, delayMs = 200, factor = 2 } = {}) {
+    let delay = delayMs;
+    for (let attempt = 0; ; attempt++) {
+      try {
+        return await fn(attempt);
+      } catch (e) {
+        if (attempt >= retries) throw e;
+        await __sleep(delay);
+        delay *= factor;
+      }
+    }
+  },
+  // Catalog entries matching `query`, best first (read-only and recently used tools rank higher).
+  rank(query) {
+    return JSON.parse(__rank_tools(String(query)));
+  },
+  // Split an array into arrays of at most `size` items.
+  chunk(arr, size) {
+    if (!(size > 0)) throw new Error('chunk size must be positive');
+    const out = [];
+    for (let i = 0; i < arr.length; i += size) out.push(arr.slice(i, i + size));
+    return out;
+  },
+  // Call fn every `intervalMs` until predicate(value) is truthy; returns that value.
+  async pollUntil(fn, predicate, { intervalMs = 500, timeoutMs = 30000 } = {}) {
+    const deadline = Date.now() + timeoutMs;
+    for (;;) {
+      const value = await fn();
+      if (await predicate(value)) return value;
+      if (Date.now() >= deadline) throw new Error(`pollUntil timed out after ${timeoutMs}ms`);
+      await __sleep(intervalMs);
+    }
+  },
+});
+"#;
+
 impl Sandbox {
-    pub async fn new(pool: Arc<ClientPool>, catalog: Arc<Catalog>) -> Result<Self> {
+    pub async fn new(
+        pool: Arc<ClientPool>,
+        catalog: Arc<Catalog>,
+        config: &SandboxConfig,
+    ) -> Result<Self> {
+        let console = ConsoleRouting::from_config(config)?;
+        let weights = config.ranking.clone().unwrap_or_default();
         let rt = AsyncRuntime::new()?;
         rt.set_memory_limit(64 * 1024 * 1024).await; // 64 MB
-        let ctx = AsyncContext::full(&rt).await?;
 
-        // Install console shim once on the global context.
+        // QuickJS polls the handler every few thousand operations; reading the
+        // clock only on every `check_interval`-th poll trades how quickly a
+        // runaway loop stops for less overhead in tight loops.
+        let deadline = Deadline::default();
+        let interrupt = deadline.clone();
+        let check_interval = config.interrupt_check_interval.unwrap_or(1).max(1);
+        let mut polls = 0u32;
+        rt.set_interrupt_handler(Some(Box::new(move || {
+            polls = polls.wrapping_add(1);
+            polls.is_multiple_of(check_interval) && interrupt.expired()
+        })))
+        .await;
+
+        Ok(Self {
+            rt,
+            console,
+            weights,
+            resource_updates: pool.resource_update_queue(config.view.clone()),
+            pool,
+            catalog,
+            last_result: std::sync::Mutex::new(serde_json::Value::Null),
+            declarations: DeclarationFilter {
+                callable_only: config.callable_only.clone(),
+                max_per_server: config.max_declared_tools,
+            },
+            view: config.view.clone(),
+            repair_json: config.repair_json.unwrap_or(false),
+            raw_results: config.raw_results.unwrap_or(false),
+            result_meta: config.result_meta.unwrap_or(false),
+            summarize_schemas: config.summarize_schemas.unwrap_or(false),
+            timeout: config
+                .timeout_secs
+                .map(|secs| duration_secs("timeout_secs", secs))
+                .transpose()?
+                .unwrap_or(DEFAULT_TIMEOUT),
+            call_deadline: config
+                .call_deadline_secs
+                .map(|secs| duration_secs("call_deadline_secs", secs))
+                .transpose()?,
+            deadline,
+            max_json_depth: config.max_json_depth.unwrap_or(DEFAULT_MAX_JSON_DEPTH),
+            server_priority: config.server_priority.clone(),
+            any_server: config
+                .any_server
+                .unwrap_or(false)
+                .then(|| config.server_priority.clone()),
+            server_aliases: config.server_aliases.clone(),
+            templates: config.templates.clone(),
+            templates_only: config.templates_only.unwrap_or(false),
+            throw_on_error: config.throw_on_error.unwrap_or(false),
+            transpiled: TranspileCache::default(),
+            setup: std::sync::OnceLock::new(),
+        })
+    }
+
+    fn setup(&self) -> &CatalogSetup {
+        self.setup.get_or_init(|| {
+            let catalog = &self.catalog;
+            let to_json = |value: serde_json::Value| {
+                serde_json::to_string(&value).unwrap_or_else(|_| "[]".to_owned())
+            };
+            CatalogSetup {
+                declarations_js: transpile_declarations(&catalog.type_declarations_filtered(&self.declarations)),
+                servers_json: to_json(catalog.servers_json_value()),
+                tools_json: to_json(if self.summarize_schemas {
+                    catalog.summarized_json_value()
+                } else {
+                    catalog.to_json_value()
+                }),
+                resources_json: to_json(catalog.resources_json_value()),
+                resource_templates_json: to_json(catalog.resource_templates_json_value()),
+                prompts_json: to_json(catalog.prompts_json_value()),
+                execute_prelude: execute_prelude(
+                    catalog,
+                    self.any_server.as_deref(),
+                    &self.server_aliases,
+                    self.throw_on_error,
+                ),
+            }
+        })
+    }
+
+    /// Create a fresh context with the console shim, `__drain`, and the
+    /// `cmcp` prelude installed. Console output is appended to `logs`, and
+    /// timers are tracked in `in_flight`.
+    async fn fresh_context(&self, logs: ConsoleBuffer, in_flight: &InFlight) -> Result<AsyncContext> {
+        let timers = in_flight.clone();
+        let ctx = AsyncContext::full(&self.rt).await?;
+        let console = self.console.clone();
+        let weights = self.weights.clone();
+        let priority = self.server_priority.clone();
+        let (rank_pool, rank_catalog) = (self.pool.clone(), self.catalog.clone());
+
         async_with!(ctx => |ctx| {
-            // __stderr: native function that writes to Rust stderr
-            let stderr_fn = Function::new(ctx.clone(), |msg: String| {
-                eprintln!("[js] {msg}");
+            // __stderr: native function that captures console output and routes it through tracing
+            let stderr_fn = Function::new(ctx.clone(), move |level: String, message: String| {
+                let line = ConsoleLine { level, message };
+                console.emit(&line.to_string());
+                logs.lock().unwrap().push(line);
             })
             .map_err(|e| anyhow::anyhow!("failed to create __stderr: {e}"))?;
 
@@ -67,170 +455,1656 @@ impl Sandbox {
                 .catch(&ctx)
                 .map_err(|e| anyhow::anyhow!("failed to install console shim: {e}"))?;
 
+            ctx.eval::<(), _>(DRAIN_SHIM)
+                .catch(&ctx)
+                .map_err(|e| anyhow::anyhow!("failed to install drain shim: {e}"))?;
+
+            // __sleep: native async timer backing the `cmcp` helpers.
+            let sleep_fn = Function::new(
+                ctx.clone(),
+                Async(move |ms: f64| {
+                    let timers = timers.clone();
+                    async move {
+                        let sleep = tokio::time::sleep(std::time::Duration::from_millis(ms.max(0.0) as u64));
+                        timers.run(sleep).await;
+                    }
+                }),
+            )
+            .map_err(|e| anyhow::anyhow!("failed to create __sleep: {e}"))?;
+
+            ctx.globals().set("__sleep", sleep_fn)
+                .map_err(|e| anyhow::anyhow!("failed to set __sleep: {e}"))?;
+
+            // __rank_tools: ranked catalog search backing `cmcp.rank`.
+            let rank_fn = Function::new(ctx.clone(), move |query: String| {
+                let usage = rank_pool.usage();
+                let ranked = rank_catalog.rank(&query, &weights, &priority, |e| {
+                    usage
+                        .get(&format!("{}.{}", e.server, e.name))
+                        .map_or(0.0, |u| u.recency())
+                });
+                serde_json::to_string(&ranked).unwrap_or_else(|_| "[]".to_owned())
+            })
+            .map_err(|e| anyhow::anyhow!("failed to create __rank_tools: {e}"))?;
+
+            ctx.globals().set("__rank_tools", rank_fn)
+                .map_err(|e| anyhow::anyhow!("failed to set __rank_tools: {e}"))?;
+
+            ctx.eval::<(), _>(PRELUDE)
+                .catch(&ctx)
+                .map_err(|e| anyhow::anyhow!("failed to install cmcp prelude: {e}"))?;
+
             Ok::<_, anyhow::Error>(())
         })
         .await?;
 
-        Ok(Self {
-            rt,
-            ctx,
-            pool,
-            catalog,
-        })
+        Ok(ctx)
+    }
+
+    /// Execute a `search()` call — agent TypeScript code that filters the tool catalog.
+    pub async fn search(&self, code: &str) -> Result<serde_json::Value> {
+        Ok(self.search_with_logs(code).await?.0)
+    }
+
+    /// Like [`Sandbox::search`], but also returns the console output of the call.
+    pub async fn search_with_logs(&self, code: &str) -> Result<(serde_json::Value, Vec<ConsoleLine>)> {
+        let setup = self.setup();
+        let code = self.transpiled.get_or_transpile(code, &setup.declarations_js)?;
+        let (deadline, timeout, max_depth) = (self.deadline.clone(), self.timeout, self.max_json_depth);
+
+        let in_flight = InFlight::default();
+        let _leftovers = CancelOnDrop(in_flight.clone());
+        let logs = ConsoleBuffer::default();
+        let ctx = self.fresh_context(logs.clone(), &in_flight).await?;
+        let result = async_with!(ctx => |ctx| {
+            set_catalog_globals(&ctx, setup)?;
+
+            let wrapped = code.wrap("");
+            run_with_deadline(&ctx, wrapped, &deadline, timeout, max_depth).await
+        })
+        .await;
+        in_flight.cancel_all();
+        self.rt.idle().await;
+        let result = result.map_err(|e| with_logs(e, &logs))?;
+
+        // The agent picked these entries, so they get their full schemas back.
+        let mut result = result;
+        if self.summarize_schemas {
+            self.catalog.attach_schemas(&mut result);
+        }
+        let logs = std::mem::take(&mut *logs.lock().unwrap());
+        Ok((result, logs))
+    }
+
+    /// Execute an `execute()` call — agent TypeScript code that calls tools across servers.
+    ///
+    /// The value returned by the previous successful call in this session is
+    /// available to agent code as `__last_result` (`null` on the first call).
+    pub async fn execute(&self, code: &str) -> Result<serde_json::Value> {
+        Ok(self.execute_with_logs(code).await?.0)
+    }
+
+    /// Like [`Sandbox::execute`], but also returns the console output of the call.
+    pub async fn execute_with_logs(&self, code: &str) -> Result<(serde_json::Value, Vec<ConsoleLine>)> {
+        let execution = self.execute_with_options(code, &ExecuteOptions::default()).await?;
+        Ok((execution.value, execution.logs))
+    }
+
+    /// Like [`Sandbox::execute_with_logs`], with per-request options.
+    ///
+    /// Tool results reach agent code whole; the largest `max_length` of the
+    /// servers called is reported in [`Execution::server_max_length`] for
+    /// cutting the response. With `record`, every tool call is returned in
+    /// [`Execution::calls`].
+    ///
+    /// With `templates_only`, arbitrary code is refused.
+    pub async fn execute_with_options(&self, code: &str, options: &ExecuteOptions) -> Result<Execution> {
+        if self.templates_only {
+            anyhow::bail!("only templates can be executed here ({})", self.template_ids());
+        }
+        self.run_execute(code, None, options).await
+    }
+
+    /// Run the configured template `id`, with `params` as the `params` global.
+    ///
+    /// The parameters are handed over as a JSON value, never spliced into
+    /// the template's source, so they can't change what the code does.
+    pub async fn execute_template(
+        &self,
+        id: &str,
+        params: &serde_json::Value,
+        options: &ExecuteOptions,
+    ) -> Result<Execution> {
+        let Some(code) = self.templates.get(id) else {
+            anyhow::bail!("unknown template \"{id}\" ({})", self.template_ids());
+        };
+        let params_json = serde_json::to_string(params)?;
+        self.run_execute(code, Some(&params_json), options).await
+    }
+
+    /// The configured template ids, for error messages.
+    fn template_ids(&self) -> String {
+        let mut ids: Vec<&str> = self.templates.keys().map(String::as_str).collect();
+        ids.sort_unstable();
+        match ids.as_slice() {
+            [] => "none are configured".to_string(),
+            ids => format!("available: {}", ids.join(", ")),
+        }
+    }
+
+    /// Run `code` for `execute`; `params_json` becomes the `params` global.
+    async fn run_execute(&self, code: &str, params_json: Option<&str>, options: &ExecuteOptions) -> Result<Execution> {
+        let pool = self.pool.clone();
+        let view = self.view.clone();
+        let view_for_resources = self.view.clone();
+        let view_for_prompts = self.view.clone();
+        let repair = self.repair_json;
+        let (raw_results, result_meta) = (self.raw_results, self.result_meta);
+        let call_deadline = self.call_deadline.map(|budget| (Instant::now() + budget, budget));
+        let (deadline, timeout, max_depth) = (self.deadline.clone(), self.timeout, self.max_json_depth);
+        let setup = self.setup();
+        let code = self.transpiled.get_or_transpile(code, &setup.declarations_js)?;
+        let last_result_json = serde_json::to_string(&*self.last_result.lock().unwrap())?;
+
+        let server_budget: Arc<std::sync::Mutex<Option<usize>>> = Arc::default();
+        let budget = server_budget.clone();
+        let recording: Arc<std::sync::Mutex<Vec<RecordedCall>>> = Arc::default();
+        let recorder = options.record.then(|| recording.clone());
+
+        let in_flight = InFlight::default();
+        // Calls the code didn't await are cancelled when it returns, or when
+        // this future is dropped mid-run.
+        let _leftovers = CancelOnDrop(in_flight.clone());
+
+        let logs = ConsoleBuffer::default();
+        let ctx = self.fresh_context(logs.clone(), &in_flight).await?;
+        let tracked = in_flight.clone();
+        let natives = in_flight.clone();
+        let result = async_with!(ctx => |ctx| {
+            // Inject __call_tool as an async native function.
+            let pool_ref = pool.clone();
+            let calls = tracked.clone();
+            let call_tool_fn = Function::new(
+                ctx.clone(),
+                Async({
+                    let pool = pool_ref.clone();
+                    move |server: String, tool: String, params_json: String, call_id: Opt<f64>| {
+                        let pool_inner = pool.clone();
+                        let view = view.clone();
+                        let calls = calls.clone();
+                        let budget = budget.clone();
+                        let recorder = recorder.clone();
+                        // Record synchronously too, so calls are kept in the order they were made.
+                        let recorded_at = recorder.as_ref().map(|recorder| {
+                            let mut recorded = recorder.lock().unwrap();
+                            recorded.push(RecordedCall {
+                                server: server.clone(),
+                                tool: tool.clone(),
+                                params: serde_json::from_str(&params_json).unwrap_or_default(),
+                                result: serde_json::Value::Null,
+                            });
+                            recorded.len() - 1
+                        });
+                        // Register synchronously so `.cancel()` right after the call still lands.
+                        let call_id = call_id
+                            .0
+                            .map(|id| id as u64)
+                            .unwrap_or_else(|| UNTRACKED_CALL_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed));
+                        let cancelled = calls.register(call_id);
+                        let run = async move {
+                            if let Some(view) = &view
+                                && !view.includes(&server, &tool)
+                            {
+                                return Err(format!("tool {server}.{tool} is not in this view"));
+                            }
+
+                            let params: serde_json::Value =
+                                serde_json::from_str(&params_json)
+                                    .unwrap_or(serde_json::Value::Object(serde_json::Map::new()));
+
+                            let call = async {
+                                let call = pool_inner.call_tool(&server, &tool, params);
+                                let Some((until, budget)) = call_deadline else {
+                                    return call.await;
+                                };
+                                tokio::time::timeout_at(until.into(), call).await.unwrap_or_else(|_| {
+                                    Err(anyhow::anyhow!(
+                                        "deadline exceeded: tool calls in this execute may take {budget:?} in total"
+                                    ))
+                                })
+                            };
+                            let outcome = tokio::select! {
+                                result = call => Some(result),
+                                _ = cancelled => None,
+                            };
+                            calls.finish(call_id);
+
+                            match outcome {
+                                Some(Ok(mut call_result)) => {
+                                    if repair {
+                                        repair_text_content(&mut call_result);
+                                    }
+                                    sanitize_text_content(&mut call_result);
+                                    if let Some(limit) = pool_inner.max_length(&server) {
+                                        let mut budget = budget.lock().unwrap();
+                                        *budget = Some(budget.map_or(limit, |b| b.max(limit)));
+                                    }
+                                    let depth = result_depth(&call_result);
+                                    if depth > max_depth {
+                                        return Err(format!(
+                                            "result of {server}.{tool} nests {depth} levels deep, over the limit of {max_depth}"
+                                        ));
+                                    }
+                                    Ok(call_result_to_json(&call_result, raw_results, result_meta))
+                                }
+                                Some(Err(e)) => Err(pool_inner.redact(&e.to_string())),
+                                None => Err(format!("call to {server}.{tool} was cancelled")),
+                            }
+                        };
+                        // The result goes under `ok` and a failure of the call itself under
+                        // `error`, so a tool that returns `{ error }` isn't taken for one.
+                        async move {
+                            let outcome = run.await;
+                            if let (Some(recorder), Some(i)) = (recorder, recorded_at) {
+                                recorder.lock().unwrap()[i].result = match &outcome {
+                                    Ok(json) => serde_json::from_str(json).unwrap_or_default(),
+                                    Err(error) => serde_json::json!({ "error": error }),
+                                };
+                            }
+                            match outcome {
+                                Ok(json) => format!(r#"{{"ok":{json}}}"#),
+                                Err(error) => serde_json::json!({ "error": error }).to_string(),
+                            }
+                        }
+                    }
+                }),
+            )
+            .map_err(|e| anyhow::anyhow!("failed to create __call_tool: {e}"))?;
+
+            ctx.globals().set("__call_tool", call_tool_fn)
+                .map_err(|e| anyhow::anyhow!("failed to set __call_tool: {e}"))?;
+
+            // __cancel_call: abort one in-flight upstream call; true if it was still running.
+            let calls = tracked;
+            let cancel_fn = Function::new(ctx.clone(), move |call_id: f64| calls.cancel(call_id as u64))
+            .map_err(|e| anyhow::anyhow!("failed to create __cancel_call: {e}"))?;
+
+            ctx.globals().set("__cancel_call", cancel_fn)
+                .map_err(|e| anyhow::anyhow!("failed to set __cancel_call: {e}"))?;
+
+            // __read_resource: fetch one resource's contents from an upstream server.
+            let read_resource_fn = Function::new(
+                ctx.clone(),
+                Async({
+                    let pool = pool.clone();
+                    let natives = natives.clone();
+                    let view = view_for_resources.clone();
+                    move |server: String, uri: String| {
+                        let pool = pool.clone();
+                        let view = view.clone();
+                        let natives = natives.clone();
+                        async move {
+                            let run = async move {
+                                if let Some(view) = &view
+                                    && !view.touches_server(&server)
+                                {
+                                    let error = format!("server {server} is not in this view");
+                                    return serde_json::json!({ "error": error }).to_string();
+                                }
+                                match pool.read_resource(&server, &uri).await {
+                                    Ok(result) => resource_result_to_json(&result),
+                                    Err(e) => serde_json::json!({ "error": pool.redact(&format!("{e:#}")) }).to_string(),
+                                }
+                            };
+                            natives.run(run).await.unwrap_or_else(cancelled)
+                        }
+                    }
+                }),
+            )
+            .map_err(|e| anyhow::anyhow!("failed to create __read_resource: {e}"))?;
+
+            ctx.globals().set("__read_resource", read_resource_fn)
+                .map_err(|e| anyhow::anyhow!("failed to set __read_resource: {e}"))?;
+
+            // __subscribe_resource / __unsubscribe_resource: toggle update
+            // notifications for one resource; they outlive this call.
+            for (name, subscribe) in [("__subscribe_resource", true), ("__unsubscribe_resource", false)] {
+                let subscribe_fn = Function::new(
+                    ctx.clone(),
+                    Async({
+                        let pool = pool.clone();
+                        let natives = natives.clone();
+                        let view = view_for_resources.clone();
+                        move |server: String, uri: String| {
+                            let pool = pool.clone();
+                            let view = view.clone();
+                            let natives = natives.clone();
+                            async move {
+                                let run = async move {
+                                    if let Some(view) = &view
+                                        && !view.touches_server(&server)
+                                    {
+                                        let error = format!("server {server} is not in this view");
+                                        return serde_json::json!({ "error": error }).to_string();
+                                    }
+                                    let result = if subscribe {
+                                        pool.subscribe_resource(&server, &uri).await
+                                    } else {
+                                        pool.unsubscribe_resource(&server, &uri).await
+                                    };
+                                    match result {
+                                        Ok(()) => serde_json::json!({ "server": server, "uri": uri }).to_string(),
+                                        Err(e) => serde_json::json!({ "error": pool.redact(&format!("{e:#}")) }).to_string(),
+                                    }
+                                };
+                                natives.run(run).await.unwrap_or_else(cancelled)
+                            }
+                        }
+                    }),
+                )
+                .map_err(|e| anyhow::anyhow!("failed to create {name}: {e}"))?;
+
+                ctx.globals().set(name, subscribe_fn)
+                    .map_err(|e| anyhow::anyhow!("failed to set {name}: {e}"))?;
+            }
+
+            // __resource_updates: take the resource updates received so far.
+            let updates_fn = Function::new(
+                ctx.clone(),
+                Async({
+                    let pool = pool.clone();
+                    let natives = natives.clone();
+                    let queue = self.resource_updates.clone();
+                    move || {
+                        let pool = pool.clone();
+                        let queue = queue.clone();
+                        let natives = natives.clone();
+                        async move {
+                            let run = async move {
+                                let updates: Vec<serde_json::Value> = pool
+                                    .take_resource_updates(&queue)
+                                    .await
+                                    .into_iter()
+                                    .map(|(server, uri)| serde_json::json!({ "server": server, "uri": uri }))
+                                    .collect();
+                                serde_json::Value::Array(updates).to_string()
+                            };
+                            natives.run(run).await.unwrap_or_else(cancelled)
+                        }
+                    }
+                }),
+            )
+            .map_err(|e| anyhow::anyhow!("failed to create __resource_updates: {e}"))?;
+
+            ctx.globals().set("__resource_updates", updates_fn)
+                .map_err(|e| anyhow::anyhow!("failed to set __resource_updates: {e}"))?;
+
+            // __get_prompt: render one prompt on an upstream server into its messages.
+            let get_prompt_fn = Function::new(
+                ctx.clone(),
+                Async({
+                    let pool = pool.clone();
+                    let natives = natives.clone();
+                    let view = view_for_prompts.clone();
+                    move |server: String, name: String, args_json: String| {
+                        let pool = pool.clone();
+                        let view = view.clone();
+                        let natives = natives.clone();
+                        async move {
+                            let run = async move {
+                                if let Some(view) = &view
+                                    && !view.touches_server(&server)
+                                {
+                                    let error = format!("server {server} is not in this view");
+                                    return serde_json::json!({ "error": error }).to_string();
+                                }
+                                let args: serde_json::Value = serde_json::from_str(&args_json)
+                                    .unwrap_or(serde_json::Value::Object(serde_json::Map::new()));
+                                match pool.get_prompt(&server, &name, args).await {
+                                    Ok(result) => serde_json::to_string(&result.messages)
+                                        .unwrap_or_else(|_| "null".to_owned()),
+                                    Err(e) => serde_json::json!({ "error": pool.redact(&format!("{e:#}")) }).to_string(),
+                                }
+                            };
+                            natives.run(run).await.unwrap_or_else(cancelled)
+                        }
+                    }
+                }),
+            )
+            .map_err(|e| anyhow::anyhow!("failed to create __get_prompt: {e}"))?;
+
+            ctx.globals().set("__get_prompt", get_prompt_fn)
+                .map_err(|e| anyhow::anyhow!("failed to set __get_prompt: {e}"))?;
+
+            set_catalog_globals(&ctx, setup)?;
+            let mut prelude = format!("{}const __last_result = {last_result_json};", setup.execute_prelude);
+            if let Some(params_json) = params_json {
+                prelude.push_str(&format!("\nconst params = Object.freeze({params_json});"));
+            }
+
+            let wrapped = code.wrap(&prelude);
+            run_with_deadline(&ctx, wrapped, &deadline, timeout, max_depth).await
+        })
+        .await;
+        // Every async native is tracked, so the cancelled leftovers finish at
+        // once and release the pool and context.
+        in_flight.cancel_all();
+        self.rt.idle().await;
+        let result = result.map_err(|e| with_logs(e, &logs))?;
+
+        *self.last_result.lock().unwrap() = result.clone();
+        let logs = std::mem::take(&mut *logs.lock().unwrap());
+        let server_max_length = *server_budget.lock().unwrap();
+        let calls = std::mem::take(&mut *recording.lock().unwrap());
+        Ok(Execution {
+            value: result,
+            logs,
+            server_max_length,
+            calls,
+        })
+    }
+
+    /// Use `catalog` from the next call on, e.g. after a server was added to
+    /// the pool. The runtime and calls already running are left alone.
+    pub fn set_catalog(&mut self, catalog: Arc<Catalog>) {
+        self.catalog = catalog;
+        self.setup = std::sync::OnceLock::new();
+    }
+
+    /// Wind down before the sandbox is replaced: tool calls of an `execute`
+    /// whose future was dropped are cancelled but only finish when the runtime
+    /// is polled, and until then they keep it and the pool alive.
+    pub async fn shutdown(&self) {
+        self.rt.idle().await;
+        self.rt.run_gc().await;
+    }
+}
+
+/// What a native call cancelled at the end of its `execute` resolves to.
+fn cancelled() -> String {
+    serde_json::json!({ "error": "cancelled" }).to_string()
+}
+
+/// Ids for `__call_tool` calls made without one; JS numbers never get this high.
+static UNTRACKED_CALL_ID: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(1 << 53);
+
+/// The async native calls (tool calls, timers, resource reads, ...) of one
+/// call still running, keyed by call id.
+#[derive(Clone, Default)]
+struct InFlight(Arc<std::sync::Mutex<InFlightCalls>>);
+
+#[derive(Default)]
+struct InFlightCalls {
+    running: HashMap<u64, oneshot::Sender<()>>,
+    /// Set once the call is over; anything started later is cancelled at once.
+    closed: bool,
+}
+
+impl InFlight {
+    /// Track a call; the receiver fires when it is cancelled.
+    fn register(&self, id: u64) -> oneshot::Receiver<()> {
+        let (tx, rx) = oneshot::channel();
+        let mut calls = self.0.lock().unwrap();
+        if calls.closed {
+            let _ = tx.send(());
+        } else {
+            calls.running.insert(id, tx);
+        }
+        rx
+    }
+
+    fn finish(&self, id: u64) {
+        self.0.lock().unwrap().running.remove(&id);
+    }
+
+    /// Cancel one call; true if it was still running.
+    fn cancel(&self, id: u64) -> bool {
+        self.0.lock().unwrap().running.remove(&id).is_some_and(|tx| tx.send(()).is_ok())
+    }
+
+    /// Cancel everything running and anything started from now on.
+    fn cancel_all(&self) {
+        let mut calls = self.0.lock().unwrap();
+        calls.closed = true;
+        for (_, tx) in calls.running.drain() {
+            let _ = tx.send(());
+        }
+    }
+
+    /// Run `future` until it finishes or everything is cancelled.
+    async fn run<F: std::future::Future>(&self, future: F) -> Option<F::Output> {
+        let id = UNTRACKED_CALL_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let cancelled = self.register(id);
+        let outcome = tokio::select! {
+            output = future => Some(output),
+            _ = cancelled => None,
+        };
+        self.finish(id);
+        outcome
+    }
+}
+
+struct CancelOnDrop(InFlight);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel_all();
+    }
+}
+
+/// Per-request settings for [`Sandbox::execute_with_options`].
+#[derive(Debug, Clone, Default)]
+pub struct ExecuteOptions {
+    /// The request's own `max_length`, which overrides per-server ones.
+    pub max_length: Option<usize>,
+    /// Record every tool call with its params and result.
+    pub record: bool,
+}
+
+/// The outcome of [`Sandbox::execute_with_options`].
+#[derive(Debug)]
+pub struct Execution {
+    pub value: serde_json::Value,
+    pub logs: Vec<ConsoleLine>,
+    /// The largest `max_length` among the servers whose tools were called.
+    pub server_max_length: Option<usize>,
+    /// Tool calls in the order they were made, if recording was requested.
+    pub calls: Vec<RecordedCall>,
+}
+
+/// One tool call made by agent code, for building fixtures and replaying runs.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RecordedCall {
+    pub server: String,
+    pub tool: String,
+    pub params: serde_json::Value,
+    /// What the call returned to agent code, including `{"error": ...}` values.
+    pub result: serde_json::Value,
+}
+
+/// Parse the catalog JSON into the `servers`, `tools`, `resources`,
+/// `resource_templates` and `prompts` globals.
+fn set_catalog_globals(ctx: &Ctx<'_>, setup: &CatalogSetup) -> Result<()> {
+    for (name, json) in [
+        ("servers", &setup.servers_json),
+        ("tools", &setup.tools_json),
+        ("resources", &setup.resources_json),
+        ("resource_templates", &setup.resource_templates_json),
+        ("prompts", &setup.prompts_json),
+    ] {
+        let value: Value = ctx
+            .json_parse(json.as_str())
+            .catch(ctx)
+            .map_err(|e| anyhow::anyhow!("failed to parse {name}: {e}"))?;
+        ctx.globals()
+            .set(name, value)
+            .map_err(|e| anyhow::anyhow!("failed to set {name}: {e}"))?;
+    }
+    Ok(())
+}
+
+/// JS defining a proxy object per server and alias, plus `anyServer` when
+/// enabled and the resource/prompt helpers. Each tool call returns a promise
+/// with a `.cancel()` handle that aborts just that upstream call. With
+/// `throw_on_error`, a failed call rejects with an `Error`.
+fn execute_prelude(
+    catalog: &Catalog,
+    any_server: Option<&[String]>,
+    server_aliases: &HashMap<String, String>,
+    throw_on_error: bool,
+) -> String {
+    let mut setup = format!("const __throw_on_error = {throw_on_error};\n");
+    setup.push_str(SERVER_PROXY_SETUP);
+
+    // Every connected server gets a proxy, even with no known tools:
+    // lazy servers are reachable before their tool list is known.
+    // Hyphens become underscores, e.g. "chrome-devtools" -> "chrome_devtools".
+    let js_names = catalog.js_names();
+    for (name, js_name) in &js_names {
+        if *js_name != name.replace('-', "_") {
+            tracing::warn!(server = %name, js_name = %js_name, "server name collides with another server or a sandbox global, exposing it under a suffixed name");
+        }
+        let name = serde_json::to_string(name).unwrap_or_default();
+        setup.push_str(&format!("const {js_name} = __server_proxy({name});\n"));
+    }
+
+    let mut aliases: Vec<_> = server_aliases.iter().collect();
+    aliases.sort();
+    for (alias, target) in aliases {
+        let js_alias = alias.replace('-', "_");
+        if js_names.values().any(|n| *n == js_alias) {
+            tracing::warn!(alias = %alias, "server alias has the name of a connected server, ignoring it");
+        } else if crate::catalog::is_reserved_js_name(&js_alias) {
+            tracing::warn!(alias = %alias, "server alias has the name of a sandbox global, ignoring it");
+        } else if let Some(target) = js_names.get(target.as_str()) {
+            setup.push_str(&format!("const {js_alias} = {target};\n"));
+        } else {
+            tracing::warn!(alias = %alias, target = %target, "server alias points to no connected server, ignoring it");
+        }
+    }
+
+    if let Some(priority) = any_server {
+        setup.push_str(&any_server_setup(catalog, &js_names, priority));
+    }
+    setup.push_str(READ_RESOURCE_SETUP);
+    setup.push_str(
+        "const get_prompt = async (server, name, args = {}) => \
+         JSON.parse(await __get_prompt(server, name, JSON.stringify(args)));\n",
+    );
+    setup
+}
+
+/// Shared factory behind every server proxy in `execute`.
+///
+/// `__call_tool` resolves to `{ ok: result }`, or `{ error }` when the call
+/// itself failed (unknown server, timeout, cancelled), which agent code gets
+/// as is. Upstream errors are results with `isError`.
+const SERVER_PROXY_SETUP: &str = r#"let __call_seq = 0;
+const __tool_error = (server, tool, outcome) => {
+  if ('error' in outcome) return outcome.error;
+  const result = outcome.ok;
+  if (result === null || typeof result !== 'object' || result.isError !== true) return null;
+  const text = (result.content || []).filter(c => c.type === 'text').map(c => c.text).join('\n');
+  return text || `${server}.${String(tool)} returned an error`;
+};
+const __server_proxy = (server) => new Proxy({}, {
+  get(_, tool) {
+    return (args = {}) => {
+      const id = ++__call_seq;
+      const call = __call_tool(server, tool, JSON.stringify(args), id).then(outcomeJson => {
+        const outcome = JSON.parse(outcomeJson);
+        const result = 'error' in outcome ? outcome : outcome.ok;
+        const message = __throw_on_error ? __tool_error(server, tool, outcome) : null;
+        if (message !== null) {
+          const error = new Error(message);
+          Object.assign(error, { server, tool: String(tool), result });
+          throw error;
+        }
+        return result;
+      });
+      call.cancel = () => __cancel_call(id);
+      return call;
+    };
+  }
+});
+"#;
+
+/// JS setup for the `anyServer` global, routing each tool name to the
+/// highest-priority server exposing it. Must run after the server proxies.
+fn any_server_setup(catalog: &Catalog, js_names: &BTreeMap<&str, String>, priority: &[String]) -> String {
+    let rank = |server: &str| priority.iter().position(|p| p == server).unwrap_or(priority.len());
+
+    let mut routes: Vec<(&str, &str)> = Vec::new();
+    let mut entries: Vec<_> = catalog.entries().iter().collect();
+    entries.sort_by(|a, b| (rank(&a.server), &a.server).cmp(&(rank(&b.server), &b.server)));
+    for entry in entries {
+        if !routes.iter().any(|(tool, _)| *tool == entry.name) {
+            routes.push((&entry.name, &entry.server));
+        }
+    }
+
+    let pairs: Vec<String> = routes
+        .iter()
+        .map(|(tool, server)| {
+            let tool = serde_json::to_string(tool).unwrap_or_default();
+            format!("[{tool}, {}]", js_names[server])
+        })
+        .collect();
+    format!(
+        r#"const __any_routes = new Map([{}]);
+const anyServer = new Proxy({{}}, {{
+  get(_, tool) {{
+    const server = __any_routes.get(tool);
+    if (!server) return async () => {{ throw new Error(`no server exposes tool ${{String(tool)}}`); }};
+    return server[tool];
+  }}
+}});
+"#,
+        pairs.join(", ")
+    )
+}
+
+/// JS setup for `read_resource(uri, server?)`, `subscribe_resource`,
+/// `unsubscribe_resource` and `resource_updates()`. The server is looked up
+/// in `resources` unless given, so URIs from resource templates work too.
+const READ_RESOURCE_SETUP: &str = r#"const read_resource = async (uri, server) => {
+  const target = server ?? resources.find(r => r.uri === uri)?.server;
+  if (target === undefined) return { error: `no resource with uri ${uri}` };
+  const json = await __read_resource(target, uri);
+  try { return JSON.parse(json); } catch { return json; }
+};
+const subscribe_resource = async (uri, server) => {
+  const target = server ?? resources.find(r => r.uri === uri)?.server;
+  if (target === undefined) return { error: `no resource with uri ${uri}` };
+  return JSON.parse(await __subscribe_resource(target, uri));
+};
+const unsubscribe_resource = async (uri, server) => {
+  const target = server ?? resources.find(r => r.uri === uri)?.server;
+  if (target === undefined) return { error: `no resource with uri ${uri}` };
+  return JSON.parse(await __unsubscribe_resource(target, uri));
+};
+const resource_updates = async () => JSON.parse(await __resource_updates());
+"#;
+
+/// Serialize a resource read for the JS side of `__read_resource`.
+///
+/// A single text block is sent as a bare JSON string; anything else (blobs,
+/// multiple parts) as the full `contents` array.
+fn resource_result_to_json(result: &ReadResourceResult) -> String {
+    if let [ResourceContents::TextResourceContents { text, .. }] = result.contents.as_slice() {
+        return serde_json::to_string(text).unwrap_or_else(|_| "null".to_owned());
+    }
+    serde_json::to_string(&result.contents).unwrap_or_else(|_| "null".to_owned())
+}
+
+/// Serialize a tool result for the JS side of `__call_tool`.
+///
+/// What agent code gets for a successful result:
+/// 1. its `structuredContent`, when there is one;
+/// 2. otherwise, if every block is text, the text joined with newlines,
+///    parsed if it is a JSON object or array and a plain string if not
+///    (so a numeric id keeps all its digits);
+/// 3. otherwise (images, embedded resources) the full `CallToolResult`.
+///
+/// Error results are always sent whole, with `isError: true`. With `raw`,
+/// only a single non-JSON text block is unwrapped, as before these rules.
+/// With `meta`, a successful result becomes `{ data, meta }`: `data` as
+/// above, `meta` the result's `_meta` (pagination cursors, trace ids) or `null`.
+fn call_result_to_json(result: &CallToolResult, raw: bool, meta: bool) -> String {
+    let data = resolve_call_result(result, raw);
+    if !meta || result.is_error == Some(true) {
+        return data;
+    }
+    let meta = serde_json::to_string(&result.meta).unwrap_or_else(|_| "null".to_owned());
+    format!(r#"{{"data":{data},"meta":{meta}}}"#)
+}
+
+/// The value a tool result resolves to; see [`call_result_to_json`].
+fn resolve_call_result(result: &CallToolResult, raw: bool) -> String {
+    let full = || serde_json::to_string(result).unwrap_or_else(|_| "null".to_owned());
+    if result.is_error == Some(true) {
+        return full();
+    }
+    if raw {
+        if result.structured_content.is_none()
+            && let [block] = result.content.as_slice()
+            && let Some(text) = block.as_text()
+            && serde_json::from_str::<serde_json::Value>(&text.text).is_err()
+        {
+            return serde_json::to_string(&text.text).unwrap_or_else(|_| "null".to_owned());
+        }
+        return full();
+    }
+    if let Some(structured) = &result.structured_content {
+        return structured.to_string();
+    }
+    let texts: Option<Vec<&str>> = result.content.iter().map(|c| c.as_text().map(|t| t.text.as_str())).collect();
+    match texts {
+        Some(texts) if !texts.is_empty() => {
+            let text = texts.join("\n");
+            let container = text.trim_start().starts_with(['{', '[']);
+            if container && serde_json::from_str::<serde::de::IgnoredAny>(&text).is_ok() {
+                text
+            } else {
+                serde_json::to_string(&text).unwrap_or_else(|_| "null".to_owned())
+            }
+        }
+        _ => full(),
+    }
+}
+
+/// Strip control characters other than tab, newline and carriage return from
+/// text blocks. Runs after JSON repair, which escapes the ones inside strings.
+pub(crate) fn sanitize_text_content(result: &mut CallToolResult) {
+    for block in &mut result.content {
+        if let RawContent::Text(text) = &mut block.raw
+            && let Some(clean) = sanitize_text(&text.text)
+        {
+            text.text = clean;
+        }
+    }
+}
+
+/// `text` without disallowed control characters, or `None` if it has none.
+fn sanitize_text(text: &str) -> Option<String> {
+    let allowed = |c: char| !c.is_control() || matches!(c, '\t' | '\n' | '\r');
+    if text.chars().all(allowed) {
+        return None;
+    }
+    Some(text.chars().filter(|&c| allowed(c)).collect())
+}
+
+/// Rewrite text blocks that hold malformed-but-repairable JSON as valid JSON.
+fn repair_text_content(result: &mut CallToolResult) {
+    for block in &mut result.content {
+        if let RawContent::Text(text) = &mut block.raw
+            && serde_json::from_str::<serde_json::Value>(&text.text).is_err()
+            && let Some(repaired) = repair_json(&text.text)
+        {
+            text.text = repaired.to_string();
+        }
+    }
+}
+
+/// Leniently parse JSON that a strict parser rejects.
+///
+/// Drops trailing commas before `}`/`]` and escapes raw control characters
+/// inside strings. Returns `None` if the text still isn't valid JSON, or
+/// doesn't look like an object/array to begin with.
+fn repair_json(text: &str) -> Option<serde_json::Value> {
+    let trimmed = text.trim();
+    if !(trimmed.starts_with('{') || trimmed.starts_with('[')) {
+        return None;
+    }
+
+    let mut out = String::with_capacity(trimmed.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    for c in trimmed.chars() {
+        if in_string {
+            match c {
+                _ if escaped => {
+                    escaped = false;
+                    out.push(c);
+                }
+                '\\' => {
+                    escaped = true;
+                    out.push(c);
+                }
+                '"' => {
+                    in_string = false;
+                    out.push(c);
+                }
+                '\n' => out.push_str("\\n"),
+                '\r' => out.push_str("\\r"),
+                '\t' => out.push_str("\\t"),
+                c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+                c => out.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' => {
+                in_string = true;
+                out.push(c);
+            }
+            '}' | ']' => {
+                // Drop a trailing comma (and whitespace after it) before the closer.
+                let kept = out.trim_end().len();
+                if out[..kept].ends_with(',') {
+                    out.truncate(kept - 1);
+                }
+                out.push(c);
+            }
+            c => out.push(c),
+        }
+    }
+
+    serde_json::from_str(&out).ok()
+}
+
+/// Nesting depth of JSON text, counted without recursion so pathological
+/// input cannot overflow the stack. Brackets inside strings are ignored.
+pub fn json_text_depth(text: &str) -> usize {
+    let (mut depth, mut max) = (0usize, 0usize);
+    let (mut in_string, mut escaped) = (false, false);
+    for b in text.bytes() {
+        if in_string {
+            match b {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match b {
+            b'"' => in_string = true,
+            b'[' | b'{' => {
+                depth += 1;
+                max = max.max(depth);
+            }
+            b']' | b'}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    max
+}
+
+/// Deepest nesting in a tool result, including JSON embedded in text blocks.
+fn result_depth(result: &CallToolResult) -> usize {
+    let text_depth = result
+        .content
+        .iter()
+        .filter_map(|c| c.as_text())
+        .map(|t| json_text_depth(&t.text));
+    let structured_depth = result
+        .structured_content
+        .iter()
+        .map(|v| json_text_depth(&v.to_string()));
+    text_depth.chain(structured_depth).max().unwrap_or(0)
+}
+
+/// Evaluate wrapped agent code and await its promise, enforcing `timeout`.
+///
+/// The interrupt deadline breaks CPU-bound loops; the async timeout covers
+/// awaits (e.g. tool calls) that never settle. Either surfaces as
+/// [`SandboxError::Timeout`].
+async fn run_with_deadline<'js>(
+    ctx: &Ctx<'js>,
+    wrapped: String,
+    deadline: &Deadline,
+    timeout: Duration,
+    max_depth: usize,
+) -> Result<serde_json::Value> {
+    let until = Instant::now() + timeout;
+    deadline.arm(until);
+
+    let result = async {
+        let promise: Promise = ctx.eval_with_options(wrapped, eval_opts())
+            .catch(ctx)
+            .map_err(|e| anyhow::anyhow!("JS eval error: {e}"))?;
+
+        let result: Value = tokio::time::timeout_at(until.into(), promise.into_future::<Value>())
+            .await
+            .map_err(|_| SandboxError::Timeout(timeout))?
+            .catch(ctx)
+            .map_err(|e| anyhow::anyhow!("JS promise rejected: {e}"))?;
+
+        stringify_result(ctx, result, max_depth)
+    }
+    .await;
+
+    let expired = deadline.expired();
+    deadline.disarm();
+    match result {
+        Err(_) if expired => Err(SandboxError::Timeout(timeout).into()),
+        other => other,
+    }
+}
+
+/// Convert a JS Value back to serde_json::Value via JSON.stringify.
+///
+/// Values nested deeper than `max_depth` are rejected before parsing.
+fn stringify_result<'js>(
+    ctx: &rquickjs::Ctx<'js>,
+    value: Value<'js>,
+    max_depth: usize,
+) -> Result<serde_json::Value> {
+    let json_rq_str = ctx.json_stringify(value)
+        .catch(ctx)
+        .map_err(|e| anyhow::anyhow!("failed to stringify: {e}"))?;
+
+    let json_std_str = match json_rq_str {
+        Some(s) => s.to_string()
+            .map_err(|e| anyhow::anyhow!("string conversion: {e}"))?,
+        None => "null".to_owned(),
+    };
+
+    if json_text_depth(&json_std_str) > max_depth {
+        return Err(SandboxError::TooDeep(max_depth).into());
+    }
+
+    serde_json::from_str(&json_std_str)
+        .map_err(|e| anyhow::anyhow!("JSON parse error: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::client::ClientPool;
+    use crate::test_support::{in_process_upstream, stand_in_config, CountingServer};
+
+    async fn test_sandbox() -> Sandbox {
+        let (pool, catalog) = ClientPool::connect(HashMap::new(), false).await.unwrap();
+        Sandbox::new(Arc::new(pool), Arc::new(catalog), &SandboxConfig::default()).await.unwrap()
+    }
+
+    /// Mock upstream whose single `wait` tool replies after `delay`.
+    #[derive(Clone)]
+    struct SlowServer {
+        delay: std::time::Duration,
+    }
+
+    impl rmcp::ServerHandler for SlowServer {
+        async fn call_tool(
+            &self,
+            _request: rmcp::model::CallToolRequestParams,
+            _context: rmcp::service::RequestContext<rmcp::RoleServer>,
+        ) -> Result<CallToolResult, rmcp::ErrorData> {
+            tokio::time::sleep(self.delay).await;
+            Ok(CallToolResult::success(vec![rmcp::model::Content::text(format!(
+                "waited {}ms",
+                self.delay.as_millis()
+            ))]))
+        }
+
+        fn get_info(&self) -> rmcp::model::ServerInfo {
+            rmcp::model::ServerInfo {
+                capabilities: rmcp::model::ServerCapabilities::builder().enable_tools().build(),
+                ..Default::default()
+            }
+        }
+    }
+
+    /// Sandbox backed by in-process `SlowServer` upstreams, one per `(name, delay_ms)`.
+    async fn slow_sandbox(delays: &[(&str, u64)]) -> Sandbox {
+        slow_sandbox_with(delays, &SandboxConfig::default()).await
+    }
+
+    async fn slow_sandbox_with(delays: &[(&str, u64)], config: &SandboxConfig) -> Sandbox {
+        slow_sandbox_with_options(delays, config, &Default::default()).await
+    }
+
+    async fn slow_sandbox_with_options(
+        delays: &[(&str, u64)],
+        config: &SandboxConfig,
+        options: &crate::config::ServerOptions,
+    ) -> Sandbox {
+        let mut services = Vec::new();
+        let mut catalog = Catalog::new();
+        for &(name, ms) in delays {
+            let server = SlowServer { delay: std::time::Duration::from_millis(ms) };
+            let (service, _) = in_process_upstream(name, server).await;
+            let schema: rmcp::model::JsonObject = serde_json::from_value(serde_json::json!({"type": "object"})).unwrap();
+            catalog.add_server_tools(name, vec![rmcp::model::Tool::new("wait", "Wait and reply", Arc::new(schema))]);
+            let config = stand_in_config(options.clone());
+            services.push((name.to_string(), service, config));
+        }
+        let pool = ClientPool::from_services(services);
+        Sandbox::new(Arc::new(pool), Arc::new(catalog), config).await.unwrap()
+    }
+
+    /// Mock upstream publishing one text and one binary resource, and no tools.
+    #[derive(Clone)]
+    struct DocsServer;
+
+    impl rmcp::ServerHandler for DocsServer {
+        async fn list_resources(
+            &self,
+            _request: Option<rmcp::model::PaginatedRequestParams>,
+            _context: rmcp::service::RequestContext<rmcp::RoleServer>,
+        ) -> Result<rmcp::model::ListResourcesResult, rmcp::ErrorData> {
+            use rmcp::model::{AnnotateAble, RawResource};
+            let mut readme = RawResource::new("docs://readme", "README");
+            readme.mime_type = Some("text/markdown".to_string());
+            let logo = RawResource::new("docs://logo", "Logo");
+            Ok(rmcp::model::ListResourcesResult::with_all_items(vec![
+                readme.no_annotation(),
+                logo.no_annotation(),
+            ]))
+        }
+
+        async fn read_resource(
+            &self,
+            request: rmcp::model::ReadResourceRequestParams,
+            _context: rmcp::service::RequestContext<rmcp::RoleServer>,
+        ) -> Result<ReadResourceResult, rmcp::ErrorData> {
+            let contents = match request.uri.as_str() {
+                "docs://readme" => ResourceContents::text("# Docs", &request.uri),
+                "docs://logo" => ResourceContents::BlobResourceContents {
+                    uri: request.uri.clone(),
+                    mime_type: Some("image/png".to_string()),
+                    blob: "iVBORw0=".to_string(),
+                    meta: None,
+                },
+                _ => return Err(rmcp::ErrorData::resource_not_found("no such resource", None)),
+            };
+            Ok(ReadResourceResult { contents: vec![contents] })
+        }
+
+        fn get_info(&self) -> rmcp::model::ServerInfo {
+            rmcp::model::ServerInfo {
+                capabilities: rmcp::model::ServerCapabilities::builder().enable_resources().build(),
+                ..Default::default()
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resources_listed_and_readable() {
+        let (service, config) = in_process_upstream("docs", DocsServer).await;
+        let pool = ClientPool::from_services(vec![("docs".to_string(), service, config)]);
+        let mut catalog = Catalog::new();
+        catalog.add_server_resources("docs", pool.list_resources("docs").await.unwrap());
+        let sandbox = Sandbox::new(Arc::new(pool), Arc::new(catalog), &SandboxConfig::default())
+            .await
+            .unwrap();
+
+        let found = sandbox
+            .search("return resources.filter(r => r.mime_type === 'text/markdown').map(r => r.uri);")
+            .await
+            .unwrap();
+        assert_eq!(found, serde_json::json!(["docs://readme"]));
+
+        let read = sandbox
+            .execute(r#"
+                const readme = await read_resource("docs://readme");
+                const logo = await read_resource("docs://logo");
+                const missing = await read_resource("docs://nope");
+                const upstreamMissing = await read_resource("docs://nope", "docs");
+                return { readme, logo: logo[0].blob, missing: missing.error, upstream: typeof upstreamMissing.error };
+            "#)
+            .await
+            .unwrap();
+        assert_eq!(read["readme"], "# Docs");
+        assert_eq!(read["logo"], "iVBORw0=");
+        assert_eq!(read["missing"], "no resource with uri docs://nope");
+        assert_eq!(read["upstream"], "string");
+    }
+
+    /// Mock upstream with a `notes://{day}` template; subscribing to a note
+    /// sends an update notification for it right away.
+    #[derive(Clone)]
+    struct NotesServer;
+
+    impl rmcp::ServerHandler for NotesServer {
+        async fn list_resource_templates(
+            &self,
+            _request: Option<rmcp::model::PaginatedRequestParams>,
+            _context: rmcp::service::RequestContext<rmcp::RoleServer>,
+        ) -> Result<rmcp::model::ListResourceTemplatesResult, rmcp::ErrorData> {
+            use rmcp::model::{AnnotateAble, RawResourceTemplate};
+            let template = RawResourceTemplate {
+                uri_template: "notes://{day}".to_string(),
+                name: "Daily note".to_string(),
+                title: None,
+                description: Some("The note for one day".to_string()),
+                mime_type: Some("text/plain".to_string()),
+                icons: None,
+            };
+            Ok(rmcp::model::ListResourceTemplatesResult::with_all_items(vec![template.no_annotation()]))
+        }
+
+        async fn read_resource(
+            &self,
+            request: rmcp::model::ReadResourceRequestParams,
+            _context: rmcp::service::RequestContext<rmcp::RoleServer>,
+        ) -> Result<ReadResourceResult, rmcp::ErrorData> {
+            let day = request.uri.trim_start_matches("notes://");
+            Ok(ReadResourceResult {
+                contents: vec![ResourceContents::text(format!("notes for {day}"), &request.uri)],
+            })
+        }
+
+        async fn subscribe(
+            &self,
+            request: rmcp::model::SubscribeRequestParams,
+            context: rmcp::service::RequestContext<rmcp::RoleServer>,
+        ) -> Result<(), rmcp::ErrorData> {
+            let update = rmcp::model::ResourceUpdatedNotificationParam { uri: request.uri };
+            let _ = context.peer.notify_resource_updated(update).await;
+            Ok(())
+        }
+
+        async fn unsubscribe(
+            &self,
+            _request: rmcp::model::UnsubscribeRequestParams,
+            _context: rmcp::service::RequestContext<rmcp::RoleServer>,
+        ) -> Result<(), rmcp::ErrorData> {
+            Ok(())
+        }
+
+        fn get_info(&self) -> rmcp::model::ServerInfo {
+            rmcp::model::ServerInfo {
+                capabilities: rmcp::model::ServerCapabilities::builder()
+                    .enable_resources()
+                    .enable_resources_subscribe()
+                    .build(),
+                ..Default::default()
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resource_templates_and_subscriptions() {
+        let (service, config) = in_process_upstream("notes", NotesServer).await;
+        let pool = ClientPool::from_services(vec![("notes".to_string(), service, config)]);
+        let mut catalog = Catalog::new();
+        catalog.add_server_resource_templates("notes", pool.list_resource_templates("notes").await.unwrap());
+        let sandbox = Sandbox::new(Arc::new(pool), Arc::new(catalog), &SandboxConfig::default())
+            .await
+            .unwrap();
+
+        let templates = sandbox.search("return resource_templates;").await.unwrap();
+        assert_eq!(
+            templates,
+            serde_json::json!([{
+                "server": "notes",
+                "uri_template": "notes://{day}",
+                "name": "Daily note",
+                "description": "The note for one day",
+                "mime_type": "text/plain",
+            }])
+        );
+
+        let result = sandbox
+            .execute(r#"
+                const t = resource_templates[0];
+                const uri = t.uri_template.replace("{day}", "monday");
+                const subscribed = await subscribe_resource(uri, t.server);
+                let updates = [];
+                for (let i = 0; i < 100 && updates.length === 0; i++) {
+                    await cmcp.sleep(5);
+                    updates = await resource_updates();
+                }
+                return {
+                    subscribed,
+                    updates,
+                    note: await read_resource(uri, t.server),
+                    unknown: await subscribe_resource("notes://tuesday"),
+                    unsubscribed: await unsubscribe_resource(uri, t.server),
+                    drained: await resource_updates(),
+                };
+            "#)
+            .await
+            .unwrap();
+        assert_eq!(
+            result,
+            serde_json::json!({
+                "subscribed": { "server": "notes", "uri": "notes://monday" },
+                "updates": [{ "server": "notes", "uri": "notes://monday" }],
+                "note": "notes for monday",
+                "unknown": { "error": "no resource with uri notes://tuesday" },
+                "unsubscribed": { "server": "notes", "uri": "notes://monday" },
+                "drained": [],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resource_updates_are_kept_per_sandbox() {
+        let (service, config) = in_process_upstream("notes", NotesServer).await;
+        let pool = Arc::new(ClientPool::from_services(vec![("notes".to_string(), service, config)]));
+        let catalog = Arc::new(Catalog::new());
+        let elsewhere = SandboxConfig {
+            view: Some(crate::config::ViewConfig { servers: vec!["elsewhere".to_string()], ..Default::default() }),
+            ..Default::default()
+        };
+        let first = Sandbox::new(pool.clone(), catalog.clone(), &SandboxConfig::default()).await.unwrap();
+        let second = Sandbox::new(pool.clone(), catalog.clone(), &SandboxConfig::default()).await.unwrap();
+        let outside = Sandbox::new(pool, catalog, &elsewhere).await.unwrap();
+
+        let updates = first
+            .execute(r#"
+                await subscribe_resource("notes://monday", "notes");
+                let updates = [];
+                for (let i = 0; i < 100 && updates.length === 0; i++) {
+                    await cmcp.sleep(5);
+                    updates = await resource_updates();
+                }
+                return updates;
+            "#)
+            .await
+            .unwrap();
+        let expected = serde_json::json!([{ "server": "notes", "uri": "notes://monday" }]);
+        assert_eq!(updates, expected);
+        assert_eq!(outside.execute("return await resource_updates();").await.unwrap(), serde_json::json!([]));
+        assert_eq!(second.execute("return await resource_updates();").await.unwrap(), expected);
+    }
+
+    /// Mock upstream publishing a single `review` prompt that takes a `language` argument.
+    #[derive(Clone)]
+    struct PromptServer;
+
+    impl rmcp::ServerHandler for PromptServer {
+        async fn list_prompts(
+            &self,
+            _request: Option<rmcp::model::PaginatedRequestParams>,
+            _context: rmcp::service::RequestContext<rmcp::RoleServer>,
+        ) -> Result<rmcp::model::ListPromptsResult, rmcp::ErrorData> {
+            use rmcp::model::{Prompt, PromptArgument};
+            let argument = |name: &str, required| PromptArgument {
+                name: name.to_string(),
+                title: None,
+                description: None,
+                required: Some(required),
+            };
+            let prompt = Prompt::new(
+                "review",
+                Some("Review a change"),
+                Some(vec![argument("language", true), argument("tone", false)]),
+            );
+            Ok(rmcp::model::ListPromptsResult::with_all_items(vec![prompt]))
+        }
+
+        async fn get_prompt(
+            &self,
+            request: rmcp::model::GetPromptRequestParams,
+            _context: rmcp::service::RequestContext<rmcp::RoleServer>,
+        ) -> Result<rmcp::model::GetPromptResult, rmcp::ErrorData> {
+            use rmcp::model::{PromptMessage, PromptMessageRole};
+            let language = request
+                .arguments
+                .and_then(|args| args.get("language").and_then(|v| v.as_str()).map(String::from))
+                .ok_or_else(|| rmcp::ErrorData::invalid_params("language is required", None))?;
+            Ok(rmcp::model::GetPromptResult {
+                description: None,
+                messages: vec![PromptMessage::new_text(
+                    PromptMessageRole::User,
+                    format!("Review this {language} change."),
+                )],
+            })
+        }
+
+        fn get_info(&self) -> rmcp::model::ServerInfo {
+            rmcp::model::ServerInfo {
+                capabilities: rmcp::model::ServerCapabilities::builder().enable_prompts().build(),
+                ..Default::default()
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_prompts_listed_and_rendered() {
+        let (service, config) = in_process_upstream("coach", PromptServer).await;
+        let pool = ClientPool::from_services(vec![("coach".to_string(), service, config)]);
+        let mut catalog = Catalog::new();
+        catalog.add_server_prompts("coach", pool.list_prompts("coach").await.unwrap());
+        let sandbox = Sandbox::new(Arc::new(pool), Arc::new(catalog), &SandboxConfig::default())
+            .await
+            .unwrap();
+
+        let found = sandbox.search("return prompts.map(p => [p.name, p.required, p.optional]);").await.unwrap();
+        assert_eq!(found, serde_json::json!([["review", ["language"], ["tone"]]]));
+
+        let rendered = sandbox
+            .execute(r#"
+                const messages = await get_prompt("coach", "review", { language: "Rust" });
+                const missing = await get_prompt("coach", "review");
+                return { text: messages[0].content.text, role: messages[0].role, error: typeof missing.error };
+            "#)
+            .await
+            .unwrap();
+        assert_eq!(rendered["text"], "Review this Rust change.");
+        assert_eq!(rendered["role"], "user");
+        assert_eq!(rendered["error"], "string");
+    }
+
+    #[tokio::test]
+    async fn test_resources_empty_without_capability() {
+        let sandbox = slow_sandbox(&[("plain", 0)]).await;
+        assert!(sandbox.pool.list_resources("plain").await.unwrap().is_empty());
+        let count = sandbox.execute("return resources.length;").await.unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[tokio::test]
+    async fn test_any_server_routes_by_priority() {
+        let servers = [("alpha", 10), ("beta", 20), ("gamma", 30)];
+        let config = SandboxConfig {
+            any_server: Some(true),
+            server_priority: vec!["beta".to_string()],
+            ..Default::default()
+        };
+        let sandbox = slow_sandbox_with(&servers, &config).await;
+        let result = sandbox.execute(r#"
+            let missing;
+            try { await anyServer.no_such_tool({}); } catch (e) { missing = e.message; }
+            return { routed: await anyServer.wait({}), missing };
+        "#).await.unwrap();
+        assert_eq!(result["routed"], serde_json::json!("waited 20ms"));
+        assert_eq!(result["missing"], serde_json::json!("no server exposes tool no_such_tool"));
+
+        // Without a priority list, servers are tried alphabetically.
+        let config = SandboxConfig { any_server: Some(true), ..Default::default() };
+        let sandbox = slow_sandbox_with(&servers, &config).await;
+        let result = sandbox.execute("return await anyServer.wait({});").await.unwrap();
+        assert_eq!(result, serde_json::json!("waited 10ms"));
+
+        // Opt-in only.
+        let sandbox = slow_sandbox(&servers).await;
+        let result = sandbox.execute("return typeof anyServer;").await.unwrap();
+        assert_eq!(result, serde_json::json!("undefined"));
+    }
+
+    #[tokio::test]
+    async fn test_server_alias_routes_to_target() {
+        let config = SandboxConfig {
+            server_aliases: HashMap::from([
+                ("old-api".to_string(), "new-api".to_string()),
+                ("other".to_string(), "new-api".to_string()),
+                ("ghost".to_string(), "missing".to_string()),
+            ]),
+            ..Default::default()
+        };
+        let sandbox = slow_sandbox_with(&[("new-api", 10), ("other", 20)], &config).await;
+        let result = sandbox.execute(r#"
+            return {
+                routed: await old_api.wait({}),
+                shadowed: await other.wait({}),
+                ghost: typeof ghost,
+            };
+        "#).await.unwrap();
+        assert_eq!(
+            result,
+            serde_json::json!({ "routed": "waited 10ms", "shadowed": "waited 20ms", "ghost": "undefined" })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_servers_global_lists_servers_with_tool_counts() {
+        let sandbox = slow_sandbox(&[("slow", 10), ("chrome-devtools", 10)]).await;
+        let result = sandbox.execute("return servers;").await.unwrap();
+        assert_eq!(
+            result,
+            serde_json::json!([
+                { "name": "chrome-devtools", "description": "", "tools": 1 },
+                { "name": "slow", "description": "", "tools": 1 },
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unawaited_calls_do_not_outlive_execute() {
+        let sandbox = slow_sandbox(&[("slow", 10_000)]).await;
+        sandbox.execute("return 1;").await.unwrap();
+        let baseline = Arc::strong_count(&sandbox.pool);
+
+        let start = std::time::Instant::now();
+        for _ in 0..20 {
+            sandbox.execute("slow.wait({}); __call_tool('slow', 'wait', '{}'); return 1;").await.unwrap();
+        }
+        sandbox.shutdown().await;
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+        // Each leftover call kept its context, and the pool, alive until it finished.
+        assert_eq!(Arc::strong_count(&sandbox.pool), baseline);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_one_of_two_concurrent_calls() {
+        let sandbox = slow_sandbox(&[("slow", 10_000), ("fast", 100)]).await;
+        let start = std::time::Instant::now();
+        let result = sandbox.execute(r#"
+            const loser = slow.wait({});
+            const winner = fast.wait({});
+            const first = await Promise.race([loser, winner]);
+            const cancelled = loser.cancel();
+            return { first, cancelled, loser: await loser, again: loser.cancel() };
+        "#).await.unwrap();
+
+        assert_eq!(result["first"], serde_json::json!("waited 100ms"));
+        assert_eq!(result["cancelled"], serde_json::json!(true));
+        assert_eq!(result["loser"]["error"], serde_json::json!("call to slow.wait was cancelled"));
+        assert_eq!(result["again"], serde_json::json!(false));
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
     }
 
-    /// Execute a `search()` call — agent TypeScript code that filters the tool catalog.
-    pub async fn search(&self, code: &str) -> Result<serde_json::Value> {
-        let catalog_json_str = serde_json::to_string(&self.catalog.to_json_value())?;
-        let code = transpile_agent_code(code, &self.catalog.type_declarations())?;
+    #[tokio::test]
+    async fn test_global_call_limit_bounds_in_flight_calls() {
+        let gauge = CountingServer::default();
+        let mut services = Vec::new();
+        let mut catalog = Catalog::new();
+        for name in ["a", "b"] {
+            let (service, config) = in_process_upstream(name, gauge.clone()).await;
+            catalog.add_server_tools(name, Vec::new());
+            services.push((name.to_string(), service, config));
+        }
+        let pool = ClientPool::from_services(services).with_max_concurrent_calls(4);
+        let sandbox = Sandbox::new(Arc::new(pool), Arc::new(catalog), &SandboxConfig::default())
+            .await
+            .unwrap();
 
-        let result = async_with!(self.ctx => |ctx| {
-            let tools_val: Value = ctx.json_parse(catalog_json_str)
-                .catch(&ctx)
-                .map_err(|e| anyhow::anyhow!("failed to parse catalog: {e}"))?;
+        let result = sandbox.execute(r#"
+            const calls = [];
+            for (let i = 0; i < 100; i++) {
+                calls.push(__call_tool(i % 2 ? "a" : "b", "work", "{}"));
+            }
+            return (await Promise.all(calls)).filter(r => JSON.parse(r).ok === "done").length;
+        "#).await.unwrap();
 
-            ctx.globals().set("tools", tools_val)
-                .map_err(|e| anyhow::anyhow!("failed to set tools: {e}"))?;
+        assert_eq!(result, 100);
+        let peak = gauge.peak.load(std::sync::atomic::Ordering::SeqCst);
+        assert!(peak <= 4, "peak in-flight calls: {peak}");
+        assert!(peak > 1, "calls should still run concurrently, peak was {peak}");
+    }
 
-            let wrapped = format!("(async () => {{ {code} }})()", code = code);
+    #[tokio::test]
+    async fn test_catalog_setup_built_once_per_sandbox() {
+        let sandbox = slow_sandbox(&[("alpha", 0), ("beta-two", 0)]).await;
+        assert!(sandbox.setup.get().is_none());
 
-            let promise: Promise = ctx.eval_with_options(wrapped, eval_opts())
-                .catch(&ctx)
-                .map_err(|e| anyhow::anyhow!("JS eval error: {e}"))?;
+        let first = sandbox.execute("return await beta_two.wait({});").await.unwrap();
+        assert_eq!(first, serde_json::json!("waited 0ms"));
+        let built: *const CatalogSetup = sandbox.setup.get().unwrap();
 
-            let result: Value = promise.into_future::<Value>()
-                .await
-                .catch(&ctx)
-                .map_err(|e| anyhow::anyhow!("JS promise rejected: {e}"))?;
+        sandbox.execute("return tools.length;").await.unwrap();
+        sandbox.search("return tools.map(t => t.server);").await.unwrap();
+        assert!(std::ptr::eq(built, sandbox.setup.get().unwrap()));
 
-            stringify_result(&ctx, result)
-        })
-        .await?;
+        // The eval'd prelude holds one line per server; the catalog itself is
+        // parsed as JSON rather than inlined into the code.
+        let prelude = &sandbox.setup.get().unwrap().execute_prelude;
+        assert!(prelude.contains(r#"const beta_two = __server_proxy("beta-two");"#), "{prelude}");
+        assert!(!prelude.contains("Wait and reply"), "{prelude}");
 
-        Ok(result)
+        // Declarations were transpiled with the setup, leaving only agent code per call.
+        assert_eq!(sandbox.setup.get().unwrap().declarations_js, "");
     }
 
-    /// Execute an `execute()` call — agent TypeScript code that calls tools across servers.
-    pub async fn execute(&self, code: &str) -> Result<serde_json::Value> {
-        let pool = self.pool.clone();
-        let catalog = self.catalog.clone();
-        let code = transpile_agent_code(code, &self.catalog.type_declarations())?;
-
-        let result = async_with!(self.ctx => |ctx| {
-            // Inject __call_tool as an async native function.
-            let pool_ref = pool.clone();
-            let call_tool_fn = Function::new(
-                ctx.clone(),
-                Async({
-                    let pool = pool_ref.clone();
-                    move |server: String, tool: String, params_json: String| {
-                        let pool_inner = pool.clone();
-                        async move {
-                            let params: serde_json::Value =
-                                serde_json::from_str(&params_json)
-                                    .unwrap_or(serde_json::Value::Object(serde_json::Map::new()));
+    #[tokio::test]
+    async fn test_call_timeout_fails_only_the_slow_call() {
+        let options = crate::config::ServerOptions {
+            call_timeout_ms: Some(300),
+            ..Default::default()
+        };
+        let sandbox = slow_sandbox_with_options(
+            &[("slow", 10_000), ("fast", 50)],
+            &SandboxConfig::default(),
+            &options,
+        )
+        .await;
+        let start = std::time::Instant::now();
+        let result = sandbox.execute(r#"
+            const [slowResult, fastResult] = await Promise.all([slow.wait({}), fast.wait({})]);
+            return { slowResult, fastResult };
+        "#).await.unwrap();
 
-                            match pool_inner.call_tool(&server, &tool, params).await {
-                                Ok(call_result) => {
-                                    serde_json::to_string(&call_result)
-                                        .unwrap_or_else(|_| "null".to_owned())
-                                }
-                                Err(e) => {
-                                    format!(r#"{{"error":"{}"}}"#, e.to_string().replace('"', "\\\""))
-                                }
-                            }
-                        }
-                    }
-                }),
-            )
-            .map_err(|e| anyhow::anyhow!("failed to create __call_tool: {e}"))?;
+        assert_eq!(result["fastResult"], serde_json::json!("waited 50ms"));
+        assert_eq!(
+            result["slowResult"]["error"],
+            serde_json::json!("tool call slow.wait timed out after 300ms")
+        );
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    }
 
-            ctx.globals().set("__call_tool", call_tool_fn)
-                .map_err(|e| anyhow::anyhow!("failed to set __call_tool: {e}"))?;
+    #[tokio::test]
+    async fn test_call_deadline_is_shared_by_sequential_calls() {
+        let config = SandboxConfig {
+            call_deadline_secs: Some(0.5),
+            ..Default::default()
+        };
+        let sandbox = slow_sandbox_with(&[("slow", 300)], &config).await;
+        let result = sandbox.execute(r#"
+            const first = await slow.wait({});
+            const second = await slow.wait({});
+            const third = await slow.wait({});
+            return { first, second, third };
+        "#).await.unwrap();
 
-            // Build JS proxy objects for each server.
-            let mut setup = String::new();
-
-            let mut server_names: Vec<&str> = catalog
-                .entries()
-                .iter()
-                .map(|e| e.server.as_str())
-                .collect::<std::collections::HashSet<_>>()
-                .into_iter()
-                .collect();
-            server_names.sort();
-
-            for name in &server_names {
-                // Convert server names with hyphens to valid JS identifiers
-                // e.g. "chrome-devtools" -> "chrome_devtools"
-                let js_name = name.replace('-', "_");
-                setup.push_str(&format!(
-                    r#"const {js_name} = new Proxy({{}}, {{
-  get(_, tool) {{
-    return async (args = {{}}) => {{
-      const resultJson = await __call_tool("{name}", tool, JSON.stringify(args));
-      try {{ return JSON.parse(resultJson); }} catch {{ return resultJson; }}
-    }};
-  }}
-}});
-"#,
-                    js_name = js_name,
-                    name = name,
-                ));
-            }
+        assert_eq!(result["first"], serde_json::json!("waited 300ms"));
+        let expected = serde_json::json!("deadline exceeded: tool calls in this execute may take 500ms in total");
+        assert_eq!(result["second"]["error"], expected);
+        assert_eq!(result["third"]["error"], expected);
 
-            // Also inject the catalog
-            let catalog_json_str = serde_json::to_string(&catalog.to_json_value())
-                .unwrap_or_else(|_| "[]".to_owned());
-            setup.push_str(&format!("const tools = {};", catalog_json_str));
+        // Each execute starts with a fresh budget.
+        let result = sandbox.execute("return await slow.wait({});").await.unwrap();
+        assert_eq!(result, serde_json::json!("waited 300ms"));
+    }
 
-            let wrapped = format!("(async () => {{ {setup}\n{code} }})()", setup = setup, code = code);
+    /// A `MakeWriter` that captures formatted tracing output into a shared buffer.
+    #[derive(Clone, Default)]
+    struct CaptureWriter(Arc<std::sync::Mutex<Vec<u8>>>);
 
-            let promise: Promise = ctx.eval_with_options(wrapped, eval_opts())
-                .catch(&ctx)
-                .map_err(|e| anyhow::anyhow!("JS eval error: {e}"))?;
+    impl std::io::Write for CaptureWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
 
-            let result: Value = promise.into_future::<Value>()
-                .await
-                .catch(&ctx)
-                .map_err(|e| anyhow::anyhow!("JS promise rejected: {e}"))?;
+    impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for CaptureWriter {
+        type Writer = Self;
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
 
-            stringify_result(&ctx, result)
-        })
-        .await?;
+    #[tokio::test]
+    async fn test_console_captured_per_call() {
+        let sandbox = test_sandbox().await;
+        let (result, logs) = sandbox
+            .execute_with_logs(r#"console.log("step", 1); console.warn({ n: 2 }); return 3;"#)
+            .await
+            .unwrap();
+        assert_eq!(result, serde_json::json!(3));
+        assert_eq!(
+            logs,
+            [
+                ConsoleLine { level: "LOG".into(), message: "step 1".into() },
+                ConsoleLine { level: "WARN".into(), message: r#"{"n":2}"#.into() },
+            ]
+        );
 
-        Ok(result)
+        let (_, logs) = sandbox.search_with_logs("console.error(tools.length); return [];").await.unwrap();
+        assert_eq!(logs, [ConsoleLine { level: "ERROR".into(), message: "0".into() }]);
     }
-}
 
-/// Convert a JS Value back to serde_json::Value via JSON.stringify.
-fn stringify_result<'js>(
-    ctx: &rquickjs::Ctx<'js>,
-    value: Value<'js>,
-) -> Result<serde_json::Value> {
-    let json_rq_str = ctx.json_stringify(value)
-        .catch(ctx)
-        .map_err(|e| anyhow::anyhow!("failed to stringify: {e}"))?;
+    #[tokio::test]
+    async fn test_console_routed_through_tracing() {
+        let writer = CaptureWriter::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(writer.clone())
+            .with_max_level(Level::INFO)
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
 
-    let json_std_str = match json_rq_str {
-        Some(s) => s.to_string()
-            .map_err(|e| anyhow::anyhow!("string conversion: {e}"))?,
-        None => "null".to_owned(),
-    };
+        let (pool, catalog) = ClientPool::connect(HashMap::new(), false).await.unwrap();
+        let (pool, catalog) = (Arc::new(pool), Arc::new(catalog));
 
-    serde_json::from_str(&json_std_str)
-        .map_err(|e| anyhow::anyhow!("JSON parse error: {e}"))
-}
+        let config = SandboxConfig {
+            console_level: Some("info".into()),
+            console_prefix: Some("agent".into()),
+            ..Default::default()
+        };
+        let sandbox = Sandbox::new(pool.clone(), catalog.clone(), &config).await.unwrap();
+        sandbox.execute(r#"console.log("visible"); return 1;"#).await.unwrap();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::collections::HashMap;
-    use crate::client::ClientPool;
+        // Below the subscriber's max level: filtered out.
+        let config = SandboxConfig {
+            console_level: Some("debug".into()),
+            ..Default::default()
+        };
+        let sandbox = Sandbox::new(pool, catalog, &config).await.unwrap();
+        sandbox.execute(r#"console.log("hidden"); return 1;"#).await.unwrap();
 
-    async fn test_sandbox() -> Sandbox {
-        let (pool, catalog) = ClientPool::connect(HashMap::new()).await.unwrap();
-        Sandbox::new(Arc::new(pool), Arc::new(catalog)).await.unwrap()
+        let out = String::from_utf8(writer.0.lock().unwrap().clone()).unwrap();
+        assert!(out.contains("[agent] LOG: visible"), "output: {out}");
+        assert!(!out.contains("hidden"), "output: {out}");
     }
 
     #[tokio::test]
@@ -254,6 +2128,30 @@ mod tests {
         assert_eq!(result, serde_json::json!(["a", "b", "c"]));
     }
 
+    #[tokio::test]
+    async fn test_unawaited_promises_in_result_are_resolved() {
+        let sandbox = test_sandbox().await;
+        let later = "const later = (v) => cmcp.sleep(1).then(() => v);";
+
+        let result = sandbox.execute(&format!("{later}\nreturn later({{ id: 7 }});")).await.unwrap();
+        assert_eq!(result, serde_json::json!({ "id": 7 }));
+
+        let result = sandbox
+            .execute(&format!("{later}\nreturn {{ items: [1, 2].map(n => later(n * 10)), total: later(2), at: new Date(0) }};"))
+            .await
+            .unwrap();
+        assert_eq!(
+            result,
+            serde_json::json!({ "items": [10, 20], "total": 2, "at": "1970-01-01T00:00:00.000Z" })
+        );
+
+        let result = sandbox
+            .execute(&format!("{later}\nfunction* ids() {{ yield later('a'); yield 'b'; }}\nreturn ids();"))
+            .await
+            .unwrap();
+        assert_eq!(result, serde_json::json!(["a", "b"]));
+    }
+
     #[tokio::test]
     async fn test_execute_chaining() {
         let sandbox = test_sandbox().await;
@@ -266,6 +2164,382 @@ mod tests {
         assert_eq!(result, serde_json::json!(25));
     }
 
+    #[tokio::test]
+    async fn test_last_result_reflects_previous_execute() {
+        let sandbox = test_sandbox().await;
+        let first = sandbox.execute("return __last_result;").await.unwrap();
+        assert_eq!(first, serde_json::Value::Null);
+
+        sandbox.execute(r#"return { items: [1, 2, 3] };"#).await.unwrap();
+        let result = sandbox.execute("return __last_result.items.length;").await.unwrap();
+        assert_eq!(result, serde_json::json!(3));
+    }
+
+    async fn sandbox_with_timeout(secs: f64) -> Sandbox {
+        let (pool, catalog) = ClientPool::connect(HashMap::new(), false).await.unwrap();
+        let config = SandboxConfig {
+            timeout_secs: Some(secs),
+            ..Default::default()
+        };
+        Sandbox::new(Arc::new(pool), Arc::new(catalog), &config).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_invalid_timeouts_are_config_errors() {
+        for secs in [-1.0, f64::NAN, f64::INFINITY, 1e19] {
+            let configs = [
+                ("timeout_secs", SandboxConfig { timeout_secs: Some(secs), ..Default::default() }),
+                ("call_deadline_secs", SandboxConfig { call_deadline_secs: Some(secs), ..Default::default() }),
+            ];
+            for (setting, config) in configs {
+                let (pool, catalog) = ClientPool::connect(HashMap::new(), false).await.unwrap();
+                let err = Sandbox::new(Arc::new(pool), Arc::new(catalog), &config).await.err().unwrap();
+                assert!(err.to_string().starts_with(&format!("{setting} must be a non-negative number")), "{err}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_globals_do_not_leak_between_calls() {
+        let sandbox = test_sandbox().await;
+        sandbox.execute("globalThis.leak = 42; return 1;").await.unwrap();
+        let result = sandbox.execute(r#"return typeof globalThis.leak === "undefined";"#).await.unwrap();
+        assert_eq!(result, serde_json::json!(true));
+
+        sandbox.search("globalThis.leak = 42; return 1;").await.unwrap();
+        let result = sandbox.search("return typeof leak;").await.unwrap();
+        assert_eq!(result, serde_json::json!("undefined"));
+    }
+
+    #[test]
+    fn test_transpile_cache_hit_returns_same_output() {
+        let cache = TranspileCache::default();
+        let code = "const n: number = 1;\nreturn n;";
+        let first = cache.get_or_transpile(code, "").unwrap();
+        let second = cache.get_or_transpile(code, "").unwrap();
+        assert_eq!(first, second);
+        assert_eq!(first, transpile_agent_code(code, "").unwrap());
+        // Other declarations are a different entry.
+        let declared = cache.get_or_transpile(code, "var x;").unwrap();
+        assert!(declared.body.starts_with("var x;"), "body: {}", declared.body);
+        assert_eq!(cache.inner.lock().unwrap().entries.len(), 2);
+
+        // Bounded, dropping the least recently used entry first.
+        for i in 0..TRANSPILE_CACHE_SIZE {
+            cache.get_or_transpile(code, "").unwrap();
+            cache.get_or_transpile(&format!("return {i};"), "").unwrap();
+        }
+        let inner = cache.inner.lock().unwrap();
+        assert_eq!(inner.entries.len(), TRANSPILE_CACHE_SIZE);
+        assert!(inner.entries.values().any(|e| e.code == code && e.declarations_js.is_empty()));
+        assert!(!inner.entries.values().any(|e| e.declarations_js == "var x;"));
+    }
+
+    #[test]
+    fn test_json_text_depth_ignores_strings() {
+        assert_eq!(json_text_depth(r#"{"a": [1, {"b": "[[[{{"}]}"#), 3);
+        assert_eq!(json_text_depth(r#""\"[" "#), 0);
+        assert_eq!(json_text_depth(&"[".repeat(100_000)), 100_000);
+    }
+
+    #[tokio::test]
+    async fn test_deep_result_rejected() {
+        let sandbox = test_sandbox().await;
+        let err = sandbox
+            .execute("let v = 1; for (let i = 0; i < 200; i++) v = [v]; return v;")
+            .await
+            .unwrap_err();
+        assert!(matches!(err.downcast_ref::<SandboxError>(), Some(SandboxError::TooDeep(64))), "err: {err}");
+
+        let ok = sandbox.execute("return [[[1]]];").await.unwrap();
+        assert_eq!(ok, serde_json::json!([[[1]]]));
+    }
+
+    #[tokio::test]
+    async fn test_timeout_interrupts_cpu_loop() {
+        let sandbox = sandbox_with_timeout(0.2).await;
+        let start = std::time::Instant::now();
+        let err = sandbox.execute("while (true) {}").await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<SandboxError>(), Some(SandboxError::Timeout(_))), "err: {err}");
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+
+        // The runtime is still usable after an interrupt.
+        assert_eq!(sandbox.execute("return 1;").await.unwrap(), serde_json::json!(1));
+    }
+
+    #[tokio::test]
+    async fn test_failed_code_keeps_its_console_output() {
+        let sandbox = sandbox_with_timeout(0.2).await;
+        let err = sandbox.execute(r#"console.log("step 1"); throw new Error("boom");"#).await.unwrap_err();
+        assert!(err.to_string().contains("boom"), "err: {err}");
+        assert_eq!(console_logs(&err), [ConsoleLine { level: "LOG".to_string(), message: "step 1".to_string() }]);
+
+        let err = sandbox.execute(r#"console.warn("looping"); while (true) {}"#).await.unwrap_err();
+        let logged = err.downcast_ref::<LoggedError>().unwrap();
+        assert!(matches!(logged.error.downcast_ref::<SandboxError>(), Some(SandboxError::Timeout(_))), "err: {err}");
+        assert_eq!(logged.logs[0].message, "looping");
+
+        let err = sandbox.search(r#"console.log("searching"); throw new Error("nope");"#).await.unwrap_err();
+        assert_eq!(console_logs(&err)[0].message, "searching");
+
+        // Nothing logged, nothing attached.
+        let err = sandbox.execute(r#"throw new Error("quiet");"#).await.unwrap_err();
+        assert!(err.downcast_ref::<LoggedError>().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_busy_loop_interrupted_with_sparse_checks() {
+        let (pool, catalog) = ClientPool::connect(HashMap::new(), false).await.unwrap();
+        let config = SandboxConfig {
+            timeout_secs: Some(0.2),
+            interrupt_check_interval: Some(100),
+            ..Default::default()
+        };
+        let sandbox = Sandbox::new(Arc::new(pool), Arc::new(catalog), &config).await.unwrap();
+
+        let start = std::time::Instant::now();
+        let err = sandbox.execute("let n = 0; while (true) { n++; }").await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<SandboxError>(), Some(SandboxError::Timeout(_))), "err: {err}");
+        let elapsed = start.elapsed();
+        assert!(elapsed < std::time::Duration::from_secs(2), "interrupted after {elapsed:?}");
+    }
+
+    #[tokio::test]
+    async fn test_timeout_on_pending_await() {
+        let sandbox = sandbox_with_timeout(0.2).await;
+        let err = sandbox.search("await new Promise(() => {}); return 1;").await.unwrap_err();
+        assert_eq!(err.to_string(), "timed out after 0.2s");
+    }
+
+    #[tokio::test]
+    async fn test_timeout_does_not_wait_for_timers() {
+        let sandbox = sandbox_with_timeout(0.5).await;
+        let start = std::time::Instant::now();
+        let err = sandbox.execute("await cmcp.sleep(5000); return 1;").await.unwrap_err();
+        assert_eq!(err.to_string(), "timed out after 0.5s");
+        assert!(start.elapsed() < std::time::Duration::from_secs(2), "took {:?}", start.elapsed());
+
+        // Nor for a timer the code didn't await, or one started after it returned.
+        let start = std::time::Instant::now();
+        let code = "cmcp.sleep(5000); cmcp.sleep(1).then(() => cmcp.sleep(5000)); return 1;";
+        assert_eq!(sandbox.execute(code).await.unwrap(), serde_json::json!(1));
+        sandbox.shutdown().await;
+        assert!(start.elapsed() < std::time::Duration::from_secs(1), "took {:?}", start.elapsed());
+    }
+
+    #[tokio::test]
+    async fn test_prelude_retry() {
+        let sandbox = test_sandbox().await;
+        let result = sandbox.execute(r#"
+            let attempts = 0;
+            const value = await cmcp.retry(async () => {
+                attempts++;
+                if (attempts < 3) throw new Error("flaky");
+                return "ok";
+            }, { retries: 3, delayMs: 1 });
+            return { value, attempts };
+        "#).await.unwrap();
+        assert_eq!(result, serde_json::json!({ "value": "ok", "attempts": 3 }));
+
+        // Gives up after the initial attempt plus `retries` retries.
+        let result = sandbox.execute(r#"
+            let attempts = 0;
+            try {
+                await cmcp.retry(async () => { attempts++; throw new Error("down"); }, { retries: 2, delayMs: 1 });
+            } catch (e) {
+                return { attempts, message: e.message };
+            }
+        "#).await.unwrap();
+        assert_eq!(result, serde_json::json!({ "attempts": 3, "message": "down" }));
+    }
+
+    #[tokio::test]
+    async fn test_execute_async_generator_collects_yields() {
+        let sandbox = test_sandbox().await;
+        let result = sandbox.execute(r#"
+            async function* chunks() {
+                yield 1;
+                yield 2;
+                yield 3;
+            }
+            return chunks();
+        "#).await.unwrap();
+        assert_eq!(result, serde_json::json!([1, 2, 3]));
+
+        let result = sandbox.execute(r#"
+            for (const page of ["a", "b", "c"]) {
+                yield await Promise.resolve(page);
+            }
+        "#).await.unwrap();
+        assert_eq!(result, serde_json::json!(["a", "b", "c"]));
+    }
+
+    #[tokio::test]
+    async fn test_colliding_server_names_each_get_a_proxy() {
+        let mut catalog = Catalog::new();
+        catalog.add_server_tools("my-server", vec![]);
+        catalog.add_server_tools("my_server", vec![]);
+        let (pool, _) = ClientPool::connect(HashMap::new(), false).await.unwrap();
+        let sandbox = Sandbox::new(Arc::new(pool), Arc::new(catalog), &SandboxConfig::default())
+            .await
+            .unwrap();
+
+        // Two `const my_server` declarations would be a syntax error.
+        let result = sandbox.execute("return [typeof my_server, typeof my_server_2];").await.unwrap();
+        assert_eq!(result, serde_json::json!(["object", "object"]));
+        // `my-server` sorts first and keeps the plain name.
+        let result = sandbox.execute("return await my_server_2.ping();").await.unwrap();
+        assert_eq!(result, serde_json::json!({ "error": "no server named 'my_server'" }));
+    }
+
+    #[tokio::test]
+    async fn test_callable_only_tool_dispatches_at_runtime() {
+        use rmcp::model::Tool;
+
+        let schema = serde_json::json!({"type": "object"});
+        let schema = Arc::new(schema.as_object().unwrap().clone());
+        let mut catalog = Catalog::new();
+        catalog.add_server_tools("jira", vec![
+            Tool::new("get_issue", "Get an issue", schema.clone()),
+            Tool::new("bulk_migrate", "Migrate projects", schema),
+        ]);
+        let (pool, _) = ClientPool::connect(HashMap::new(), false).await.unwrap();
+
+        let config = SandboxConfig {
+            callable_only: vec!["jira.bulk_migrate".to_string()],
+            ..Default::default()
+        };
+        let sandbox = Sandbox::new(Arc::new(pool), Arc::new(catalog), &config).await.unwrap();
+        assert!(!sandbox.catalog.type_declarations_filtered(&sandbox.declarations).contains("bulk_migrate"));
+
+        // The Proxy still dispatches the undeclared tool to the pool (which has no
+        // live "jira" connection here, so the call comes back as an error value).
+        let result = sandbox.execute("return await jira.bulk_migrate({});").await.unwrap();
+        let err = result.get("error").and_then(|e| e.as_str()).unwrap_or_default();
+        assert!(err.contains("jira"), "result: {result}");
+    }
+
+    #[tokio::test]
+    async fn test_search_results_get_full_schemas_back() {
+        use rmcp::model::Tool;
+
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "repo": { "type": "string", "description": "owner/name" },
+                "labels": { "type": "array", "items": { "type": "string" } },
+            },
+            "required": ["repo"],
+        });
+        let mut catalog = Catalog::new();
+        catalog.add_server_tools("github", vec![
+            Tool::new("list_issues", "List issues", Arc::new(schema.as_object().unwrap().clone())),
+            Tool::new("create_issue", "Create an issue", Arc::new(schema.as_object().unwrap().clone())),
+        ]);
+        let (pool, _) = ClientPool::connect(HashMap::new(), false).await.unwrap();
+        let config = SandboxConfig {
+            summarize_schemas: Some(true),
+            ..Default::default()
+        };
+        let sandbox = Sandbox::new(Arc::new(pool), Arc::new(catalog), &config).await.unwrap();
+
+        let result = sandbox
+            .search(
+                "return { seen: tools[0].input_schema, \
+                 matches: tools.filter(t => t.name.startsWith('list')), \
+                 names: tools.map(t => ({ server: t.server, name: t.name })) };",
+            )
+            .await
+            .unwrap();
+        // The injected array only had summaries...
+        assert_eq!(
+            result["seen"],
+            serde_json::json!({
+                "type": "object",
+                "required": ["repo"],
+                "properties": { "repo": { "type": "string" }, "labels": { "type": "array" } },
+            })
+        );
+        // ...but the entries the search returned carry the full schema.
+        assert_eq!(result["matches"][0]["input_schema"], schema);
+        // Projections without a schema are left alone.
+        assert_eq!(result["names"][0].get("input_schema"), None);
+    }
+
+    #[test]
+    fn test_repair_json_trailing_commas() {
+        let repaired = repair_json("{\"items\": [1, 2, 3,], \"next\": null,}").unwrap();
+        assert_eq!(repaired, serde_json::json!({ "items": [1, 2, 3], "next": null }));
+
+        let repaired = repair_json("{\"note\": \"line one\nline two\"}").unwrap();
+        assert_eq!(repaired, serde_json::json!({ "note": "line one\nline two" }));
+
+        // Commas inside strings are left alone; plain text is not "repaired".
+        let repaired = repair_json("[\"a,]\",]").unwrap();
+        assert_eq!(repaired, serde_json::json!(["a,]"]));
+        assert!(repair_json("not json, at all").is_none());
+    }
+
+    #[test]
+    fn test_repair_text_content_in_result() {
+        use rmcp::model::Content;
+
+        let mut result = CallToolResult::success(vec![Content::text("{\"ok\": true,}")]);
+        repair_text_content(&mut result);
+        assert_eq!(result.content[0].as_text().unwrap().text, r#"{"ok":true}"#);
+    }
+
+    #[test]
+    fn test_sanitize_strips_control_characters() {
+        use rmcp::model::Content;
+
+        // An upstream that decoded invalid bytes lossily and passed a NUL and ESC through.
+        let text = String::from_utf8_lossy(b"ok\xff\xfe\x00 line\x1b[0m\n\tdone").into_owned();
+        let mut result = CallToolResult::success(vec![Content::text(text)]);
+        sanitize_text_content(&mut result);
+
+        let clean = &result.content[0].as_text().unwrap().text;
+        assert_eq!(clean, "ok\u{fffd}\u{fffd} line[0m\n\tdone");
+        assert_eq!(call_result_to_json(&result, false, false), serde_json::to_string(clean).unwrap());
+        assert_eq!(sanitize_text("plain\ttext\r\n"), None);
+    }
+
+    #[test]
+    fn test_results_resolve_to_structured_content_or_text() {
+        use rmcp::model::Content;
+
+        let resolve = |result: &CallToolResult, raw: bool| -> serde_json::Value {
+            serde_json::from_str(&call_result_to_json(result, raw, false)).unwrap()
+        };
+
+        let plain = CallToolResult::success(vec![Content::text("Page loaded: Example Domain")]);
+        assert_eq!(resolve(&plain, false), serde_json::json!("Page loaded: Example Domain"));
+        assert_eq!(resolve(&plain, true), serde_json::json!("Page loaded: Example Domain"));
+
+        let lines = CallToolResult::success(vec![Content::text("one"), Content::text("two")]);
+        assert_eq!(resolve(&lines, false), serde_json::json!("one\ntwo"));
+
+        // Only objects and arrays are parsed; a big numeric id stays exact.
+        let id = CallToolResult::success(vec![Content::text("1234567890123456789")]);
+        assert_eq!(resolve(&id, false), serde_json::json!("1234567890123456789"));
+        let flag = CallToolResult::success(vec![Content::text("true")]);
+        assert_eq!(resolve(&flag, false), serde_json::json!("true"));
+
+        let json_text = CallToolResult::success(vec![Content::text(r#"{"ok":true}"#)]);
+        assert_eq!(resolve(&json_text, false), serde_json::json!({ "ok": true }));
+        assert!(resolve(&json_text, true).get("content").is_some());
+
+        let mut structured = CallToolResult::structured(serde_json::json!({ "number": 7 }));
+        structured.content = vec![Content::text("Issue #7")];
+        assert_eq!(resolve(&structured, false), serde_json::json!({ "number": 7 }));
+
+        // Errors and results with non-text blocks stay whole.
+        let error = CallToolResult::error(vec![Content::text("not found")]);
+        assert_eq!(resolve(&error, false)["isError"], true);
+        let image = CallToolResult::success(vec![Content::text("page"), Content::image("iVBORw0=", "image/png")]);
+        assert_eq!(resolve(&image, false)["content"][1]["type"], "image");
+    }
+
     #[tokio::test]
     async fn test_call_tool_nonexistent_server_returns_error() {
         let sandbox = test_sandbox().await;
@@ -353,23 +2627,138 @@ mod tests {
     }
 }
 
-/// Prepend type declarations, wrap in async function, and transpile TypeScript to JavaScript.
+/// Transpiled agent code, ready to be wrapped for QuickJS.
+#[derive(Debug, Clone, PartialEq)]
+struct AgentCode {
+    /// The function body extracted from the transpiled output.
+    body: String,
+    /// Whether the agent code uses `yield` and runs as an async generator.
+    is_generator: bool,
+}
+
+impl AgentCode {
+    /// Wrap the body (prefixed with `setup`) in an IIFE whose result is drained
+    /// by `__drain`, so generator results come back as arrays.
+    fn wrap(&self, setup: &str) -> String {
+        let body = &self.body;
+        if self.is_generator {
+            format!("(async () => __drain((async function* () {{ {setup}\n{body} }})()))()")
+        } else {
+            format!("(async () => __drain(await (async () => {{ {setup}\n{body} }})()))()")
+        }
+    }
+}
+
+/// How many transpiled snippets a sandbox keeps.
+const TRANSPILE_CACHE_SIZE: usize = 128;
+
+/// Recently transpiled agent code, keyed by a hash of the code and the
+/// declarations it was transpiled with. Agents often retry the same snippet,
+/// and the oxc pipeline allocates heavily. When full, the least recently
+/// used entry is dropped. Transpile errors are not cached.
+#[derive(Default)]
+struct TranspileCache {
+    inner: std::sync::Mutex<TranspileCacheInner>,
+}
+
+#[derive(Default)]
+struct TranspileCacheInner {
+    entries: HashMap<u64, CachedTranspile>,
+    /// Bumped on every lookup; entries keep the value of their last use.
+    clock: u64,
+}
+
+struct CachedTranspile {
+    /// The source, checked on lookup so a hash collision is only a miss.
+    code: String,
+    declarations_js: String,
+    agent: AgentCode,
+    last_used: u64,
+}
+
+impl TranspileCache {
+    fn get_or_transpile(&self, code: &str, declarations_js: &str) -> Result<AgentCode> {
+        use std::hash::{Hash, Hasher};
+
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        (code, declarations_js).hash(&mut hasher);
+        let key = hasher.finish();
+        {
+            let mut inner = self.inner.lock().unwrap();
+            inner.clock += 1;
+            let clock = inner.clock;
+            if let Some(entry) = inner.entries.get_mut(&key)
+                && entry.code == code
+                && entry.declarations_js == declarations_js
+            {
+                entry.last_used = clock;
+                return Ok(entry.agent.clone());
+            }
+        }
+
+        let agent = transpile_agent_code(code, declarations_js)?;
+        let mut inner = self.inner.lock().unwrap();
+        if inner.entries.len() >= TRANSPILE_CACHE_SIZE
+            && !inner.entries.contains_key(&key)
+            && let Some(oldest) = inner.entries.iter().min_by_key(|(_, e)| e.last_used).map(|(k, _)| *k)
+        {
+            inner.entries.remove(&oldest);
+        }
+        let last_used = inner.clock;
+        inner.entries.insert(
+            key,
+            CachedTranspile {
+                code: code.to_string(),
+                declarations_js: declarations_js.to_string(),
+                agent: agent.clone(),
+                last_used,
+            },
+        );
+        Ok(agent)
+    }
+}
+
+/// Transpile the catalog's type declarations, once per catalog.
+///
+/// Declarations only describe globals, so they never change how agent code is
+/// transpiled; keeping them out of [`transpile_agent_code`] saves re-parsing
+/// the whole block on every call. If they fail to transpile, agent code still runs.
+fn transpile_declarations(type_decls: &str) -> String {
+    match transpile::ts_to_js(type_decls) {
+        Ok(js) => js.trim().to_string(),
+        Err(e) => {
+            tracing::warn!(error = %e, "type declarations failed to transpile");
+            String::new()
+        }
+    }
+}
+
+/// Wrap agent code in an async function, transpile TypeScript to JavaScript,
+/// and prepend the already transpiled declarations.
 ///
 /// The agent code may contain `return` statements (e.g. `return tools.filter(...)`),
 /// so we wrap in `async function __agent__() { ... }` before transpiling. After
 /// transpilation we extract the function body for QuickJS to wrap in its own IIFE.
-fn transpile_agent_code(code: &str, type_decls: &str) -> Result<String> {
+///
+/// If the code only parses as a generator (it uses `yield`), it is re-transpiled
+/// as `async function* __agent__()` instead.
+fn transpile_agent_code(code: &str, declarations_js: &str) -> Result<AgentCode> {
     // Wrap agent code in a function so `return` is valid during transpilation.
-    let ts_source = format!(
-        "{type_decls}\nasync function __agent__() {{\n{code}\n}}",
-    );
-    let js = transpile::ts_to_js(&ts_source)
-        .map_err(|e| anyhow::anyhow!("TypeScript transpile error: {e}"))?;
+    let ts_source = format!("async function __agent__() {{\n{code}\n}}");
+    let (js, is_generator) = match transpile::ts_to_js(&ts_source) {
+        Ok(js) => (js, false),
+        Err(e) if code.contains("yield") => {
+            let gen_source = format!("async function* __agent__() {{\n{code}\n}}");
+            let js = transpile::ts_to_js(&gen_source)
+                .map_err(|_| anyhow::anyhow!("TypeScript transpile error: {e}"))?;
+            (js, true)
+        }
+        Err(e) => anyhow::bail!("TypeScript transpile error: {e}"),
+    };
 
     // Extract the function body — everything between first `{` and last `}`.
     // The transpiled output looks like: `async function __agent__() { <body> }`
-    // (type declarations are stripped, so only the function remains)
-    let body = if let Some(start) = js.find("async function __agent__()") {
+    let body = if let Some(start) = js.find("__agent__()") {
         let after_fn = &js[start..];
         if let Some(open) = after_fn.find('{') {
             let inner = &after_fn[open + 1..];
@@ -385,6 +2774,10 @@ fn transpile_agent_code(code: &str, type_decls: &str) -> Result<String> {
         // Fallback: return the full transpiled output.
         js
     };
+    let body = match declarations_js {
+        "" => body,
+        declarations => format!("{declarations}\n{body}"),
+    };
 
-    Ok(body)
+    Ok(AgentCode { body, is_generator })
 }
@@ -1,7 +1,12 @@
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::Duration;
 
+use notify_debouncer_mini::notify::RecursiveMode;
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult};
 use rmcp::handler::server::tool::ToolRouter;
 use rmcp::handler::server::wrapper::Parameters;
 use rmcp::model::*;
@@ -12,14 +17,19 @@ use tokio::sync::Mutex;
 use tracing::info;
 
 use cmcp_core::config;
+use cmcp_core::sandbox::{ConsoleLine, ExecuteOptions, console_logs};
 use cmcp_core::{ProxyEngine, truncate_response};
 
 /// Default max response length in characters (~10k tokens).
 const DEFAULT_MAX_LENGTH: usize = 40_000;
 
+/// How long config files must be quiet before a change is reloaded. Editors
+/// often write a file several times when saving.
+const RELOAD_DEBOUNCE: Duration = Duration::from_millis(300);
+
 #[derive(Debug, Deserialize, JsonSchema)]
 struct SearchRequest {
-    #[schemars(description = "TypeScript code to filter/explore the tools catalog. A typed `tools` array is available with fields: { server, name, description, input_schema }. Must return a value. Example: return tools.filter(t => t.description.toLowerCase().includes(\"design\"))")]
+    #[schemars(description = "TypeScript code to filter/explore the tools catalog. A typed `tools` array is available with fields: { server, name, description, input_schema, required, optional }. Must return a value. Example: return tools.filter(t => t.description.toLowerCase().includes(\"design\"))")]
     code: String,
     #[schemars(description = "Max response length in characters. Default: 40000. Use your code to extract only what you need rather than increasing this.")]
     #[serde(default)]
@@ -29,16 +39,65 @@ struct SearchRequest {
 #[derive(Debug, Deserialize, JsonSchema)]
 struct ExecuteRequest {
     #[schemars(description = "TypeScript code to execute. Each connected server is a typed global object where every tool is an async function. Type declarations are auto-generated from tool schemas. Chain calls sequentially: await chrome_devtools.navigate_page({ url: \"https://example.com\" }); const screenshot = await chrome_devtools.take_screenshot({ format: \"png\" }); return screenshot; Or run calls in parallel with Promise.all: const [issues, designs] = await Promise.all([github.list_issues({ repo: \"myorg/app\" }), canva.list_designs({})]);")]
+    #[serde(default)]
     code: String,
+    #[schemars(description = "Id of a configured template to run instead of code, where only templates are allowed.")]
+    #[serde(default)]
+    template: Option<String>,
+    #[schemars(description = "Parameters for the template, available to it as `params`.")]
+    #[serde(default)]
+    params: Option<serde_json::Value>,
     #[schemars(description = "Max response length in characters. Default: 40000. Use your code to extract only what you need rather than increasing this.")]
     #[serde(default)]
     max_length: Option<usize>,
+    #[schemars(description = "Also return every tool call made (server, tool, params, result) as a JSON array, e.g. to build test fixtures. Default: false.")]
+    #[serde(default)]
+    record: bool,
 }
 
-/// Hot-reload state: tracks config file mtimes.
+/// Hot-reload state: what was last applied and what failed.
 struct HotReloadState {
-    user_mtime: Option<SystemTime>,
-    project_mtime: Option<SystemTime>,
+    /// Content fingerprint of the config last applied. Rewriting it unchanged
+    /// doesn't reload.
+    applied_fingerprint: u64,
+    /// Content fingerprint of the last config that failed to reload.
+    /// A config with the same fingerprint is not re-attempted.
+    failed_fingerprint: Option<u64>,
+    /// Servers and sandbox settings of the config last applied.
+    servers: HashMap<String, config::ServerConfig>,
+    sandbox: config::SandboxConfig,
+}
+
+impl HotReloadState {
+    /// Whether a reload should be attempted for config with the given fingerprint.
+    fn should_attempt(&self, fingerprint: u64) -> bool {
+        self.applied_fingerprint != fingerprint && self.failed_fingerprint != Some(fingerprint)
+    }
+
+    /// The enabled servers `cfg` adds to the applied config, if adding servers
+    /// is all that changed. Those can be connected without a full reload.
+    fn added_servers(&self, cfg: &config::Config) -> Option<Vec<(String, config::ServerConfig)>> {
+        if cfg.sandbox != self.sandbox {
+            return None;
+        }
+        let same = |a: &config::ServerConfig, b: &config::ServerConfig| {
+            serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+        };
+        let changed = self
+            .servers
+            .iter()
+            .any(|(name, old)| cfg.servers.get(name).is_none_or(|new| !same(old, new)));
+        if changed {
+            return None;
+        }
+        let added: Vec<_> = cfg
+            .servers
+            .iter()
+            .filter(|(name, server)| !self.servers.contains_key(*name) && server.is_enabled())
+            .map(|(name, server)| (name.clone(), server.clone()))
+            .collect();
+        (!added.is_empty()).then_some(added)
+    }
 }
 
 /// The code-mode MCP server that exposes `search` and `execute` tools.
@@ -47,137 +106,291 @@ pub struct CodeModeServer {
     engine: Arc<ProxyEngine>,
     reload_state: Arc<Mutex<HotReloadState>>,
     config_path: Option<PathBuf>,
-    tool_router: ToolRouter<Self>,
+    /// View re-applied to the config on every reload.
+    view: Option<String>,
+    /// Rebuilt on reload, so `execute`'s description lists the current templates.
+    tool_router: Arc<std::sync::RwLock<ToolRouter<Self>>>,
+}
+
+/// Config files that feed into the merged config, in merge order.
+fn config_paths(config_path: Option<&PathBuf>) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    if let Ok(p) = config::default_config_path() {
+        paths.push(p);
+    }
+    paths.push(config::project_config_path());
+    paths.extend(config::env_config_path());
+    paths.extend(config_path.cloned());
+    paths
 }
 
-/// Get the modification time of a file, or None if it doesn't exist.
-fn file_mtime(path: &PathBuf) -> Option<SystemTime> {
-    std::fs::metadata(path).ok().and_then(|m| m.modified().ok())
+/// Hash the contents of all config files that feed into the merged config.
+/// Missing files hash differently from empty ones.
+fn config_fingerprint(paths: &[PathBuf]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for path in paths {
+        path.hash(&mut hasher);
+        std::fs::read(path).ok().hash(&mut hasher);
+    }
+    hasher.finish()
 }
 
 impl CodeModeServer {
+    /// With `no_connect`, returns before any server is connected so the MCP
+    /// handshake isn't held up; `search`/`execute` wait for the connections.
+    /// With `lazy`, each server is only connected when first used.
+    ///
+    /// Config files are watched from then on and reloaded in the background
+    /// when they change.
     pub async fn new(
-        servers: std::collections::HashMap<String, config::ServerConfig>,
+        cfg: config::Config,
         config_path: Option<PathBuf>,
+        view: Option<String>,
+        no_connect: bool,
+        lazy: bool,
     ) -> anyhow::Result<Self> {
-        let engine = ProxyEngine::from_configs(servers).await?;
-
-        // Snapshot current config file mtimes.
-        let user_mtime = config::default_config_path()
-            .ok()
-            .and_then(|p| file_mtime(&p));
-        let project_mtime = file_mtime(&config::project_config_path());
+        let (servers, sandbox) = (cfg.servers.clone(), cfg.sandbox.clone());
+        let router = Self::router_for(&cfg.sandbox);
+        let engine = if no_connect && !lazy {
+            ProxyEngine::connect_in_background(cfg.servers, cfg.sandbox).await?
+        } else {
+            Arc::new(ProxyEngine::from_configs(cfg.servers, cfg.sandbox, lazy).await?)
+        };
 
-        Ok(Self {
-            engine: Arc::new(engine),
+        let server = Self {
+            engine,
             reload_state: Arc::new(Mutex::new(HotReloadState {
-                user_mtime,
-                project_mtime,
+                applied_fingerprint: config_fingerprint(&config_paths(config_path.as_ref())),
+                failed_fingerprint: None,
+                servers,
+                sandbox,
             })),
             config_path,
-            tool_router: Self::tool_router(),
-        })
+            view,
+            tool_router: Arc::new(std::sync::RwLock::new(router)),
+        };
+        if let Err(e) = server.spawn_config_watcher() {
+            tracing::warn!(error = %e, "failed to watch config files, hot reload is off");
+        }
+        Ok(server)
     }
 
-    /// Check if config files have changed and reload if needed.
-    async fn maybe_reload(&self) {
-        let needs_reload = {
-            let state = self.reload_state.lock().await;
+    /// The tool router, with `execute`'s description listing the current templates.
+    fn current_router(&self) -> ToolRouter<Self> {
+        self.tool_router.read().unwrap().clone()
+    }
 
-            let current_user_mtime = config::default_config_path()
-                .ok()
-                .and_then(|p| file_mtime(&p));
-            let current_project_mtime = file_mtime(&config::project_config_path());
+    /// A tool router whose `execute` description lists `sandbox`'s templates.
+    fn router_for(sandbox: &config::SandboxConfig) -> ToolRouter<Self> {
+        let mut router = Self::tool_router();
+        if let Some(route) = router.map.get_mut("execute") {
+            let base = route.attr.description.clone().unwrap_or_default();
+            route.attr.description = Some(execute_description(&base, sandbox).into());
+        }
+        router
+    }
 
-            current_user_mtime != state.user_mtime
-                || current_project_mtime != state.project_mtime
-        };
+    /// The engine behind the tools, shared across hot reloads.
+    pub fn engine(&self) -> Arc<ProxyEngine> {
+        self.engine.clone()
+    }
+
+    /// Watch the directories holding the config files and reload after
+    /// writes to any of them settle. The watcher lives as long as the task.
+    fn spawn_config_watcher(&self) -> notify_debouncer_mini::notify::Result<()> {
+        let paths = config_paths(self.config_path.as_ref());
+        let names: HashSet<OsString> = paths.iter().filter_map(|p| p.file_name()).map(Into::into).collect();
+        let (changed_tx, mut changed_rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut debouncer = new_debouncer(RELOAD_DEBOUNCE, move |result: DebounceEventResult| match result {
+            Ok(events) => {
+                if events.iter().any(|e| e.path.file_name().is_some_and(|n| names.contains(n))) {
+                    let _ = changed_tx.send(());
+                }
+            }
+            Err(e) => tracing::warn!(error = %e, "config watcher error"),
+        })?;
+
+        let mut dirs: Vec<PathBuf> = paths
+            .iter()
+            .filter_map(|p| std::path::absolute(p).ok()?.parent().map(Path::to_path_buf))
+            .collect();
+        dirs.sort();
+        dirs.dedup();
+        for dir in dirs {
+            // The user config directory may not exist until `cmcp add` writes to it.
+            if let Err(e) = std::fs::create_dir_all(&dir) {
+                tracing::debug!(dir = %dir.display(), error = %e, "cannot create config directory");
+            }
+            if let Err(e) = debouncer.watcher().watch(&dir, RecursiveMode::NonRecursive) {
+                tracing::warn!(dir = %dir.display(), error = %e, "cannot watch config directory");
+            }
+        }
+
+        let server = self.clone();
+        tokio::spawn(async move {
+            let _debouncer = debouncer;
+            while changed_rx.recv().await.is_some() {
+                server.reload().await;
+            }
+        });
+        Ok(())
+    }
 
-        if !needs_reload {
+    /// Reload the config if its contents changed since it was last applied.
+    ///
+    /// If a reload fails, the config's content fingerprint is remembered and
+    /// the same broken config is not re-attempted until its contents change.
+    async fn reload(&self) {
+        // Let a background connect finish first so it can't overwrite a newer reload.
+        self.engine.wait_until_connected().await;
+
+        let fingerprint = config_fingerprint(&config_paths(self.config_path.as_ref()));
+        if !self.reload_state.lock().await.should_attempt(fingerprint) {
             return;
         }
 
         info!("config change detected, reloading servers...");
 
-        let cfg = match config::Config::load_merged(self.config_path.as_ref()) {
+        let loaded = config::Config::load_merged(self.config_path.as_ref()).and_then(|mut cfg| {
+            if let Some(view) = &self.view {
+                cfg.select_view(view)?;
+            }
+            Ok(cfg)
+        });
+        let cfg = match loaded {
             Ok(cfg) => cfg,
             Err(e) => {
-                tracing::warn!(error = %e, "failed to reload config, keeping current state");
+                tracing::warn!(error = %self.engine.redact(&e.to_string()), "failed to reload config, keeping current state");
+                self.engine.metrics().record_reload(false);
+                self.reload_state.lock().await.failed_fingerprint = Some(fingerprint);
                 return;
             }
         };
 
-        if let Err(e) = self.engine.reload(cfg.servers).await {
-            tracing::warn!(error = %e, "failed to reload proxy engine, keeping current state");
+        let added = self.reload_state.lock().await.added_servers(&cfg);
+        if let Some(added) = added {
+            // Only new servers: connect them and leave the others as they are.
+            for (name, server) in added {
+                if let Err(e) = self.engine.add_server(&name, server).await {
+                    tracing::warn!(server = %name, error = %self.engine.redact(&e.to_string()), "failed to add server, skipping");
+                }
+            }
+        } else if let Err(e) = self.engine.reload(cfg.servers.clone(), cfg.sandbox.clone()).await {
+            tracing::warn!(error = %self.engine.redact(&e.to_string()), "failed to reload proxy engine, keeping current state");
+            self.engine.metrics().record_reload(false);
+            self.reload_state.lock().await.failed_fingerprint = Some(fingerprint);
             return;
         }
 
         info!("{}", self.engine.summary().await);
+        self.engine.metrics().record_reload(true);
 
-        let user_mtime = config::default_config_path()
-            .ok()
-            .and_then(|p| file_mtime(&p));
-        let project_mtime = file_mtime(&config::project_config_path());
-
+        *self.tool_router.write().unwrap() = Self::router_for(&cfg.sandbox);
         let mut state = self.reload_state.lock().await;
-        state.user_mtime = user_mtime;
-        state.project_mtime = project_mtime;
+        state.applied_fingerprint = fingerprint;
+        state.failed_fingerprint = None;
+        state.servers = cfg.servers;
+        state.sandbox = cfg.sandbox;
 
         info!("hot-reload complete");
     }
 }
 
+/// `base` plus the ids of the configured templates, if there are any.
+fn execute_description(base: &str, sandbox: &config::SandboxConfig) -> String {
+    if sandbox.templates.is_empty() {
+        return base.to_string();
+    }
+    let mut ids: Vec<String> = sandbox.templates.keys().map(|id| format!("`{id}`")).collect();
+    ids.sort();
+    let ids = ids.join(", ");
+    if sandbox.templates_only == Some(true) {
+        format!("{base} Only configured templates can run here: pass `template` (one of {ids}) and its `params` instead of `code`.")
+    } else {
+        format!("{base} Configured templates, run by passing `template` and `params` instead of `code`: {ids}.")
+    }
+}
+
+/// Console output from agent code as a separate text block, if there was any.
+fn console_content(logs: &[ConsoleLine]) -> Option<Content> {
+    if logs.is_empty() {
+        return None;
+    }
+    let lines: Vec<String> = logs.iter().map(ToString::to_string).collect();
+    Some(Content::text(format!("[console]\n{}", lines.join("\n"))))
+}
+
 #[tool_router]
 impl CodeModeServer {
     #[tool(
         name = "search",
-        description = "Search across all tools from all connected MCP servers. Write TypeScript code to filter the tool catalog. A typed `tools` array is available with { server, name, description, input_schema } fields."
+        description = "Search across all tools from all connected MCP servers. Write TypeScript code to filter the tool catalog. A typed `tools` array is available with { server, name, description, input_schema, required, optional } fields, plus `servers` ({ name, description, tools }), `resources` ({ server, uri, name, description, mime_type }) and `prompts` ({ server, name, description, required, optional }) arrays."
     )]
     async fn search(
         &self,
         Parameters(req): Parameters<SearchRequest>,
     ) -> Result<CallToolResult, McpError> {
-        self.maybe_reload().await;
-
         match self.engine.search(&req.code, req.max_length).await {
             Ok(result) => {
-                let text = serde_json::to_string_pretty(&result).unwrap_or_default();
-                Ok(CallToolResult::success(vec![Content::text(
-                    truncate_response(text, req.max_length.unwrap_or(DEFAULT_MAX_LENGTH)),
-                )]))
+                let text = serde_json::to_string_pretty(&result.value).unwrap_or_default();
+                let mut content = vec![Content::text(truncate_response(
+                    text,
+                    req.max_length.unwrap_or(DEFAULT_MAX_LENGTH),
+                ))];
+                content.extend(console_content(&result.logs));
+                Ok(CallToolResult::success(content))
+            }
+            Err(e) => {
+                let mut content = vec![Content::text(self.engine.redact(&format!("search error: {e}")))];
+                content.extend(console_content(console_logs(&e)));
+                Ok(CallToolResult::error(content))
             }
-            Err(e) => Ok(CallToolResult::error(vec![Content::text(format!(
-                "search error: {e}"
-            ))])),
         }
     }
 
     #[tool(
         name = "execute",
-        description = "Execute TypeScript code that calls tools across all connected MCP servers. Each server is a typed global object (e.g. `canva`, `figma`) where every tool is an async function with typed parameters: `await server.tool_name({ param: value })`. Chain calls sequentially or run them in parallel with Promise.all across different servers."
+        description = "Execute TypeScript code that calls tools across all connected MCP servers. Each server is a typed global object (e.g. `canva`, `figma`) where every tool is an async function with typed parameters: `await server.tool_name({ param: value })`. A `servers` array lists them as { name, description, tools } with a tool count. Chain calls sequentially or run them in parallel with Promise.all across different servers."
     )]
     async fn execute(
         &self,
         Parameters(req): Parameters<ExecuteRequest>,
     ) -> Result<CallToolResult, McpError> {
-        self.maybe_reload().await;
-
-        match self.engine.execute(&req.code, req.max_length).await {
+        let options = ExecuteOptions {
+            max_length: req.max_length,
+            record: req.record,
+        };
+        let outcome = match &req.template {
+            Some(_) if !req.code.is_empty() => Err(anyhow::anyhow!("pass either code or a template, not both")),
+            Some(template) => {
+                let params = req.params.clone().unwrap_or_else(|| serde_json::json!({}));
+                self.engine.execute_template(template, &params, &options).await
+            }
+            None => self.engine.execute_with_options(&req.code, &options).await,
+        };
+        match outcome {
             Ok(result) => {
                 let mut content = vec![Content::text(result.text)];
                 for img in result.images {
                     content.push(Content::image(img.data, img.mime_type));
                 }
+                content.extend(console_content(&result.logs));
+                if req.record {
+                    let calls = serde_json::to_string_pretty(&result.calls).unwrap_or_default();
+                    content.push(Content::text(format!("[calls]\n{calls}")));
+                }
                 Ok(CallToolResult::success(content))
             }
-            Err(e) => Ok(CallToolResult::error(vec![Content::text(format!(
-                "execute error: {e}"
-            ))])),
+            Err(e) => {
+                let mut content = vec![Content::text(self.engine.redact(&format!("execute error: {e}")))];
+                content.extend(console_content(console_logs(&e)));
+                Ok(CallToolResult::error(content))
+            }
         }
     }
 }
 
-#[tool_handler]
+#[tool_handler(router = self.current_router())]
 impl ServerHandler for CodeModeServer {
     fn get_info(&self) -> ServerInfo {
         ServerInfo {
@@ -186,8 +399,13 @@ impl ServerHandler for CodeModeServer {
                  Use `search` to discover available tools by writing TypeScript filter code.\n\
                  Use `execute` to call tools across servers by writing TypeScript code.\n\n\
                  Each connected server is a typed object in `execute` with auto-generated type declarations from tool schemas.\n\
-                 Example: `await canva.create_design({ type: \"poster\" })`\n\n\
-                 Hot-reload: add or remove servers with `cmcp add`/`cmcp remove` — changes are picked up on the next call."
+                 Example: `await canva.create_design({ type: \"poster\" })`\n\
+                 Tool calls return cancellable promises: `const p = github.list_issues({}); p.cancel();` aborts just that call.\n\
+                 The value returned by the previous `execute` call is available as `__last_result`.\n\
+                 Resources published by servers are listed in `resources` ({ server, uri, name, description, mime_type }); fetch one with `await read_resource(uri)`.\n\
+                 Prompts are listed in `prompts` ({ server, name, description, required, optional }); `await get_prompt(server, name, args)` returns the rendered messages.\n\
+                 Helpers: `cmcp.retry(fn, { retries, delayMs })`, `cmcp.chunk(arr, size)`, `cmcp.pollUntil(fn, predicate, { intervalMs, timeoutMs })`, `cmcp.sleep(ms)`, `cmcp.rank(query)` (ranked tool search).\n\n\
+                 Hot-reload: add or remove servers with `cmcp add`/`cmcp remove` — changes are picked up automatically."
                     .to_string(),
             ),
             capabilities: ServerCapabilities::builder().enable_tools().build(),
@@ -195,3 +413,163 @@ impl ServerHandler for CodeModeServer {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_broken_config_not_reattempted() {
+        let dir = std::env::temp_dir().join(format!("cmcp-reload-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        std::fs::write(&path, "[servers.broken\ntransport = ").unwrap();
+        assert!(config::Config::load_from(&path).is_err());
+
+        let mut state = HotReloadState {
+            applied_fingerprint: 0,
+            failed_fingerprint: None,
+            servers: HashMap::new(),
+            sandbox: Default::default(),
+        };
+
+        let paths = vec![path.clone()];
+        let first = config_fingerprint(&paths);
+        assert!(state.should_attempt(first));
+        state.failed_fingerprint = Some(first);
+
+        // Rewriting identical contents (new mtime, same bytes) is not re-attempted.
+        std::fs::write(&path, "[servers.broken\ntransport = ").unwrap();
+        assert!(!state.should_attempt(config_fingerprint(&paths)));
+
+        // A real change is attempted again.
+        std::fs::write(&path, "[servers]\n").unwrap();
+        assert!(state.should_attempt(config_fingerprint(&paths)));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_only_added_servers_skip_the_full_reload() {
+        let stdio = |command: &str| config::ServerConfig::Stdio {
+            command: command.to_string(),
+            args: Vec::new(),
+            env: HashMap::new(),
+            options: Default::default(),
+        };
+        let state = HotReloadState {
+            applied_fingerprint: 0,
+            failed_fingerprint: None,
+            servers: HashMap::from([("github".to_string(), stdio("gh-mcp"))]),
+            sandbox: Default::default(),
+        };
+        let with = |servers: &[(&str, &str)]| config::Config {
+            servers: servers.iter().map(|(name, command)| (name.to_string(), stdio(command))).collect(),
+            ..Default::default()
+        };
+
+        let added = state.added_servers(&with(&[("github", "gh-mcp"), ("jira", "jira-mcp")])).unwrap();
+        assert_eq!(added.len(), 1);
+        assert_eq!(added[0].0, "jira");
+
+        // A changed or removed server, or nothing new, needs the full reload.
+        assert!(state.added_servers(&with(&[("github", "other"), ("jira", "jira-mcp")])).is_none());
+        assert!(state.added_servers(&with(&[("jira", "jira-mcp")])).is_none());
+        assert!(state.added_servers(&with(&[("github", "gh-mcp")])).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_config_changes_reload_in_the_background() {
+        let dir = std::env::temp_dir().join(format!("cmcp-watch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        std::fs::write(&path, "[servers]\n").unwrap();
+        let cfg = config::Config::load_merged(Some(&path)).unwrap();
+        let server = CodeModeServer::new(cfg, Some(path.clone()), None, false, false).await.unwrap();
+
+        // A burst of writes, as editors make, settles into the last one.
+        std::fs::write(&path, "[sandbox]\ncall_deadline_secs = 1\n").unwrap();
+        std::fs::write(&path, "[sandbox]\ncall_deadline_secs = 5\n").unwrap();
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+        while server.reload_state.lock().await.sandbox.call_deadline_secs != Some(5.0) {
+            assert!(tokio::time::Instant::now() < deadline, "config change was not reloaded");
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_no_connect_answers_initialize_before_servers_connect() {
+        use rmcp::ServiceExt;
+
+        // Accepts connections but never answers, so connecting takes the full timeout.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                held.push(socket);
+            }
+        });
+
+        let mut cfg = config::Config::default();
+        cfg.servers.insert(
+            "stalled".to_string(),
+            config::ServerConfig::Http {
+                url: format!("http://{addr}/mcp"),
+                auth: None,
+                headers: Default::default(),
+                options: config::ServerOptions {
+                    connect_timeout_ms: Some(1500),
+                    ..Default::default()
+                },
+            },
+        );
+
+        let start = std::time::Instant::now();
+        let server = CodeModeServer::new(cfg, None, None, true, false).await.unwrap();
+        let (client_io, server_io) = tokio::io::duplex(4096);
+        tokio::spawn(async move {
+            let running = server.serve(server_io).await.unwrap();
+            let _ = running.waiting().await;
+        });
+        let client = ().serve(client_io).await.unwrap();
+        assert!(start.elapsed() < std::time::Duration::from_millis(1000));
+        assert!(client.peer_info().is_some());
+
+        // The first search waits for the stalled server to time out, then runs without it.
+        let result = client
+            .call_tool(CallToolRequestParams {
+                meta: None,
+                name: "search".into(),
+                arguments: serde_json::json!({ "code": "return tools.length;" }).as_object().cloned(),
+                task: None,
+            })
+            .await
+            .unwrap();
+        assert!(start.elapsed() >= std::time::Duration::from_millis(1500));
+        assert_eq!(result.content[0].as_text().unwrap().text, "0");
+    }
+
+    #[test]
+    fn test_execute_description_lists_templates() {
+        let base = "Execute TypeScript code.";
+        let mut sandbox = config::SandboxConfig::default();
+        assert_eq!(execute_description(base, &sandbox), base);
+
+        sandbox.templates = HashMap::from([
+            ("list_issues".to_string(), "return 1;".to_string()),
+            ("close_issue".to_string(), "return 2;".to_string()),
+        ]);
+        assert_eq!(
+            execute_description(base, &sandbox),
+            "Execute TypeScript code. Configured templates, run by passing `template` and `params` instead of `code`: `close_issue`, `list_issues`."
+        );
+
+        sandbox.templates_only = Some(true);
+        let router = CodeModeServer::router_for(&sandbox);
+        let description = router.get("execute").unwrap().description.as_deref().unwrap();
+        assert!(description.ends_with("Only configured templates can run here: pass `template` (one of `close_issue`, `list_issues`) and its `params` instead of `code`."), "{description}");
+    }
+}
@@ -0,0 +1,189 @@
+//! The HTTP client behind `http` and `sse` servers.
+//!
+//! rmcp's reqwest client always sends `Accept: text/event-stream, application/json`
+//! and refuses `Accept` among the custom headers. Some servers only take the
+//! order from the spec, so this client sends `application/json, text/event-stream`
+//! on POST and lets an `Accept` in the server's `headers` replace it. Everything
+//! else, including the GET stream, is rmcp's client unchanged.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use futures::StreamExt;
+use futures::stream::BoxStream;
+use http::header::{ACCEPT, CONTENT_TYPE, WWW_AUTHENTICATE};
+use http::{HeaderName, HeaderValue};
+use rmcp::model::{ClientJsonRpcMessage, ServerJsonRpcMessage};
+use rmcp::transport::StreamableHttpClientTransport;
+use rmcp::transport::common::http_header::{
+    EVENT_STREAM_MIME_TYPE, HEADER_LAST_EVENT_ID, HEADER_MCP_PROTOCOL_VERSION, HEADER_SESSION_ID,
+    JSON_MIME_TYPE,
+};
+use rmcp::transport::streamable_http_client::{
+    AuthRequiredError, InsufficientScopeError, SseError, StreamableHttpClient, StreamableHttpClientTransportConfig,
+    StreamableHttpError, StreamableHttpPostResponse,
+};
+use sse_stream::{Sse, SseStream};
+
+/// `Accept` sent with every POST unless the config overrides it.
+pub const DEFAULT_ACCEPT: &str = "application/json, text/event-stream";
+
+/// Headers the transport sets itself. `Accept` is taken out of the custom
+/// headers before they get here.
+const RESERVED_HEADERS: [&str; 3] = [HEADER_SESSION_ID, HEADER_MCP_PROTOCOL_VERSION, HEADER_LAST_EVENT_ID];
+
+#[derive(Clone, Debug, Default)]
+pub struct HttpClient {
+    inner: reqwest::Client,
+    /// `Accept` from the server's `headers`, sent on POST instead of [`DEFAULT_ACCEPT`].
+    accept: Option<HeaderValue>,
+}
+
+/// A streamable HTTP transport for `config`. An `Accept` among its custom
+/// headers replaces the one sent with each POST.
+pub fn transport(
+    mut config: StreamableHttpClientTransportConfig,
+) -> StreamableHttpClientTransport<HttpClient> {
+    let client = HttpClient {
+        accept: config.custom_headers.remove(&ACCEPT),
+        ..Default::default()
+    };
+    StreamableHttpClientTransport::with_client(client, config)
+}
+
+fn invalid_www_authenticate() -> StreamableHttpError<reqwest::Error> {
+    StreamableHttpError::UnexpectedServerResponse(Cow::from("invalid www-authenticate header value"))
+}
+
+/// The `scope` parameter of a `WWW-Authenticate` challenge.
+fn required_scope(header: &str) -> Option<String> {
+    let start = header.to_ascii_lowercase().find("scope=")? + "scope=".len();
+    let value = &header[start..];
+    let scope = match value.strip_prefix('"') {
+        Some(quoted) => &quoted[..quoted.find('"')?],
+        None => value.split(|c: char| c == ',' || c == ';' || c.is_whitespace()).next()?,
+    };
+    (!scope.is_empty()).then(|| scope.to_string())
+}
+
+impl StreamableHttpClient for HttpClient {
+    type Error = reqwest::Error;
+
+    async fn post_message(
+        &self,
+        uri: Arc<str>,
+        message: ClientJsonRpcMessage,
+        session_id: Option<Arc<str>>,
+        auth_header: Option<String>,
+        custom_headers: HashMap<HeaderName, HeaderValue>,
+    ) -> Result<StreamableHttpPostResponse, StreamableHttpError<Self::Error>> {
+        let accept = self.accept.clone().unwrap_or(HeaderValue::from_static(DEFAULT_ACCEPT));
+        let mut request = self.inner.post(uri.as_ref()).header(ACCEPT, accept);
+        if let Some(token) = auth_header {
+            request = request.bearer_auth(token);
+        }
+        for (name, value) in custom_headers {
+            if RESERVED_HEADERS.iter().any(|r| name.as_str().eq_ignore_ascii_case(r)) {
+                return Err(StreamableHttpError::ReservedHeaderConflict(name.to_string()));
+            }
+            request = request.header(name, value);
+        }
+        if let Some(session_id) = session_id {
+            request = request.header(HEADER_SESSION_ID, session_id.as_ref());
+        }
+        let response = request.json(&message).send().await?;
+
+        let status = response.status();
+        if let Some(header) = response.headers().get(WWW_AUTHENTICATE) {
+            if status == reqwest::StatusCode::UNAUTHORIZED {
+                let header = header.to_str().map_err(|_| invalid_www_authenticate())?;
+                return Err(StreamableHttpError::AuthRequired(AuthRequiredError {
+                    www_authenticate_header: header.to_string(),
+                }));
+            }
+            if status == reqwest::StatusCode::FORBIDDEN {
+                let header = header.to_str().map_err(|_| invalid_www_authenticate())?;
+                return Err(StreamableHttpError::InsufficientScope(InsufficientScopeError {
+                    www_authenticate_header: header.to_string(),
+                    required_scope: required_scope(header),
+                }));
+            }
+        }
+        if matches!(status, reqwest::StatusCode::ACCEPTED | reqwest::StatusCode::NO_CONTENT) {
+            return Ok(StreamableHttpPostResponse::Accepted);
+        }
+        // A 406 for the Accept header surfaces here rather than as a bad content type.
+        let response = response.error_for_status()?;
+        let session_id = response
+            .headers()
+            .get(HEADER_SESSION_ID)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        match response.headers().get(CONTENT_TYPE) {
+            Some(ct) if ct.as_bytes().starts_with(EVENT_STREAM_MIME_TYPE.as_bytes()) => {
+                let stream = SseStream::from_bytes_stream(response.bytes_stream()).boxed();
+                Ok(StreamableHttpPostResponse::Sse(stream, session_id))
+            }
+            Some(ct) if ct.as_bytes().starts_with(JSON_MIME_TYPE.as_bytes()) => {
+                let message: ServerJsonRpcMessage = response.json().await?;
+                Ok(StreamableHttpPostResponse::Json(message, session_id))
+            }
+            other => Err(StreamableHttpError::UnexpectedContentType(
+                other.map(|ct| String::from_utf8_lossy(ct.as_bytes()).into_owned()),
+            )),
+        }
+    }
+
+    async fn delete_session(
+        &self,
+        uri: Arc<str>,
+        session_id: Arc<str>,
+        auth_header: Option<String>,
+    ) -> Result<(), StreamableHttpError<Self::Error>> {
+        self.inner.delete_session(uri, session_id, auth_header).await
+    }
+
+    async fn get_stream(
+        &self,
+        uri: Arc<str>,
+        session_id: Arc<str>,
+        last_event_id: Option<String>,
+        auth_header: Option<String>,
+    ) -> Result<BoxStream<'static, Result<Sse, SseError>>, StreamableHttpError<Self::Error>> {
+        self.inner.get_stream(uri, session_id, last_event_id, auth_header).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_required_scope() {
+        assert_eq!(
+            required_scope(r#"Bearer error="insufficient_scope", scope="files:read files:write""#).as_deref(),
+            Some("files:read files:write")
+        );
+        assert_eq!(required_scope(r#"Bearer scope=read:data, error="insufficient_scope""#).as_deref(), Some("read:data"));
+        assert_eq!(required_scope(r#"Bearer error="invalid_token""#), None);
+    }
+
+    #[tokio::test]
+    async fn test_forbidden_asks_for_scope() {
+        let addr = crate::test_support::http_server(|_, _| {
+            let challenge = r#"Bearer error="insufficient_scope", scope="files:write""#;
+            (format!("403 Forbidden\r\nwww-authenticate: {challenge}"), String::new())
+        })
+        .await;
+        let message: ClientJsonRpcMessage =
+            serde_json::from_value(serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "ping" })).unwrap();
+        let result = HttpClient::default()
+            .post_message(format!("http://{addr}/mcp").into(), message, None, None, HashMap::new())
+            .await;
+        match result {
+            Err(StreamableHttpError::InsufficientScope(e)) => assert_eq!(e.get_required_scope(), Some("files:write")),
+            other => panic!("expected InsufficientScope, got {other:?}"),
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! OpenTelemetry export of the `search`, `execute` and `call_tool` spans.
+//!
+//! Only with the `otel` feature: spans go to the OTLP endpoint set under
+//! `[telemetry]`, and upstream tool calls carry the W3C trace context
+//! (`traceparent`) in their `_meta`, so servers can join the same trace.
+
+use rmcp::model::Meta;
+
+#[cfg(feature = "otel")]
+pub use otel::{layer, layer_for, shutdown};
+
+/// The current span's trace context for a request's `_meta`, if spans are exported.
+pub fn trace_meta() -> Option<Meta> {
+    #[cfg(feature = "otel")]
+    return otel::trace_meta();
+    #[cfg(not(feature = "otel"))]
+    None
+}
+
+#[cfg(feature = "otel")]
+mod otel {
+    use std::collections::HashMap;
+    use std::sync::OnceLock;
+
+    use anyhow::{Context, Result};
+    use opentelemetry::propagation::TextMapPropagator;
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::Resource;
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+    use opentelemetry_sdk::trace::SdkTracerProvider;
+    use rmcp::model::Meta;
+    use tracing::{Level, Subscriber};
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+    use tracing_subscriber::Layer;
+    use tracing_subscriber::filter::Targets;
+    use tracing_subscriber::registry::LookupSpan;
+
+    use crate::config::TelemetryConfig;
+
+    /// Kept so [`shutdown`] can flush spans still waiting for a batch.
+    static PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+    /// A layer exporting cmcp's spans over OTLP/HTTP, or `None` without an endpoint.
+    /// Independent of `RUST_LOG`, which only filters the log output.
+    pub fn layer<S>(config: &TelemetryConfig) -> Result<Option<impl Layer<S> + use<S>>>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        let Some(endpoint) = &config.otlp_endpoint else {
+            return Ok(None);
+        };
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()
+            .context("failed to create the OTLP span exporter")?;
+        let service_name = config.service_name.clone().unwrap_or_else(|| "cmcp".to_string());
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(Resource::builder().with_service_name(service_name).build())
+            .build();
+        let layer = layer_for(&provider).with_filter(Targets::new().with_target("cmcp_core", Level::INFO));
+        let _ = PROVIDER.set(provider);
+        Ok(Some(layer))
+    }
+
+    /// A layer recording spans into `provider`.
+    pub fn layer_for<S>(provider: &SdkTracerProvider) -> impl Layer<S> + use<S>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        tracing_opentelemetry::layer().with_tracer(provider.tracer("cmcp"))
+    }
+
+    /// Flush buffered spans. Call before exiting.
+    pub fn shutdown() {
+        if let Some(provider) = PROVIDER.get()
+            && let Err(e) = provider.shutdown()
+        {
+            tracing::warn!(error = %e, "failed to flush spans");
+        }
+    }
+
+    pub(super) fn trace_meta() -> Option<Meta> {
+        let context = tracing::Span::current().context();
+        let mut fields = HashMap::new();
+        TraceContextPropagator::new().inject_context(&context, &mut fields);
+        if fields.is_empty() {
+            return None;
+        }
+        Some(Meta(fields.into_iter().map(|(k, v)| (k, v.into())).collect()))
+    }
+}
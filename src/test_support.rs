@@ -0,0 +1,132 @@
+//! Fixtures shared by the unit tests of several modules.
+
+/// A local HTTP/1.1 server answering each request with `respond(headers, body)`.
+/// Header names are lowercased and the body is parsed as JSON (null if it
+/// isn't). `respond` returns the status line, plus any extra header lines,
+/// and the response body.
+pub(crate) async fn http_server<F>(respond: F) -> std::net::SocketAddr
+where
+    F: Fn(&std::collections::HashMap<String, String>, serde_json::Value) -> (String, String)
+        + Clone
+        + Send
+        + 'static,
+{
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            let respond = respond.clone();
+            tokio::spawn(async move {
+                let mut socket = BufReader::new(socket);
+                loop {
+                    let mut headers = std::collections::HashMap::new();
+                    let mut line = String::new();
+                    while socket.read_line(&mut line).await.unwrap_or(0) > 0 && line != "\r\n" {
+                        if let Some((name, value)) = line.split_once(':') {
+                            headers.insert(name.to_ascii_lowercase(), value.trim().to_string());
+                        }
+                        line.clear();
+                    }
+                    if line.is_empty() {
+                        return;
+                    }
+                    let length = headers.get("content-length").map_or(0, |l| l.parse().unwrap());
+                    let mut body = vec![0; length];
+                    socket.read_exact(&mut body).await.unwrap();
+                    let (head, body) = respond(&headers, serde_json::from_slice(&body).unwrap_or_default());
+                    let response = format!("HTTP/1.1 {head}\r\ncontent-length: {}\r\n\r\n{body}", body.len());
+                    if socket.get_mut().write_all(response.as_bytes()).await.is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+    addr
+}
+
+/// A streamable HTTP server with one `ping` tool that answers 406 unless the
+/// `header` request header is exactly `required`.
+pub(crate) async fn header_checking_server(header: &'static str, required: &'static str) -> std::net::SocketAddr {
+    http_server(move |headers, request| {
+        let result = match request["method"].as_str() {
+            _ if headers.get(header).map(String::as_str) != Some(required) => {
+                return ("406 Not Acceptable".to_string(), String::new());
+            }
+            Some("initialize") => serde_json::json!({
+                "protocolVersion": "2025-03-26",
+                "capabilities": { "tools": {} },
+                "serverInfo": { "name": "picky", "version": "1.0.0" },
+            }),
+            Some("tools/list") => serde_json::json!({
+                "tools": [{ "name": "ping", "inputSchema": { "type": "object" } }],
+            }),
+            _ if request.get("id").is_none() => return ("202 Accepted".to_string(), String::new()),
+            _ => serde_json::Value::Null,
+        };
+        let body = serde_json::json!({ "jsonrpc": "2.0", "id": request["id"], "result": result });
+        ("200 OK\r\ncontent-type: application/json".to_string(), body.to_string())
+    })
+    .await
+}
+
+/// Serve `handler` over an in-memory pipe and connect to it as upstream
+/// `name`. The config is a stand-in, only used if the pool reconnects.
+pub(crate) async fn in_process_upstream<H: rmcp::ServerHandler>(
+    name: &str,
+    handler: H,
+) -> (crate::client::UpstreamService, crate::config::ServerConfig) {
+    use rmcp::ServiceExt;
+
+    let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move {
+        let running = handler.serve(server_io).await.unwrap();
+        let _ = running.waiting().await;
+    });
+    let service = crate::client::UpstreamHandler::new(name).serve(client_io).await.unwrap();
+    (service, stand_in_config(Default::default()))
+}
+
+/// A stdio config for in-process upstreams, carrying `options`.
+pub(crate) fn stand_in_config(options: crate::config::ServerOptions) -> crate::config::ServerConfig {
+    crate::config::ServerConfig::Stdio {
+        command: "true".to_string(),
+        args: Vec::new(),
+        env: std::collections::HashMap::new(),
+        options,
+    }
+}
+
+/// Mock upstream whose tools all reply "done" after a short delay, counting
+/// the calls and the most it ever had in flight.
+#[derive(Clone, Default)]
+pub(crate) struct CountingServer {
+    in_flight: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    pub peak: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    pub calls: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+}
+
+impl rmcp::ServerHandler for CountingServer {
+    async fn call_tool(
+        &self,
+        _request: rmcp::model::CallToolRequestParams,
+        _context: rmcp::service::RequestContext<rmcp::RoleServer>,
+    ) -> Result<rmcp::model::CallToolResult, rmcp::ErrorData> {
+        use std::sync::atomic::Ordering;
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        let now = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        self.peak.fetch_max(now, Ordering::SeqCst);
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+        Ok(rmcp::model::CallToolResult::success(vec![rmcp::model::Content::text("done")]))
+    }
+
+    fn get_info(&self) -> rmcp::model::ServerInfo {
+        rmcp::model::ServerInfo {
+            capabilities: rmcp::model::ServerCapabilities::builder().enable_tools().build(),
+            ..Default::default()
+        }
+    }
+}
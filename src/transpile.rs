@@ -80,6 +80,22 @@ return tools.filter(t => t.name.includes("screenshot"))
         assert!(!js.contains("declare"), "declarations not stripped: {js}");
     }
 
+    #[test]
+    fn test_declarations_transpile_separately() {
+        let decls = r#"
+declare const tools: Array<{ server: string; name: string }>;
+declare const github: {
+  list_issues(params: { repo: string; state?: "open" | "closed" }): Promise<any>;
+};
+"#;
+        let agent = "async function __agent__() {\nconst issues: any[] = await github.list_issues({ repo: \"a/b\" });\nreturn issues.length as number;\n}";
+
+        assert_eq!(ts_to_js(decls).unwrap().trim(), "");
+        // Leaving the declarations out doesn't change the agent's output.
+        let together = ts_to_js(&format!("{decls}\n{agent}")).unwrap();
+        assert_eq!(together, ts_to_js(agent).unwrap());
+    }
+
     #[test]
     fn test_arrow_function() {
         let source = "async function __agent__() {\nconst result = tools.map(t => ({ server: t.server, name: t.name }));\nreturn result;\n}";
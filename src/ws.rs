@@ -0,0 +1,133 @@
+//! WebSocket transport for upstream servers (`transport = "ws"`).
+//!
+//! rmcp has no WebSocket client, so each JSON-RPC message is sent as one text
+//! frame over tokio-tungstenite and rmcp is handed the sink/stream pair.
+
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use rmcp::model::{ClientJsonRpcMessage, ServerJsonRpcMessage};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+/// Open a WebSocket to `url`. `auth` and `headers` are sent on the upgrade
+/// request and must already have `env:` references resolved.
+pub async fn connect(
+    url: &str,
+    auth: Option<String>,
+    headers: HashMap<String, String>,
+) -> Result<(
+    impl Sink<ClientJsonRpcMessage, Error = std::io::Error> + Send + Unpin + 'static,
+    impl Stream<Item = ServerJsonRpcMessage> + Send + Unpin + 'static,
+)> {
+    let mut request = url
+        .into_client_request()
+        .with_context(|| format!("invalid WebSocket URL {url}"))?;
+    if let Some(token) = auth {
+        let value = http::HeaderValue::try_from(format!("Bearer {token}"))
+            .context("auth token is not a valid header value")?;
+        request.headers_mut().insert(http::header::AUTHORIZATION, value);
+    }
+    for (k, v) in &headers {
+        let name = http::HeaderName::try_from(k.as_str()).with_context(|| format!("invalid header name \"{k}\""))?;
+        let value = http::HeaderValue::try_from(v.as_str())
+            .with_context(|| format!("header \"{k}\" has a value that is not a valid header value"))?;
+        request.headers_mut().insert(name, value);
+    }
+
+    let (socket, _response) = tokio_tungstenite::connect_async(request).await?;
+    let (sink, stream) = socket.split::<Message>();
+
+    let sink = sink
+        .sink_map_err(std::io::Error::other)
+        .with(|message: ClientJsonRpcMessage| {
+            futures::future::ready(
+                serde_json::to_string(&message)
+                    .map(Message::text)
+                    .map_err(std::io::Error::from),
+            )
+        });
+
+    // The stream ends at the first close frame or socket error.
+    let stream = stream
+        .take_while(|frame| futures::future::ready(frame.as_ref().is_ok_and(|m| !m.is_close())))
+        .filter_map(|frame| {
+            let message = match frame {
+                Ok(Message::Text(text)) => Some(serde_json::from_str::<ServerJsonRpcMessage>(&text)),
+                Ok(Message::Binary(bytes)) => Some(serde_json::from_slice::<ServerJsonRpcMessage>(&bytes)),
+                _ => None,
+            };
+            let message = match message {
+                Some(Ok(message)) => Some(message),
+                Some(Err(e)) => {
+                    tracing::warn!(error = %e, "ignoring undecodable WebSocket frame");
+                    None
+                }
+                None => None,
+            };
+            futures::future::ready(message)
+        });
+
+    Ok((sink, stream))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_connect_sends_headers_and_frames_messages() {
+        use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/mcp", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (tcp, _) = listener.accept().await.unwrap();
+            let mut seen = Vec::new();
+            // The callback signature is fixed by tungstenite.
+            #[allow(clippy::result_large_err)]
+            let check = |request: &Request, response: Response| {
+                for name in ["authorization", "x-team"] {
+                    seen.push(request.headers()[name].to_str().unwrap().to_string());
+                }
+                Ok(response)
+            };
+            let mut socket = tokio_tungstenite::accept_hdr_async(tcp, check).await.unwrap();
+            let received = socket.next().await.unwrap().unwrap();
+            socket
+                .send(Message::text(r#"{"jsonrpc":"2.0","id":1,"method":"ping"}"#))
+                .await
+                .unwrap();
+            socket.close(None).await.unwrap();
+            (seen, received.into_text().unwrap().to_string())
+        });
+
+        let headers = HashMap::from([("X-Team".to_string(), "infra".to_string())]);
+        let (mut sink, mut stream) = connect(&url, Some("abc".to_string()), headers).await.unwrap();
+        let initialized: ClientJsonRpcMessage =
+            serde_json::from_str(r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#).unwrap();
+        sink.send(initialized).await.unwrap();
+        let ping = stream.next().await.unwrap();
+        assert_eq!(serde_json::to_value(&ping).unwrap()["method"], "ping");
+        assert!(stream.next().await.is_none(), "stream should end at the close frame");
+
+        let (seen, received) = server.await.unwrap();
+        assert_eq!(seen, ["Bearer abc", "infra"]);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&received).unwrap()["method"],
+            "notifications/initialized"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invalid_header_is_an_error() {
+        let headers = HashMap::from([("Bad Header".to_string(), "x".to_string())]);
+        let err = connect("ws://127.0.0.1:9/mcp", None, headers).await.err().unwrap();
+        assert_eq!(err.to_string(), "invalid header name \"Bad Header\"");
+
+        let headers = HashMap::from([("X-Key".to_string(), "line\nbreak".to_string())]);
+        let err = connect("ws://127.0.0.1:9/mcp", None, headers).await.err().unwrap();
+        assert!(err.to_string().starts_with("header \"X-Key\""), "{err}");
+    }
+}